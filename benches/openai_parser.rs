@@ -0,0 +1,99 @@
+// benches/openai_parser.rs
+
+use bytes::{Bytes, BytesMut};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use futures::executor::block_on;
+use rust_llm_logger::parsers::{BackendStreamParser, OpenAIParser};
+
+const CHUNKS: usize = 10_000;
+
+/// A synthetic chat completion stream of `CHUNKS` delta chunks plus a usage chunk
+fn synthetic_stream() -> Vec<Bytes> {
+    let mut chunks: Vec<Bytes> = (0..CHUNKS)
+        .map(|i| {
+            Bytes::from(format!(
+                "data: {{\"id\":\"chatcmpl-bench\",\"object\":\"chat.completion.chunk\",\"choices\":[{{\"index\":0,\"delta\":{{\"content\":\"token {i} \"}},\"finish_reason\":null}}]}}\n\n"
+            ))
+        })
+        .collect();
+    chunks.push(Bytes::from_static(
+        b"data: {\"choices\":[],\"usage\":{\"prompt_tokens\":12,\"completion_tokens\":10000}}\n\ndata: [DONE]\n\n",
+    ));
+    chunks
+}
+
+/// The same stream with every event split across two network reads
+fn split_stream(chunks: &[Bytes]) -> Vec<Bytes> {
+    chunks
+        .iter()
+        .flat_map(|chunk| {
+            let mid = chunk.len() / 2;
+            [chunk.slice(..mid), chunk.slice(mid..)]
+        })
+        .collect()
+}
+
+/// The parser's previous scanning strategy, kept as a baseline: the whole
+/// remaining buffer is decoded into a String for every event found
+fn lossy_rescan_parse(chunks: &[Bytes]) -> Option<u32> {
+    let mut buffer = BytesMut::new();
+    let mut completion_tokens = None;
+
+    for chunk in chunks {
+        buffer.extend_from_slice(chunk);
+        loop {
+            let buffer_str = String::from_utf8_lossy(&buffer);
+            let Some(pos) = buffer_str.find("\n\n") else {
+                break;
+            };
+            let event_block = buffer.split_to(pos + 2);
+            let event_str = String::from_utf8_lossy(&event_block);
+            for line in event_str.lines() {
+                if let Some(data) = line.trim().strip_prefix("data: ") {
+                    if let Ok(value) = serde_json::from_str::<serde_json::Value>(data) {
+                        if let Some(tokens) = value.pointer("/usage/completion_tokens") {
+                            completion_tokens = tokens.as_u64().map(|t| t as u32);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    completion_tokens
+}
+
+fn scanner_parse(chunks: &[Bytes]) -> Option<u32> {
+    block_on(async {
+        let mut parser: Box<dyn BackendStreamParser> = Box::new(OpenAIParser::new());
+        for chunk in chunks {
+            parser.feed_chunk(chunk).await;
+        }
+        parser.finalize().await.completion_tokens
+    })
+}
+
+fn bench_openai_parser(c: &mut Criterion) {
+    let whole = synthetic_stream();
+    let split = split_stream(&whole);
+    assert_eq!(scanner_parse(&split), Some(CHUNKS as u32));
+    assert_eq!(lossy_rescan_parse(&split), Some(CHUNKS as u32));
+
+    // Many events arriving in one read is where rescanning the buffer hurts most;
+    // the baseline is quadratic here, so keep the batch small enough to finish
+    let single = vec![Bytes::from(whole[..1_000].concat())];
+
+    let mut group = c.benchmark_group("openai_parser_10k_chunks");
+    for (name, chunks) in [("per_event", &whole), ("split_events", &split), ("single_read_1k", &single)] {
+        group.bench_function(format!("lossy_rescan/{name}"), |b| {
+            b.iter_batched(|| chunks.clone(), |chunks| lossy_rescan_parse(&chunks), BatchSize::LargeInput)
+        });
+        group.bench_function(format!("scanner/{name}"), |b| {
+            b.iter_batched(|| chunks.clone(), |chunks| scanner_parse(&chunks), BatchSize::LargeInput)
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_openai_parser);
+criterion_main!(benches);
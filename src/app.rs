@@ -0,0 +1,248 @@
+use axum::{
+    body::Body,
+    routing::{any, get, post},
+    Router,
+};
+use hyper_util::client::legacy::connect::HttpConnector;
+use std::collections::HashSet;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_util::task::TaskTracker;
+use tower_http::trace::{MakeSpan, TraceLayer};
+use tracing::Span;
+
+use crate::archive::Archive;
+use crate::balancer::Balancer;
+use crate::circuit_breaker::CircuitBreakers;
+use crate::config::{Config, UpstreamPoolConfig};
+use crate::headers::RedactedHeaders;
+use crate::limiter::Limiters;
+use crate::parsers::{BackendStreamParser, BackendType};
+use crate::pricing::Pricing;
+use crate::replay::replay_handler;
+use crate::sampling::Sampler;
+use crate::sinks::{FileSink, GenAiSink, MetricsSink, TracingSink, WebhookSink};
+use crate::stats::{stats_handler, Stats};
+use crate::tokens::{local_tokenizer, TokenEstimators};
+use crate::{middleware, proxy};
+
+/// HTTP client used to reach upstream LLM servers
+pub type HttpClient = hyper_util::client::legacy::Client<HttpConnector, Body>;
+
+/// Builds the parser for each response in place of the built-in parsers
+#[cfg(feature = "test-util")]
+pub type ParserFactory = Arc<dyn Fn(BackendType) -> Box<dyn BackendStreamParser> + Send + Sync>;
+
+/// Shared state available to the proxy handler and middleware
+#[derive(Clone)]
+pub struct AppState {
+    pub client: Arc<HttpClient>,
+    /// HTTP/2-only client for upstreams with `http2` enabled
+    pub h2_client: Arc<HttpClient>,
+    /// Upstreams that refused HTTP/2, reached over HTTP/1.1 from then on
+    pub http1_upstreams: Arc<RwLock<HashSet<String>>>,
+    pub config: Arc<Config>,
+    pub sinks: Vec<Arc<dyn MetricsSink>>,
+    pub breakers: Arc<CircuitBreakers>,
+    /// Picks an upstream for requests to a named pool
+    pub balancer: Arc<Balancer>,
+    pub archive: Option<Arc<Archive>>,
+    pub limiters: Arc<Limiters>,
+    /// Totals since startup, served at `/stats`
+    pub stats: Arc<RwLock<Stats>>,
+    /// Picks which requests reach the sinks
+    pub sampler: Arc<Sampler>,
+    /// Backfills completion tokens the upstream didn't report
+    pub estimators: Arc<TokenEstimators>,
+    /// Fills the `estimated_*_tokens` fields when set (the `tiktoken` feature)
+    pub tokenizer: Option<Arc<TokenEstimators>>,
+    /// Model prices for `cost_usd`
+    pub pricing: Arc<Pricing>,
+    /// Stream-tee tasks, webhook deliveries, archive writes, and log compression that
+    /// must finish before shutdown completes
+    pub tasks: TaskTracker,
+    /// Replaces the built-in parsers when set
+    #[cfg(feature = "test-util")]
+    pub parser_factory: Option<ParserFactory>,
+}
+
+impl AppState {
+    pub fn new(config: Config) -> Self {
+        let client = Arc::new(create_http_client(&config.upstream_pool));
+        let tasks = TaskTracker::new();
+
+        if config.debug.enabled {
+            tracing::warn!("Debug mode is enabled; do not run this configuration in production");
+        }
+
+        let tokenizer = config.estimate_tokens_locally.then(local_tokenizer).flatten();
+        if config.estimate_tokens_locally && tokenizer.is_none() {
+            tracing::warn!("estimate_tokens_locally needs the tiktoken feature; no local estimates will be recorded");
+        }
+
+        let mut sinks: Vec<Arc<dyn MetricsSink>> = vec![Arc::new(TracingSink::new(config.log.clone()))];
+        if config.genai_attributes {
+            sinks.push(Arc::new(GenAiSink));
+        }
+        if let Some(file_sink) = &config.file_sink {
+            sinks.push(Arc::new(FileSink::new(file_sink.clone(), tasks.clone())));
+        }
+        if let Some(webhook) = &config.webhook {
+            sinks.push(Arc::new(WebhookSink::new(client.clone(), webhook.clone(), tasks.clone())));
+        }
+
+        Self {
+            client,
+            h2_client: Arc::new(create_http2_client(&config.upstream_pool)),
+            http1_upstreams: Arc::new(RwLock::new(HashSet::new())),
+            breakers: Arc::new(CircuitBreakers::new(config.circuit_breaker.clone())),
+            balancer: Arc::new(Balancer::new(&config.pools)),
+            archive: config.archive.clone().map(|a| Arc::new(Archive::new(a))),
+            limiters: Arc::new(Limiters::new(&config.concurrency)),
+            stats: Arc::new(RwLock::new(Stats::new())),
+            sampler: Arc::new(Sampler::new(config.sampling.clone())),
+            estimators: Arc::new(TokenEstimators::default()),
+            tokenizer: tokenizer.map(Arc::new),
+            pricing: Arc::new(Pricing::new(&config.pricing)),
+            tasks,
+            #[cfg(feature = "test-util")]
+            parser_factory: None,
+            config: Arc::new(config),
+            sinks,
+        }
+    }
+
+    /// Replaces the token estimators used when the upstream reports no usage
+    pub fn with_estimators(mut self, estimators: TokenEstimators) -> Self {
+        self.estimators = Arc::new(estimators);
+        self
+    }
+
+    /// Replaces the local tokenizer behind the `estimated_*_tokens` fields
+    pub fn with_tokenizer(mut self, tokenizer: TokenEstimators) -> Self {
+        self.tokenizer = Some(Arc::new(tokenizer));
+        self
+    }
+
+    /// Parses every response with parsers from `factory` instead of the built-in ones
+    #[cfg(feature = "test-util")]
+    pub fn with_parser_factory(mut self, factory: ParserFactory) -> Self {
+        self.parser_factory = Some(factory);
+        self
+    }
+
+    /// The parser from the test-util factory, if one is set
+    #[cfg(feature = "test-util")]
+    pub(crate) fn factory_parser(&self, backend_type: BackendType) -> Option<Box<dyn BackendStreamParser>> {
+        self.parser_factory.as_ref().map(|factory| factory(backend_type))
+    }
+
+    #[cfg(not(feature = "test-util"))]
+    pub(crate) fn factory_parser(&self, _backend_type: BackendType) -> Option<Box<dyn BackendStreamParser>> {
+        None
+    }
+
+    /// Adds another destination for completed request metrics
+    pub fn with_sink(mut self, sink: Arc<dyn MetricsSink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+}
+
+/// Request spans like tower-http's default, plus headers with credentials redacted
+#[derive(Clone, Copy)]
+struct RedactedMakeSpan;
+
+impl<B> MakeSpan<B> for RedactedMakeSpan {
+    fn make_span(&mut self, request: &hyper::Request<B>) -> Span {
+        tracing::debug_span!(
+            "request",
+            method = %request.method(),
+            uri = %request.uri(),
+            version = ?request.version(),
+            headers = ?RedactedHeaders(request.headers()),
+        )
+    }
+}
+
+/// Builds the application router
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/proxy/:backend/*path", any(proxy::proxy_handler))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::extract_request_data,
+        ))
+        // Added after the request-data layer so they aren't treated as LLM calls
+        .route("/stats", get(stats_handler))
+        .route("/replay/:request_id", post(replay_handler))
+        .layer(TraceLayer::new_for_http().make_span_with(RedactedMakeSpan))
+        .with_state(state)
+}
+
+/// Serves the proxy until `shutdown` resolves, then drains in-flight streams
+///
+/// After the listener stops accepting connections, waits up to
+/// `shutdown_timeout_secs` for every stream-tee task to finish forwarding and
+/// recording its metrics, and for webhook deliveries and archive writes to finish.
+pub async fn serve<F>(listener: TcpListener, state: AppState, shutdown: F) -> std::io::Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let tasks = state.tasks.clone();
+    let drain_timeout = Duration::from_secs(state.config.shutdown_timeout_secs);
+
+    // Connection info lets the middleware record each client's address
+    axum::serve(listener, router(state).into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown)
+        .await?;
+
+    tasks.close();
+    if !tasks.is_empty() {
+        tracing::info!("Waiting for {} in-flight streams and deliveries to finish", tasks.len());
+    }
+    if tokio::time::timeout(drain_timeout, tasks.wait()).await.is_err() {
+        tracing::warn!(
+            "Shutdown timed out with {} streams or deliveries still in flight; their metrics are lost",
+            tasks.len()
+        );
+    }
+    Ok(())
+}
+
+/// Creates the shared HTTP client for proxying
+pub fn create_http_client(pool: &UpstreamPoolConfig) -> HttpClient {
+    client_builder(pool).build(connector(pool))
+}
+
+/// Creates the client for upstreams spoken to over HTTP/2 with prior knowledge
+///
+/// One connection per upstream carries all concurrent requests as separate streams.
+pub fn create_http2_client(pool: &UpstreamPoolConfig) -> HttpClient {
+    client_builder(pool).http2_only(true).build(connector(pool))
+}
+
+fn client_builder(pool: &UpstreamPoolConfig) -> hyper_util::client::legacy::Builder {
+    let mut builder = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new());
+    let idle_timeout = match pool.idle_timeout_ms {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    };
+    builder
+        .pool_max_idle_per_host(pool.max_idle_per_host)
+        .pool_idle_timeout(idle_timeout)
+        .pool_timer(hyper_util::rt::TokioTimer::new());
+    builder
+}
+
+fn connector(pool: &UpstreamPoolConfig) -> HttpConnector {
+    let mut connector = HttpConnector::new();
+    connector.set_keepalive(match pool.tcp_keepalive_secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    });
+    connector
+}
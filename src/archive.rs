@@ -0,0 +1,140 @@
+use bytes::Bytes;
+use hyper::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+use crate::headers::DEFAULT_PROTECTED_HEADERS;
+
+/// Headers saved with an archived request besides the provider headers in
+/// `DEFAULT_PROTECTED_HEADERS`; anything else (cookies, credentials, identity
+/// headers) never reaches the disk
+const ARCHIVED_HEADERS: &[&str] = &["accept", "content-encoding", "content-type", "user-agent"];
+
+/// Raw body archive settings
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ArchiveConfig {
+    /// Directory receiving `<request_id>.request`, `<request_id>.request.json`, and
+    /// `<request_id>.response` files
+    pub dir: PathBuf,
+    /// Serves `POST /replay/<request_id>`, which re-sends archived requests upstream
+    pub replay: bool,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("archive"),
+            replay: false,
+        }
+    }
+}
+
+/// Method, proxy URI, and headers of an archived request, saved beside its body
+///
+/// Only content negotiation and provider headers are kept; credentials and the
+/// request ID are left out, so a replay supplies its own.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct RequestHead {
+    pub method: String,
+    /// Path and query the proxy received, e.g. `/proxy/11434/api/generate`
+    pub uri: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl RequestHead {
+    pub fn new(method: &hyper::Method, uri: &hyper::Uri, headers: &HeaderMap) -> Self {
+        let headers = headers
+            .iter()
+            .filter(|(name, _)| {
+                ARCHIVED_HEADERS
+                    .iter()
+                    .chain(DEFAULT_PROTECTED_HEADERS)
+                    .any(|archived| name.as_str() == *archived)
+            })
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        Self {
+            method: method.to_string(),
+            uri: uri.path_and_query().map_or_else(|| uri.path().to_string(), |pq| pq.to_string()),
+            headers,
+        }
+    }
+}
+
+/// Stores the exact request and response bytes of every proxied request, keyed by request ID
+pub struct Archive {
+    dir: PathBuf,
+}
+
+impl Archive {
+    pub fn new(config: ArchiveConfig) -> Self {
+        Self { dir: config.dir }
+    }
+
+    pub fn request_path(&self, request_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.request", request_id))
+    }
+
+    pub fn request_head_path(&self, request_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.request.json", request_id))
+    }
+
+    pub fn response_path(&self, request_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.response", request_id))
+    }
+
+    /// Writes the (already buffered) request body and its head
+    ///
+    /// The head is written last and renamed into place, so once it exists the
+    /// request can be loaded whole.
+    pub async fn save_request(&self, request_id: &str, head: &RequestHead, body: Bytes) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(self.request_path(request_id), &body).await?;
+        let head_path = self.request_head_path(request_id);
+        let partial = head_path.with_extension("json.partial");
+        tokio::fs::write(&partial, serde_json::to_vec(head)?).await?;
+        tokio::fs::rename(&partial, &head_path).await
+    }
+
+    /// The archived head and body of a request, or `None` if either wasn't saved
+    pub async fn load_request(&self, request_id: &str) -> std::io::Result<Option<(RequestHead, Bytes)>> {
+        let head = match tokio::fs::read(self.request_head_path(request_id)).await {
+            Ok(head) => serde_json::from_slice(&head)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        match tokio::fs::read(self.request_path(request_id)).await {
+            Ok(body) => Ok(Some((head, Bytes::from(body)))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Opens a writer that appends response chunks as they stream through the tee
+    pub async fn response_writer(&self, request_id: &str) -> std::io::Result<ResponseArchiveWriter> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let file = File::create(self.response_path(request_id)).await?;
+        Ok(ResponseArchiveWriter {
+            writer: BufWriter::new(file),
+        })
+    }
+}
+
+/// Streaming writer for an archived response body
+pub struct ResponseArchiveWriter {
+    writer: BufWriter<File>,
+}
+
+impl ResponseArchiveWriter {
+    pub async fn write_chunk(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(chunk).await
+    }
+
+    /// Flushes buffered bytes to disk
+    pub async fn finish(mut self) -> std::io::Result<()> {
+        self.writer.flush().await
+    }
+}
@@ -0,0 +1,105 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Identical upstreams served under one name, e.g. `/proxy/ollama/...`
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+    /// Ports of the upstreams in the pool
+    pub targets: Vec<u16>,
+    /// How each request picks its first upstream
+    pub strategy: BalanceStrategy,
+}
+
+/// How a pool spreads requests across its upstreams
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceStrategy {
+    /// Each upstream in turn
+    #[default]
+    RoundRobin,
+    /// The upstream with the fewest requests in flight, taking turns on ties
+    LeastConnections,
+}
+
+/// Picks upstreams for the configured pools
+pub struct Balancer {
+    pools: HashMap<String, Pool>,
+}
+
+struct Pool {
+    targets: Vec<u16>,
+    strategy: BalanceStrategy,
+    next: AtomicUsize,
+    /// Requests in flight per target, in `targets` order
+    in_flight: Vec<Arc<AtomicUsize>>,
+}
+
+impl Balancer {
+    pub fn new(pools: &HashMap<String, PoolConfig>) -> Self {
+        let pools = pools
+            .iter()
+            .map(|(name, config)| {
+                let pool = Pool {
+                    targets: config.targets.clone(),
+                    strategy: config.strategy,
+                    next: AtomicUsize::new(0),
+                    in_flight: config.targets.iter().map(|_| Arc::default()).collect(),
+                };
+                (name.clone(), pool)
+            })
+            .collect();
+        Self { pools }
+    }
+
+    /// Ports of the named pool in the order to try them, or `None` for an unknown pool
+    ///
+    /// The first port is the strategy's pick; the rest follow in rotation for failover.
+    pub fn candidates(&self, name: &str) -> Option<Vec<u16>> {
+        let pool = self.pools.get(name)?;
+        let count = pool.targets.len();
+        if count == 0 {
+            return Some(Vec::new());
+        }
+
+        let offset = pool.next.fetch_add(1, Ordering::Relaxed);
+        let first = match pool.strategy {
+            BalanceStrategy::RoundRobin => offset % count,
+            BalanceStrategy::LeastConnections => (0..count)
+                .map(|i| (offset + i) % count)
+                .min_by_key(|&i| pool.in_flight[i].load(Ordering::Relaxed))
+                .unwrap_or(0),
+        };
+        Some((0..count).map(|i| pool.targets[(first + i) % count]).collect())
+    }
+
+    /// Counts a request to `port` as in flight until the returned guard is dropped
+    pub fn track(&self, name: &str, port: u16) -> Option<InFlight> {
+        let counter = self.counter(name, port)?.clone();
+        counter.fetch_add(1, Ordering::Relaxed);
+        Some(InFlight(counter))
+    }
+
+    /// Requests in flight to `port` through the named pool
+    pub fn in_flight(&self, name: &str, port: u16) -> usize {
+        self.counter(name, port)
+            .map_or(0, |counter| counter.load(Ordering::Relaxed))
+    }
+
+    fn counter(&self, name: &str, port: u16) -> Option<&Arc<AtomicUsize>> {
+        let pool = self.pools.get(name)?;
+        let index = pool.targets.iter().position(|&target| target == port)?;
+        Some(&pool.in_flight[index])
+    }
+}
+
+/// A request counted against its upstream's in-flight total
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,207 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Circuit breaker settings shared by every upstream
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    pub enabled: bool,
+    /// Weighted failure score within `window_secs` that opens the breaker
+    pub failure_threshold: f64,
+    pub window_secs: u64,
+    /// How long an open breaker rejects requests before letting a probe through, and
+    /// how long a probe may go unanswered before another is let through
+    pub cooldown_secs: u64,
+    /// Weight of a request that never reached the upstream
+    pub connection_failure_weight: f64,
+    /// Weight per upstream status; statuses not listed count as successes
+    #[serde(deserialize_with = "crate::config::deserialize_u16_keys")]
+    pub status_weights: HashMap<u16, f64>,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            failure_threshold: 5.0,
+            window_secs: 30,
+            cooldown_secs: 30,
+            connection_failure_weight: 1.0,
+            status_weights: [(500, 1.0), (502, 1.0), (503, 1.0), (504, 1.0)]
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+/// State of a single upstream's breaker
+#[derive(Debug)]
+enum Circuit {
+    Closed { failures: VecDeque<(Instant, f64)> },
+    Open { until: Instant },
+    /// A single probe request is in flight; others are rejected until it completes,
+    /// or until the cooldown passes again in case the probe never reports back
+    HalfOpen { since: Instant },
+}
+
+impl Default for Circuit {
+    fn default() -> Self {
+        Circuit::Closed { failures: VecDeque::new() }
+    }
+}
+
+/// A breaker's state as reported at `/stats`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum BreakerStatus {
+    /// Passing requests; `failure_score` is the weighted failures within the window
+    Closed { failure_score: f64 },
+    /// Rejecting requests; the next one after the cooldown is let through as a probe
+    Open { cooldown_remaining_secs: u64 },
+    /// A probe request is in flight
+    HalfOpen,
+}
+
+/// Per-upstream circuit breakers keyed by target address
+pub struct CircuitBreakers {
+    config: CircuitBreakerConfig,
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+impl CircuitBreakers {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            circuits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns false if the target's breaker is open and the request should be short-circuited
+    pub fn try_acquire(&self, target: &str) -> bool {
+        if !self.config.enabled {
+            return true;
+        }
+
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(target.to_string()).or_default();
+        match circuit {
+            Circuit::Closed { .. } => true,
+            Circuit::Open { until } => {
+                let now = Instant::now();
+                if now >= *until {
+                    tracing::info!("Circuit breaker for {} half-open, sending probe", target);
+                    *circuit = Circuit::HalfOpen { since: now };
+                    true
+                } else {
+                    false
+                }
+            }
+            Circuit::HalfOpen { since } => {
+                // A probe rejected before reaching the upstream never records a result
+                let now = Instant::now();
+                if now.duration_since(*since) >= Duration::from_secs(self.config.cooldown_secs) {
+                    tracing::warn!("Circuit breaker probe for {} never completed, sending another", target);
+                    *since = now;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    /// Current state of every breaker that has seen a request, keyed by target
+    pub fn snapshot(&self) -> BTreeMap<String, BreakerStatus> {
+        let now = Instant::now();
+        let window = Duration::from_secs(self.config.window_secs);
+        let circuits = self.circuits.lock().unwrap();
+        circuits
+            .iter()
+            .map(|(target, circuit)| {
+                let status = match circuit {
+                    Circuit::Closed { failures } => BreakerStatus::Closed {
+                        failure_score: failures
+                            .iter()
+                            .filter(|(at, _)| now.duration_since(*at) <= window)
+                            .map(|(_, weight)| weight)
+                            .sum(),
+                    },
+                    Circuit::Open { until } => BreakerStatus::Open {
+                        cooldown_remaining_secs: until.saturating_duration_since(now).as_secs_f64().ceil() as u64,
+                    },
+                    Circuit::HalfOpen { .. } => BreakerStatus::HalfOpen,
+                };
+                (target.clone(), status)
+            })
+            .collect()
+    }
+
+    /// Records the upstream response status for a request that reached the target
+    pub fn record_status(&self, target: &str, status: u16) {
+        match self.config.status_weights.get(&status) {
+            Some(&weight) if weight > 0.0 => self.record_failure(target, weight),
+            _ => self.record_success(target),
+        }
+    }
+
+    /// Records a request that failed before the upstream produced a response
+    pub fn record_connection_failure(&self, target: &str) {
+        self.record_failure(target, self.config.connection_failure_weight);
+    }
+
+    fn record_success(&self, target: &str) {
+        if !self.config.enabled {
+            return;
+        }
+
+        let mut circuits = self.circuits.lock().unwrap();
+        if let Some(circuit) = circuits.get_mut(target) {
+            if matches!(circuit, Circuit::HalfOpen { .. }) {
+                tracing::info!("Circuit breaker for {} closed after successful probe", target);
+            }
+            *circuit = Circuit::default();
+        }
+    }
+
+    fn record_failure(&self, target: &str, weight: f64) {
+        if !self.config.enabled {
+            return;
+        }
+
+        let now = Instant::now();
+        let window = Duration::from_secs(self.config.window_secs);
+        let cooldown = Duration::from_secs(self.config.cooldown_secs);
+
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(target.to_string()).or_default();
+        match circuit {
+            Circuit::Closed { failures } => {
+                failures.push_back((now, weight));
+                while failures
+                    .front()
+                    .is_some_and(|(at, _)| now.duration_since(*at) > window)
+                {
+                    failures.pop_front();
+                }
+
+                let score: f64 = failures.iter().map(|(_, w)| w).sum();
+                if score >= self.config.failure_threshold {
+                    tracing::warn!(
+                        "Circuit breaker for {} opened (failure score {:.2} >= {:.2})",
+                        target,
+                        score,
+                        self.config.failure_threshold
+                    );
+                    *circuit = Circuit::Open { until: now + cooldown };
+                }
+            }
+            Circuit::HalfOpen { .. } => {
+                tracing::warn!("Circuit breaker for {} re-opened after failed probe", target);
+                *circuit = Circuit::Open { until: now + cooldown };
+            }
+            Circuit::Open { .. } => {}
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+
+use crate::archive::ArchiveConfig;
+use crate::balancer::PoolConfig;
+use crate::circuit_breaker::CircuitBreakerConfig;
+use crate::debug::DebugConfig;
+use crate::headers::{BackendHeaderRules, HeaderConfig};
+use crate::limiter::ConcurrencyConfig;
+use crate::parsers::{BackendType, DEFAULT_MAX_BUFFER_BYTES};
+use crate::policy::ModelPolicyConfig;
+use crate::pricing::PricingConfig;
+use crate::retry::RetryConfig;
+use crate::sampling::SamplingConfig;
+use crate::sinks::file::FileSinkConfig;
+use crate::sinks::log::LogConfig;
+use crate::sinks::webhook::WebhookConfig;
+use crate::text::TextLogging;
+
+/// Environment variable pointing at an optional TOML config file
+pub const CONFIG_ENV_VAR: &str = "LLM_LOGGER_CONFIG";
+
+/// Proxy configuration, loaded from a TOML file with defaults for every field
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Address the proxy listens on
+    pub listen_addr: String,
+    /// How requests carrying `Expect: 100-continue` are handled
+    pub expect_continue: ExpectContinueMode,
+    /// Request headers dropped or protected on the way upstream
+    pub headers: HeaderConfig,
+    /// Overrides for individual upstreams, keyed by backend port
+    #[serde(deserialize_with = "deserialize_u16_keys")]
+    pub backends: HashMap<u16, BackendConfig>,
+    /// Named groups of identical upstreams, addressed as `/proxy/{name}/...`
+    pub pools: HashMap<String, PoolConfig>,
+    /// Per-upstream circuit breaker
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// Resending requests that fail upstream; off unless `retry.max_attempts` is above 1
+    pub retry: RetryConfig,
+    /// Chunks buffered between the upstream reader and the client
+    ///
+    /// Larger buffers absorb bursts from fast upstreams at the cost of memory per
+    /// in-flight stream; smaller ones keep memory flat but pause upstream reads sooner.
+    pub stream_channel_capacity: usize,
+    /// Chunks queued for the parser task; a parser this far behind stops parsing the
+    /// response (the client still gets all of it) and flags the record `parse_truncated`
+    pub parser_channel_capacity: usize,
+    /// Give up on an upstream that sends nothing for this long mid-stream (0 disables)
+    pub stream_idle_timeout_ms: u64,
+    /// Longest an upstream call may take, from sending the request to the end of the
+    /// response (0 disables); 504 if no response arrived, otherwise the stream is truncated
+    pub upstream_timeout_ms: u64,
+    /// Talk to upstreams over HTTP/2 with prior knowledge (h2c), falling back to
+    /// HTTP/1.1 for any that refuse it; `backends.<port>.http2` overrides this per upstream
+    pub upstream_http2: bool,
+    /// Connection reuse and keep-alive for upstream connections
+    pub upstream_pool: UpstreamPoolConfig,
+    /// What happens when the client stops keeping up and the buffer fills
+    pub slow_client: SlowClientPolicy,
+    /// How long shutdown waits for in-flight streams to finish and record metrics
+    pub shutdown_timeout_secs: u64,
+    /// Upstream concurrency cap with priority queueing
+    pub concurrency: ConcurrencyConfig,
+    /// Format of the metrics record written to the log
+    pub log: LogConfig,
+    /// Fraction of requests written to the sinks under heavy load
+    pub sampling: SamplingConfig,
+    /// Also records each request under the OpenTelemetry GenAI semantic-convention attribute names
+    pub genai_attributes: bool,
+    /// Adds `stream_options.include_usage` to streaming OpenAI chat/completions requests
+    /// that don't set it, so the upstream reports usage
+    pub inject_include_usage: bool,
+    /// Estimates completion tokens from the response text when the upstream reports none
+    pub estimate_missing_tokens: bool,
+    /// Counts the prompt and response text with the local tokenizer (the `tiktoken`
+    /// feature) as `estimated_prompt_tokens` and `estimated_completion_tokens`
+    pub estimate_tokens_locally: bool,
+    /// Adds completion-quality heuristics (length, empty, looks truncated) to each record
+    pub completion_heuristics: bool,
+    /// Which part of a chat request is recorded as `prompt`
+    pub prompt_capture: PromptCapture,
+    /// How much prompt text each record keeps, for deployments that can't store user content
+    ///
+    /// Also applies to `response_text` when it is captured.
+    pub prompt_logging: TextLogging,
+    /// Characters kept by `prompt_logging = "truncated"`
+    pub prompt_logging_max_chars: usize,
+    /// How much of the system prompt each record keeps; unset follows `prompt_logging`
+    pub system_prompt_logging: Option<TextLogging>,
+    /// Records the response text in each record as `response_text`
+    pub log_response_text: bool,
+    /// Characters of response text kept when `log_response_text` is on (0 keeps all)
+    pub response_text_max_chars: usize,
+    /// Records a SHA-256 of the response text (up to `parser_max_buffer_bytes`) as
+    /// `response_text_sha256`
+    pub hash_response_text: bool,
+    /// Request header recorded as `client.identity`, e.g. `x-team`
+    ///
+    /// Naming a credential header such as `authorization` records a SHA-256 of the
+    /// credential instead of its value.
+    pub client_identity_header: Option<String>,
+    /// Names for API key fingerprints, recorded as `api_key_label`, e.g.
+    /// `"3f9a12bc" = "billing-service"`
+    pub api_key_labels: HashMap<String, String>,
+    /// Posts each metrics record to an external collector when set
+    pub webhook: Option<WebhookConfig>,
+    /// Appends each metrics record to a rotating JSONL file when set
+    pub file_sink: Option<FileSinkConfig>,
+    /// Saves raw request and response bodies for replay when set (high volume)
+    pub archive: Option<ArchiveConfig>,
+    /// Logs up to this many trailing bytes of every non-2xx response body when set
+    pub error_body_log_bytes: Option<usize>,
+    /// Parser used when the content-type doesn't identify the backend (single-backend setups)
+    pub default_backend_type: Option<BackendType>,
+    /// Largest incomplete record a parser buffers before discarding it
+    pub parser_max_buffer_bytes: usize,
+    /// Parsers tried in order on unidentified streams; the first to extract usage wins
+    ///
+    /// Buffers the whole response body, so leave empty unless the environment is messy.
+    pub fallback_parsers: Vec<BackendType>,
+    /// Model prices used to compute `cost_usd`
+    pub pricing: PricingConfig,
+    /// Models rejected with 403 before any upstream call
+    pub model_policy: ModelPolicyConfig,
+    /// Testing aids such as delay injection; disabled unless `debug.enabled` is set
+    pub debug: DebugConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen_addr: "127.0.0.1:3000".to_string(),
+            expect_continue: ExpectContinueMode::default(),
+            headers: HeaderConfig::default(),
+            backends: HashMap::new(),
+            pools: HashMap::new(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            retry: RetryConfig::default(),
+            stream_channel_capacity: 32,
+            parser_channel_capacity: 1024,
+            stream_idle_timeout_ms: 0,
+            upstream_timeout_ms: 0,
+            upstream_http2: false,
+            upstream_pool: UpstreamPoolConfig::default(),
+            slow_client: SlowClientPolicy::default(),
+            shutdown_timeout_secs: 30,
+            concurrency: ConcurrencyConfig::default(),
+            log: LogConfig::default(),
+            sampling: SamplingConfig::default(),
+            genai_attributes: false,
+            inject_include_usage: false,
+            estimate_missing_tokens: false,
+            estimate_tokens_locally: false,
+            completion_heuristics: false,
+            prompt_capture: PromptCapture::default(),
+            prompt_logging: TextLogging::default(),
+            prompt_logging_max_chars: 256,
+            system_prompt_logging: None,
+            log_response_text: false,
+            response_text_max_chars: 4096,
+            hash_response_text: false,
+            client_identity_header: None,
+            api_key_labels: HashMap::new(),
+            webhook: None,
+            file_sink: None,
+            archive: None,
+            error_body_log_bytes: None,
+            default_backend_type: None,
+            parser_max_buffer_bytes: DEFAULT_MAX_BUFFER_BYTES,
+            fallback_parsers: Vec::new(),
+            pricing: PricingConfig::default(),
+            model_policy: ModelPolicyConfig::default(),
+            debug: DebugConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config from the file named by `LLM_LOGGER_CONFIG`, or defaults if unset
+    pub fn load() -> anyhow::Result<Self> {
+        match std::env::var(CONFIG_ENV_VAR) {
+            Ok(path) => {
+                let contents = std::fs::read_to_string(&path)
+                    .map_err(|e| anyhow::anyhow!("Failed to read config file {}: {}", path, e))?;
+                Self::from_toml(&contents)
+            }
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// Parses a config from TOML source
+    pub fn from_toml(source: &str) -> anyhow::Result<Self> {
+        let config: Self = toml::from_str(source)?;
+        for (name, pool) in &config.pools {
+            if name.parse::<u16>().is_ok() {
+                anyhow::bail!("Pool name {} is a port number", name);
+            }
+            if pool.targets.is_empty() {
+                anyhow::bail!("Pool {} has no targets", name);
+            }
+        }
+        Ok(config)
+    }
+
+    /// The prompt as a metrics record keeps it under `prompt_logging`
+    pub fn recorded_prompt(&self, prompt: &str) -> String {
+        self.prompt_logging.render(prompt, self.prompt_logging_max_chars)
+    }
+
+    /// The system prompt as a metrics record keeps it, unless logging it is off
+    pub fn recorded_system_prompt(&self, system_prompt: &str) -> Option<String> {
+        match self.system_prompt_logging.unwrap_or(self.prompt_logging) {
+            TextLogging::None => None,
+            mode => Some(mode.render(system_prompt, self.prompt_logging_max_chars)),
+        }
+    }
+
+    /// The response text as a metrics record keeps it, if it is captured at all
+    pub fn recorded_response_text(&self, text: &str) -> Option<String> {
+        if !self.log_response_text {
+            return None;
+        }
+        match self.prompt_logging {
+            TextLogging::Full => Some(crate::text::excerpt(text, self.response_text_max_chars).to_string()),
+            TextLogging::None => None,
+            mode => Some(mode.render(text, self.prompt_logging_max_chars)),
+        }
+    }
+
+    /// Whether any record field is derived from the response text, so parsers must keep it
+    pub fn reads_response_text(&self) -> bool {
+        self.log_response_text
+            || self.hash_response_text
+            || self.completion_heuristics
+            || self.estimate_missing_tokens
+            || self.estimate_tokens_locally
+    }
+
+    /// Settings for the upstream on `port`, if it has any
+    pub fn backend(&self, port: u16) -> Option<&BackendConfig> {
+        self.backends.get(&port)
+    }
+
+    /// Whether requests to the upstream on `port` try HTTP/2 first
+    pub fn http2(&self, port: u16) -> bool {
+        self.backend(port)
+            .and_then(|b| b.http2)
+            .unwrap_or(self.upstream_http2)
+    }
+}
+
+/// How connections to upstreams are kept for reuse
+///
+/// An HTTP/1.1 connection carries one request at a time, and a streamed response
+/// holds it until the stream ends, so N concurrent streams to one upstream need N
+/// connections. Afterwards up to `max_idle_per_host` of them stay open for the
+/// next requests instead of being torn down and redialed.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct UpstreamPoolConfig {
+    /// Idle connections kept open per upstream (0 closes each after its request)
+    pub max_idle_per_host: usize,
+    /// Close connections idle for longer than this (0 keeps them indefinitely)
+    pub idle_timeout_ms: u64,
+    /// TCP keep-alive probe interval, which notices an upstream that vanished
+    /// without closing its connections (0 disables)
+    pub tcp_keepalive_secs: u64,
+}
+
+impl Default for UpstreamPoolConfig {
+    fn default() -> Self {
+        Self {
+            max_idle_per_host: 64,
+            idle_timeout_ms: 90_000,
+            tcp_keepalive_secs: 60,
+        }
+    }
+}
+
+/// Settings that apply to a single upstream
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct BackendConfig {
+    /// Ask the upstream for uncompressed responses by forcing `Accept-Encoding: identity`
+    ///
+    /// Defaults to on for loopback upstreams, where compression only costs CPU.
+    pub strip_accept_encoding: Option<bool>,
+    /// Talk to this upstream over HTTP/2; defaults to `upstream_http2`
+    pub http2: Option<bool>,
+    /// Parser to use for every response from this upstream, skipping detection
+    pub backend_type: Option<BackendType>,
+    /// Headers stripped from or injected into requests to this upstream
+    #[serde(flatten)]
+    pub header_rules: BackendHeaderRules,
+}
+
+/// Which part of a chat request becomes the recorded `prompt`
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptCapture {
+    /// Every message as `role: text` lines, after any separate system prompt
+    #[default]
+    Full,
+    /// Only the text of the last user message, keeping records small for long histories
+    LastUserMessage,
+}
+
+/// Handling of the `Expect: 100-continue` request header
+///
+/// The middleware buffers the full request body before proxying, so the proxy is
+/// the party that answers the handshake: hyper sends `100 Continue` to the client
+/// as soon as the body is first read.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpectContinueMode {
+    /// Answer the handshake locally and remove the header before forwarding
+    #[default]
+    Strip,
+    /// Answer the handshake locally but forward the header unchanged
+    Forward,
+    /// Refuse the request with `417 Expectation Failed` without reading the body
+    Reject,
+}
+
+/// Behavior when a client reads slower than the upstream produces
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SlowClientPolicy {
+    /// Pause upstream reads until the client drains the buffer, so nothing is dropped
+    #[default]
+    Backpressure,
+    /// Disconnect the client as soon as the buffer is full, releasing the upstream
+    Disconnect,
+}
+
+/// Deserializes a map keyed by integers, which TOML can only express as string keys
+pub(crate) fn deserialize_u16_keys<'de, D, V>(deserializer: D) -> Result<HashMap<u16, V>, D::Error>
+where
+    D: Deserializer<'de>,
+    V: Deserialize<'de>,
+{
+    HashMap::<String, V>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(key, value)| {
+            key.parse::<u16>()
+                .map(|key| (key, value))
+                .map_err(|_| serde::de::Error::custom(format!("invalid numeric key: {}", key)))
+        })
+        .collect()
+}
@@ -0,0 +1,49 @@
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Request header that sets the injected delay for a single request
+pub const INJECT_DELAY_HEADER: &str = "x-inject-delay-ms";
+
+/// Upper bound on any injected delay, so a typo can't hang a client indefinitely
+const MAX_INJECTED_DELAY_MS: u64 = 60_000;
+
+/// Testing aids that must never be active in production
+///
+/// Nothing here takes effect unless `enabled` is set.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DebugConfig {
+    /// Master switch for every debug feature
+    pub enabled: bool,
+    /// Fixed delay added before each response is forwarded
+    pub inject_delay_ms: u64,
+    /// Random extra delay of up to this many milliseconds
+    pub inject_jitter_ms: u64,
+    /// Let clients choose their own delay via `x-inject-delay-ms`
+    pub allow_delay_header: bool,
+}
+
+impl DebugConfig {
+    /// Returns the delay to inject for a request, if any
+    ///
+    /// A valid header value replaces the configured fixed delay; jitter applies either way.
+    pub fn injected_delay(&self, header: Option<&str>) -> Option<Duration> {
+        if !self.enabled {
+            return None;
+        }
+
+        let base = header
+            .filter(|_| self.allow_delay_header)
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .unwrap_or(self.inject_delay_ms);
+        let jitter = match self.inject_jitter_ms {
+            0 => 0,
+            max => fastrand::u64(0..=max),
+        };
+
+        match base.saturating_add(jitter).min(MAX_INJECTED_DELAY_MS) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+}
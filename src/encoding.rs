@@ -0,0 +1,80 @@
+use std::io::Write;
+
+use bytes::Bytes;
+use flate2::write::{GzDecoder, ZlibDecoder};
+use hyper::header::{HeaderMap, HeaderValue, ACCEPT_ENCODING};
+
+/// Undoes the upstream's `Content-Encoding` on the copy of the body fed to the parser
+///
+/// The client still receives the original bytes; only the parser sees plain text.
+pub enum ParserDecoder {
+    Identity,
+    Gzip(GzDecoder<Vec<u8>>),
+    Deflate(ZlibDecoder<Vec<u8>>),
+    /// An encoding we can't decode, or a decoder that hit corrupt input
+    Unreadable,
+}
+
+impl ParserDecoder {
+    /// Picks a decoder from the response's `Content-Encoding` header
+    pub fn for_encoding(content_encoding: Option<&str>) -> Self {
+        let encoding = content_encoding.map(|e| e.trim().to_ascii_lowercase());
+        match encoding.as_deref() {
+            None | Some("") | Some("identity") => ParserDecoder::Identity,
+            Some("gzip") | Some("x-gzip") => ParserDecoder::Gzip(GzDecoder::new(Vec::new())),
+            Some("deflate") => ParserDecoder::Deflate(ZlibDecoder::new(Vec::new())),
+            Some(other) => {
+                tracing::warn!("Cannot decode Content-Encoding '{}', token usage will not be parsed", other);
+                ParserDecoder::Unreadable
+            }
+        }
+    }
+
+    /// Decodes the next chunk, returning whatever plain bytes are ready
+    ///
+    /// Uncompressed chunks come back as the same buffer, without copying.
+    pub fn decode(&mut self, chunk: &Bytes) -> Option<Bytes> {
+        let result = match self {
+            ParserDecoder::Identity => return Some(chunk.clone()),
+            ParserDecoder::Unreadable => return None,
+            ParserDecoder::Gzip(decoder) => decoder.write_all(chunk).map(|_| drain(decoder.get_mut())),
+            ParserDecoder::Deflate(decoder) => decoder.write_all(chunk).map(|_| drain(decoder.get_mut())),
+        };
+        self.non_empty_or_fail(result)
+    }
+
+    /// Flushes any plain bytes still held once the body has ended
+    pub fn finish(&mut self) -> Option<Bytes> {
+        let result = match self {
+            ParserDecoder::Identity | ParserDecoder::Unreadable => return None,
+            ParserDecoder::Gzip(decoder) => decoder.try_finish().map(|_| drain(decoder.get_mut())),
+            ParserDecoder::Deflate(decoder) => decoder.try_finish().map(|_| drain(decoder.get_mut())),
+        };
+        self.non_empty_or_fail(result)
+    }
+
+    fn non_empty_or_fail(&mut self, result: std::io::Result<Bytes>) -> Option<Bytes> {
+        match result {
+            Ok(decoded) if decoded.is_empty() => None,
+            Ok(decoded) => Some(decoded),
+            Err(e) => {
+                tracing::warn!("Failed to decode compressed response body, parsing stopped: {}", e);
+                *self = ParserDecoder::Unreadable;
+                None
+            }
+        }
+    }
+}
+
+/// Asks the upstream for an uncompressed response so the parsers see plain text
+pub fn force_identity_encoding(headers: &mut HeaderMap) {
+    if headers.get(ACCEPT_ENCODING).is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"identity")) {
+        return;
+    }
+    // An absent Accept-Encoding means any coding is acceptable, so set it explicitly
+    headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("identity"));
+}
+
+fn drain(output: &mut Vec<u8>) -> Bytes {
+    Bytes::from(std::mem::take(output))
+}
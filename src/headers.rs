@@ -0,0 +1,397 @@
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, CONNECTION, CONTENT_LENGTH, TE, TRANSFER_ENCODING, UPGRADE};
+use hyper::Method;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// Correlation ID a client or upstream may attach to a request or response
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Response header naming the ID the proxy recorded the request under
+pub const PROXY_REQUEST_ID_HEADER: &str = "x-llm-logger-request-id";
+
+/// Comma-separated tags for the request, e.g. `eval,run-42`; never forwarded upstream
+pub const TAGS_HEADER: &str = "x-llm-logger-tags";
+/// JSON object of string key/value pairs for the request; never forwarded upstream
+pub const META_HEADER: &str = "x-llm-logger-meta";
+/// Explicit conversation ID grouping multi-turn requests; never forwarded upstream
+pub const CONVERSATION_ID_HEADER: &str = "x-llm-logger-conversation-id";
+/// Most tags, and most metadata entries, kept per request
+pub const MAX_ANNOTATIONS: usize = 16;
+/// Longest tag, metadata key, or metadata value kept, in bytes
+pub const MAX_ANNOTATION_BYTES: usize = 256;
+
+/// Headers that carry provider credentials, which are never recorded or logged as-is
+pub const CREDENTIAL_HEADERS: &[&str] = &["authorization", "api-key", "x-api-key"];
+/// Hex characters of a credential's SHA-256 kept as its fingerprint
+pub const API_KEY_FINGERPRINT_LEN: usize = 8;
+/// Logged in place of credential header values
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Longest client-supplied request ID the proxy will reuse
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Connection-scoped headers that must not be forwarded by a proxy (RFC 9110 §7.6.1)
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Provider headers that select API versions and beta features; always forwarded
+pub const DEFAULT_PROTECTED_HEADERS: &[&str] = &[
+    "anthropic-beta",
+    "anthropic-version",
+    "openai-beta",
+    "openai-organization",
+    "openai-project",
+];
+
+/// How request headers are filtered before they are sent upstream
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct HeaderConfig {
+    /// Extra headers to drop from every upstream request
+    pub strip: Vec<String>,
+    /// Headers to forward no matter what the stripping rules say, in addition
+    /// to `DEFAULT_PROTECTED_HEADERS`
+    pub protected: Vec<String>,
+}
+
+impl HeaderConfig {
+    /// Whether a header must reach the upstream unmodified
+    pub fn is_protected(&self, name: &str) -> bool {
+        DEFAULT_PROTECTED_HEADERS
+            .iter()
+            .copied()
+            .chain(self.protected.iter().map(String::as_str))
+            .any(|p| p.eq_ignore_ascii_case(name))
+    }
+
+    /// Removes hop-by-hop headers, headers listed in `Connection`, and the
+    /// configured `strip` list, leaving protected headers in place
+    pub fn prepare_upstream(&self, headers: &mut HeaderMap) {
+        // `TE: trailers` is the one TE value that matters end to end: HTTP/1.1
+        // upstreams only send trailers to clients that ask for them
+        let accepts_trailers = headers
+            .get_all(TE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|coding| coding.trim().eq_ignore_ascii_case("trailers"));
+
+        // Names listed in Connection are hop-by-hop too, but a client can't use
+        // that to drop a protected header
+        let connection_listed: Vec<String> = headers
+            .get_all(CONNECTION)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect();
+
+        let doomed = HOP_BY_HOP
+            .iter()
+            .map(|name| name.to_string())
+            .chain(connection_listed)
+            .chain(self.strip.iter().map(|name| name.to_ascii_lowercase()));
+
+        for name in doomed {
+            if self.is_protected(&name) {
+                continue;
+            }
+            if let Ok(name) = HeaderName::from_bytes(name.as_bytes()) {
+                headers.remove(name);
+            }
+        }
+
+        if accepts_trailers {
+            headers.insert(TE, HeaderValue::from_static("trailers"));
+        }
+    }
+}
+
+/// Whether the header carries a provider credential
+pub fn is_credential_header(name: &str) -> bool {
+    CREDENTIAL_HEADERS.iter().any(|credential| name.eq_ignore_ascii_case(credential))
+}
+
+/// Marks credential header values sensitive, so they never print through `Debug`
+pub fn mark_credentials_sensitive(headers: &mut HeaderMap) {
+    for (name, value) in headers.iter_mut() {
+        if is_credential_header(name.as_str()) {
+            value.set_sensitive(true);
+        }
+    }
+}
+
+/// `Debug` view of a header map with credential values replaced by `[REDACTED]`
+pub struct RedactedHeaders<'a>(pub &'a HeaderMap);
+
+impl fmt::Debug for RedactedHeaders<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.0.iter().map(|(name, value)| {
+                let value = if value.is_sensitive() || is_credential_header(name.as_str()) {
+                    REDACTED
+                } else {
+                    value.to_str().unwrap_or("<non-ascii>")
+                };
+                (name.as_str(), value)
+            }))
+            .finish()
+    }
+}
+
+/// Per-upstream header rules, applied after the global `HeaderConfig` filtering
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct BackendHeaderRules {
+    /// Request headers removed before forwarding, e.g. client API keys a local model shouldn't see
+    pub strip_headers: Vec<String>,
+    /// Request headers set on every request, replacing any the client sent
+    pub inject_headers: HashMap<String, Secret>,
+}
+
+impl BackendHeaderRules {
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        for name in &self.strip_headers {
+            if let Ok(name) = HeaderName::from_bytes(name.as_bytes()) {
+                headers.remove(name);
+            }
+        }
+        for (name, value) in &self.inject_headers {
+            match (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value.expose())) {
+                (Ok(name), Ok(mut value)) => {
+                    value.set_sensitive(true);
+                    headers.insert(name, value);
+                }
+                // Only the name is logged; the value may be a credential
+                _ => tracing::warn!("Skipping invalid injected header {}", name),
+            }
+        }
+    }
+}
+
+/// A configured header value, written inline or read from an environment variable
+///
+/// Written as `"value"` or `{ env = "VAR", prefix = "Bearer " }`. The variable is
+/// read when the config loads, and `Debug` never shows the value.
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "SecretSource")]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SecretSource {
+    Inline(String),
+    Env {
+        env: String,
+        #[serde(default)]
+        prefix: String,
+    },
+}
+
+impl TryFrom<SecretSource> for Secret {
+    type Error = String;
+
+    fn try_from(source: SecretSource) -> Result<Self, Self::Error> {
+        match source {
+            SecretSource::Inline(value) => Ok(Secret(value)),
+            SecretSource::Env { env, prefix } => std::env::var(&env)
+                .map(|value| Secret(prefix + &value))
+                .map_err(|_| format!("environment variable {} is not set", env)),
+        }
+    }
+}
+
+/// Whether the request is a WebSocket handshake: a GET without a body asking to
+/// switch the connection to WebSocket
+pub fn is_websocket_upgrade(method: &Method, headers: &HeaderMap) -> bool {
+    let bodyless = !headers.contains_key(TRANSFER_ENCODING)
+        && headers.get(CONTENT_LENGTH).is_none_or(|len| len.as_bytes() == b"0");
+    if method != Method::GET || !bodyless {
+        return false;
+    }
+    let connection_upgrade = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+    connection_upgrade
+        && headers
+            .get(UPGRADE)
+            .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"websocket"))
+}
+
+/// The client's `x-request-id` when it is safe to reuse, otherwise a fresh UUID
+///
+/// Request IDs name archive files, so only short values made of ASCII letters,
+/// digits, `-`, and `_` are accepted.
+pub fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// Whether `id` is safe to use as a request ID, and so as an archive file name
+pub fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// The value of the configured client identity header
+///
+/// Credential headers are recorded as the SHA-256 of the credential (without any
+/// `Bearer` prefix), never the credential itself.
+pub fn client_identity(headers: &HeaderMap, header: &str) -> Option<String> {
+    let value = headers.get(header)?.to_str().ok()?.trim();
+    if value.is_empty() {
+        return None;
+    }
+    if is_credential_header(header) {
+        return Some(crate::text::sha256_hex(credential(value)));
+    }
+    Some(value.to_string())
+}
+
+/// Short, stable fingerprint of the request's API key: the first
+/// `API_KEY_FINGERPRINT_LEN` hex characters of its SHA-256
+///
+/// Read from `Authorization` (with or without `Bearer`), Azure's `api-key`, or
+/// Anthropic's `x-api-key`, in that order.
+pub fn api_key_fingerprint(headers: &HeaderMap) -> Option<String> {
+    CREDENTIAL_HEADERS.iter().find_map(|name| {
+        let credential = credential(headers.get(*name)?.to_str().ok()?.trim());
+        if credential.is_empty() {
+            return None;
+        }
+        let mut fingerprint = crate::text::sha256_hex(credential);
+        fingerprint.truncate(API_KEY_FINGERPRINT_LEN);
+        Some(fingerprint)
+    })
+}
+
+/// A credential header's secret, without any `Bearer` scheme
+fn credential(value: &str) -> &str {
+    value
+        .split_once(' ')
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map_or(value, |(_, token)| token.trim())
+}
+
+/// Trailer fields for the metrics record, with credential values redacted
+pub fn trailer_fields(trailers: &HeaderMap) -> BTreeMap<String, String> {
+    trailers
+        .iter()
+        .map(|(name, value)| {
+            let value = if is_credential_header(name.as_str()) {
+                REDACTED.to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.as_str().to_string(), value)
+        })
+        .collect()
+}
+
+/// The failure a gRPC-style `grpc-status` trailer reports, if it reports one
+///
+/// Status 0 is success; anything else means the stream ended in an error even
+/// though the body itself arrived intact.
+pub fn trailer_error(trailers: &HeaderMap) -> Option<String> {
+    let status = trailers.get("grpc-status")?.to_str().ok()?.trim();
+    if status == "0" {
+        return None;
+    }
+    match trailers.get("grpc-message").and_then(|v| v.to_str().ok()) {
+        Some(message) if !message.is_empty() => Some(format!("grpc-status {}: {}", status, message)),
+        _ => Some(format!("grpc-status {}", status)),
+    }
+}
+
+/// Caller-supplied labels for filtering metrics
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Annotations {
+    pub tags: Vec<String>,
+    pub metadata: BTreeMap<String, String>,
+    pub conversation_id: Option<String>,
+    /// ID of the archived request this one re-runs, set by `/replay`
+    pub replay_of: Option<String>,
+}
+
+/// Removes the tags, metadata, and conversation ID headers and returns what they held
+///
+/// Entries past `MAX_ANNOTATIONS` and values longer than `MAX_ANNOTATION_BYTES` are
+/// dropped, as is metadata that isn't a JSON object. Non-string metadata values are
+/// kept as their JSON text.
+pub fn take_annotations(headers: &mut HeaderMap) -> Annotations {
+    let fits = |s: &str| !s.is_empty() && s.len() <= MAX_ANNOTATION_BYTES;
+
+    let tags = headers
+        .remove(TAGS_HEADER)
+        .and_then(|v| v.to_str().ok().map(str::to_string))
+        .map(|v| {
+            v.split(',')
+                .map(str::trim)
+                .filter(|tag| fits(tag))
+                .take(MAX_ANNOTATIONS)
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let metadata = match headers.remove(META_HEADER) {
+        Some(value) => match serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(value.as_bytes()) {
+            Ok(map) => map
+                .into_iter()
+                .map(|(key, value)| match value {
+                    serde_json::Value::String(s) => (key, s),
+                    other => (key, other.to_string()),
+                })
+                .filter(|(key, value)| fits(key) && fits(value))
+                .take(MAX_ANNOTATIONS)
+                .collect(),
+            Err(e) => {
+                tracing::warn!("Ignoring {} header that isn't a JSON object: {}", META_HEADER, e);
+                BTreeMap::new()
+            }
+        },
+        None => BTreeMap::new(),
+    };
+
+    let conversation_id = headers
+        .remove(CONVERSATION_ID_HEADER)
+        .and_then(|v| v.to_str().ok().map(|id| id.trim().to_string()))
+        .filter(|id| fits(id));
+
+    Annotations {
+        tags,
+        metadata,
+        conversation_id,
+        replay_of: None,
+    }
+}
@@ -1,4 +1,39 @@
+//! Streaming reverse proxy that logs LLM request metrics.
+//!
+//! Besides the proxy itself, the parsers can be used offline to recompute token
+//! usage from archived response bodies:
+//!
+//! ```no_run
+//! use rust_llm_logger::parsers::{parse_response, BackendType};
+//!
+//! # async fn example() {
+//! let body = std::fs::read("response.ndjson").unwrap();
+//! let usage = parse_response(BackendType::Ollama, &body).await;
+//! println!("{:?}", usage);
+//! # }
+//! ```
+
+pub mod app;
+pub mod archive;
+pub mod balancer;
+pub mod circuit_breaker;
+pub mod config;
+pub mod debug;
+pub mod encoding;
+pub mod headers;
+pub mod limiter;
 pub mod parsers;
+pub mod policy;
+pub mod pricing;
 pub mod proxy;
+pub mod quality;
+pub mod replay;
+pub mod retry;
 pub mod middleware;
+pub mod sampling;
+pub mod sinks;
+pub mod stats;
+pub mod text;
+pub mod timing;
+pub mod tokens;
 pub mod types;
@@ -0,0 +1,256 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+/// Concurrency limiter settings
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ConcurrencyConfig {
+    /// Maximum upstream requests in flight; unset disables the limiter
+    pub max_concurrent: Option<usize>,
+    /// Caps for individual models, applied on top of `max_concurrent`
+    pub per_model: HashMap<String, usize>,
+    /// Requests allowed to wait for a slot before the rest get 503; unset queues
+    /// without bound, 0 rejects as soon as every slot is taken
+    pub max_queued: Option<usize>,
+    /// Request header carrying the priority (`high`, `normal`, or `low`)
+    pub priority_header: String,
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent: None,
+            per_model: HashMap::new(),
+            max_queued: None,
+            priority_header: "x-priority".to_string(),
+        }
+    }
+}
+
+/// Scheduling priority for queued requests
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    /// Parses a priority header value, defaulting to `Normal` for missing or invalid values
+    pub fn from_header(value: Option<&str>) -> Self {
+        match value.map(|v| v.trim().to_ascii_lowercase()).as_deref() {
+            None => Priority::Normal,
+            Some("high") => Priority::High,
+            Some("normal") => Priority::Normal,
+            Some("low") => Priority::Low,
+            Some(other) => {
+                tracing::debug!("Ignoring invalid priority {:?}, using normal", other);
+                Priority::Normal
+            }
+        }
+    }
+}
+
+/// A queued request waiting for a permit
+struct Waiter {
+    priority: Priority,
+    seq: u64,
+    tx: oneshot::Sender<Permit>,
+}
+
+impl Ord for Waiter {
+    /// Higher priority first, then first-come first-served
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+struct LimiterState {
+    in_flight: usize,
+    next_seq: u64,
+    waiters: BinaryHeap<Waiter>,
+}
+
+/// Caps concurrent upstream requests, handing freed permits to the highest-priority waiter
+pub struct PriorityLimiter {
+    max_concurrent: usize,
+    max_queued: Option<usize>,
+    state: Mutex<LimiterState>,
+}
+
+impl PriorityLimiter {
+    pub fn new(max_concurrent: usize, max_queued: Option<usize>) -> Arc<Self> {
+        Arc::new(Self {
+            max_concurrent,
+            max_queued,
+            state: Mutex::new(LimiterState {
+                in_flight: 0,
+                next_seq: 0,
+                waiters: BinaryHeap::new(),
+            }),
+        })
+    }
+
+    /// Waits for a permit, or returns `None` at once if the queue is full
+    ///
+    /// The permit is released when dropped.
+    pub async fn acquire(self: &Arc<Self>, priority: Priority) -> Option<Permit> {
+        let rx = {
+            let mut state = self.state.lock().unwrap();
+            if state.in_flight < self.max_concurrent && state.waiters.is_empty() {
+                state.in_flight += 1;
+                return Some(Permit {
+                    limiter: self.clone(),
+                });
+            }
+            if self.max_queued.is_some_and(|max| state.waiters.len() >= max) {
+                return None;
+            }
+
+            let (tx, rx) = oneshot::channel();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.waiters.push(Waiter { priority, seq, tx });
+            rx
+        };
+
+        // Permits are only dropped after being handed to a live waiter, so the sender is never lost
+        Some(rx.await.expect("limiter dropped a queued waiter"))
+    }
+
+    /// Number of permits currently held
+    pub fn in_flight(&self) -> usize {
+        self.state.lock().unwrap().in_flight
+    }
+
+    /// Current load, as reported at `/stats`
+    pub fn status(&self) -> LimiterStatus {
+        let state = self.state.lock().unwrap();
+        LimiterStatus {
+            limit: self.max_concurrent,
+            in_flight: state.in_flight,
+            queued: state.waiters.len(),
+        }
+    }
+}
+
+/// One limiter's load
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LimiterStatus {
+    pub limit: usize,
+    pub in_flight: usize,
+    pub queued: usize,
+}
+
+/// The global limiter and any per-model ones
+pub struct Limiters {
+    global: Option<Arc<PriorityLimiter>>,
+    models: HashMap<String, Arc<PriorityLimiter>>,
+}
+
+impl Limiters {
+    pub fn new(config: &ConcurrencyConfig) -> Self {
+        Self {
+            global: config
+                .max_concurrent
+                .map(|max| PriorityLimiter::new(max, config.max_queued)),
+            models: config
+                .per_model
+                .iter()
+                .map(|(model, &max)| (model.clone(), PriorityLimiter::new(max, config.max_queued)))
+                .collect(),
+        }
+    }
+
+    /// Waits for a slot under the model's limit and then the global one
+    ///
+    /// Returns `None` if either queue is full.
+    pub async fn acquire(&self, model: &str, priority: Priority) -> Option<Permits> {
+        let model = match self.models.get(model) {
+            Some(limiter) => Some(limiter.acquire(priority).await?),
+            None => None,
+        };
+        let global = match &self.global {
+            Some(limiter) => Some(limiter.acquire(priority).await?),
+            None => None,
+        };
+        Some(Permits {
+            _global: global,
+            _model: model,
+        })
+    }
+
+    /// Current load of every limiter, as reported at `/stats`
+    pub fn status(&self) -> ConcurrencyStatus {
+        ConcurrencyStatus {
+            global: self.global.as_ref().map(|limiter| limiter.status()),
+            models: self
+                .models
+                .iter()
+                .map(|(model, limiter)| (model.clone(), limiter.status()))
+                .collect(),
+        }
+    }
+}
+
+/// Load of the global limiter (absent when disabled) and of each per-model one
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConcurrencyStatus {
+    pub global: Option<LimiterStatus>,
+    pub models: BTreeMap<String, LimiterStatus>,
+}
+
+/// The slots a request holds until its response finishes streaming
+pub struct Permits {
+    _global: Option<Permit>,
+    _model: Option<Permit>,
+}
+
+/// A held concurrency slot
+pub struct Permit {
+    limiter: Arc<PriorityLimiter>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let next = {
+            let mut state = self.limiter.state.lock().unwrap();
+            match state.waiters.pop() {
+                Some(waiter) => Some(waiter.tx),
+                None => {
+                    state.in_flight -= 1;
+                    None
+                }
+            }
+        };
+
+        // Transfer the slot; if the waiter gave up, the returned permit is dropped and
+        // passes the slot on to the next waiter
+        if let Some(tx) = next {
+            let _ = tx.send(Permit {
+                limiter: self.limiter.clone(),
+            });
+        }
+    }
+}
@@ -1,8 +1,6 @@
-use rust_llm_logger::{middleware, proxy};
+use rust_llm_logger::app::{self, AppState};
+use rust_llm_logger::config::Config;
 
-use axum::{routing::any, Router};
-use std::sync::Arc;
-use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[tokio::main]
@@ -16,32 +14,47 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    // Create shared HTTP client for proxying
-    let client = Arc::new(create_http_client());
-
-    // Build the application router
-    let app = Router::new()
-        .route("/proxy/:backend_port/*path", any(proxy::proxy_handler))
-        .layer(axum::middleware::from_fn(middleware::extract_request_data))
-        .layer(TraceLayer::new_for_http())
-        .with_state(client);
+    // Load configuration
+    let config = Config::load().expect("Failed to load config");
+    let listen_addr = config.listen_addr.clone();
 
     // Start the server
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
+    let listener = tokio::net::TcpListener::bind(&listen_addr)
         .await
-        .expect("Failed to bind to port 3000");
+        .unwrap_or_else(|e| panic!("Failed to bind to {}: {}", listen_addr, e));
 
     tracing::info!("LLM Logging Proxy listening on {}", listener.local_addr().unwrap());
 
-    axum::serve(listener, app)
+    app::serve(listener, AppState::new(config), shutdown_signal())
         .await
         .expect("Server failed");
+
+    tracing::info!("Shutdown complete");
 }
 
-fn create_http_client() -> hyper_util::client::legacy::Client<
-    hyper_util::client::legacy::connect::HttpConnector,
-    axum::body::Body,
-> {
-    hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
-        .build_http()
+/// Resolves on Ctrl+C or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("Shutdown signal received, draining in-flight requests");
 }
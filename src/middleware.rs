@@ -1,46 +1,324 @@
 use axum::{
     body::Body,
-    extract::Request,
+    extract::{ConnectInfo, Request, State},
     middleware::Next,
     response::Response,
 };
+use bytes::{Bytes, BytesMut};
 use http_body_util::BodyExt;
+use tracing::Instrument;
+use hyper::header::{HeaderMap, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, EXPECT, TRANSFER_ENCODING, USER_AGENT};
+use hyper::Method;
+use hyper::StatusCode;
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
+use std::time::Duration;
 
-use crate::types::{GenericRequest, RequestData};
+use crate::app::AppState;
+use crate::archive::RequestHead;
+use crate::config::{ExpectContinueMode, PromptCapture};
+use crate::headers::{
+    api_key_fingerprint, client_identity, is_websocket_upgrade, mark_credentials_sensitive, request_id,
+    take_annotations, Annotations, PROXY_REQUEST_ID_HEADER,
+};
+use crate::parsers::RequestShape;
+use crate::replay::ReplayOf;
+use crate::text::normalized_sha256;
+use crate::tokens::PromptTokenCounter;
+use crate::types::{
+    Arrival, Attachments, ClientInfo, GenericRequest, ModelField, PromptStats, RequestData, SamplingParams,
+};
 
 /// Extracts model and prompt from the request body, then reconstructs the body
-pub async fn extract_request_data(mut req: Request, next: Next) -> Response {
-    // Read the entire body
-    let body = req.body_mut();
-    let collected = match body.collect().await {
-        Ok(c) => c,
-        Err(e) => {
-            tracing::error!("Failed to read request body: {}", e);
-            return Response::builder()
-                .status(400)
-                .body(Body::from("Failed to read request body"))
-                .unwrap();
+///
+/// Every request gets an ID, taken from the client's `x-request-id` when usable.
+/// It tags the request's log lines and is returned in `x-llm-logger-request-id`.
+pub async fn extract_request_data(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    // Our own annotation headers are never forwarded upstream
+    let mut annotations = take_annotations(req.headers_mut());
+    annotations.replay_of = req.extensions().get::<ReplayOf>().map(|ReplayOf(id)| id.clone());
+    mark_credentials_sensitive(req.headers_mut());
+
+    let request_id = request_id(req.headers());
+    let span = tracing::info_span!("llm_request", request_id = %request_id);
+    let mut response = extract_and_forward(state, req, next, request_id.clone(), annotations)
+        .instrument(span)
+        .await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(PROXY_REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+async fn extract_and_forward(
+    state: AppState,
+    mut req: Request,
+    next: Next,
+    request_id: String,
+    mut annotations: Annotations,
+) -> Response {
+    let arrival = Arrival::now();
+
+    // Refuse 100-continue before touching the body so the client never sends it
+    if expects_continue(&req) && state.config.expect_continue == ExpectContinueMode::Reject {
+        tracing::debug!("Rejecting request with Expect: 100-continue");
+        return Response::builder()
+            .status(StatusCode::EXPECTATION_FAILED)
+            .body(Body::from("Expect: 100-continue is not supported"))
+            .unwrap();
+    }
+
+    let client = client_info(&req, state.config.client_identity_header.as_deref());
+    let api_key_fingerprint = api_key_fingerprint(req.headers());
+    let api_key_label = api_key_fingerprint
+        .as_ref()
+        .and_then(|fingerprint| state.config.api_key_labels.get(fingerprint))
+        .cloned();
+
+    // Bodyless requests (list models, deletes, WebSocket handshakes) and multipart
+    // forms, audio, or other binary uploads go straight through without buffering or
+    // parsing. A body labelled otherwise that opens like a JSON object is read as one,
+    // so a wrong content-type can't carry a request past the model policy.
+    let bodyless = is_bodyless(&req);
+    let mut unparsed = bodyless;
+    let mut prefix = Bytes::new();
+    if !unparsed && !has_json_body(req.headers()) {
+        prefix = match read_prefix(req.body_mut()).await {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                tracing::error!("Failed to read request body: {}", e);
+                return Response::builder()
+                    .status(400)
+                    .body(Body::from("Failed to read request body"))
+                    .unwrap();
+            }
+        };
+        if !opens_json_object(&prefix) {
+            unparsed = true;
+            let head = futures::stream::once(std::future::ready(Ok::<_, axum::Error>(prefix.clone())));
+            let rest = std::mem::take(req.body_mut()).into_data_stream();
+            *req.body_mut() = Body::from_stream(futures::StreamExt::chain(head, rest));
+        }
+    }
+    if unparsed {
+        let model = azure_deployment(req.uri().path())
+            .or_else(|| query_model(req.uri()))
+            .map(str::to_string);
+        // Under an allowlist an unnamed upload is refused like an unnamed JSON body. A
+        // bodyless request that isn't a WebSocket handshake (listing models, a delete)
+        // can't start a generation, so it needs no model.
+        let policy = &state.config.model_policy;
+        let refusal = match model.as_deref() {
+            Some(model) => policy.check(model),
+            None if !bodyless || is_websocket_upgrade(req.method(), req.headers()) => policy.check_unknown(),
+            None => None,
+        };
+        if let Some(reason) = refusal {
+            tracing::warn!("Rejecting unparsed request: {}", reason);
+            return model_denied_response(&reason);
+        }
+
+        req.extensions_mut().insert(RequestData {
+            request_id,
+            model: model.unwrap_or_else(|| "unknown".to_string()),
+            prompt: String::new(),
+            system_prompt: None,
+            full_prompt_chars: 0,
+            full_prompt: None,
+            prompt_sha256: None,
+            streamed_prompt_tokens: None,
+            request_shape: RequestShape::Unknown,
+            logprobs_requested: false,
+            top_logprobs: None,
+            params: SamplingParams::default(),
+            // Nothing was parsed, so nothing failed to parse
+            request_parse_ok: true,
+            client,
+            api_key_fingerprint,
+            api_key_label,
+            annotations,
+            turn_index: None,
+            message_count: None,
+            system_prompt_present: false,
+            prompt_stats: None,
+            attachments: Attachments::default(),
+            tools_offered_count: 0,
+            tools_offered: Vec::new(),
+            streamed: false,
+            arrival,
+            body_read: Duration::ZERO,
+            raw_body: Bytes::new(),
+        });
+        return next.run(req).await;
+    }
+
+    // Read the entire body (hyper answers a pending 100-continue on first read),
+    // counting prompt tokens as the frames arrive
+    let mut buffer = BytesMut::from(&prefix[..]);
+    let mut prompt_counter = PromptTokenCounter::new();
+    prompt_counter.feed(&prefix);
+    while let Some(frame) = req.body_mut().frame().await {
+        match frame {
+            Ok(frame) => {
+                if let Ok(data) = frame.into_data() {
+                    prompt_counter.feed(&data);
+                    buffer.extend_from_slice(&data);
+                }
+            }
+            Err(e) => {
+                tracing::error!("Failed to read request body: {}", e);
+                return Response::builder()
+                    .status(400)
+                    .body(Body::from("Failed to read request body"))
+                    .unwrap();
+            }
+        }
+    }
+
+    let body_read = arrival.instant.elapsed();
+    let mut body_bytes = buffer.freeze();
+    if state.config.inject_include_usage {
+        if let Some(rewritten) = inject_include_usage(req.uri().path(), &body_bytes) {
+            tracing::debug!("Added stream_options.include_usage to the upstream request");
+            // The rewritten body has a known length, so it no longer goes out chunked
+            req.headers_mut().remove(TRANSFER_ENCODING);
+            req.headers_mut().insert(CONTENT_LENGTH, HeaderValue::from(rewritten.len()));
+            body_bytes = rewritten;
         }
+    }
+    let streamed_prompt_tokens = prompt_counter.prompt_tokens();
+
+    // Enforce the model policy before anything reaches the upstream. The model is
+    // read on its own, so a body the full parse rejects is still checked; Azure
+    // OpenAI names it by deployment in the path instead of the body.
+    let model = serde_json::from_slice::<ModelField>(&body_bytes)
+        .ok()
+        .and_then(|field| field.model)
+        .or_else(|| azure_deployment(req.uri().path()).map(str::to_string));
+    let policy = &state.config.model_policy;
+    let refusal = match model.as_deref() {
+        Some(model) => policy.check(model),
+        None => policy.check_unknown(),
     };
+    if let Some(reason) = refusal {
+        tracing::warn!("Rejecting request {}: {}", request_id, reason);
+        return model_denied_response(&reason);
+    }
+    let model = model.unwrap_or_else(|| "unknown".to_string());
 
-    let body_bytes = collected.to_bytes();
+    // Archive the accepted request off the request path; shutdown waits for the write
+    if let Some(archive) = state.archive.clone() {
+        let request_id = request_id.clone();
+        let head = RequestHead::new(req.method(), req.uri(), req.headers());
+        let body = body_bytes.clone();
+        state.tasks.spawn(async move {
+            if let Err(e) = archive.save_request(&request_id, &head, body).await {
+                tracing::error!("Failed to archive request {}: {}", request_id, e);
+            }
+        });
+    }
 
     // Try to parse the request body
     if let Ok(parsed) = serde_json::from_slice::<GenericRequest>(&body_bytes) {
-        let prompt = extract_prompt(&parsed);
-        let model = parsed.model.unwrap_or_else(|| "unknown".to_string());
+        let extracted_prompt = extract_prompt(&parsed);
+        let tokenized_prompt = state.tokenizer.as_ref().and(extracted_prompt.clone());
+        let full_prompt = extracted_prompt.unwrap_or_else(|| "no prompt found".to_string());
+        let full_prompt_chars = full_prompt.chars().count() as u64;
+        let prompt_sha256 = normalized_sha256(&full_prompt);
+        let prompt = match state.config.prompt_capture {
+            PromptCapture::Full => full_prompt,
+            PromptCapture::LastUserMessage => last_user_message(&parsed).unwrap_or(full_prompt),
+        };
+        let message_count = parsed
+            .messages
+            .as_ref()
+            .map(|m| m.len() as u32 + u32::from(parsed.system.is_some()));
+        let system_prompt_present = parsed.system.is_some()
+            || parsed
+                .messages
+                .iter()
+                .flatten()
+                .any(|m| is_system_role(&m.role));
+        let prompt_stats = prompt_stats(&parsed);
+        if annotations.conversation_id.is_none() {
+            annotations.conversation_id = derive_conversation_id(&parsed);
+        }
+        let turn_index = parsed.messages.as_ref().map(|m| m.len() as u32);
+        let streamed = parsed.streamed(req.uri().path());
+        let (tools_offered_count, tools_offered) = parsed.tools_offered();
+        let request_shape = if parsed.messages.is_some() {
+            RequestShape::Messages
+        } else if parsed.prompt.is_some() {
+            RequestShape::Prompt
+        } else {
+            RequestShape::Unknown
+        };
 
         // Store the extracted data in request extensions
         req.extensions_mut().insert(RequestData {
+            request_id,
             model,
             prompt,
+            system_prompt: extract_system_prompt(&parsed),
+            full_prompt_chars,
+            full_prompt: tokenized_prompt,
+            prompt_sha256: Some(prompt_sha256),
+            streamed_prompt_tokens,
+            request_shape,
+            logprobs_requested: parsed.logprobs_requested(),
+            top_logprobs: parsed.top_logprobs(),
+            params: parsed.sampling_params(),
+            request_parse_ok: true,
+            client,
+            api_key_fingerprint,
+            api_key_label,
+            annotations,
+            turn_index,
+            message_count,
+            system_prompt_present,
+            prompt_stats,
+            attachments: parsed.attachments(),
+            tools_offered_count,
+            tools_offered,
+            streamed,
+            arrival,
+            body_read,
             raw_body: body_bytes.clone(),
         });
     } else {
         tracing::warn!("Failed to parse request body as JSON, storing raw body");
         req.extensions_mut().insert(RequestData {
-            model: "unknown".to_string(),
+            request_id,
+            model,
             prompt: "unparseable".to_string(),
+            system_prompt: None,
+            full_prompt_chars: 0,
+            full_prompt: None,
+            prompt_sha256: None,
+            streamed_prompt_tokens,
+            request_shape: RequestShape::Unknown,
+            logprobs_requested: false,
+            top_logprobs: None,
+            params: SamplingParams::default(),
+            request_parse_ok: false,
+            client,
+            api_key_fingerprint,
+            api_key_label,
+            annotations,
+            turn_index: None,
+            message_count: None,
+            system_prompt_present: false,
+            prompt_stats: None,
+            attachments: Attachments::default(),
+            tools_offered_count: 0,
+            tools_offered: Vec::new(),
+            streamed: false,
+            arrival,
+            body_read,
             raw_body: body_bytes.clone(),
         });
     }
@@ -51,18 +329,232 @@ pub async fn extract_request_data(mut req: Request, next: Next) -> Response {
     next.run(req).await
 }
 
-/// Extracts the prompt from either the prompt field or messages field
-fn extract_prompt(request: &GenericRequest) -> String {
+/// Collects the client's address, user agent, and configured identity header
+fn client_info(req: &Request, identity_header: Option<&str>) -> ClientInfo {
+    let headers = req.headers();
+    ClientInfo {
+        addr: req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.to_string()),
+        user_agent: headers
+            .get(USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string),
+        identity: identity_header.and_then(|header| client_identity(headers, header)),
+    }
+}
+
+/// Builds the 403 returned for a model refused by the policy
+fn model_denied_response(reason: &str) -> Response {
+    let body = serde_json::json!({
+        "error": {
+            "message": reason,
+            "type": "model_not_permitted",
+        }
+    });
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// Whether the request is a GET, HEAD, or DELETE that carries no body
+fn is_bodyless(req: &Request) -> bool {
+    let headers = req.headers();
+    matches!(*req.method(), Method::GET | Method::HEAD | Method::DELETE)
+        && !headers.contains_key(TRANSFER_ENCODING)
+        && headers.get(CONTENT_LENGTH).is_none_or(|len| len.as_bytes() == b"0")
+}
+
+/// Whether the body may be a JSON LLM request worth buffering and parsing
+///
+/// A missing content-type and `text/plain` (the browser `fetch` default) are
+/// treated as JSON, since clients often forget to label it.
+fn has_json_body(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()) else {
+        return true;
+    };
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    mime.is_empty() || mime == "text/plain" || mime.ends_with("/json") || mime.ends_with("+json")
+}
+
+/// Reads data frames up to the first one holding a non-whitespace byte, or the
+/// whole body if it has none
+async fn read_prefix(body: &mut Body) -> Result<Bytes, axum::Error> {
+    let mut prefix = BytesMut::new();
+    while let Some(frame) = body.frame().await {
+        if let Ok(data) = frame?.into_data() {
+            prefix.extend_from_slice(&data);
+            if data.iter().any(|b| !b.is_ascii_whitespace()) {
+                break;
+            }
+        }
+    }
+    Ok(prefix.freeze())
+}
+
+/// Whether a body's first bytes open a JSON object
+fn opens_json_object(prefix: &[u8]) -> bool {
+    prefix.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{')
+}
+
+/// Returns true if the request carries `Expect: 100-continue`
+pub fn expects_continue<B>(req: &hyper::Request<B>) -> bool {
+    req.headers()
+        .get(EXPECT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("100-continue"))
+}
+
+/// Asks an OpenAI-compatible endpoint to send a usage chunk at the end of the stream
+///
+/// Applies only to streaming chat/completions bodies without `stream_options`.
+/// The field is spliced in after the opening brace, so every other byte of the
+/// body is forwarded exactly as the client sent it.
+pub fn inject_include_usage(path: &str, body: &Bytes) -> Option<Bytes> {
+    if !path.ends_with("/completions") {
+        return None;
+    }
+    let fields = serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(body).ok()?;
+    if fields.get("stream") != Some(&serde_json::Value::Bool(true)) || fields.contains_key("stream_options") {
+        return None;
+    }
+
+    // A parsed object always starts with `{` after any leading whitespace
+    let open = body.iter().position(|&b| b == b'{')? + 1;
+    let mut rewritten = BytesMut::with_capacity(body.len() + 48);
+    rewritten.extend_from_slice(&body[..open]);
+    rewritten.extend_from_slice(br#""stream_options":{"include_usage":true},"#);
+    rewritten.extend_from_slice(&body[open..]);
+    Some(rewritten.freeze())
+}
+
+/// Returns the deployment name from an Azure OpenAI path
+/// (`.../openai/deployments/{deployment}/chat/completions`)
+pub fn azure_deployment(path: &str) -> Option<&str> {
+    let mut segments = path.split('/');
+    while let Some(segment) = segments.next() {
+        if segment == "openai" {
+            let mut rest = segments.clone();
+            if rest.next() == Some("deployments") {
+                return rest.next().filter(|deployment| !deployment.is_empty());
+            }
+        }
+    }
+    None
+}
+
+/// The `model` query parameter, which the OpenAI Realtime API's WebSocket handshake uses
+fn query_model(uri: &hyper::Uri) -> Option<&str> {
+    uri.query()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("model="))
+        .filter(|model| !model.is_empty())
+}
+
+/// Length of the conversation ID derived from a chat's opening messages, in hex characters
+const CONVERSATION_ID_LEN: usize = 16;
+
+/// Derives a conversation ID from the opening of a chat request
+///
+/// The opening is any Anthropic `system` prompt plus every message up to and
+/// including the first user message. Chat clients resend it unchanged each turn,
+/// so every turn of a conversation gets the same ID. The ID is the first 16 hex
+/// characters of a SHA-256 over each opening part's role, a NUL byte, its text,
+/// and another NUL byte, in order. Separate chats that open identically share an ID.
+fn derive_conversation_id(request: &GenericRequest) -> Option<String> {
+    let messages = request.messages.as_ref().filter(|m| !m.is_empty())?;
+    let opening_len = messages
+        .iter()
+        .position(|m| m.role == "user")
+        .map_or(messages.len(), |first_user| first_user + 1);
+
+    let mut hasher = Sha256::new();
+    let system = request.system.iter().map(|s| ("system", s.text()));
+    let opening = messages[..opening_len].iter().map(|m| (m.role.as_str(), m.content.text()));
+    for (role, text) in system.chain(opening) {
+        hasher.update(role.as_bytes());
+        hasher.update([0]);
+        hasher.update(text.as_bytes());
+        hasher.update([0]);
+    }
+    let mut id = hex::encode(hasher.finalize());
+    id.truncate(CONVERSATION_ID_LEN);
+    Some(id)
+}
+
+/// Roles that carry instructions rather than conversation
+fn is_system_role(role: &str) -> bool {
+    role == "system" || role == "developer"
+}
+
+/// Role counts, system prompt size, and image presence for a chat request
+fn prompt_stats(request: &GenericRequest) -> Option<PromptStats> {
+    if request.messages.is_none() && request.system.is_none() {
+        return None;
+    }
+
+    let mut stats = PromptStats::default();
+    if let Some(system) = &request.system {
+        *stats.roles.entry("system".to_string()).or_default() += 1;
+        stats.system_prompt_chars += system.text().chars().count() as u64;
+    }
+    for message in request.messages.iter().flatten() {
+        *stats.roles.entry(message.role.clone()).or_default() += 1;
+        if is_system_role(&message.role) {
+            stats.system_prompt_chars += message.content.text().chars().count() as u64;
+        }
+        stats.has_images |= message.content.has_images() || !message.images.is_empty();
+    }
+    Some(stats)
+}
+
+/// Text of the last user message in a chat request
+fn last_user_message(request: &GenericRequest) -> Option<String> {
+    request
+        .messages
+        .as_ref()?
+        .iter()
+        .rev()
+        .find(|m| m.role == "user")
+        .map(|m| m.content.text())
+}
+
+/// Extracts the prompt from either the prompt field or the non-system messages
+fn extract_prompt(request: &GenericRequest) -> Option<String> {
     if let Some(prompt) = &request.prompt {
-        prompt.clone()
-    } else if let Some(messages) = &request.messages {
-        // Concatenate all message contents
-        messages
+        Some(prompt.clone())
+    } else if request.messages.is_some() || request.system.is_some() {
+        let prompt = request
+            .messages
             .iter()
-            .map(|m| format!("{}: {}", m.role, m.content))
+            .flatten()
+            .filter(|m| !is_system_role(&m.role))
+            .map(|m| format!("{}: {}", m.role, m.content.text()))
             .collect::<Vec<_>>()
-            .join("\n")
+            .join("\n");
+        Some(prompt)
     } else {
-        "no prompt found".to_string()
+        None
     }
 }
+
+/// The top-level `system` prompt (Anthropic, Ollama) and any system or developer
+/// messages, joined by newlines
+fn extract_system_prompt(request: &GenericRequest) -> Option<String> {
+    let messages = request
+        .messages
+        .iter()
+        .flatten()
+        .filter(|m| is_system_role(&m.role))
+        .map(|m| &m.content);
+    let parts: Vec<String> = request.system.iter().chain(messages).map(|c| c.text()).collect();
+    (!parts.is_empty()).then(|| parts.join("\n"))
+}
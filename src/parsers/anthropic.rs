@@ -0,0 +1,173 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::parsers::sse::{SseEvent, SseScanner};
+use crate::parsers::{BackendStreamParser, DEFAULT_MAX_BUFFER_BYTES};
+use crate::types::{AnthropicEvent, AnthropicUsage, TokenUsage};
+
+/// Parser for the Anthropic Messages API (`/v1/messages`)
+///
+/// Input tokens arrive on `message_start` and the final output count on the
+/// last `message_delta`; a non-streaming response carries both in `usage`.
+pub struct AnthropicParser {
+    scanner: SseScanner,
+    max_buffer: usize,
+    token_usage: TokenUsage,
+    /// Most bytes of generated text kept in `completion_text` (0 keeps none)
+    text_limit: usize,
+    saw_content: bool,
+}
+
+impl AnthropicParser {
+    pub fn new() -> Self {
+        Self::with_max_buffer(DEFAULT_MAX_BUFFER_BYTES)
+    }
+
+    /// Creates a parser that drops any partial event longer than `max_buffer` bytes
+    pub fn with_max_buffer(max_buffer: usize) -> Self {
+        Self {
+            scanner: SseScanner::new(),
+            max_buffer,
+            token_usage: TokenUsage::default(),
+            text_limit: 0,
+            saw_content: false,
+        }
+    }
+
+    /// Process every complete SSE event in the scanner
+    fn process_events(&mut self) {
+        while let Some(event) = self.scanner.next_event() {
+            self.handle_event(event);
+        }
+
+        // An event that never ends would otherwise grow the buffer without limit
+        if self.scanner.buffered_len() > self.max_buffer {
+            tracing::warn!(
+                "Anthropic event exceeded {} bytes without a delimiter, discarding it",
+                self.max_buffer
+            );
+            self.scanner.clear();
+            self.token_usage.parse_truncated = true;
+        }
+    }
+
+    /// Process a single SSE event
+    fn handle_event(&mut self, event: SseEvent) {
+        self.token_usage.event_count += 1;
+
+        let Ok(event) = serde_json::from_str::<AnthropicEvent>(&event.data) else {
+            tracing::debug!("Failed to parse Anthropic event: {:?}", event.data);
+            return;
+        };
+
+        match event.event_type.as_str() {
+            "message_start" => {
+                if let Some(message) = event.message {
+                    self.token_usage.record_response_model(message.model.as_deref());
+                    if let Some(usage) = message.usage {
+                        self.apply_usage(usage);
+                    }
+                }
+            }
+            "content_block_start" if event.content_block.is_some_and(|b| b.block_type == "tool_use") => {
+                self.token_usage.tool_call_count += 1;
+                self.saw_content = true;
+            }
+            "content_block_delta" => {
+                if let Some(text) = event.delta.and_then(|d| d.text) {
+                    self.saw_content |= !text.is_empty();
+                    self.token_usage.push_completion_text(&text, self.text_limit);
+                }
+            }
+            "message_stop" => self.token_usage.saw_terminal = true,
+            "message_delta" => {
+                if let Some(reason) = event.delta.and_then(|d| d.stop_reason) {
+                    self.token_usage.finish_reason = Some(reason);
+                }
+                if let Some(usage) = event.usage {
+                    self.apply_usage(usage);
+                }
+            }
+            // A complete non-streaming response
+            "message" => {
+                self.token_usage.saw_terminal = true;
+                self.token_usage.record_response_model(event.model.as_deref());
+                for block in event.content {
+                    match block.block_type.as_str() {
+                        "text" => {
+                            let text = block.text.unwrap_or_default();
+                            self.token_usage.push_completion_text(&text, self.text_limit);
+                        }
+                        "tool_use" => self.token_usage.tool_call_count += 1,
+                        _ => {}
+                    }
+                }
+                self.token_usage.finish_reason = event.stop_reason;
+                if let Some(usage) = event.usage {
+                    self.apply_usage(usage);
+                }
+            }
+            "error" => tracing::warn!("Anthropic stream reported an error"),
+            _ => {}
+        }
+    }
+
+    fn apply_usage(&mut self, usage: AnthropicUsage) {
+        tracing::debug!(
+            "Parsed Anthropic usage: input_tokens={:?}, output_tokens={:?}, cache_creation={:?}, cache_read={:?}",
+            usage.input_tokens,
+            usage.output_tokens,
+            usage.cache_creation_input_tokens,
+            usage.cache_read_input_tokens
+        );
+        if usage.input_tokens.is_some() {
+            self.token_usage.prompt_tokens = usage.input_tokens;
+        }
+        if usage.output_tokens.is_some() {
+            self.token_usage.completion_tokens = usage.output_tokens;
+        }
+        if usage.cache_creation_input_tokens.is_some() {
+            self.token_usage.cache_write_tokens = usage.cache_creation_input_tokens;
+        }
+        if usage.cache_read_input_tokens.is_some() {
+            self.token_usage.cache_read_tokens = usage.cache_read_input_tokens;
+        }
+    }
+}
+
+impl Default for AnthropicParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl BackendStreamParser for AnthropicParser {
+    async fn feed_chunk(&mut self, chunk: &Bytes) {
+        // Append chunk to the scanner
+        self.scanner.push(chunk);
+
+        // Process any complete events
+        self.process_events();
+    }
+
+    fn saw_content(&self) -> bool {
+        self.saw_content
+    }
+
+    fn capture_completion_text(&mut self, max_bytes: usize) {
+        self.text_limit = max_bytes;
+    }
+
+    async fn finalize(mut self: Box<Self>) -> TokenUsage {
+        // Process any remaining data in the buffer
+        self.process_events();
+
+        // The stream may end without the blank line that terminates the last event
+        if let Some(event) = self.scanner.finish() {
+            self.handle_event(event);
+        }
+
+        self.token_usage
+    }
+}
@@ -0,0 +1,241 @@
+use async_trait::async_trait;
+use base64::Engine;
+use bytes::{Buf, Bytes, BytesMut};
+
+use crate::parsers::{BackendStreamParser, DEFAULT_MAX_BUFFER_BYTES};
+use crate::types::{BedrockChunk, BedrockChunkPayload, BedrockConverseMetadata, TokenUsage};
+
+/// Prelude: total length, headers length, prelude CRC
+const PRELUDE_LEN: usize = 12;
+/// Trailing message CRC
+const MESSAGE_CRC_LEN: usize = 4;
+
+/// Parser for AWS Bedrock's binary `application/vnd.amazon.eventstream` framing
+///
+/// Handles both `InvokeModelWithResponseStream` (base64 model chunks, with
+/// `amazon-bedrock-invocationMetrics` on the last one) and `ConverseStream`
+/// (a `metadata` event carrying usage).
+pub struct BedrockParser {
+    buffer: BytesMut,
+    /// Frames declaring a larger length are treated as corrupt rather than buffered
+    max_buffer: usize,
+    token_usage: TokenUsage,
+    /// Most bytes of generated text kept in `completion_text` (0 keeps none)
+    text_limit: usize,
+    saw_content: bool,
+}
+
+/// The headers of one event-stream message that the parser cares about
+#[derive(Debug, Default)]
+struct FrameHeaders {
+    message_type: Option<String>,
+    event_type: Option<String>,
+}
+
+impl BedrockParser {
+    pub fn new() -> Self {
+        Self::with_max_buffer(DEFAULT_MAX_BUFFER_BYTES)
+    }
+
+    /// Creates a parser that rejects frames longer than `max_buffer` bytes
+    pub fn with_max_buffer(max_buffer: usize) -> Self {
+        Self {
+            buffer: BytesMut::new(),
+            max_buffer,
+            token_usage: TokenUsage::default(),
+            text_limit: 0,
+            saw_content: false,
+        }
+    }
+
+    /// Process complete frames from the buffer
+    fn process_frames(&mut self) {
+        while self.buffer.len() >= PRELUDE_LEN {
+            let total_len = (&self.buffer[0..4]).get_u32() as usize;
+            if !(PRELUDE_LEN + MESSAGE_CRC_LEN..=self.max_buffer).contains(&total_len) {
+                tracing::warn!("Invalid Bedrock frame length {}, dropping buffered data", total_len);
+                self.buffer.clear();
+                self.token_usage.parse_truncated = true;
+                return;
+            }
+            if self.buffer.len() < total_len {
+                return;
+            }
+
+            let frame = self.buffer.split_to(total_len);
+            self.process_frame(&frame);
+        }
+    }
+
+    fn process_frame(&mut self, frame: &[u8]) {
+        let headers_len = (&frame[4..8]).get_u32() as usize;
+        let prelude_crc = (&frame[8..12]).get_u32();
+        let message_crc = (&frame[frame.len() - MESSAGE_CRC_LEN..]).get_u32();
+
+        if crc32fast::hash(&frame[..8]) != prelude_crc
+            || crc32fast::hash(&frame[..frame.len() - MESSAGE_CRC_LEN]) != message_crc
+        {
+            tracing::debug!("Skipping Bedrock frame with bad checksum");
+            return;
+        }
+
+        let payload_start = PRELUDE_LEN + headers_len;
+        let payload_end = frame.len() - MESSAGE_CRC_LEN;
+        if payload_start > payload_end {
+            tracing::debug!("Skipping Bedrock frame with oversized headers");
+            return;
+        }
+
+        let headers = parse_headers(&frame[PRELUDE_LEN..payload_start]);
+        let payload = &frame[payload_start..payload_end];
+        self.token_usage.event_count += 1;
+
+        if headers.message_type.as_deref() == Some("exception") {
+            tracing::warn!(
+                "Bedrock stream exception: type={:?}, payload={}",
+                headers.event_type,
+                String::from_utf8_lossy(payload)
+            );
+            return;
+        }
+
+        match headers.event_type.as_deref() {
+            Some("chunk") => self.handle_chunk(payload),
+            Some("contentBlockDelta") => self.saw_content = true,
+            Some("metadata") => {
+                // ConverseStream sends usage last
+                self.token_usage.saw_terminal = true;
+                if let Ok(metadata) = serde_json::from_slice::<BedrockConverseMetadata>(payload) {
+                    if let Some(usage) = metadata.usage {
+                        self.set_usage(usage.input_tokens, usage.output_tokens);
+                        self.token_usage.total_tokens = usage.total_tokens;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Decode an `InvokeModelWithResponseStream` chunk
+    fn handle_chunk(&mut self, payload: &[u8]) {
+        let Ok(wrapper) = serde_json::from_slice::<BedrockChunkPayload>(payload) else {
+            tracing::debug!("Failed to parse Bedrock chunk payload");
+            return;
+        };
+        let Ok(decoded) = base64::engine::general_purpose::STANDARD.decode(wrapper.bytes) else {
+            tracing::debug!("Failed to decode Bedrock chunk bytes");
+            return;
+        };
+        let Ok(chunk) = serde_json::from_slice::<BedrockChunk>(&decoded) else {
+            tracing::trace!("Bedrock chunk is not a JSON object");
+            return;
+        };
+
+        if chunk.has_content() {
+            self.saw_content = true;
+        }
+        if let Some(text) = chunk.text() {
+            self.token_usage.push_completion_text(text, self.text_limit);
+        }
+        if let Some(reason) = chunk.stop_reason.or(chunk.completion_reason) {
+            self.token_usage.finish_reason = Some(reason);
+        }
+        if let Some(metrics) = chunk.invocation_metrics {
+            // Only the final chunk carries invocation metrics
+            self.token_usage.saw_terminal = true;
+            self.set_usage(metrics.input_token_count, metrics.output_token_count);
+        }
+    }
+
+    fn set_usage(&mut self, input_tokens: u32, output_tokens: u32) {
+        tracing::debug!(
+            "Parsed Bedrock usage: input_tokens={}, output_tokens={}",
+            input_tokens,
+            output_tokens
+        );
+        self.token_usage.prompt_tokens = Some(input_tokens);
+        self.token_usage.completion_tokens = Some(output_tokens);
+    }
+}
+
+/// Reads the string-valued headers of interest, skipping values of every other type
+fn parse_headers(mut headers: &[u8]) -> FrameHeaders {
+    let mut parsed = FrameHeaders::default();
+
+    while headers.remaining() > 0 {
+        let name_len = headers.get_u8() as usize;
+        if headers.remaining() < name_len + 1 {
+            break;
+        }
+        let name = String::from_utf8_lossy(&headers[..name_len]).to_string();
+        headers.advance(name_len);
+
+        let value_len = match headers.get_u8() {
+            // bool true / bool false carry no value
+            0 | 1 => 0,
+            2 => 1,
+            3 => 2,
+            4 => 4,
+            5 | 8 => 8,
+            9 => 16,
+            // byte array / string: u16 length prefix
+            6 | 7 => {
+                if headers.remaining() < 2 {
+                    break;
+                }
+                headers.get_u16() as usize
+            }
+            other => {
+                tracing::debug!("Unknown Bedrock header type {}", other);
+                break;
+            }
+        };
+        if headers.remaining() < value_len {
+            break;
+        }
+
+        let value = &headers[..value_len];
+        match name.as_str() {
+            ":message-type" => parsed.message_type = Some(String::from_utf8_lossy(value).to_string()),
+            ":event-type" => parsed.event_type = Some(String::from_utf8_lossy(value).to_string()),
+            _ => {}
+        }
+        headers.advance(value_len);
+    }
+
+    parsed
+}
+
+impl Default for BedrockParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl BackendStreamParser for BedrockParser {
+    async fn feed_chunk(&mut self, chunk: &Bytes) {
+        // Append chunk to buffer
+        self.buffer.extend_from_slice(chunk);
+
+        // Process any complete frames
+        self.process_frames();
+    }
+
+    fn saw_content(&self) -> bool {
+        self.saw_content
+    }
+
+    fn capture_completion_text(&mut self, max_bytes: usize) {
+        self.text_limit = max_bytes;
+    }
+
+    async fn finalize(mut self: Box<Self>) -> TokenUsage {
+        self.process_frames();
+        if !self.buffer.is_empty() {
+            tracing::debug!("Discarding {} bytes of incomplete Bedrock frame", self.buffer.len());
+        }
+
+        self.token_usage
+    }
+}
@@ -0,0 +1,141 @@
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+
+use crate::parsers::{BackendStreamParser, DEFAULT_MAX_BUFFER_BYTES};
+use crate::types::{CohereStreamEvent, TokenUsage};
+
+/// Parser for Cohere's newline-delimited JSON chat stream
+///
+/// Usage only arrives on the terminal `stream-end` event, so a stream aborted
+/// before it leaves the token counts as `None`.
+pub struct CohereParser {
+    buffer: BytesMut,
+    /// Bytes at the front of the buffer already known to contain no newline
+    scanned: usize,
+    max_buffer: usize,
+    token_usage: TokenUsage,
+    /// Most bytes of generated text kept in `completion_text` (0 keeps none)
+    text_limit: usize,
+    saw_content: bool,
+}
+
+impl CohereParser {
+    pub fn new() -> Self {
+        Self::with_max_buffer(DEFAULT_MAX_BUFFER_BYTES)
+    }
+
+    /// Creates a parser that drops any partial line longer than `max_buffer` bytes
+    pub fn with_max_buffer(max_buffer: usize) -> Self {
+        Self {
+            buffer: BytesMut::new(),
+            scanned: 0,
+            max_buffer,
+            token_usage: TokenUsage::default(),
+            text_limit: 0,
+            saw_content: false,
+        }
+    }
+
+    /// Process complete lines from the buffer
+    fn process_lines(&mut self) {
+        while let Some(offset) = self.buffer[self.scanned..].iter().position(|&b| b == b'\n') {
+            let line = self.buffer.split_to(self.scanned + offset + 1);
+            self.scanned = 0;
+            self.process_line(&line);
+        }
+        self.scanned = self.buffer.len();
+
+        // A line that never ends would otherwise grow the buffer without limit
+        if self.buffer.len() > self.max_buffer {
+            tracing::warn!(
+                "Cohere line exceeded {} bytes without a newline, discarding it",
+                self.max_buffer
+            );
+            self.buffer.clear();
+            self.scanned = 0;
+            self.token_usage.parse_truncated = true;
+        }
+    }
+
+    fn process_line(&mut self, line: &[u8]) {
+        // Treat CRLF like LF and skip empty lines
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            return;
+        }
+
+        let Ok(event) = serde_json::from_slice::<CohereStreamEvent>(line) else {
+            tracing::debug!(
+                "Failed to parse Cohere JSON line: {:?}",
+                String::from_utf8_lossy(line)
+            );
+            return;
+        };
+        self.token_usage.event_count += 1;
+
+        if event.has_content() {
+            self.saw_content = true;
+            self.token_usage.push_completion_text(event.text.as_deref().unwrap_or_default(), self.text_limit);
+        }
+
+        match event.event_type.as_str() {
+            "tool-calls-generation" => {
+                let calls = event.tool_calls.as_ref().map_or(0, Vec::len);
+                self.token_usage.tool_call_count += calls as u32;
+                if calls > 0 {
+                    self.saw_content = true;
+                }
+            }
+            "stream-end" => {
+                self.token_usage.saw_terminal = true;
+                self.token_usage.finish_reason = event.finish_reason;
+                let tokens = event.response.and_then(|r| r.meta).and_then(|m| m.tokens);
+                if let Some(tokens) = tokens {
+                    tracing::debug!(
+                        "Parsed Cohere usage: input_tokens={:?}, output_tokens={:?}",
+                        tokens.input_tokens,
+                        tokens.output_tokens
+                    );
+                    self.token_usage.prompt_tokens = tokens.input_tokens;
+                    self.token_usage.completion_tokens = tokens.output_tokens;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Default for CohereParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl BackendStreamParser for CohereParser {
+    async fn feed_chunk(&mut self, chunk: &Bytes) {
+        // Append chunk to buffer
+        self.buffer.extend_from_slice(chunk);
+
+        // Process any complete lines
+        self.process_lines();
+    }
+
+    fn saw_content(&self) -> bool {
+        self.saw_content
+    }
+
+    fn capture_completion_text(&mut self, max_bytes: usize) {
+        self.text_limit = max_bytes;
+    }
+
+    async fn finalize(mut self: Box<Self>) -> TokenUsage {
+        // The final event may arrive without a trailing newline
+        if !self.buffer.is_empty() {
+            let remaining = self.buffer.split();
+            self.process_line(&remaining);
+        }
+
+        self.token_usage
+    }
+}
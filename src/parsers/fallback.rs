@@ -0,0 +1,82 @@
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+
+use crate::parsers::{
+    create_parser_with_max_buffer, BackendStreamParser, BackendType, DEFAULT_MAX_BUFFER_BYTES,
+};
+use crate::types::TokenUsage;
+
+/// Parser for streams whose backend couldn't be identified
+///
+/// Buffers the whole response and, at finalize, runs it through each candidate
+/// parser in order, keeping the first result that reports any token counts.
+/// This holds the full body in memory, so it is only used when configured.
+pub struct FallbackParser {
+    candidates: Vec<BackendType>,
+    buffer: BytesMut,
+    max_buffer: usize,
+    /// Passed on to the candidate parsers
+    text_limit: usize,
+    truncated: bool,
+}
+
+impl FallbackParser {
+    pub fn new(candidates: Vec<BackendType>) -> Self {
+        Self::with_max_buffer(candidates, DEFAULT_MAX_BUFFER_BYTES)
+    }
+
+    /// Creates a parser that stops buffering once the body exceeds `max_buffer` bytes
+    pub fn with_max_buffer(candidates: Vec<BackendType>, max_buffer: usize) -> Self {
+        Self {
+            candidates,
+            buffer: BytesMut::new(),
+            max_buffer,
+            text_limit: 0,
+            truncated: false,
+        }
+    }
+}
+
+#[async_trait]
+impl BackendStreamParser for FallbackParser {
+    async fn feed_chunk(&mut self, chunk: &Bytes) {
+        if self.truncated {
+            return;
+        }
+        if self.buffer.len() + chunk.len() > self.max_buffer {
+            tracing::warn!("Response exceeded {} bytes, abandoning fallback parsing", self.max_buffer);
+            self.buffer = BytesMut::new();
+            self.truncated = true;
+            return;
+        }
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    fn capture_completion_text(&mut self, max_bytes: usize) {
+        self.text_limit = max_bytes;
+    }
+
+    async fn finalize(self: Box<Self>) -> TokenUsage {
+        if self.truncated {
+            return TokenUsage {
+                parse_truncated: true,
+                ..TokenUsage::default()
+            };
+        }
+
+        let body = self.buffer.freeze();
+        for candidate in &self.candidates {
+            let mut parser = create_parser_with_max_buffer(*candidate, self.max_buffer);
+            parser.capture_completion_text(self.text_limit);
+            parser.feed_chunk(&body).await;
+            let usage = parser.finalize().await;
+            if usage.prompt_tokens.is_some() || usage.completion_tokens.is_some() {
+                tracing::debug!("Fallback parsing matched {:?}", candidate);
+                return usage;
+            }
+        }
+
+        tracing::debug!("No fallback parser extracted usage from {} bytes", body.len());
+        TokenUsage::default()
+    }
+}
@@ -1,13 +1,24 @@
+mod anthropic;
+mod bedrock;
+mod cohere;
+mod fallback;
 mod ollama;
 mod openai;
 mod passthrough;
+mod sse;
 
+pub use anthropic::AnthropicParser;
+pub use bedrock::BedrockParser;
+pub use cohere::CohereParser;
+pub use fallback::FallbackParser;
 pub use ollama::OllamaParser;
 pub use openai::OpenAIParser;
 pub use passthrough::PassthroughParser;
+pub use sse::{SseEvent, SseScanner};
 
 use async_trait::async_trait;
 use bytes::Bytes;
+use serde::{Deserialize, Serialize};
 
 use crate::types::TokenUsage;
 
@@ -17,24 +28,146 @@ pub trait BackendStreamParser: Send {
     /// Feed a chunk of data to the parser
     async fn feed_chunk(&mut self, chunk: &Bytes);
 
+    /// Whether a chunk carrying generated content has been parsed so far
+    fn saw_content(&self) -> bool {
+        false
+    }
+
+    /// Keeps up to `max_bytes` of the generated text in `TokenUsage::completion_text`
+    ///
+    /// Parsers keep none by default, so text is only held when something reads it.
+    fn capture_completion_text(&mut self, _max_bytes: usize) {}
+
     /// Finalize parsing and return token usage
     async fn finalize(self: Box<Self>) -> TokenUsage;
 }
 
+/// Default cap on the bytes a parser holds while waiting for a record delimiter
+pub const DEFAULT_MAX_BUFFER_BYTES: usize = 4 * 1024 * 1024;
+
+/// Creates the parser for a backend type with the default buffer cap
+pub fn create_parser(backend_type: BackendType) -> Box<dyn BackendStreamParser> {
+    create_parser_with_max_buffer(backend_type, DEFAULT_MAX_BUFFER_BYTES)
+}
+
+/// Creates the parser for a backend type
+///
+/// If an incomplete record grows past `max_buffer` bytes the parser drops it
+/// and reports `parse_truncated`, so a delimiter-free stream can't exhaust memory.
+pub fn create_parser_with_max_buffer(
+    backend_type: BackendType,
+    max_buffer: usize,
+) -> Box<dyn BackendStreamParser> {
+    match backend_type {
+        BackendType::Ollama => Box::new(OllamaParser::with_max_buffer(max_buffer)),
+        BackendType::OpenAI => Box::new(OpenAIParser::with_max_buffer(max_buffer)),
+        BackendType::Anthropic => Box::new(AnthropicParser::with_max_buffer(max_buffer)),
+        BackendType::Bedrock => Box::new(BedrockParser::with_max_buffer(max_buffer)),
+        BackendType::Cohere => Box::new(CohereParser::with_max_buffer(max_buffer)),
+        BackendType::Unknown => Box::new(PassthroughParser),
+    }
+}
+
+/// Parses a complete, already-buffered response body and returns its token usage
+///
+/// Intended for offline analysis of archived responses: the whole body is fed to
+/// the backend's parser as a single chunk and then finalized, keeping its text.
+pub async fn parse_response(backend_type: BackendType, body: &[u8]) -> TokenUsage {
+    let mut parser = create_parser(backend_type);
+    parser.capture_completion_text(body.len());
+    parser.feed_chunk(&Bytes::copy_from_slice(body)).await;
+    parser.finalize().await
+}
+
 /// Detected backend type based on content-type
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum BackendType {
-    Ollama,  // application/x-ndjson
-    OpenAI,  // text/event-stream
+    Ollama,    // application/x-ndjson, /api/generate, /api/chat
+    OpenAI,    // text/event-stream, /v1/chat/completions, /v1/completions, /v1/embeddings
+    Anthropic, // /v1/messages
+    Bedrock,   // application/vnd.amazon.eventstream
+    Cohere,    // application/stream+json
+    #[default]
+    Unknown,
+}
+
+/// Which prompt field the request body used, as a last-resort hint at the API
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RequestShape {
+    /// Chat-style `messages` array
+    Messages,
+    /// Completion-style `prompt` string
+    Prompt,
+    #[default]
     Unknown,
 }
 
+/// Everything known about an exchange that can identify its backend
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DetectionHints<'a> {
+    /// Parser forced for this upstream in config
+    pub forced: Option<BackendType>,
+    /// Request path as sent upstream
+    pub path: &'a str,
+    /// Response content-type
+    pub content_type: &'a str,
+    /// Configured parser for responses nothing else identifies
+    pub default: Option<BackendType>,
+    /// Shape of the request body
+    pub request_shape: RequestShape,
+}
+
+/// Picks the parser for an exchange
+///
+/// Sources are tried in order of reliability: explicit per-backend config, the
+/// request path, the response content-type, the configured default, and
+/// finally the request body's shape.
+pub fn detect_backend(hints: &DetectionHints) -> BackendType {
+    if let Some(forced) = hints.forced {
+        return forced;
+    }
+    let detected = match detect_backend_from_path(hints.path) {
+        BackendType::Unknown => detect_backend_type(hints.content_type),
+        detected => detected,
+    };
+    match (detected, hints.default, hints.request_shape) {
+        (BackendType::Unknown, Some(default), _) => default,
+        (BackendType::Unknown, None, RequestShape::Messages) => BackendType::OpenAI,
+        (BackendType::Unknown, None, RequestShape::Prompt) => BackendType::Ollama,
+        (detected, _, _) => detected,
+    }
+}
+
+/// Detect backend type from well-known API paths
+///
+/// Matches on the path's suffix so gateway prefixes (`/openai/v1/...`,
+/// `/openai/deployments/{name}/...`) are still recognized.
+pub fn detect_backend_from_path(path: &str) -> BackendType {
+    let path = path.split('?').next().unwrap_or(path).trim_end_matches('/');
+    if path.ends_with("/api/generate") || path.ends_with("/api/chat") {
+        BackendType::Ollama
+    } else if path.ends_with("/v1/messages") {
+        BackendType::Anthropic
+    } else if path.ends_with("/chat/completions") || path.ends_with("/v1/completions") || path.ends_with("/embeddings") {
+        BackendType::OpenAI
+    } else if path.ends_with("/invoke-with-response-stream") || path.ends_with("/converse-stream") {
+        BackendType::Bedrock
+    } else {
+        BackendType::Unknown
+    }
+}
+
 /// Detect backend type from content-type header
 pub fn detect_backend_type(content_type: &str) -> BackendType {
     if content_type.contains("application/x-ndjson") || content_type.contains("application/json") {
         BackendType::Ollama
     } else if content_type.contains("text/event-stream") {
         BackendType::OpenAI
+    } else if content_type.contains("application/vnd.amazon.eventstream") {
+        BackendType::Bedrock
+    } else if content_type.contains("application/stream+json") {
+        BackendType::Cohere
     } else {
         BackendType::Unknown
     }
@@ -1,55 +1,128 @@
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
 
-use crate::parsers::BackendStreamParser;
+use crate::parsers::{BackendStreamParser, DEFAULT_MAX_BUFFER_BYTES};
 use crate::types::{OllamaStreamResponse, TokenUsage};
 
 /// Parser for Ollama's NDJSON streaming format
 pub struct OllamaParser {
     buffer: BytesMut,
+    /// Bytes at the front of the buffer already known to contain no newline
+    scanned: usize,
+    max_buffer: usize,
     token_usage: TokenUsage,
+    /// Most bytes of generated text kept in `completion_text` (0 keeps none)
+    text_limit: usize,
+    saw_content: bool,
 }
 
 impl OllamaParser {
     pub fn new() -> Self {
+        Self::with_max_buffer(DEFAULT_MAX_BUFFER_BYTES)
+    }
+
+    /// Creates a parser that drops any partial line longer than `max_buffer` bytes
+    pub fn with_max_buffer(max_buffer: usize) -> Self {
         Self {
             buffer: BytesMut::new(),
+            scanned: 0,
+            max_buffer,
             token_usage: TokenUsage::default(),
+            text_limit: 0,
+            saw_content: false,
         }
     }
 
+    /// Record the token counts and timings carried by the final `done` object
+    fn apply_final(&mut self, response: &OllamaStreamResponse) {
+        self.token_usage.saw_terminal = true;
+        if response.done_reason.is_some() {
+            self.token_usage.finish_reason = response.done_reason.clone();
+        }
+        if response.prompt_eval_count.is_some() {
+            self.token_usage.prompt_tokens = response.prompt_eval_count;
+        }
+        if response.eval_count.is_some() {
+            self.token_usage.completion_tokens = response.eval_count;
+        }
+
+        let usage = &mut self.token_usage;
+        usage.total_duration_ms = response.total_duration.map(nanos_to_ms).or(usage.total_duration_ms);
+        usage.load_duration_ms = response.load_duration.map(nanos_to_ms).or(usage.load_duration_ms);
+        usage.prompt_eval_duration_ms = response
+            .prompt_eval_duration
+            .map(nanos_to_ms)
+            .or(usage.prompt_eval_duration_ms);
+        usage.eval_duration_ms = response.eval_duration.map(nanos_to_ms).or(usage.eval_duration_ms);
+
+        tracing::debug!(
+            "Ollama timings: total_ms={:?}, load_ms={:?}, prompt_eval_ms={:?}, eval_ms={:?}",
+            usage.total_duration_ms,
+            usage.load_duration_ms,
+            usage.prompt_eval_duration_ms,
+            usage.eval_duration_ms
+        );
+    }
+
     /// Process complete lines from the buffer
     fn process_lines(&mut self) {
-        while let Some(newline_pos) = self.buffer.iter().position(|&b| b == b'\n') {
+        while let Some(offset) = self.buffer[self.scanned..].iter().position(|&b| b == b'\n') {
             // Extract the line
-            let line = self.buffer.split_to(newline_pos + 1);
+            let line = self.buffer.split_to(self.scanned + offset + 1);
+            self.scanned = 0;
 
-            // Skip empty lines
-            if line.trim_ascii().is_empty() {
+            // Treat CRLF like LF and skip empty lines
+            let line = line.trim_ascii();
+            if line.is_empty() {
                 continue;
             }
 
             // Try to parse as JSON
-            if let Ok(response) = serde_json::from_slice::<OllamaStreamResponse>(&line) {
+            if let Ok(response) = serde_json::from_slice::<OllamaStreamResponse>(line) {
+                self.token_usage.event_count += 1;
                 tracing::debug!("Parsed Ollama response: done={}, prompt_eval_count={:?}, eval_count={:?}",
                     response.done,
                     response.prompt_eval_count,
                     response.eval_count
                 );
+                self.token_usage.record_response_model(response.model.as_deref());
+
+                if response.has_content() {
+                    self.saw_content = true;
+                    self.token_usage.push_completion_text(response.text(), self.text_limit);
+                }
 
                 // If this is the final response with the "done" flag, extract token counts
                 if response.done {
-                    if response.prompt_eval_count.is_some() {
-                        self.token_usage.prompt_tokens = response.prompt_eval_count;
-                    }
-                    if response.eval_count.is_some() {
-                        self.token_usage.completion_tokens = response.eval_count;
-                    }
+                    self.apply_final(&response);
                 }
             } else {
-                tracing::debug!("Failed to parse Ollama JSON line: {:?}", String::from_utf8_lossy(&line));
+                tracing::debug!("Failed to parse Ollama JSON line: {:?}", String::from_utf8_lossy(line));
             }
         }
+        self.scanned = self.buffer.len();
+
+        // A line that never ends would otherwise grow the buffer without limit
+        if self.buffer.len() > self.max_buffer {
+            tracing::warn!(
+                "Ollama line exceeded {} bytes without a newline, discarding it",
+                self.max_buffer
+            );
+            self.buffer.clear();
+            self.scanned = 0;
+            self.token_usage.parse_truncated = true;
+        }
+    }
+}
+
+/// Ollama reports durations in nanoseconds
+fn nanos_to_ms(nanos: u64) -> u64 {
+    nanos / 1_000_000
+}
+
+impl Default for OllamaParser {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -63,18 +136,23 @@ impl BackendStreamParser for OllamaParser {
         self.process_lines();
     }
 
+    fn saw_content(&self) -> bool {
+        self.saw_content
+    }
+
+    fn capture_completion_text(&mut self, max_bytes: usize) {
+        self.text_limit = max_bytes;
+    }
+
     async fn finalize(mut self: Box<Self>) -> TokenUsage {
         // Process any remaining data in the buffer
         if !self.buffer.is_empty() {
             // Try to parse the remaining buffer as a final JSON object
             if let Ok(response) = serde_json::from_slice::<OllamaStreamResponse>(&self.buffer) {
+                self.token_usage.event_count += 1;
+                self.token_usage.record_response_model(response.model.as_deref());
                 if response.done {
-                    if response.prompt_eval_count.is_some() {
-                        self.token_usage.prompt_tokens = response.prompt_eval_count;
-                    }
-                    if response.eval_count.is_some() {
-                        self.token_usage.completion_tokens = response.eval_count;
-                    }
+                    self.apply_final(&response);
                 }
             }
         }
@@ -1,91 +1,195 @@
 use async_trait::async_trait;
-use bytes::{Bytes, BytesMut};
+use bytes::Bytes;
 
-use crate::parsers::BackendStreamParser;
-use crate::types::{OpenAIResponse, TokenUsage};
+use crate::parsers::sse::{SseEvent, SseScanner};
+use crate::parsers::{BackendStreamParser, DEFAULT_MAX_BUFFER_BYTES};
+use crate::types::{OpenAIPayload, OpenAIResponse, OpenAIResponsesEvent, TokenUsage};
 
 /// Parser for OpenAI-compatible SSE (Server-Sent Events) format
 pub struct OpenAIParser {
-    buffer: BytesMut,
+    scanner: SseScanner,
+    max_buffer: usize,
     token_usage: TokenUsage,
+    /// Most bytes of generated text kept in `completion_text` (0 keeps none)
+    text_limit: usize,
+    saw_content: bool,
 }
 
 impl OpenAIParser {
     pub fn new() -> Self {
+        Self::with_max_buffer(DEFAULT_MAX_BUFFER_BYTES)
+    }
+
+    /// Creates a parser that drops any partial event longer than `max_buffer` bytes
+    pub fn with_max_buffer(max_buffer: usize) -> Self {
         Self {
-            buffer: BytesMut::new(),
+            scanner: SseScanner::new(),
+            max_buffer,
             token_usage: TokenUsage::default(),
+            text_limit: 0,
+            saw_content: false,
+        }
+    }
+
+    /// Record content and usage from a single parsed response object
+    fn handle_response(&mut self, response: OpenAIResponse) {
+        if response.has_content() {
+            self.saw_content = true;
+        }
+        self.token_usage.tool_call_count += response.new_tool_calls();
+        self.token_usage.record_response_model(response.model.as_deref());
+        self.token_usage.logprobs_returned |= response.has_logprobs();
+        if let Some(text) = response.text() {
+            self.token_usage.push_completion_text(text, self.text_limit);
+        }
+        if let Some(reason) = response.finish_reason() {
+            self.token_usage.finish_reason = Some(reason.to_string());
+        }
+
+        if let Some(usage) = response.usage {
+            tracing::debug!(
+                "Parsed OpenAI usage: prompt_tokens={}, completion_tokens={:?}",
+                usage.prompt_tokens,
+                usage.completion_tokens
+            );
+
+            // Usage only arrives once generation is over
+            self.token_usage.saw_terminal = true;
+            self.token_usage.prompt_tokens = Some(usage.prompt_tokens);
+            self.token_usage.completion_tokens = usage.completion_tokens;
+            self.token_usage.total_tokens = usage.total_tokens;
         }
     }
 
-    /// Process SSE events from the buffer
+    /// Process every complete SSE event in the scanner
     fn process_events(&mut self) {
-        // SSE format uses "data: " prefix and "\n\n" as delimiter
-
-        // Look for complete SSE messages (delimited by \n\n)
-        loop {
-            let buffer_str = String::from_utf8_lossy(&self.buffer);
-            let pos = match buffer_str.find("\n\n") {
-                Some(p) => p,
-                None => break,
-            };
-
-            let event_block = self.buffer.split_to(pos + 2);
-            let event_str = String::from_utf8_lossy(&event_block);
-
-            // Process each line in the event block
-            for line in event_str.lines() {
-                let line = line.trim();
-
-                // Skip empty lines and comments
-                if line.is_empty() || line.starts_with(':') {
-                    continue;
-                }
+        while let Some(event) = self.scanner.next_event() {
+            self.handle_event(event);
+        }
 
-                // Check for [DONE] marker
-                if line == "data: [DONE]" {
-                    tracing::debug!("Received [DONE] marker from OpenAI stream");
-                    continue;
-                }
+        // An event that never ends would otherwise grow the buffer without limit
+        if self.scanner.buffered_len() > self.max_buffer {
+            tracing::warn!(
+                "SSE event exceeded {} bytes without a delimiter, discarding it",
+                self.max_buffer
+            );
+            self.scanner.clear();
+            self.token_usage.parse_truncated = true;
+        }
+    }
+
+    /// Process a single SSE event, dispatching on its `event:` name
+    ///
+    /// Chat and legacy completions send unnamed events; the Responses API names
+    /// each one (`response.output_text.delta`, `response.completed`, ...). Names
+    /// this parser doesn't know are read as completion chunks, as before.
+    fn handle_event(&mut self, event: SseEvent) {
+        self.token_usage.event_count += 1;
+
+        match event.event.as_deref() {
+            Some("error") => tracing::warn!("OpenAI stream reported an error: {}", event.data),
+            Some(name) if name.starts_with("response.") => self.handle_responses_event(name, &event.data),
+            _ => self.handle_chunk(&event.data),
+        }
+    }
+
+    /// Process the data of an unnamed (chat or legacy completions) event
+    fn handle_chunk(&mut self, data: &str) {
+        // [DONE] only marks the end of content; a usage chunk may still follow it
+        if data == "[DONE]" {
+            tracing::debug!("Received [DONE] marker from OpenAI stream");
+            self.token_usage.saw_terminal = true;
+            return;
+        }
+
+        // Try to parse as JSON; some gateways wrap the object in an array
+        if let Ok(payload) = serde_json::from_str::<OpenAIPayload>(data) {
+            for response in payload.into_responses() {
+                self.handle_response(response);
+            }
+        } else {
+            // This is a normal delta chunk without usage info
+            tracing::trace!("Parsed OpenAI delta chunk (no usage info)");
+        }
+    }
 
-                // Parse data: prefix
-                if let Some(data) = line.strip_prefix("data: ") {
-                    // Try to parse as JSON
-                    if let Ok(response) = serde_json::from_str::<OpenAIResponse>(data) {
-                        if let Some(usage) = response.usage {
-                            tracing::debug!(
-                                "Parsed OpenAI usage: prompt_tokens={}, completion_tokens={}",
-                                usage.prompt_tokens,
-                                usage.completion_tokens
-                            );
-
-                            self.token_usage.prompt_tokens = Some(usage.prompt_tokens);
-                            self.token_usage.completion_tokens = Some(usage.completion_tokens);
-                        }
-                    } else {
-                        // This is a normal delta chunk without usage info
-                        tracing::trace!("Parsed OpenAI delta chunk (no usage info)");
-                    }
+    /// Process the data of a Responses API `response.*` event
+    fn handle_responses_event(&mut self, name: &str, data: &str) {
+        let Ok(event) = serde_json::from_str::<OpenAIResponsesEvent>(data) else {
+            tracing::debug!("Failed to parse OpenAI {} event: {:?}", name, data);
+            return;
+        };
+
+        match name {
+            "response.output_text.delta" => {
+                if let Some(text) = event.delta {
+                    self.saw_content |= !text.is_empty();
+                    self.token_usage.push_completion_text(&text, self.text_limit);
+                }
+            }
+            "response.output_item.added" if event.item.is_some_and(|item| item.item_type == "function_call") => {
+                self.token_usage.tool_call_count += 1;
+                self.saw_content = true;
+            }
+            "response.created" => {
+                if let Some(response) = event.response {
+                    self.token_usage.record_response_model(response.model.as_deref());
+                }
+            }
+            "response.completed" | "response.incomplete" | "response.failed" => {
+                self.token_usage.saw_terminal = true;
+                let Some(response) = event.response else { return };
+                self.token_usage.record_response_model(response.model.as_deref());
+                self.token_usage.finish_reason = response.status;
+                if let Some(usage) = response.usage {
+                    tracing::debug!(
+                        "Parsed OpenAI Responses usage: input_tokens={:?}, output_tokens={:?}",
+                        usage.input_tokens,
+                        usage.output_tokens
+                    );
+                    self.token_usage.prompt_tokens = usage.input_tokens;
+                    self.token_usage.completion_tokens = usage.output_tokens;
+                    self.token_usage.total_tokens = usage.total_tokens;
                 }
             }
+            _ => {}
         }
     }
 }
 
+impl Default for OpenAIParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[async_trait]
 impl BackendStreamParser for OpenAIParser {
     async fn feed_chunk(&mut self, chunk: &Bytes) {
-        // Append chunk to buffer
-        self.buffer.extend_from_slice(chunk);
+        // Append chunk to the scanner
+        self.scanner.push(chunk);
 
         // Process any complete events
         self.process_events();
     }
 
+    fn saw_content(&self) -> bool {
+        self.saw_content
+    }
+
+    fn capture_completion_text(&mut self, max_bytes: usize) {
+        self.text_limit = max_bytes;
+    }
+
     async fn finalize(mut self: Box<Self>) -> TokenUsage {
         // Process any remaining data in the buffer
         self.process_events();
 
+        // The stream may end without the blank line that terminates the last event
+        if let Some(event) = self.scanner.finish() {
+            self.handle_event(event);
+        }
+
         self.token_usage
     }
 }
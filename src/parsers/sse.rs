@@ -0,0 +1,159 @@
+use bytes::BytesMut;
+
+/// A single dispatched Server-Sent Event
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SseEvent {
+    /// Value of the `event:` field, if the block set one
+    pub event: Option<String>,
+    /// All `data:` lines of the block joined with newlines
+    pub data: String,
+    /// Value of the `id:` field, if the block set one
+    pub id: Option<String>,
+    /// Reconnection delay from the `retry:` field, in milliseconds
+    pub retry: Option<u64>,
+}
+
+/// Incremental SSE scanner shared by the SSE-based parsers
+///
+/// Bytes are pushed in as they arrive and complete events are pulled out; a
+/// partial event stays buffered until its terminating blank line shows up.
+/// Boundaries are found on raw bytes and only complete blocks are decoded, so a
+/// multi-byte character split across chunks is never cut in half.
+/// Blocks without any `data:` line (comments, keep-alives, bare `retry:`) are
+/// not dispatched, per the SSE spec.
+///
+/// Each buffered byte is scanned for a delimiter once, however the stream is
+/// chunked, and only the `data:` payload of a block is decoded into a String.
+///
+/// A body that opens with `{` or `[` is a non-streaming JSON response rather
+/// than SSE; it is held whole and `finish` returns it as one event's data.
+#[derive(Debug, Default)]
+pub struct SseScanner {
+    buffer: BytesMut,
+    /// Start of the first line not yet known to be complete
+    scan_from: usize,
+    /// Whether the body is plain JSON, once its first non-blank byte has arrived
+    json_body: Option<bool>,
+}
+
+impl SseScanner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends raw bytes from the stream
+    pub fn push(&mut self, chunk: &[u8]) {
+        if self.json_body.is_none() {
+            self.json_body = chunk
+                .iter()
+                .find(|b| !b.is_ascii_whitespace())
+                .map(|&b| b == b'{' || b == b'[');
+        }
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    /// Bytes of the incomplete event currently held
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Discards the incomplete event
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.scan_from = 0;
+    }
+
+    /// Returns the next complete event, if one is buffered
+    pub fn next_event(&mut self) -> Option<SseEvent> {
+        if self.json_body == Some(true) {
+            return None;
+        }
+        loop {
+            let end = self.find_event_end()?;
+            let block = self.buffer.split_to(end);
+            self.scan_from = 0;
+            if let Some(event) = parse_block(&block) {
+                return Some(event);
+            }
+        }
+    }
+
+    /// Parses whatever is left once the stream has ended
+    ///
+    /// Streams may close without the blank line that terminates the last event.
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        self.scan_from = 0;
+        let block = self.buffer.split();
+        if self.json_body == Some(true) {
+            return Some(SseEvent {
+                data: String::from_utf8_lossy(block.trim_ascii()).into_owned(),
+                ..SseEvent::default()
+            });
+        }
+        parse_block(&block)
+    }
+
+    /// Returns the offset just past the blank line that ends the first event
+    ///
+    /// `\n` and `\r\n` line endings are treated the same, in any mix. Lines
+    /// already scanned are skipped, so scanning resumes where it left off.
+    fn find_event_end(&mut self) -> Option<usize> {
+        while let Some(offset) = memchr::memchr(b'\n', &self.buffer[self.scan_from..]) {
+            let line_end = self.scan_from + offset;
+            let line = &self.buffer[self.scan_from..line_end];
+            self.scan_from = line_end + 1;
+            if line.is_empty() || line == b"\r" {
+                return Some(line_end + 1);
+            }
+        }
+        None
+    }
+}
+
+/// Parses the fields of one event block, returning None if it carried no data
+fn parse_block(block: &[u8]) -> Option<SseEvent> {
+    let mut event = SseEvent::default();
+    let mut data_lines: Vec<&[u8]> = Vec::new();
+
+    for line in block.split(|&b| b == b'\n') {
+        let line = line.trim_ascii();
+
+        // Skip empty lines and comments
+        if line.is_empty() || line[0] == b':' {
+            continue;
+        }
+
+        // A line without a colon is a field name with an empty value; the space
+        // after the colon is optional
+        let (field, value) = match memchr::memchr(b':', line) {
+            Some(colon) => {
+                let value = &line[colon + 1..];
+                (&line[..colon], value.strip_prefix(b" ").unwrap_or(value))
+            }
+            None => (line, &[][..]),
+        };
+
+        match field {
+            b"data" => data_lines.push(value),
+            b"event" => event.event = Some(String::from_utf8_lossy(value).into_owned()),
+            // Ids containing NUL are ignored per the spec
+            b"id" if !value.contains(&0) => event.id = Some(String::from_utf8_lossy(value).into_owned()),
+            b"retry" => {
+                if let Some(retry) = std::str::from_utf8(value).ok().and_then(|v| v.parse().ok()) {
+                    event.retry = Some(retry);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    event.data = match data_lines.as_slice() {
+        [] => return None,
+        [data] => String::from_utf8_lossy(data).into_owned(),
+        lines => String::from_utf8_lossy(&lines.join(&b'\n')).into_owned(),
+    };
+    Some(event)
+}
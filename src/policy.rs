@@ -0,0 +1,39 @@
+use serde::Deserialize;
+
+/// Which models clients may request
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ModelPolicyConfig {
+    /// Models that are always rejected
+    pub deny: Vec<String>,
+    /// When set, only these models are permitted
+    pub allow: Option<Vec<String>>,
+}
+
+impl ModelPolicyConfig {
+    /// Returns the reason a model is refused, or None if it may be proxied
+    ///
+    /// Names are compared case-insensitively; the denylist wins over the allowlist.
+    pub fn check(&self, model: &str) -> Option<String> {
+        let listed = |names: &[String]| names.iter().any(|n| n.eq_ignore_ascii_case(model));
+
+        if listed(&self.deny) {
+            return Some(format!("Model '{}' is not permitted by this proxy", model));
+        }
+        match &self.allow {
+            Some(allow) if !listed(allow) => {
+                Some(format!("Model '{}' is not on this proxy's allowlist", model))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the reason a request body whose model couldn't be read is refused
+    ///
+    /// Only an allowlist refuses these; a denylist can't match a name it never sees.
+    pub fn check_unknown(&self) -> Option<String> {
+        self.allow
+            .as_ref()
+            .map(|_| "Request does not name a model on this proxy's allowlist".to_string())
+    }
+}
@@ -0,0 +1,153 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::parsers::BackendType;
+use crate::types::TokenUsage;
+
+/// Anthropic bills prompt-cache writes at 1.25x and reads at 0.1x the input rate
+pub const CACHE_WRITE_MULTIPLIER: f64 = 1.25;
+pub const CACHE_READ_MULTIPLIER: f64 = 0.1;
+
+/// List prices in USD per million tokens when this table was written; override
+/// them under `[pricing.models]` as providers change their rates
+const BUILTIN_PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4o*", 2.50, 10.00),
+    ("gpt-4o-mini*", 0.15, 0.60),
+    ("gpt-4-turbo*", 10.00, 30.00),
+    ("gpt-4*", 30.00, 60.00),
+    ("gpt-3.5-turbo*", 0.50, 1.50),
+    ("o1*", 15.00, 60.00),
+    ("o1-mini*", 3.00, 12.00),
+    ("o3-mini*", 1.10, 4.40),
+    ("claude-3-opus*", 15.00, 75.00),
+    ("claude-3-sonnet*", 3.00, 15.00),
+    ("claude-3-haiku*", 0.25, 1.25),
+    ("claude-3-5-sonnet*", 3.00, 15.00),
+    ("claude-3-5-haiku*", 0.80, 4.00),
+    ("claude-3-7-sonnet*", 3.00, 15.00),
+    ("claude-sonnet-4*", 3.00, 15.00),
+    ("claude-opus-4*", 15.00, 75.00),
+];
+
+/// Price of one model in USD per million tokens
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+    /// Prompt-cache write rate; defaults to `CACHE_WRITE_MULTIPLIER` times `input`
+    #[serde(default)]
+    pub cache_write: Option<f64>,
+    /// Prompt-cache read rate; defaults to `CACHE_READ_MULTIPLIER` times `input`
+    #[serde(default)]
+    pub cache_read: Option<f64>,
+}
+
+impl ModelPrice {
+    pub fn new(input: f64, output: f64) -> Self {
+        Self {
+            input,
+            output,
+            cache_write: None,
+            cache_read: None,
+        }
+    }
+
+    /// Cost of one request, or None when the upstream reported no token counts
+    pub fn cost(&self, usage: &TokenUsage) -> Option<f64> {
+        if usage.prompt_tokens.is_none() && usage.completion_tokens.is_none() {
+            return None;
+        }
+        let tokens = |count: Option<u32>| f64::from(count.unwrap_or(0));
+        let cache_write = self.cache_write.unwrap_or(self.input * CACHE_WRITE_MULTIPLIER);
+        let cache_read = self.cache_read.unwrap_or(self.input * CACHE_READ_MULTIPLIER);
+
+        let micro_dollars = tokens(usage.prompt_tokens) * self.input
+            + tokens(usage.completion_tokens) * self.output
+            + tokens(usage.cache_write_tokens) * cache_write
+            + tokens(usage.cache_read_tokens) * cache_read;
+        Some(micro_dollars / 1_000_000.0)
+    }
+}
+
+/// Pricing settings
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct PricingConfig {
+    /// Include the built-in table of hosted model prices
+    pub builtin: bool,
+    /// Prices keyed by model name or `prefix*` pattern; replaces a built-in entry with the same pattern
+    pub models: HashMap<String, ModelPrice>,
+    /// Price for any model of a backend no pattern matches, e.g. $0 for local Ollama
+    pub backends: HashMap<BackendType, ModelPrice>,
+}
+
+impl Default for PricingConfig {
+    fn default() -> Self {
+        Self {
+            builtin: true,
+            models: HashMap::new(),
+            backends: HashMap::new(),
+        }
+    }
+}
+
+/// Looks up model prices and computes per-request cost
+pub struct Pricing {
+    /// Lowercase patterns: an exact name, or a prefix when `is_prefix` is set
+    patterns: Vec<(String, bool, ModelPrice)>,
+    backends: HashMap<BackendType, ModelPrice>,
+}
+
+impl Pricing {
+    pub fn new(config: &PricingConfig) -> Self {
+        let mut merged: HashMap<String, ModelPrice> = HashMap::new();
+        if config.builtin {
+            for &(pattern, input, output) in BUILTIN_PRICES {
+                merged.insert(pattern.to_string(), ModelPrice::new(input, output));
+            }
+        }
+        for (pattern, price) in &config.models {
+            merged.insert(pattern.to_ascii_lowercase(), *price);
+        }
+
+        let patterns = merged
+            .into_iter()
+            .map(|(pattern, price)| match pattern.strip_suffix('*') {
+                Some(prefix) => (prefix.to_string(), true, price),
+                None => (pattern, false, price),
+            })
+            .collect();
+        Self {
+            patterns,
+            backends: config.backends.clone(),
+        }
+    }
+
+    /// The most specific price for `model`: an exact name beats any prefix, and longer prefixes beat shorter
+    pub fn price_for(&self, model: &str) -> Option<&ModelPrice> {
+        let model = model.to_ascii_lowercase();
+        self.patterns
+            .iter()
+            .filter(|(pattern, is_prefix, _)| {
+                if *is_prefix {
+                    model.starts_with(pattern.as_str())
+                } else {
+                    model == *pattern
+                }
+            })
+            .max_by_key(|(pattern, is_prefix, _)| (!is_prefix, pattern.len()))
+            .map(|(_, _, price)| price)
+    }
+
+    /// Cost of a request in USD, or None for unpriced models
+    ///
+    /// The model the upstream reported is tried first, since it names the exact
+    /// snapshot, then the requested model, then the backend's catch-all price.
+    pub fn cost(&self, backend: BackendType, models: &[&str], usage: &TokenUsage) -> Option<f64> {
+        models
+            .iter()
+            .find_map(|model| self.price_for(model))
+            .or_else(|| self.backends.get(&backend))
+            .and_then(|price| price.cost(usage))
+    }
+}
@@ -3,148 +3,691 @@ use axum::{
     extract::{Path, Request, State},
     response::{IntoResponse, Response},
 };
-use bytes::Bytes;
+use bytes::{Buf, Bytes, BytesMut};
 use http_body_util::{BodyExt, StreamBody};
-use hyper::StatusCode;
-use std::sync::Arc;
+use hyper::body::Frame;
+use hyper::{StatusCode, Version};
+use std::collections::BTreeMap;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
-use tokio_stream::StreamExt;
+use tracing::Instrument;
 
-use crate::parsers::{detect_backend_type, BackendStreamParser, BackendType};
-use crate::types::{LLMMetrics, RequestData};
-
-type HttpClient = hyper_util::client::legacy::Client<
-    hyper_util::client::legacy::connect::HttpConnector,
-    Body,
->;
+use crate::app::AppState;
+use crate::balancer::InFlight;
+use crate::config::{ExpectContinueMode, PromptCapture, SlowClientPolicy};
+use crate::debug::INJECT_DELAY_HEADER;
+use crate::encoding::{force_identity_encoding, ParserDecoder};
+use crate::headers::{is_websocket_upgrade, trailer_error, trailer_fields, REQUEST_ID_HEADER};
+use crate::limiter::{Permits, Priority};
+use crate::parsers::{
+    create_parser_with_max_buffer, detect_backend, detect_backend_type, BackendStreamParser, BackendType,
+    DetectionHints, FallbackParser, RequestShape,
+};
+use crate::quality::CompletionQuality;
+use crate::text;
+use crate::timing::{compute_throughput, ChunkGapStats, TimingMarks, Timings};
+use crate::types::{LLMMetrics, RequestData, TokenUsage, SCHEMA_VERSION};
 
 /// Main proxy handler that routes to different backends
+///
+/// The first path segment is an upstream port or the name of a configured pool.
 pub async fn proxy_handler(
-    State(client): State<Arc<HttpClient>>,
-    Path((backend_port, path)): Path<(u16, String)>,
+    State(state): State<AppState>,
+    Path((backend, path)): Path<(String, String)>,
     req: Request,
 ) -> Response {
     // Start latency timer
     let start_time = tokio::time::Instant::now();
+    let path = format!("/{}", path.trim_start_matches('/'));
 
     // Extract request data from extensions (added by middleware)
     let request_data = req.extensions().get::<RequestData>().cloned();
 
-    // Construct the upstream URI
-    let upstream_uri = format!("http://127.0.0.1:{}/{}", backend_port, path.trim_start_matches('/'));
-
-    // Add query string if present
-    let upstream_uri = if let Some(query) = req.uri().query() {
-        format!("{}?{}", upstream_uri, query)
-    } else {
-        upstream_uri
+    // Resolve the route to upstream ports, in the order they are tried
+    let (pool, ports) = match backend.parse::<u16>() {
+        Ok(port) => (None, vec![port]),
+        Err(_) => match state.balancer.candidates(&backend) {
+            Some(ports) => (Some(backend.as_str()), ports),
+            None => {
+                let error = format!("Unknown backend: {}", backend);
+                let failure = UpstreamFailure::new(StatusCode::NOT_FOUND, error, 0, &path);
+                return reject(&state, request_data, failure).await;
+            }
+        },
     };
+    let first_port = ports.first().copied().unwrap_or_default();
 
-    tracing::debug!("Proxying request to: {}", upstream_uri);
+    // Wait for a concurrency slot; it is held until the response finishes streaming.
+    // It is taken before the circuit breakers so a queue rejection never claims a probe.
+    let priority = Priority::from_header(
+        req.headers()
+            .get(state.config.concurrency.priority_header.as_str())
+            .and_then(|v| v.to_str().ok()),
+    );
+    let model = request_data.as_ref().map_or("", |r| r.model.as_str());
+    let Some(permit) = state.limiters.acquire(model, priority).await else {
+        tracing::warn!("Concurrency queue full for {}, rejecting request", model);
+        let error = "Too many concurrent requests";
+        let failure = UpstreamFailure::new(StatusCode::SERVICE_UNAVAILABLE, error, first_port, &path);
+        return reject(&state, request_data, failure).await;
+    };
 
-    // Parse the URI
-    let uri = match upstream_uri.parse::<hyper::Uri>() {
-        Ok(u) => u,
-        Err(e) => {
-            tracing::error!("Failed to parse upstream URI: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Invalid upstream URI").into_response();
+    // Fail fast while every upstream's circuit breaker is open; later candidates are
+    // only checked if the request fails over to them
+    let mut candidates = ports.into_iter().filter(|&port| {
+        let target = upstream_target(port);
+        let acquired = state.breakers.try_acquire(&target);
+        if !acquired {
+            tracing::warn!("Circuit breaker open for {}, rejecting request", target);
         }
+        acquired
+    });
+    let Some(mut backend_port) = candidates.next() else {
+        let error = "Upstream circuit breaker open";
+        let failure = UpstreamFailure::new(StatusCode::SERVICE_UNAVAILABLE, error, first_port, &path);
+        return reject(&state, request_data, failure).await;
     };
+    let query = req.uri().query().map(str::to_string);
+
+    // Debug-only artificial latency, applied once the upstream has answered
+    let injected_delay = state.config.debug.injected_delay(
+        req.headers()
+            .get(INJECT_DELAY_HEADER)
+            .and_then(|v| v.to_str().ok()),
+    );
 
-    // Build the upstream request
     let (mut parts, body) = req.into_parts();
-    parts.uri = uri;
 
     // Remove host header to avoid conflicts
     parts.headers.remove("host");
+    parts.headers.remove(INJECT_DELAY_HEADER);
+    parts.headers.remove(state.config.concurrency.priority_header.as_str());
 
-    let upstream_request = hyper::Request::from_parts(parts, body);
+    // Drop hop-by-hop and denylisted headers; provider headers are protected
+    let websocket = is_websocket_upgrade(&parts.method, &parts.headers);
+    state.config.headers.prepare_upstream(&mut parts.headers);
 
-    // Send request to upstream
-    let upstream_response = match client.request(upstream_request).await {
-        Ok(resp) => resp,
-        Err(e) => {
-            tracing::error!("Failed to proxy request: {}", e);
-            return (StatusCode::BAD_GATEWAY, format!("Upstream error: {}", e)).into_response();
+    if websocket {
+        let target = upstream_target(backend_port);
+        parts.uri = match upstream_uri(&target, &path, query.as_deref()).parse() {
+            Ok(uri) => uri,
+            Err(e) => {
+                tracing::error!("Failed to parse upstream URI: {}", e);
+                let error = "Invalid upstream URI";
+                let failure = UpstreamFailure::new(StatusCode::INTERNAL_SERVER_ERROR, error, backend_port, &path);
+                return reject(&state, request_data, failure).await;
+            }
+        };
+        if let Some(backend) = state.config.backend(backend_port) {
+            backend.header_rules.apply(&mut parts.headers);
+        }
+        let session = WebSocketSession {
+            backend_port,
+            path: &path,
+            request_data,
+            start_time,
+        };
+        return proxy_websocket(&state, &target, parts, body, permit, session).await;
+    }
+
+    // The middleware already answered any 100-continue handshake and holds the full body
+    if state.config.expect_continue == ExpectContinueMode::Strip {
+        parts.headers.remove(hyper::header::EXPECT);
+    }
+
+    // Pool requests keep their body so it can be resent to the next upstream, HTTP/2
+    // requests so it can be resent over HTTP/1.1, and any request that may be retried
+    let resendable = pool.is_some() || state.config.http2(backend_port) || state.config.retry.enabled();
+    let (mut body, replay) = if resendable {
+        match body.collect().await {
+            Ok(collected) => (None, Some(collected.to_bytes())),
+            Err(e) => {
+                tracing::error!("Failed to read request body: {}", e);
+                let error = "Failed to read request body";
+                let failure = UpstreamFailure::new(StatusCode::BAD_REQUEST, error, backend_port, &path);
+                return reject(&state, request_data, failure).await;
+            }
+        }
+    } else {
+        (Some(body), None)
+    };
+
+    // Send request to upstream, giving up at the deadline
+    let deadline = match state.config.upstream_timeout_ms {
+        0 => None,
+        ms => Some(tokio::time::Instant::now() + std::time::Duration::from_millis(ms)),
+    };
+    let mut http1_fallback = false;
+    // Failovers and retries each count as an attempt; falling back to HTTP/1.1 doesn't
+    let mut attempts: u8 = 1;
+    let (upstream_response, target, upstream_url, in_flight, sent_at) = loop {
+        let target = upstream_target(backend_port);
+        let http2 = state.config.http2(backend_port)
+            && !http1_fallback
+            && !state.http1_upstreams.read().unwrap_or_else(|e| e.into_inner()).contains(&target);
+        let in_flight = pool.and_then(|name| state.balancer.track(name, backend_port));
+        let upstream_url = format!("http://{}{}", target, path);
+        let upstream_uri = upstream_uri(&target, &path, query.as_deref());
+        tracing::debug!("Proxying request to: {}", upstream_uri);
+
+        let uri = match upstream_uri.parse::<hyper::Uri>() {
+            Ok(u) => u,
+            Err(e) => {
+                tracing::error!("Failed to parse upstream URI: {}", e);
+                let error = "Invalid upstream URI";
+                let failure = UpstreamFailure::new(StatusCode::INTERNAL_SERVER_ERROR, error, backend_port, &path);
+                return reject(&state, request_data, failure).await;
+            }
+        };
+
+        let mut headers = parts.headers.clone();
+        let backend = state.config.backend(backend_port);
+        if let Some(backend) = backend {
+            backend.header_rules.apply(&mut headers);
+        }
+
+        // Upstreams are on loopback, where compression buys nothing and complicates parsing
+        if backend.and_then(|b| b.strip_accept_encoding).unwrap_or(true) {
+            force_identity_encoding(&mut headers);
+        }
+
+        let body = match &replay {
+            Some(bytes) => Body::from(bytes.clone()),
+            None => body.take().unwrap_or_else(Body::empty),
+        };
+        let mut upstream_request = hyper::Request::new(body);
+        *upstream_request.method_mut() = parts.method.clone();
+        *upstream_request.uri_mut() = uri;
+        *upstream_request.version_mut() = if http2 { Version::HTTP_2 } else { parts.version };
+        *upstream_request.headers_mut() = headers;
+
+        let client = if http2 { &state.h2_client } else { &state.client };
+        let sent_at = start_time.elapsed();
+        let sent = client.request(upstream_request);
+        let upstream_response = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, sent).await,
+            None => Ok(sent.await),
+        };
+        match upstream_response {
+            Ok(Ok(resp)) => {
+                if http1_fallback {
+                    tracing::info!("Upstream {} only speaks HTTP/1.1, no longer trying HTTP/2", target);
+                    state.http1_upstreams.write().unwrap_or_else(|e| e.into_inner()).insert(target.clone());
+                }
+                let status = resp.status();
+                if state.config.retry.retries_status(status) && state.config.retry.allows_another(attempts) {
+                    tracing::warn!("Upstream {} answered {} on attempt {}", target, status, attempts);
+                    if let Some((next, backoff)) = next_attempt(&state, &mut candidates, backend_port, attempts, deadline) {
+                        // A response that is passed on is recorded after the loop
+                        state.breakers.record_status(&target, status.as_u16());
+                        drop(resp);
+                        tokio::time::sleep(backoff).await;
+                        backend_port = next;
+                        attempts = attempts.saturating_add(1);
+                        http1_fallback = false;
+                        continue;
+                    }
+                }
+                break (resp, target, upstream_url, in_flight, sent_at);
+            }
+            Ok(Err(e)) if http2 => {
+                tracing::warn!("HTTP/2 request to {} failed, retrying over HTTP/1.1: {}", target, e);
+                http1_fallback = true;
+            }
+            Ok(Err(e)) => {
+                tracing::error!("Failed to proxy request: {}", e);
+                state.breakers.record_connection_failure(&target);
+                if let Some(next) = candidates.next() {
+                    tracing::warn!("Failing over from {} to port {}", target, next);
+                    backend_port = next;
+                    attempts = attempts.saturating_add(1);
+                    http1_fallback = false;
+                    continue;
+                }
+                if let Some((next, backoff)) = next_attempt(&state, &mut candidates, backend_port, attempts, deadline) {
+                    tokio::time::sleep(backoff).await;
+                    backend_port = next;
+                    attempts = attempts.saturating_add(1);
+                    http1_fallback = false;
+                    continue;
+                }
+                let failure = UpstreamFailure::new(StatusCode::BAD_GATEWAY, e.to_string(), backend_port, &path)
+                    .after_attempts(attempts);
+                return respond_without_upstream(&state, request_data, failure).await;
+            }
+            Err(_) => {
+                let error = format!("no response within {}ms", state.config.upstream_timeout_ms);
+                tracing::warn!("Upstream {} sent {}", target, error);
+                state.breakers.record_connection_failure(&target);
+                let failure = UpstreamFailure::new(StatusCode::GATEWAY_TIMEOUT, error, backend_port, &path)
+                    .after_attempts(attempts);
+                return respond_without_upstream(&state, request_data, failure).await;
+            }
         }
     };
 
     // Extract response parts
     let (parts, body) = upstream_response.into_parts();
+    let connect_time = start_time.elapsed();
+    state.breakers.record_status(&target, parts.status.as_u16());
+    let upstream_request_id = parts
+        .headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
     let content_type = parts
         .headers
         .get("content-type")
         .and_then(|v| v.to_str().ok())
         .unwrap_or("");
 
-    // Detect backend type from content-type
-    let backend_type = detect_backend_type(content_type);
+    // Pick the parser from config, the request path, the content-type, or the body's shape.
+    // Configured fallback parsers try real candidates, so they win over guessing from the body.
+    let request_shape = match &request_data {
+        Some(req_data) if state.config.fallback_parsers.is_empty() => req_data.request_shape,
+        _ => RequestShape::Unknown,
+    };
+    let backend_type = detect_backend(&DetectionHints {
+        forced: state.config.backend(backend_port).and_then(|b| b.backend_type),
+        path: &path,
+        content_type,
+        default: state.config.default_backend_type,
+        request_shape,
+    });
+    // An unrecognized content-type says nothing either way, so trust what the request asked for
+    let requested_stream = request_data.as_ref().is_some_and(|r| r.streamed);
+    let streaming = is_streaming_content_type(content_type)
+        || (detect_backend_type(content_type) == BackendType::Unknown && requested_stream);
+    let decoder = ParserDecoder::for_encoding(
+        parts
+            .headers
+            .get(hyper::header::CONTENT_ENCODING)
+            .and_then(|v| v.to_str().ok()),
+    );
 
     tracing::debug!("Detected backend type: {:?}, content-type: {}", backend_type, content_type);
 
+    if let Some(delay) = injected_delay {
+        tracing::debug!("Injecting {:?} of artificial delay", delay);
+        tokio::time::sleep(delay).await;
+    }
+
     // Create the stream-tee architecture
-    let (tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(32);
+    let capacity = state.config.stream_channel_capacity.max(1);
+    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, std::io::Error>>(capacity);
 
     // Spawn task to handle stream inspection
-    let request_data_clone = request_data.clone();
-    tokio::spawn(async move {
-        handle_stream_tee(
-            body,
-            tx,
-            backend_type,
-            request_data_clone,
-            start_time,
-        )
-        .await;
-    });
+    let context = TeeContext {
+        backend_type,
+        streaming,
+        decoder,
+        status: parts.status,
+        backend_port,
+        upstream_url,
+        attempts,
+        sent_at,
+        connect_time,
+        deadline,
+        upstream_request_id,
+        request_data,
+        start_time,
+        state: state.clone(),
+        permit,
+        in_flight,
+    };
+    // Tracked so graceful shutdown waits for the stream and its metrics
+    state.tasks.spawn(
+        async move {
+            handle_stream_tee(body, tx, context).await;
+        }
+        .in_current_span(),
+    );
 
     // Create the response body from the receiver
-    let stream = ReceiverStream::new(rx);
-    let body = StreamBody::new(stream.map(|result| {
-        result.map(hyper::body::Frame::data)
-    }));
+    let body = StreamBody::new(ReceiverStream::new(rx));
 
     // Reconstruct the response
     Response::from_parts(parts, Body::new(body))
 }
 
-/// Handles the stream-tee: forwards chunks to client and parser simultaneously
-async fn handle_stream_tee(
-    mut upstream_body: hyper::body::Incoming,
-    client_tx: mpsc::Sender<Result<Bytes, std::io::Error>>,
+/// The request behind a WebSocket tunnel, for its metrics record
+struct WebSocketSession<'a> {
+    backend_port: u16,
+    path: &'a str,
+    request_data: Option<RequestData>,
+    start_time: tokio::time::Instant,
+}
+
+/// Tunnels a WebSocket upgrade to the upstream without inspecting the frames
+///
+/// The record is written when the socket closes, without token counts. The
+/// concurrency slot is released once the handshake completes, so long-lived
+/// sockets don't starve regular requests.
+async fn proxy_websocket(
+    state: &AppState,
+    target: &str,
+    mut parts: hyper::http::request::Parts,
+    body: Body,
+    permit: Permits,
+    session: WebSocketSession<'_>,
+) -> Response {
+    let client_upgrade = parts.extensions.remove::<hyper::upgrade::OnUpgrade>();
+
+    // prepare_upstream drops the hop-by-hop handshake headers, but this hop needs them
+    parts
+        .headers
+        .insert(hyper::header::CONNECTION, hyper::header::HeaderValue::from_static("upgrade"));
+    parts
+        .headers
+        .insert(hyper::header::UPGRADE, hyper::header::HeaderValue::from_static("websocket"));
+
+    let mut upstream_response = match state.client.request(hyper::Request::from_parts(parts, body)).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            tracing::error!("Failed to proxy WebSocket handshake: {}", e);
+            state.breakers.record_connection_failure(target);
+            let failure = UpstreamFailure::new(StatusCode::BAD_GATEWAY, e.to_string(), session.backend_port, session.path)
+                .after_attempts(1);
+            record_failure(state, session.request_data, failure).await;
+            return (StatusCode::BAD_GATEWAY, format!("Upstream error: {}", e)).into_response();
+        }
+    };
+    let status = upstream_response.status();
+    state.breakers.record_status(target, status.as_u16());
+    drop(permit);
+
+    let backend = backend_without_response(state, session.backend_port, session.path, session.request_data.as_ref());
+    let arrived = session.request_data.as_ref().map_or(session.start_time, |r| r.arrival.instant);
+    let mut metrics = session.request_data.map(|req_data| LLMMetrics {
+        backend_port: session.backend_port,
+        upstream_url: format!("http://{}{}", target, session.path),
+        status_code: status.as_u16(),
+        attempts: 1,
+        ..request_metrics(state, req_data, backend)
+    });
+
+    // A refused handshake is an ordinary response
+    let Some(client_upgrade) = client_upgrade.filter(|_| status == StatusCode::SWITCHING_PROTOCOLS) else {
+        if let Some(metrics) = &mut metrics {
+            let elapsed_ms = arrived.elapsed().as_millis() as u64;
+            (metrics.total_ms, metrics.latency_ms) = (elapsed_ms, elapsed_ms);
+            emit_metrics(state, metrics).await;
+        }
+        return upstream_response.map(Body::new);
+    };
+
+    let upstream_upgrade = hyper::upgrade::on(&mut upstream_response);
+    let target = target.to_string();
+    let state = state.clone();
+    // Tracked so graceful shutdown waits for open sockets and their records
+    state.tasks.clone().spawn(
+        async move {
+            let copied = match tokio::try_join!(client_upgrade, upstream_upgrade) {
+                Ok((client, upstream)) => {
+                    let mut client = hyper_util::rt::TokioIo::new(client);
+                    let mut upstream = hyper_util::rt::TokioIo::new(upstream);
+                    tokio::io::copy_bidirectional(&mut client, &mut upstream).await
+                }
+                Err(e) => Err(std::io::Error::other(e)),
+            };
+            let stream_error = match &copied {
+                Ok((sent, received)) => {
+                    tracing::debug!("WebSocket to {} closed: sent {} bytes, received {}", target, sent, received);
+                    None
+                }
+                Err(e) => {
+                    tracing::debug!("WebSocket to {} closed with error: {}", target, e);
+                    Some(e.to_string())
+                }
+            };
+
+            let Some(metrics) = metrics else { return };
+            let finished_at = chrono::Utc::now();
+            let elapsed_ms = arrived.elapsed().as_millis() as u64;
+            let metrics = LLMMetrics {
+                success: stream_error.is_none(),
+                completed: stream_error.is_none(),
+                response_bytes: copied.as_ref().map_or(0, |(_, received)| *received),
+                stream_error,
+                total_ms: elapsed_ms,
+                latency_ms: elapsed_ms,
+                finished_at: finished_at.to_rfc3339(),
+                finished_at_ms: epoch_ms(finished_at),
+                timestamp: finished_at.to_rfc3339(),
+                ..metrics
+            };
+            emit_metrics(&state, &metrics).await;
+        }
+        .in_current_span(),
+    );
+
+    let (parts, _) = upstream_response.into_parts();
+    Response::from_parts(parts, Body::empty())
+}
+
+/// Address of the loopback upstream on `port`
+fn upstream_target(port: u16) -> String {
+    format!("127.0.0.1:{}", port)
+}
+
+/// Full upstream URI for a request, including its query string
+fn upstream_uri(target: &str, path: &str, query: Option<&str>) -> String {
+    match query {
+        Some(query) => format!("http://{}{}?{}", target, path, query),
+        None => format!("http://{}{}", target, path),
+    }
+}
+
+/// Returns true for content-types that deliver a response incrementally
+fn is_streaming_content_type(content_type: &str) -> bool {
+    content_type.contains("application/x-ndjson")
+        || content_type.contains("text/event-stream")
+        || content_type.contains("application/vnd.amazon.eventstream")
+        || content_type.contains("application/stream+json")
+}
+
+/// Everything the stream-tee needs to know about the request besides the bodies
+struct TeeContext {
     backend_type: BackendType,
+    streaming: bool,
+    /// Decompresses the parser's copy of the body
+    decoder: ParserDecoder,
+    status: StatusCode,
+    backend_port: u16,
+    upstream_url: String,
+    /// Upstream attempts made, the last being the one that answered
+    attempts: u8,
+    /// When the request was sent to the upstream that answered, relative to `start_time`
+    sent_at: std::time::Duration,
+    /// When the upstream's response headers arrived, relative to `start_time`
+    connect_time: std::time::Duration,
+    /// When the whole upstream call must be finished, if limited
+    deadline: Option<tokio::time::Instant>,
+    upstream_request_id: Option<String>,
     request_data: Option<RequestData>,
     start_time: tokio::time::Instant,
+    state: AppState,
+    permit: Permits,
+    /// Counts the request against its pool upstream until the stream ends
+    in_flight: Option<InFlight>,
+}
+
+/// Handles the stream-tee: forwards chunks to client and parser simultaneously
+async fn handle_stream_tee(
+    mut upstream_body: hyper::body::Incoming,
+    client_tx: mpsc::Sender<Result<Frame<Bytes>, std::io::Error>>,
+    context: TeeContext,
 ) {
-    // Create the appropriate parser
-    let mut parser: Box<dyn BackendStreamParser> = match backend_type {
-        BackendType::Ollama => Box::new(crate::parsers::OllamaParser::new()),
-        BackendType::OpenAI => Box::new(crate::parsers::OpenAIParser::new()),
-        BackendType::Unknown => Box::new(crate::parsers::PassthroughParser),
+    let TeeContext {
+        backend_type,
+        streaming,
+        decoder,
+        status,
+        backend_port,
+        upstream_url,
+        attempts,
+        sent_at,
+        connect_time,
+        deadline,
+        upstream_request_id,
+        request_data,
+        start_time,
+        state,
+        permit,
+        in_flight,
+    } = context;
+
+    // Create the appropriate parser, trying the configured candidates on unidentified streams
+    let max_buffer = state.config.parser_max_buffer_bytes;
+    let mut parser: Box<dyn BackendStreamParser> = if let Some(parser) = state.factory_parser(backend_type) {
+        parser
+    } else if backend_type == BackendType::Unknown && !state.config.fallback_parsers.is_empty() {
+        Box::new(FallbackParser::with_max_buffer(
+            state.config.fallback_parsers.clone(),
+            max_buffer,
+        ))
+    } else {
+        create_parser_with_max_buffer(backend_type, max_buffer)
+    };
+    // Response text is only kept when something reads it, and never past the buffer cap
+    if state.config.reads_response_text() {
+        parser.capture_completion_text(max_buffer);
+    }
+
+    // Retain the tail of error bodies for diagnostics
+    let error_body = state
+        .config
+        .error_body_log_bytes
+        .filter(|_| !status.is_success())
+        .map(ErrorBodyBuffer::new);
+
+    // Parsing runs on its own task so it never holds up the client's copy of the stream.
+    // A parser that falls too far behind is cut off rather than left to queue the response.
+    let parser_capacity = state.config.parser_channel_capacity.max(1);
+    let (parser_tx, parser_rx) = mpsc::channel(parser_capacity);
+    let mut parser_tx = Some(parser_tx);
+    let mut parser_lagged = false;
+    let parser_task = tokio::spawn(run_parser(parser, decoder, error_body, parser_rx).in_current_span());
+
+    // Stream the response body to the archive as it passes through
+    let mut archive_writer = match (&state.archive, &request_data) {
+        (Some(archive), Some(req_data)) => match archive.response_writer(&req_data.request_id).await {
+            Ok(writer) => Some(writer),
+            Err(e) => {
+                tracing::error!("Failed to open response archive for {}: {}", req_data.request_id, e);
+                None
+            }
+        },
+        _ => None,
+    };
+
+    // Time of the first forwarded data frame
+    let mut first_frame_at = None;
+    let mut last_frame_at = None;
+    let mut chunk_gaps = ChunkGapStats::new();
+    let mut response_bytes = 0u64;
+    let mut frame_count = 0u64;
+    let mut truncated = false;
+    let mut client_disconnected = false;
+    let mut stream_error = None;
+    let mut trailers = BTreeMap::new();
+
+    let idle_timeout = match state.config.stream_idle_timeout_ms {
+        0 => None,
+        ms => Some(std::time::Duration::from_millis(ms)),
     };
 
     // Process the stream
     loop {
-        match upstream_body.frame().await {
-            Some(Ok(frame)) => {
-                if let Ok(data) = frame.into_data() {
-                    // Feed chunk to parser (non-blocking)
-                    parser.feed_chunk(&data).await;
-
-                    // Forward chunk to client
-                    if client_tx.send(Ok(data)).await.is_err() {
-                        tracing::debug!("Client disconnected");
+        // Wait for the next frame until the idle timeout or the overall deadline, whichever is sooner
+        let idle_deadline = idle_timeout.map(|idle_timeout| tokio::time::Instant::now() + idle_timeout);
+        let wait_until = match (idle_deadline, deadline) {
+            (Some(idle), Some(total)) => Some(idle.min(total)),
+            (idle, total) => idle.or(total),
+        };
+        let next_frame = match wait_until {
+            Some(wait_until) => match tokio::time::timeout_at(wait_until, upstream_body.frame()).await {
+                Ok(frame) => frame,
+                Err(_) => {
+                    let reason = if deadline == Some(wait_until) {
+                        tracing::warn!(
+                            "Upstream exceeded the {}ms request timeout, truncating stream",
+                            state.config.upstream_timeout_ms
+                        );
+                        "upstream request timeout"
+                    } else {
+                        tracing::warn!("Upstream stalled for {:?}, truncating stream", idle_timeout.unwrap_or_default());
+                        "upstream stream idle timeout"
+                    };
+                    truncated = true;
+                    stream_error = Some(reason.to_string());
+                    let _ = client_tx
+                        .send(Err(std::io::Error::new(std::io::ErrorKind::TimedOut, reason)))
+                        .await;
+                    break;
+                }
+            },
+            None => upstream_body.frame().await,
+        };
+
+        match next_frame {
+            Some(Ok(frame)) => match frame.into_data() {
+                Ok(data) => {
+                    // The parser gets its own handle on the chunk and the client gets the original.
+                    // `Bytes::clone` only bumps a refcount, so neither path copies the payload;
+                    // keep it that way (no `to_vec`) so large responses stay allocation-free here.
+                    if let Some(tx) = &parser_tx {
+                        if tx.try_send((data.clone(), start_time.elapsed())).is_err() {
+                            tracing::warn!(
+                                "Parser fell {} chunks behind, leaving the rest of the response unparsed",
+                                parser_capacity
+                            );
+                            parser_tx = None;
+                            parser_lagged = true;
+                        }
+                    }
+
+                    if let Some(writer) = &mut archive_writer {
+                        if let Err(e) = writer.write_chunk(&data).await {
+                            tracing::error!("Failed to archive response chunk, disabling archive: {}", e);
+                            archive_writer = None;
+                        }
+                    }
+
+                    if first_frame_at.is_none() {
+                        first_frame_at = Some(start_time.elapsed());
+                    }
+                    response_bytes += data.len() as u64;
+                    frame_count += 1;
+
+                    let now = start_time.elapsed();
+                    last_frame_at = Some(now);
+                    chunk_gaps.record_frame(now);
+
+                    if !forward_frame(&client_tx, Frame::data(data), state.config.slow_client).await {
+                        client_disconnected = true;
                         break;
                     }
                 }
-            }
+                // Trailers end the body; pass them on and keep what they say about the outcome
+                Err(frame) => {
+                    if let Ok(fields) = frame.into_trailers() {
+                        if let Some(error) = trailer_error(&fields) {
+                            tracing::warn!("Upstream trailers report a failure: {}", error);
+                            stream_error = Some(error);
+                        }
+                        trailers = trailer_fields(&fields);
+                        if !forward_frame(&client_tx, Frame::trailers(fields), state.config.slow_client).await {
+                            client_disconnected = true;
+                            break;
+                        }
+                    }
+                }
+            },
             Some(Err(e)) => {
                 tracing::error!("Error reading upstream body: {}", e);
-                let _ = client_tx.send(Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    e.to_string(),
-                ))).await;
+                let _ = client_tx.send(Err(std::io::Error::other(e.to_string()))).await;
+                stream_error = Some(e.to_string());
                 break;
             }
             None => {
@@ -154,34 +697,540 @@ async fn handle_stream_tee(
         }
     }
 
-    // Finalize parser and get token usage
-    let token_usage = parser.finalize().await;
-
-    // Calculate final latency
+    // Close the client stream before finalizing so sinks never delay the response
+    drop(client_tx);
     let latency = start_time.elapsed();
+    // Marks so far are relative to the handler's start; the record counts from arrival
+    let handler_start = request_data
+        .as_ref()
+        .map_or(std::time::Duration::ZERO, |r| start_time.saturating_duration_since(r.arrival.instant));
+    let wall_clock = request_data.as_ref().map(|r| r.arrival.bounds());
+    let timings = request_data.as_ref().map(|r| {
+        Timings::new(&TimingMarks {
+            body_read: r.body_read,
+            sent: handler_start + sent_at,
+            headers: handler_start + connect_time,
+            first_frame: first_frame_at.map(|at| handler_start + at),
+            last_frame: last_frame_at.map(|at| handler_start + at),
+            finished: handler_start + latency,
+        })
+    });
+
+    // The upstream is done with this request, so let the next queued one through
+    drop(permit);
+    drop(in_flight);
+
+    // Let the parser work through what's left and finalize
+    drop(parser_tx);
+    let ParserOutput {
+        mut token_usage,
+        first_content_at,
+        error_body,
+    } = match parser_task.await {
+        Ok(output) => output,
+        Err(e) => {
+            tracing::error!("Parser task failed: {}", e);
+            ParserOutput::default()
+        }
+    };
+    token_usage.parse_truncated |= parser_lagged;
+
+    if let Some(writer) = archive_writer {
+        if let Err(e) = writer.finish().await {
+            tracing::error!("Failed to flush response archive: {}", e);
+        }
+    }
+
+    if let Some(error_body) = error_body {
+        tracing::warn!(
+            "Upstream error response: request_id={}, status={}, truncated={}, body={}",
+            request_data.as_ref().map_or("-", |r| r.request_id.as_str()),
+            status.as_u16(),
+            error_body.truncated,
+            String::from_utf8_lossy(&error_body.buffer)
+        );
+    }
+
+    if state.config.estimate_missing_tokens {
+        let model = request_data.as_ref().map_or("", |r| r.model.as_str());
+        state.estimators.backfill(model, &mut token_usage);
+    }
+
+    // Prefer the first content chunk; parsers that can't see content fall back to the first frame
+    let ttft = if streaming {
+        first_content_at.or(first_frame_at)
+    } else {
+        Some(latency)
+    };
+
+    let throughput = compute_throughput(
+        token_usage.completion_tokens,
+        first_content_at,
+        last_frame_at,
+        token_usage.eval_duration_ms,
+    );
+
+    let gaps = chunk_gaps.summary();
 
     // Log the metrics
-    if let Some(req_data) = request_data {
+    if let (Some(mut req_data), Some((started_at, finished_at))) = (request_data, wall_clock) {
+        let quality = state.config.completion_heuristics.then(|| {
+            CompletionQuality::assess(&token_usage.completion_text, token_usage.finish_reason.as_deref())
+        });
+        let prompt_chars = (quality.is_some() || state.config.prompt_capture == PromptCapture::LastUserMessage)
+            .then_some(req_data.full_prompt_chars);
+        let total_tokens = token_usage.total();
+        let response_text = state.config.recorded_response_text(&token_usage.completion_text);
+        let response_text_sha256 = state
+            .config
+            .hash_response_text
+            .then(|| text::sha256_hex(&token_usage.completion_text));
+
+        // The reported model names the exact snapshot, so it is priced ahead of the requested alias
+        let models: Vec<&str> = token_usage
+            .response_model
+            .iter()
+            .map(String::as_str)
+            .chain([req_data.model.as_str()])
+            .collect();
+        let cost_usd = state.pricing.cost(backend_type, &models, &token_usage);
+
+        // Tokenizing a long prompt takes a while, so it runs on the blocking pool
+        // instead of stalling the other connections served by this worker
+        let (estimated_prompt_tokens, estimated_completion_tokens) = match &state.tokenizer {
+            Some(tokenizer) => {
+                let tokenizer = tokenizer.clone();
+                let model = req_data.model.clone();
+                // The full prompt, not just what prompt_capture keeps of it
+                let prompt = req_data.full_prompt.take().unwrap_or_default();
+                let system_prompt = req_data.system_prompt.clone().unwrap_or_default();
+                let completion_text = std::mem::take(&mut token_usage.completion_text);
+                let estimated = tokio::task::spawn_blocking(move || {
+                    let tokenizer = tokenizer.for_model(&model);
+                    let count = |text: &str| (!text.is_empty()).then(|| tokenizer.estimate(text));
+                    let prompt_tokens = [system_prompt.as_str(), prompt.as_str()]
+                        .into_iter()
+                        .filter_map(count)
+                        .reduce(|a, b| a + b);
+                    (prompt_tokens, count(&completion_text))
+                })
+                .await;
+                estimated.unwrap_or_else(|e| {
+                    tracing::error!("Failed to estimate token counts: {}", e);
+                    (None, None)
+                })
+            }
+            None => (None, None),
+        };
+
+        let completed = stream_error.is_none() && (token_usage.saw_terminal || backend_type == BackendType::Unknown);
+
         let metrics = LLMMetrics {
+            schema_version: SCHEMA_VERSION,
+            request_id: req_data.request_id,
+            upstream_request_id,
+            backend: backend_type,
+            backend_port,
+            upstream_url,
+            status_code: status.as_u16(),
+            success: status.is_success() && completed,
             model: req_data.model,
-            prompt: req_data.prompt,
+            response_model: token_usage.response_model,
+            logprobs_requested: req_data.logprobs_requested,
+            top_logprobs: req_data.top_logprobs,
+            logprobs_returned: token_usage.logprobs_returned,
+            params: req_data.params,
+            request_parse_ok: req_data.request_parse_ok,
+            client: req_data.client,
+            api_key_fingerprint: req_data.api_key_fingerprint,
+            api_key_label: req_data.api_key_label,
+            tags: req_data.annotations.tags,
+            metadata: req_data.annotations.metadata,
+            conversation_id: req_data.annotations.conversation_id,
+            replay_of: req_data.annotations.replay_of,
+            turn_index: req_data.turn_index,
+            streamed: req_data.streamed,
+            prompt: state.config.recorded_prompt(&req_data.prompt),
+            prompt_len: req_data.prompt.chars().count() as u64,
+            prompt_sha256: req_data.prompt_sha256,
+            message_count: req_data.message_count,
+            system_prompt_present: req_data.system_prompt_present,
+            system_prompt: req_data.system_prompt.as_deref().and_then(|s| state.config.recorded_system_prompt(s)),
+            system_prompt_hash: req_data.system_prompt.as_deref().map(text::normalized_sha256),
+            prompt_stats: req_data.prompt_stats,
+            image_count: req_data.attachments.image_count,
+            audio_count: req_data.attachments.audio_count,
+            attachment_bytes: req_data.attachments.attachment_bytes,
+            tools_offered_count: req_data.tools_offered_count,
+            tools_offered: req_data.tools_offered,
             prompt_tokens: token_usage.prompt_tokens,
             completion_tokens: token_usage.completion_tokens,
-            latency_ms: latency.as_millis() as u64,
-            timestamp: chrono::Utc::now().to_rfc3339(),
+            tokens_estimated: token_usage.estimated,
+            total_tokens,
+            cache_write_tokens: token_usage.cache_write_tokens,
+            cache_read_tokens: token_usage.cache_read_tokens,
+            cost_usd,
+            streamed_prompt_tokens: req_data.streamed_prompt_tokens,
+            estimated_prompt_tokens,
+            estimated_completion_tokens,
+            upstream_connect_ms: Some((handler_start + connect_time).as_millis() as u64),
+            total_ms: (handler_start + latency).as_millis() as u64,
+            timings,
+            latency_ms: (handler_start + latency).as_millis() as u64,
+            attempts,
+            retried: attempts > 1,
+            attempt_latency_ms: Some(latency.saturating_sub(sent_at).as_millis() as u64),
+            ttft_ms: ttft.map(|t| (handler_start + t).as_millis() as u64),
+            generation_time_ms: throughput.generation_time_ms,
+            tokens_per_second: throughput.tokens_per_second,
+            tokens_per_second_source: throughput.source,
+            upstream_total_duration_ms: token_usage.total_duration_ms,
+            upstream_load_duration_ms: token_usage.load_duration_ms,
+            upstream_prompt_eval_duration_ms: token_usage.prompt_eval_duration_ms,
+            upstream_eval_duration_ms: token_usage.eval_duration_ms,
+            chunk_gap_ms_min: gaps.as_ref().map(|g| g.min_ms),
+            chunk_gap_ms_mean: gaps.as_ref().map(|g| g.mean_ms),
+            chunk_gap_ms_max: gaps.as_ref().map(|g| g.max_ms),
+            chunk_gap_ms_p95: gaps.as_ref().map(|g| g.p95_ms),
+            response_bytes,
+            frame_count,
+            event_count: token_usage.event_count,
+            had_tool_calls: token_usage.tool_call_count > 0,
+            tool_call_count: token_usage.tool_call_count,
+            truncated,
+            parse_truncated: token_usage.parse_truncated,
+            client_disconnected,
+            completed,
+            upstream_error: None,
+            stream_error,
+            trailers,
+            prompt_chars,
+            completion_chars: quality.as_ref().map(|q| q.chars),
+            empty_completion: quality.as_ref().map(|q| q.empty),
+            looks_truncated: quality.as_ref().map(|q| q.looks_truncated),
+            response_text,
+            response_text_sha256,
+            started_at: started_at.to_rfc3339(),
+            started_at_ms: epoch_ms(started_at),
+            finished_at: finished_at.to_rfc3339(),
+            finished_at_ms: epoch_ms(finished_at),
+            timestamp: finished_at.to_rfc3339(),
         };
 
-        tracing::info!(
-            "LLM Request Complete: model={}, prompt_tokens={:?}, completion_tokens={:?}, latency_ms={}",
-            metrics.model,
-            metrics.prompt_tokens,
-            metrics.completion_tokens,
-            metrics.latency_ms
-        );
+        emit_metrics(&state, &metrics).await;
+    }
+}
+
+/// What the parser task learned from the response body
+#[derive(Default)]
+struct ParserOutput {
+    token_usage: TokenUsage,
+    /// When the chunk that carried the first generated content arrived
+    first_content_at: Option<std::time::Duration>,
+    error_body: Option<ErrorBodyBuffer>,
+}
+
+/// Decodes and parses the response body's chunks, each tagged with its arrival time
+async fn run_parser(
+    mut parser: Box<dyn BackendStreamParser>,
+    mut decoder: ParserDecoder,
+    mut error_body: Option<ErrorBodyBuffer>,
+    mut chunks: mpsc::Receiver<(Bytes, std::time::Duration)>,
+) -> ParserOutput {
+    let mut first_content_at = None;
+    while let Some((data, arrived_at)) = chunks.recv().await {
+        if let Some(plain) = decoder.decode(&data) {
+            parser.feed_chunk(&plain).await;
+            if let Some(error_body) = &mut error_body {
+                error_body.push(&plain);
+            }
+        }
+        if first_content_at.is_none() && parser.saw_content() {
+            first_content_at = Some(arrived_at);
+        }
+    }
+
+    if let Some(plain) = decoder.finish() {
+        parser.feed_chunk(&plain).await;
+        if let Some(error_body) = &mut error_body {
+            error_body.push(&plain);
+        }
+    }
+
+    ParserOutput {
+        token_usage: parser.finalize().await,
+        first_content_at,
+        error_body,
+    }
+}
+
+/// Hands a frame to the client under the slow-client policy; false once the client is gone
+async fn forward_frame(
+    client_tx: &mpsc::Sender<Result<Frame<Bytes>, std::io::Error>>,
+    frame: Frame<Bytes>,
+    policy: SlowClientPolicy,
+) -> bool {
+    match policy {
+        SlowClientPolicy::Backpressure => {
+            if client_tx.send(Ok(frame)).await.is_err() {
+                tracing::debug!("Client disconnected");
+                return false;
+            }
+        }
+        SlowClientPolicy::Disconnect => match client_tx.try_send(Ok(frame)) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                tracing::warn!("Client too slow, disconnecting");
+                return false;
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                tracing::debug!("Client disconnected");
+                return false;
+            }
+        },
+    }
+    true
+}
+
+/// Why a request never got a response from its upstream
+struct UpstreamFailure<'a> {
+    /// Status returned to the client and recorded in the metrics
+    status: StatusCode,
+    error: String,
+    /// Port of the upstream the request was meant for; 0 when none was chosen
+    backend_port: u16,
+    path: &'a str,
+    upstream_url: String,
+    /// Upstream attempts made before giving up; 0 when none was sent
+    attempts: u8,
+}
+
+impl<'a> UpstreamFailure<'a> {
+    fn new(
+        status: StatusCode,
+        error: impl Into<String>,
+        backend_port: u16,
+        path: &'a str,
+    ) -> Self {
+        let upstream_url = match backend_port {
+            0 => String::new(),
+            port => format!("http://{}{}", upstream_target(port), path),
+        };
+        Self {
+            status,
+            error: error.into(),
+            backend_port,
+            path,
+            upstream_url,
+            attempts: 0,
+        }
+    }
+
+    /// Records how many upstream attempts failed this way
+    fn after_attempts(mut self, attempts: u8) -> Self {
+        self.attempts = attempts;
+        self
+    }
+}
+
+/// Where and after what wait the attempt following a failed one goes, if another is
+/// allowed: the pool's next upstream right away, otherwise the same one after a backoff
+fn next_attempt(
+    state: &AppState,
+    candidates: &mut impl Iterator<Item = u16>,
+    port: u16,
+    attempts: u8,
+    deadline: Option<tokio::time::Instant>,
+) -> Option<(u16, std::time::Duration)> {
+    let retry = &state.config.retry;
+    if !retry.allows_another(attempts) {
+        return None;
+    }
+    if let Some(next) = candidates.next() {
+        tracing::warn!("Retrying on port {} (attempt {})", next, attempts + 1);
+        return Some((next, std::time::Duration::ZERO));
+    }
+
+    let backoff = retry.backoff(attempts);
+    if deadline.is_some_and(|deadline| tokio::time::Instant::now() + backoff >= deadline) {
+        return None;
+    }
+    let target = upstream_target(port);
+    if !state.breakers.try_acquire(&target) {
+        tracing::warn!("Circuit breaker open for {}, not retrying", target);
+        return None;
+    }
+    tracing::warn!("Retrying {} in {:?} (attempt {})", target, backoff, attempts + 1);
+    Some((port, backoff))
+}
+
+/// Records a request the upstream never answered, then reports the failure to the
+/// client in its API's error format
+async fn respond_without_upstream(
+    state: &AppState,
+    request_data: Option<RequestData>,
+    failure: UpstreamFailure<'_>,
+) -> Response {
+    let status = failure.status;
+    let message = format!("Upstream error: {}", failure.error);
+    let backend = record_failure(state, request_data, failure).await;
+    error_response(backend, status, &message)
+}
+
+/// Records a request the proxy turned away before trying an upstream, then tells the
+/// client why in plain text
+async fn reject(state: &AppState, request_data: Option<RequestData>, failure: UpstreamFailure<'_>) -> Response {
+    let response = (failure.status, failure.error.clone()).into_response();
+    record_failure(state, request_data, failure).await;
+    response
+}
+
+/// Emits the metrics record for a request that got no upstream response, returning
+/// the backend type it was detected as
+async fn record_failure(
+    state: &AppState,
+    request_data: Option<RequestData>,
+    failure: UpstreamFailure<'_>,
+) -> BackendType {
+    let backend = backend_without_response(state, failure.backend_port, failure.path, request_data.as_ref());
+
+    if let Some(req_data) = request_data {
+        let elapsed_ms = req_data.arrival.instant.elapsed().as_millis() as u64;
+        let metrics = LLMMetrics {
+            backend_port: failure.backend_port,
+            upstream_url: failure.upstream_url,
+            status_code: failure.status.as_u16(),
+            total_ms: elapsed_ms,
+            latency_ms: elapsed_ms,
+            attempts: failure.attempts,
+            retried: failure.attempts > 1,
+            upstream_error: Some(failure.error),
+            ..request_metrics(state, req_data, backend)
+        };
+        emit_metrics(state, &metrics).await;
+    }
+    backend
+}
+
+/// The backend type of a request whose response was never parsed, from its route alone
+fn backend_without_response(
+    state: &AppState,
+    backend_port: u16,
+    path: &str,
+    request_data: Option<&RequestData>,
+) -> BackendType {
+    detect_backend(&DetectionHints {
+        forced: state.config.backend(backend_port).and_then(|b| b.backend_type),
+        path,
+        default: state.config.default_backend_type,
+        request_shape: request_data.map_or(RequestShape::Unknown, |r| r.request_shape),
+        ..DetectionHints::default()
+    })
+}
+
+/// A record holding what is known from the request alone, for requests without a
+/// parsed response; ends as of now
+fn request_metrics(state: &AppState, req_data: RequestData, backend: BackendType) -> LLMMetrics {
+    let (started_at, finished_at) = req_data.arrival.bounds();
+    LLMMetrics {
+        schema_version: SCHEMA_VERSION,
+        request_id: req_data.request_id,
+        backend,
+        model: req_data.model,
+        params: req_data.params,
+        request_parse_ok: req_data.request_parse_ok,
+        client: req_data.client,
+        api_key_fingerprint: req_data.api_key_fingerprint,
+        api_key_label: req_data.api_key_label,
+        tags: req_data.annotations.tags,
+        metadata: req_data.annotations.metadata,
+        conversation_id: req_data.annotations.conversation_id,
+        replay_of: req_data.annotations.replay_of,
+        turn_index: req_data.turn_index,
+        streamed: req_data.streamed,
+        prompt: state.config.recorded_prompt(&req_data.prompt),
+        prompt_len: req_data.prompt.chars().count() as u64,
+        prompt_sha256: req_data.prompt_sha256,
+        message_count: req_data.message_count,
+        system_prompt_present: req_data.system_prompt_present,
+        system_prompt: req_data.system_prompt.as_deref().and_then(|s| state.config.recorded_system_prompt(s)),
+        system_prompt_hash: req_data.system_prompt.as_deref().map(text::normalized_sha256),
+        prompt_stats: req_data.prompt_stats,
+        image_count: req_data.attachments.image_count,
+        audio_count: req_data.attachments.audio_count,
+        attachment_bytes: req_data.attachments.attachment_bytes,
+        tools_offered_count: req_data.tools_offered_count,
+        tools_offered: req_data.tools_offered,
+        streamed_prompt_tokens: req_data.streamed_prompt_tokens,
+        started_at: started_at.to_rfc3339(),
+        started_at_ms: epoch_ms(started_at),
+        finished_at: finished_at.to_rfc3339(),
+        finished_at_ms: epoch_ms(finished_at),
+        timestamp: finished_at.to_rfc3339(),
+        ..LLMMetrics::default()
+    }
+}
+
+/// Milliseconds since the Unix epoch
+fn epoch_ms(time: chrono::DateTime<chrono::Utc>) -> u64 {
+    time.timestamp_millis().max(0) as u64
+}
+
+/// An error in the body format the backend's client SDKs parse
+///
+/// The error comes before any stream starts, so it is plain JSON even for streaming
+/// requests, as the providers themselves send it. Unknown backends get plain text.
+fn error_response(backend: BackendType, status: StatusCode, message: &str) -> Response {
+    let body = match backend {
+        BackendType::OpenAI => serde_json::json!({
+            "error": { "message": message, "type": "server_error", "param": null, "code": null }
+        }),
+        BackendType::Anthropic => serde_json::json!({
+            "type": "error",
+            "error": { "type": "api_error", "message": message }
+        }),
+        BackendType::Ollama => serde_json::json!({ "error": message }),
+        _ => return (status, message.to_string()).into_response(),
+    };
+    (status, axum::Json(body)).into_response()
+}
+
+/// Counts a finished request in `/stats` and writes it to the sinks if sampled
+async fn emit_metrics(state: &AppState, metrics: &LLMMetrics) {
+    state.stats.write().unwrap_or_else(|e| e.into_inner()).record(metrics);
+
+    if state.sampler.should_record(metrics) {
+        for sink in &state.sinks {
+            sink.record(metrics).await;
+        }
+    }
+}
+
+/// Keeps the last `capacity` bytes of an error response body
+struct ErrorBodyBuffer {
+    buffer: BytesMut,
+    capacity: usize,
+    truncated: bool,
+}
+
+impl ErrorBodyBuffer {
+    fn new(capacity: usize) -> Self {
+        Self {
+            buffer: BytesMut::with_capacity(capacity.min(64 * 1024)),
+            capacity,
+            truncated: false,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        // Only the tail of an oversized chunk can survive
+        let data = &data[data.len().saturating_sub(self.capacity)..];
+        self.buffer.extend_from_slice(data);
 
-        // Here you could write to a database, file, or other logging backend
-        if let Ok(json) = serde_json::to_string_pretty(&metrics) {
-            tracing::info!("Metrics: {}", json);
+        let excess = self.buffer.len().saturating_sub(self.capacity);
+        if excess > 0 {
+            self.buffer.advance(excess);
+            self.truncated = true;
         }
     }
 }
@@ -0,0 +1,30 @@
+/// Cheap quality signals derived from the captured completion text
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompletionQuality {
+    /// Length of the completion in characters
+    pub chars: u64,
+    /// The upstream produced no text (tool-call-only responses count as empty)
+    pub empty: bool,
+    /// The text stops mid-sentence and the upstream didn't report a natural stop
+    pub looks_truncated: bool,
+}
+
+/// Finish reasons that mean the model ended its turn on its own, across providers
+const NATURAL_STOPS: &[&str] = &["stop", "end_turn", "stop_sequence", "complete", "finish", "tool_calls"];
+
+/// Characters a finished sentence or block plausibly ends with
+const TERMINATORS: &[char] = &['.', '!', '?', '"', '\'', ')', ']', '}', '`', ':', '…', '。', '！', '？'];
+
+impl CompletionQuality {
+    pub fn assess(text: &str, finish_reason: Option<&str>) -> Self {
+        let trimmed = text.trim_end();
+        let natural_stop = finish_reason.is_some_and(|r| NATURAL_STOPS.iter().any(|s| r.eq_ignore_ascii_case(s)));
+        let ends_mid_sentence = trimmed.chars().last().is_some_and(|c| !TERMINATORS.contains(&c));
+
+        Self {
+            chars: text.chars().count() as u64,
+            empty: trimmed.is_empty(),
+            looks_truncated: !natural_stop && ends_mid_sentence,
+        }
+    }
+}
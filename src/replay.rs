@@ -0,0 +1,91 @@
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Path, Request, State},
+    response::{IntoResponse, Response},
+};
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Method, StatusCode};
+use std::net::SocketAddr;
+use tower::Service;
+
+use crate::app::{self, AppState};
+use crate::headers::{
+    is_valid_request_id, CONVERSATION_ID_HEADER, CREDENTIAL_HEADERS, META_HEADER, REQUEST_ID_HEADER, TAGS_HEADER,
+};
+
+/// Marks a request re-issued by `/replay`, carrying the ID of the archived original
+///
+/// Set as a request extension rather than a header so clients can't claim a replay.
+#[derive(Clone, Debug)]
+pub struct ReplayOf(pub String);
+
+/// Headers taken from the `/replay` call rather than the archive: credentials and the
+/// request ID are never archived, and annotations label the new run
+const CALLER_HEADERS: &[&str] = &[REQUEST_ID_HEADER, TAGS_HEADER, META_HEADER, CONVERSATION_ID_HEADER];
+
+/// Re-sends an archived request through the proxy and streams back the new response
+///
+/// The new request gets its own request ID and metrics record, with `replay_of`
+/// naming the original.
+pub async fn replay_handler(
+    State(state): State<AppState>,
+    Path(request_id): Path<String>,
+    req: Request,
+) -> Response {
+    let Some(archive) = state.archive.clone() else {
+        return (StatusCode::NOT_FOUND, "Request archiving is not enabled").into_response();
+    };
+    if !state.config.archive.as_ref().is_some_and(|archive| archive.replay) {
+        return (StatusCode::NOT_FOUND, "Replay is not enabled").into_response();
+    }
+    if !is_valid_request_id(&request_id) {
+        return (StatusCode::BAD_REQUEST, "Invalid request ID").into_response();
+    }
+
+    let (head, body) = match archive.load_request(&request_id).await {
+        Ok(Some(archived)) => archived,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, format!("No archived request {}", request_id)).into_response();
+        }
+        Err(e) => {
+            tracing::error!("Failed to load archived request {}: {}", request_id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load archived request").into_response();
+        }
+    };
+
+    let (Ok(method), Ok(uri)) = (Method::from_bytes(head.method.as_bytes()), head.uri.parse::<hyper::Uri>()) else {
+        tracing::error!("Archived request {} has an invalid method or URI", request_id);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load archived request").into_response();
+    };
+    if !uri.path().starts_with("/proxy/") {
+        return (StatusCode::BAD_REQUEST, "Archived request was not proxied").into_response();
+    }
+
+    let mut replay = Request::new(Body::from(body));
+    *replay.method_mut() = method;
+    *replay.uri_mut() = uri;
+    let headers = replay.headers_mut();
+    for (name, value) in &head.headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+            headers.append(name, value);
+        }
+    }
+    for name in CREDENTIAL_HEADERS.iter().chain(CALLER_HEADERS) {
+        if let Some(value) = req.headers().get(*name) {
+            headers.insert(*name, value.clone());
+        }
+    }
+
+    // The caller, not the original client, is recorded as the sender
+    if let Some(connect_info) = req.extensions().get::<ConnectInfo<SocketAddr>>() {
+        replay.extensions_mut().insert(*connect_info);
+    }
+    replay.extensions_mut().insert(ReplayOf(request_id));
+
+    tracing::info!("Replaying archived request {}", head.uri);
+    // A router is always ready, so it can be called without polling first
+    match app::router(state).call(replay).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    }
+}
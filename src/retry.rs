@@ -0,0 +1,56 @@
+use hyper::StatusCode;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Resending requests whose upstream failed, with exponential backoff
+///
+/// Connection failures and the listed statuses are retried, moving on to the next
+/// upstream of a pool first. Only the attempt that answers reaches the client or the
+/// metrics; earlier ones are counted in `attempts`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Tries per request, counting the first (1 disables retries)
+    pub max_attempts: u8,
+    /// Wait before the first retry, doubled for each one after
+    pub backoff_ms: u64,
+    /// Longest wait between two attempts
+    pub max_backoff_ms: u64,
+    /// Upstream statuses that are retried
+    pub retry_on_status: Vec<u16>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff_ms: 100,
+            max_backoff_ms: 2_000,
+            retry_on_status: vec![502, 503, 504],
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Whether a request may be sent more than once, so its body must be kept
+    pub fn enabled(&self) -> bool {
+        self.max_attempts > 1
+    }
+
+    /// Whether another attempt may follow attempt number `attempts`
+    pub fn allows_another(&self, attempts: u8) -> bool {
+        attempts < self.max_attempts
+    }
+
+    /// Whether an upstream answering with `status` is tried again
+    pub fn retries_status(&self, status: StatusCode) -> bool {
+        self.retry_on_status.contains(&status.as_u16())
+    }
+
+    /// Wait after attempt number `attempts` failed
+    pub fn backoff(&self, attempts: u8) -> Duration {
+        let exponent = u32::from(attempts.saturating_sub(1)).min(16);
+        let ms = self.backoff_ms.saturating_mul(1 << exponent).min(self.max_backoff_ms);
+        Duration::from_millis(ms)
+    }
+}
@@ -0,0 +1,72 @@
+use serde::Deserialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::types::LLMMetrics;
+
+/// Which completed requests are written to the metrics sinks
+///
+/// Sampling only thins the sinks; `/stats` still counts every request.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SamplingConfig {
+    /// Log one request in every `every_n` (takes precedence over `rate`)
+    pub every_n: Option<u64>,
+    /// Probability of logging each request, from 0.0 to 1.0
+    pub rate: f64,
+    /// Always log requests that failed or ended abnormally
+    pub always_log_errors: bool,
+    /// Always log requests at least this slow
+    pub always_log_latency_ms: Option<u64>,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        Self {
+            every_n: None,
+            rate: 1.0,
+            always_log_errors: true,
+            always_log_latency_ms: None,
+        }
+    }
+}
+
+/// Decides per request whether its metrics are written to the sinks
+pub struct Sampler {
+    config: SamplingConfig,
+    seen: AtomicU64,
+}
+
+impl Sampler {
+    pub fn new(config: SamplingConfig) -> Self {
+        Self {
+            config,
+            seen: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether this request's metrics should be recorded
+    pub fn should_record(&self, metrics: &LLMMetrics) -> bool {
+        if self.config.always_log_errors && is_abnormal(metrics) {
+            return true;
+        }
+        if self
+            .config
+            .always_log_latency_ms
+            .is_some_and(|threshold| metrics.latency_ms >= threshold)
+        {
+            return true;
+        }
+
+        match self.config.every_n {
+            Some(0) | Some(1) => true,
+            Some(n) => self.seen.fetch_add(1, Ordering::Relaxed).is_multiple_of(n),
+            None if self.config.rate >= 1.0 => true,
+            None => fastrand::f64() < self.config.rate,
+        }
+    }
+}
+
+/// Errors and cut-off streams are the records most worth keeping
+fn is_abnormal(metrics: &LLMMetrics) -> bool {
+    !metrics.success || metrics.stream_error.is_some() || metrics.truncated
+}
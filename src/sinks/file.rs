@@ -0,0 +1,177 @@
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Deserialize;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tokio_util::task::TaskTracker;
+
+use crate::sinks::MetricsSink;
+use crate::types::LLMMetrics;
+
+/// JSONL file sink settings
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct FileSinkConfig {
+    /// Path of the active log file; closed segments are written next to it
+    pub path: PathBuf,
+    /// Rotate once the active file would exceed this many bytes
+    pub max_bytes: Option<u64>,
+    /// Rotate when the UTC date changes
+    pub rotate_daily: bool,
+    /// Compress closed segments to `.gz`
+    pub gzip: bool,
+}
+
+impl Default for FileSinkConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("llm_metrics.jsonl"),
+            max_bytes: None,
+            rotate_daily: false,
+            gzip: false,
+        }
+    }
+}
+
+/// The active segment being appended to
+struct Segment {
+    file: File,
+    size: u64,
+    opened_on: NaiveDate,
+}
+
+/// Sink that appends one JSON object per line to a rotating file
+///
+/// All writes and rotations happen under a single lock, so a record is never
+/// written to a segment that is being closed.
+pub struct FileSink {
+    config: FileSinkConfig,
+    segment: Mutex<Option<Segment>>,
+    /// Compression of rotated segments, which graceful shutdown waits for
+    tasks: TaskTracker,
+}
+
+impl FileSink {
+    pub fn new(config: FileSinkConfig, tasks: TaskTracker) -> Self {
+        Self {
+            config,
+            segment: Mutex::new(None),
+            tasks,
+        }
+    }
+
+    async fn open_segment(&self) -> std::io::Result<Segment> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.config.path)
+            .await?;
+        let metadata = file.metadata().await?;
+        let size = metadata.len();
+        // A file left from an earlier run belongs to the day it was last written
+        let opened_on = match metadata.modified() {
+            Ok(modified) if size > 0 => DateTime::<Utc>::from(modified).date_naive(),
+            _ => Utc::now().date_naive(),
+        };
+        Ok(Segment { file, size, opened_on })
+    }
+
+    fn needs_rotation(&self, segment: &Segment, incoming: u64) -> bool {
+        let over_size = self
+            .config
+            .max_bytes
+            .is_some_and(|max| segment.size > 0 && segment.size + incoming > max);
+        let new_day = self.config.rotate_daily && segment.opened_on != Utc::now().date_naive();
+        over_size || new_day
+    }
+
+    /// Closes the active segment under a timestamped name and compresses it if configured
+    async fn rotate(&self, segment: Segment) -> std::io::Result<()> {
+        let mut file = segment.file;
+        file.flush().await?;
+        drop(file);
+
+        let closed = rotated_path(&self.config.path);
+        tokio::fs::rename(&self.config.path, &closed).await?;
+        tracing::info!("Rotated metrics log to {}", closed.display());
+
+        if self.config.gzip {
+            self.tasks.spawn_blocking(move || {
+                if let Err(e) = gzip_file(&closed) {
+                    tracing::error!("Failed to compress {}: {}", closed.display(), e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    async fn write_line(&self, line: &[u8]) -> std::io::Result<()> {
+        let mut guard = self.segment.lock().await;
+
+        // A segment just opened may be a full or stale file from an earlier run
+        let mut segment = match guard.take() {
+            Some(segment) => segment,
+            None => self.open_segment().await?,
+        };
+        if self.needs_rotation(&segment, line.len() as u64) {
+            self.rotate(segment).await?;
+            segment = self.open_segment().await?;
+        }
+
+        let segment = guard.insert(segment);
+        segment.file.write_all(line).await?;
+        segment.file.flush().await?;
+        segment.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MetricsSink for FileSink {
+    async fn record(&self, metrics: &LLMMetrics) {
+        let mut line = match serde_json::to_vec(metrics) {
+            Ok(l) => l,
+            Err(e) => {
+                tracing::error!("Failed to serialize metrics for file sink: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        if let Err(e) = self.write_line(&line).await {
+            tracing::error!("Failed to write metrics to {}: {}", self.config.path.display(), e);
+        }
+    }
+}
+
+/// Builds `<stem>-<UTC timestamp>.<ext>` next to the active file, avoiding collisions
+fn rotated_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("metrics");
+    let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("jsonl");
+    let timestamp = Utc::now().format("%Y%m%dT%H%M%S%.3fZ");
+
+    let mut candidate = path.with_file_name(format!("{}-{}.{}", stem, timestamp, ext));
+    let mut counter = 1;
+    while candidate.exists() {
+        candidate = path.with_file_name(format!("{}-{}-{}.{}", stem, timestamp, counter, ext));
+        counter += 1;
+    }
+    candidate
+}
+
+/// Compresses `path` to `path.gz` and removes the original
+fn gzip_file(path: &Path) -> std::io::Result<()> {
+    let mut gz_name = path.as_os_str().to_owned();
+    gz_name.push(".gz");
+
+    // Streamed, since a day's segment can be far larger than memory allows
+    let mut input = BufReader::new(std::fs::File::open(path)?);
+    let output = std::fs::File::create(PathBuf::from(gz_name))?;
+    let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+    std::io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+    std::fs::remove_file(path)
+}
@@ -0,0 +1,44 @@
+use async_trait::async_trait;
+
+use crate::parsers::BackendType;
+use crate::sinks::MetricsSink;
+use crate::types::LLMMetrics;
+
+/// Tracing target of the GenAI events, for filtering or routing to an OTel exporter
+pub const GENAI_TARGET: &str = "gen_ai";
+
+/// Sink that records metrics under the OpenTelemetry GenAI semantic-convention names
+///
+/// Each request becomes one `gen_ai` tracing event whose fields are named
+/// `gen_ai.system`, `gen_ai.request.model`, `gen_ai.usage.input_tokens`, and so on,
+/// so a `tracing-opentelemetry` bridge exports them as standard attributes.
+pub struct GenAiSink;
+
+/// Maps a backend type to its `gen_ai.system` value
+pub fn genai_system(backend: BackendType) -> &'static str {
+    match backend {
+        BackendType::Ollama => "ollama",
+        BackendType::OpenAI => "openai",
+        BackendType::Anthropic => "anthropic",
+        BackendType::Bedrock => "aws.bedrock",
+        BackendType::Cohere => "cohere",
+        BackendType::Unknown => "_OTHER",
+    }
+}
+
+#[async_trait]
+impl MetricsSink for GenAiSink {
+    async fn record(&self, metrics: &LLMMetrics) {
+        tracing::event!(
+            target: GENAI_TARGET,
+            tracing::Level::INFO,
+            gen_ai.system = genai_system(metrics.backend),
+            gen_ai.request.model = metrics.model.as_str(),
+            gen_ai.usage.input_tokens = metrics.prompt_tokens,
+            gen_ai.usage.output_tokens = metrics.completion_tokens,
+            gen_ai.server.request.duration_ms = metrics.latency_ms,
+            gen_ai.server.time_to_first_token_ms = metrics.ttft_ms,
+            "gen_ai request"
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::sinks::MetricsSink;
+use crate::types::LLMMetrics;
+
+/// How each metrics record is written to the log
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Indented multi-line JSON; easiest to read, most expensive to write
+    Pretty,
+    /// Single-line JSON
+    Compact,
+    /// Single-line `key=value` pairs
+    Logfmt,
+}
+
+impl Default for LogFormat {
+    /// Pretty while developing, compact in release builds
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            LogFormat::Pretty
+        } else {
+            LogFormat::Compact
+        }
+    }
+}
+
+/// Metrics log settings
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Encoding of the metrics record
+    pub format: LogFormat,
+    /// Also log a short human-readable summary line before the record
+    pub summary_line: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::default(),
+            summary_line: true,
+        }
+    }
+}
+
+/// Sink that writes metrics to the tracing log
+#[derive(Default)]
+pub struct TracingSink {
+    config: LogConfig,
+}
+
+impl TracingSink {
+    pub fn new(config: LogConfig) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait]
+impl MetricsSink for TracingSink {
+    async fn record(&self, metrics: &LLMMetrics) {
+        if self.config.summary_line {
+            tracing::info!(
+                "LLM Request Complete: model={}, prompt_tokens={:?}, completion_tokens={:?}, latency_ms={}, ttft_ms={:?}, upstream_load_ms={:?}, upstream_prompt_eval_ms={:?}, upstream_eval_ms={:?}",
+                metrics.model,
+                metrics.prompt_tokens,
+                metrics.completion_tokens,
+                metrics.latency_ms,
+                metrics.ttft_ms,
+                metrics.upstream_load_duration_ms,
+                metrics.upstream_prompt_eval_duration_ms,
+                metrics.upstream_eval_duration_ms
+            );
+        }
+
+        let formatted = match self.config.format {
+            LogFormat::Pretty => serde_json::to_string_pretty(metrics),
+            LogFormat::Compact => serde_json::to_string(metrics),
+            LogFormat::Logfmt => Ok(to_logfmt(metrics)),
+        };
+        if let Ok(formatted) = formatted {
+            tracing::info!("Metrics: {}", formatted);
+        }
+    }
+}
+
+/// Renders a metrics record as logfmt, skipping fields without a value
+///
+/// Strings are quoted only when they contain spaces, quotes, `=`, or nothing at all.
+pub fn to_logfmt(metrics: &LLMMetrics) -> String {
+    let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(metrics) else {
+        return String::new();
+    };
+
+    let mut line = String::new();
+    for (key, value) in fields {
+        let value = match value {
+            serde_json::Value::Null => continue,
+            serde_json::Value::String(s) => quote_logfmt(&s),
+            other => quote_logfmt(&other.to_string()),
+        };
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&key);
+        line.push('=');
+        line.push_str(&value);
+    }
+    line
+}
+
+fn quote_logfmt(value: &str) -> String {
+    let needs_quotes = value.is_empty() || value.chars().any(|c| c.is_whitespace() || c == '"' || c == '=' || c.is_control());
+    if needs_quotes {
+        // JSON string escaping is a valid logfmt quoted value
+        serde_json::Value::from(value).to_string()
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,29 @@
+use async_trait::async_trait;
+use std::sync::Mutex;
+
+use crate::sinks::MetricsSink;
+use crate::types::LLMMetrics;
+
+/// Sink that keeps every record in memory, useful for tests and embedding
+#[derive(Default)]
+pub struct MemorySink {
+    records: Mutex<Vec<LLMMetrics>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a copy of all records received so far
+    pub fn records(&self) -> Vec<LLMMetrics> {
+        self.records.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl MetricsSink for MemorySink {
+    async fn record(&self, metrics: &LLMMetrics) {
+        self.records.lock().unwrap().push(metrics.clone());
+    }
+}
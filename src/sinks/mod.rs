@@ -0,0 +1,22 @@
+pub mod file;
+pub mod genai;
+pub mod log;
+mod memory;
+pub mod webhook;
+
+pub use file::FileSink;
+pub use genai::GenAiSink;
+pub use log::TracingSink;
+pub use memory::MemorySink;
+pub use webhook::WebhookSink;
+
+use async_trait::async_trait;
+
+use crate::types::LLMMetrics;
+
+/// Destination for completed request metrics
+#[async_trait]
+pub trait MetricsSink: Send + Sync {
+    /// Record the metrics for one completed request
+    async fn record(&self, metrics: &LLMMetrics);
+}
@@ -0,0 +1,129 @@
+use async_trait::async_trait;
+use axum::body::Body;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::task::TaskTracker;
+
+use crate::app::HttpClient;
+use crate::sinks::MetricsSink;
+use crate::types::LLMMetrics;
+
+/// Header carrying the HMAC-SHA256 signature of the payload, as `sha256=<hex>`
+pub const SIGNATURE_HEADER: &str = "x-llm-logger-signature";
+
+/// Webhook delivery settings
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    /// Collector URL that receives a POST per completed request
+    pub url: String,
+    /// Timeout for a single delivery attempt
+    pub timeout_ms: u64,
+    /// Retries after the first failed attempt
+    pub max_retries: u32,
+    /// Signs each payload with HMAC-SHA256 when set
+    pub hmac_secret: Option<String>,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            timeout_ms: 2000,
+            max_retries: 2,
+            hmac_secret: None,
+        }
+    }
+}
+
+/// Sink that POSTs each metrics record as JSON to an external collector
+pub struct WebhookSink {
+    client: Arc<HttpClient>,
+    config: Arc<WebhookConfig>,
+    /// Deliveries in progress, which graceful shutdown waits for
+    tasks: TaskTracker,
+}
+
+impl WebhookSink {
+    pub fn new(client: Arc<HttpClient>, config: WebhookConfig, tasks: TaskTracker) -> Self {
+        Self {
+            client,
+            config: Arc::new(config),
+            tasks,
+        }
+    }
+}
+
+#[async_trait]
+impl MetricsSink for WebhookSink {
+    async fn record(&self, metrics: &LLMMetrics) {
+        let payload = match serde_json::to_vec(metrics) {
+            Ok(p) => p,
+            Err(e) => {
+                tracing::error!("Failed to serialize metrics for webhook: {}", e);
+                return;
+            }
+        };
+
+        // Deliver in the background so retries never hold up other sinks, but on the
+        // shared tracker so shutdown doesn't drop deliveries still in progress
+        let client = self.client.clone();
+        let config = self.config.clone();
+        self.tasks.spawn(async move {
+            deliver(&client, &config, payload).await;
+        });
+    }
+}
+
+/// Sends the payload, retrying with exponential backoff up to `max_retries` times
+async fn deliver(client: &HttpClient, config: &WebhookConfig, payload: Vec<u8>) {
+    let signature = config
+        .hmac_secret
+        .as_deref()
+        .map(|secret| sign_payload(secret, &payload));
+
+    for attempt in 0..=config.max_retries {
+        if attempt > 0 {
+            tokio::time::sleep(Duration::from_millis(100 * 2u64.pow(attempt - 1))).await;
+        }
+
+        let mut builder = hyper::Request::post(&config.url).header("content-type", "application/json");
+        if let Some(signature) = &signature {
+            builder = builder.header(SIGNATURE_HEADER, signature);
+        }
+        let request = match builder.body(Body::from(payload.clone())) {
+            Ok(r) => r,
+            Err(e) => {
+                tracing::error!("Invalid webhook request for {}: {}", config.url, e);
+                return;
+            }
+        };
+
+        let timeout = Duration::from_millis(config.timeout_ms);
+        match tokio::time::timeout(timeout, client.request(request)).await {
+            Ok(Ok(response)) if response.status().is_success() => return,
+            Ok(Ok(response)) => {
+                tracing::warn!("Webhook {} returned status {}", config.url, response.status());
+            }
+            Ok(Err(e)) => tracing::warn!("Webhook {} request failed: {}", config.url, e),
+            Err(_) => tracing::warn!("Webhook {} timed out after {:?}", config.url, timeout),
+        }
+    }
+
+    tracing::error!(
+        "Giving up on webhook {} after {} attempts",
+        config.url,
+        config.max_retries + 1
+    );
+}
+
+/// Computes the `sha256=<hex>` HMAC signature for a payload
+pub fn sign_payload(secret: &str, payload: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(payload);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+
+use axum::extract::State;
+use axum::Json;
+use serde::Serialize;
+
+use crate::app::AppState;
+use crate::circuit_breaker::BreakerStatus;
+use crate::limiter::ConcurrencyStatus;
+use crate::types::LLMMetrics;
+
+/// Distinct models tracked individually; later ones are folded into `OTHER_MODELS`
+pub const MAX_TRACKED_MODELS: usize = 256;
+
+/// Bucket for models seen after `MAX_TRACKED_MODELS` was reached
+pub const OTHER_MODELS: &str = "(other)";
+
+/// Running totals for one slice of traffic
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Totals {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_latency_ms: u64,
+    pub average_latency_ms: f64,
+}
+
+impl Totals {
+    fn record(&mut self, metrics: &LLMMetrics) {
+        self.requests += 1;
+        self.prompt_tokens += u64::from(metrics.prompt_tokens.unwrap_or(0));
+        self.completion_tokens += u64::from(metrics.completion_tokens.unwrap_or(0));
+        self.total_latency_ms += metrics.latency_ms;
+        self.average_latency_ms = self.total_latency_ms as f64 / self.requests as f64;
+    }
+}
+
+/// Aggregates of every request since startup, served at `/stats`
+///
+/// Only totals are kept, so memory stays flat however many requests pass through.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Stats {
+    #[serde(flatten)]
+    pub totals: Totals,
+    pub models: BTreeMap<String, Totals>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Folds one completed request into the aggregates
+    pub fn record(&mut self, metrics: &LLMMetrics) {
+        self.totals.record(metrics);
+
+        let model = if self.models.contains_key(&metrics.model) || self.models.len() < MAX_TRACKED_MODELS {
+            metrics.model.as_str()
+        } else {
+            OTHER_MODELS
+        };
+        self.models.entry(model.to_string()).or_default().record(metrics);
+    }
+}
+
+/// Body of `/stats`: the aggregates plus each upstream's circuit breaker and current concurrency
+#[derive(Clone, Debug, Serialize)]
+pub struct StatsResponse {
+    #[serde(flatten)]
+    pub stats: Stats,
+    /// Breakers keyed by upstream address; empty while breakers are disabled
+    pub breakers: BTreeMap<String, BreakerStatus>,
+    /// Requests holding or waiting for a concurrency slot
+    pub concurrency: ConcurrencyStatus,
+}
+
+/// Serves the aggregates, breaker states, and concurrency as JSON
+pub async fn stats_handler(State(state): State<AppState>) -> Json<StatsResponse> {
+    let stats = state.stats.read().unwrap_or_else(|e| e.into_inner()).clone();
+    Json(StatsResponse {
+        stats,
+        breakers: state.breakers.snapshot(),
+        concurrency: state.limiters.status(),
+    })
+}
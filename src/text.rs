@@ -0,0 +1,67 @@
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// The first `max_chars` characters of `text`, or all of it when `max_chars` is 0
+///
+/// Cuts on a char boundary so the excerpt is always valid UTF-8.
+pub fn excerpt(text: &str, max_chars: usize) -> &str {
+    if max_chars == 0 {
+        return text;
+    }
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+/// Hex-encoded SHA-256 of `text`, for matching identical texts without storing them
+pub fn sha256_hex(text: &str) -> String {
+    hex::encode(Sha256::digest(text.as_bytes()))
+}
+
+/// Hex-encoded SHA-256 of `text` with whitespace runs collapsed to single spaces and
+/// the ends trimmed, so copies differing only in formatting hash alike
+pub fn normalized_sha256(text: &str) -> String {
+    let mut hasher = Sha256::new();
+    for (i, word) in text.split_whitespace().enumerate() {
+        if i > 0 {
+            hasher.update(b" ");
+        }
+        hasher.update(word.as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// How much of the user's prompt (and captured response text) a metrics record keeps
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TextLogging {
+    /// The text as-is
+    #[default]
+    Full,
+    /// `sha256:<hex> chars:<count>`, enough to match identical texts
+    Hash,
+    /// The first `max_chars` characters, ending in `…` when anything was cut
+    Truncated,
+    /// Nothing
+    None,
+}
+
+impl TextLogging {
+    /// What a record keeps of `text`; `max_chars` applies to `Truncated` only
+    pub fn render(self, text: &str, max_chars: usize) -> String {
+        match self {
+            TextLogging::Full => text.to_string(),
+            TextLogging::Hash => format!("sha256:{} chars:{}", sha256_hex(text), text.chars().count()),
+            TextLogging::Truncated => {
+                let cut = excerpt(text, max_chars);
+                if cut.len() < text.len() {
+                    format!("{}…", cut)
+                } else {
+                    cut.to_string()
+                }
+            }
+            TextLogging::None => String::new(),
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Where a tokens-per-second figure came from
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThroughputSource {
+    /// Upstream-reported generation time (Ollama `eval_duration`)
+    Upstream,
+    /// Proxy-measured time between the first content chunk and the last chunk
+    Proxy,
+}
+
+/// Decode throughput for a single response
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Throughput {
+    pub generation_time_ms: Option<u64>,
+    pub tokens_per_second: Option<f64>,
+    pub source: Option<ThroughputSource>,
+}
+
+/// Computes decode throughput, preferring the upstream-reported generation time
+///
+/// `first_content` and `last_chunk` are offsets from the request start. A response
+/// whose content arrived in a single chunk has no measurable generation window, so
+/// no rate is reported for it.
+pub fn compute_throughput(
+    completion_tokens: Option<u32>,
+    first_content: Option<Duration>,
+    last_chunk: Option<Duration>,
+    upstream_eval_ms: Option<u64>,
+) -> Throughput {
+    let (generation_time, source) = match (upstream_eval_ms, first_content, last_chunk) {
+        (Some(eval_ms), _, _) => (Duration::from_millis(eval_ms), ThroughputSource::Upstream),
+        (None, Some(first), Some(last)) if last > first => (last - first, ThroughputSource::Proxy),
+        _ => return Throughput::default(),
+    };
+
+    let secs = generation_time.as_secs_f64();
+    let tokens_per_second = match completion_tokens {
+        Some(tokens) if secs > 0.0 => Some(f64::from(tokens) / secs),
+        _ => None,
+    };
+
+    Throughput {
+        generation_time_ms: Some(generation_time.as_millis() as u64),
+        tokens_per_second,
+        source: tokens_per_second.map(|_| source),
+    }
+}
+
+/// Where a request's time went, separating the proxy's share from the upstream's
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Timings {
+    /// Reading the client's request body in the middleware
+    pub body_read_ms: f64,
+    /// From sending the request upstream to the first response body frame
+    pub upstream_ttfb_ms: Option<f64>,
+    /// From the first response body frame to the last
+    pub stream_ms: Option<f64>,
+    /// Time from arrival to the end of the response that the upstream doesn't
+    /// account for: reading the body, queueing, failed attempts, and forwarding
+    pub proxy_overhead_ms: f64,
+}
+
+/// Points in a request's life, as offsets from its arrival at the proxy
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimingMarks {
+    /// The request body was fully read
+    pub body_read: Duration,
+    /// The request went to the upstream that answered it
+    pub sent: Duration,
+    /// The upstream's response headers arrived
+    pub headers: Duration,
+    pub first_frame: Option<Duration>,
+    pub last_frame: Option<Duration>,
+    /// The response finished
+    pub finished: Duration,
+}
+
+impl Timings {
+    /// Splits a request's time into phases
+    ///
+    /// The upstream accounts for the time from sending it the request until its last
+    /// body frame (or its headers, for an empty body); the rest is the proxy's.
+    pub fn new(marks: &TimingMarks) -> Self {
+        let upstream_done = marks.last_frame.unwrap_or(marks.headers);
+        let upstream = upstream_done.saturating_sub(marks.sent);
+        Self {
+            body_read_ms: millis(marks.body_read),
+            upstream_ttfb_ms: marks.first_frame.map(|first| millis(first.saturating_sub(marks.sent))),
+            stream_ms: marks
+                .first_frame
+                .zip(marks.last_frame)
+                .map(|(first, last)| millis(last.saturating_sub(first))),
+            proxy_overhead_ms: millis(marks.finished.saturating_sub(upstream)),
+        }
+    }
+}
+
+/// Milliseconds to the nearest microsecond
+fn millis(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 1_000_000.0).round() / 1000.0
+}
+
+/// Number of gaps retained for the p95 estimate
+const GAP_RESERVOIR_SIZE: usize = 256;
+
+/// Summary of the gaps between consecutive data frames
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChunkGapSummary {
+    pub min_ms: f64,
+    pub mean_ms: f64,
+    pub max_ms: f64,
+    pub p95_ms: f64,
+}
+
+/// Streaming accumulator for inter-chunk gaps with bounded memory
+///
+/// Min, max, and mean are exact. The p95 comes from a fixed-size uniform
+/// reservoir sample, so long generations never store more than
+/// `GAP_RESERVOIR_SIZE` gaps.
+#[derive(Debug)]
+pub struct ChunkGapStats {
+    last_frame: Option<Duration>,
+    count: u64,
+    sum_ms: f64,
+    min_ms: f64,
+    max_ms: f64,
+    reservoir: Vec<f64>,
+    rng_state: u64,
+}
+
+impl Default for ChunkGapStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChunkGapStats {
+    pub fn new() -> Self {
+        Self {
+            last_frame: None,
+            count: 0,
+            sum_ms: 0.0,
+            min_ms: f64::INFINITY,
+            max_ms: 0.0,
+            reservoir: Vec::with_capacity(GAP_RESERVOIR_SIZE),
+            rng_state: 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+
+    /// Records a data frame arriving at `at` (offset from the request start)
+    pub fn record_frame(&mut self, at: Duration) {
+        if let Some(last) = self.last_frame.replace(at) {
+            self.record_gap(at.saturating_sub(last).as_secs_f64() * 1000.0);
+        }
+    }
+
+    fn record_gap(&mut self, gap_ms: f64) {
+        self.count += 1;
+        self.sum_ms += gap_ms;
+        self.min_ms = self.min_ms.min(gap_ms);
+        self.max_ms = self.max_ms.max(gap_ms);
+
+        // Algorithm R: keep each gap with probability size / count
+        if self.reservoir.len() < GAP_RESERVOIR_SIZE {
+            self.reservoir.push(gap_ms);
+        } else {
+            let slot = self.next_random() % self.count;
+            if let Some(entry) = self.reservoir.get_mut(slot as usize) {
+                *entry = gap_ms;
+            }
+        }
+    }
+
+    /// xorshift64; statistical quality is plenty for reservoir sampling
+    fn next_random(&mut self) -> u64 {
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng_state = x;
+        x
+    }
+
+    /// Returns the summary, or None if fewer than two frames were seen
+    pub fn summary(&self) -> Option<ChunkGapSummary> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let mut sorted = self.reservoir.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let rank = ((sorted.len() as f64) * 0.95).ceil() as usize;
+        let p95_ms = sorted[rank.saturating_sub(1).min(sorted.len() - 1)];
+
+        Some(ChunkGapSummary {
+            min_ms: self.min_ms,
+            mean_ms: self.sum_ms / self.count as f64,
+            max_ms: self.max_ms,
+            p95_ms,
+        })
+    }
+}
@@ -0,0 +1,320 @@
+use crate::types::TokenUsage;
+
+/// Rough token estimate for text whose provider-reported count isn't available
+///
+/// Uses the common rule of thumb of one token per four characters, which is
+/// close enough for English prose across the popular BPE vocabularies.
+pub fn estimate_tokens(text: &str) -> u32 {
+    estimate_tokens_from_chars(text.chars().count() as u64)
+}
+
+fn estimate_tokens_from_chars(chars: u64) -> u32 {
+    chars.div_ceil(4).min(u64::from(u32::MAX)) as u32
+}
+
+/// Counts tokens in text for one family of models
+pub trait TokenEstimator: Send + Sync {
+    fn estimate(&self, text: &str) -> u32;
+}
+
+/// The flat characters-per-token rule of thumb behind `estimate_tokens`
+pub struct CharsPerToken(pub f64);
+
+impl TokenEstimator for CharsPerToken {
+    fn estimate(&self, text: &str) -> u32 {
+        let chars = text.chars().count() as f64;
+        (chars / self.0.max(1.0)).ceil().min(f64::from(u32::MAX)) as u32
+    }
+}
+
+/// Approximates how BPE vocabularies (GPT, Claude, Llama) split text
+///
+/// A short word is one token, longer words split every six letters, digits
+/// group in threes, and each punctuation mark or non-ASCII character is its
+/// own token. Close to the real count for English prose; punctuation-heavy
+/// text such as code comes out high.
+pub struct LexicalEstimator;
+
+impl TokenEstimator for LexicalEstimator {
+    fn estimate(&self, text: &str) -> u32 {
+        let mut tokens = 0u64;
+        let mut letters = 0u64;
+        let mut digits = 0u64;
+
+        for c in text.chars() {
+            if c.is_ascii_alphabetic() {
+                tokens += digits.div_ceil(3);
+                digits = 0;
+                letters += 1;
+                continue;
+            }
+            if c.is_ascii_digit() {
+                tokens += letters.div_ceil(6);
+                letters = 0;
+                digits += 1;
+                continue;
+            }
+            tokens += letters.div_ceil(6) + digits.div_ceil(3);
+            letters = 0;
+            digits = 0;
+            if !c.is_whitespace() {
+                tokens += 1;
+            }
+        }
+        tokens += letters.div_ceil(6) + digits.div_ceil(3);
+        tokens.min(u64::from(u32::MAX)) as u32
+    }
+}
+
+/// Picks a token estimator by model name, for backfilling counts the upstream didn't report
+pub struct TokenEstimators {
+    /// Lowercase model-name prefixes and their estimators, longest prefix wins
+    families: Vec<(String, Box<dyn TokenEstimator>)>,
+    default: Box<dyn TokenEstimator>,
+}
+
+impl TokenEstimators {
+    pub fn new(default: Box<dyn TokenEstimator>) -> Self {
+        Self {
+            families: Vec::new(),
+            default,
+        }
+    }
+
+    /// Uses `estimator` for models whose name starts with `prefix` (case-insensitive)
+    pub fn with_family(mut self, prefix: &str, estimator: Box<dyn TokenEstimator>) -> Self {
+        self.families.push((prefix.to_ascii_lowercase(), estimator));
+        self
+    }
+
+    /// The estimator for `model`
+    pub fn for_model(&self, model: &str) -> &dyn TokenEstimator {
+        let model = model.to_ascii_lowercase();
+        self.families
+            .iter()
+            .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default.as_ref(), |(_, estimator)| estimator.as_ref())
+    }
+
+    /// Estimates completion tokens from the captured text when the upstream reported none
+    pub fn backfill(&self, model: &str, usage: &mut TokenUsage) {
+        if usage.completion_tokens.is_some() || usage.completion_text.is_empty() {
+            return;
+        }
+        usage.completion_tokens = Some(self.for_model(model).estimate(&usage.completion_text));
+        usage.estimated = true;
+    }
+}
+
+impl Default for TokenEstimators {
+    fn default() -> Self {
+        Self::new(Box::new(LexicalEstimator))
+    }
+}
+
+/// Model-name prefixes tokenized with `o200k_base`; everything else uses `cl100k_base`
+pub const O200K_MODEL_PREFIXES: &[&str] = &["gpt-4o", "chatgpt-4o", "gpt-4.1", "gpt-4.5", "gpt-5", "o1", "o3", "o4"];
+
+/// Exact token counts from one of OpenAI's BPE vocabularies (`tiktoken` feature)
+#[cfg(feature = "tiktoken")]
+pub struct Tiktoken(&'static tiktoken_rs::CoreBPE);
+
+#[cfg(feature = "tiktoken")]
+impl Tiktoken {
+    pub fn cl100k() -> Self {
+        Self(tiktoken_rs::cl100k_base_singleton())
+    }
+
+    pub fn o200k() -> Self {
+        Self(tiktoken_rs::o200k_base_singleton())
+    }
+}
+
+#[cfg(feature = "tiktoken")]
+impl TokenEstimator for Tiktoken {
+    fn estimate(&self, text: &str) -> u32 {
+        self.0.encode_ordinary(text).len().min(u32::MAX as usize) as u32
+    }
+}
+
+/// The local tokenizer behind `estimated_prompt_tokens` and `estimated_completion_tokens`
+///
+/// `None` unless built with the `tiktoken` feature.
+pub fn local_tokenizer() -> Option<TokenEstimators> {
+    #[cfg(feature = "tiktoken")]
+    {
+        let tokenizer = O200K_MODEL_PREFIXES
+            .iter()
+            .fold(TokenEstimators::new(Box::new(Tiktoken::cl100k())), |tokenizer, prefix| {
+                tokenizer.with_family(prefix, Box::new(Tiktoken::o200k()))
+            });
+        Some(tokenizer)
+    }
+    #[cfg(not(feature = "tiktoken"))]
+    None
+}
+
+/// Progress through a JSON escape sequence inside a string
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    Backslash,
+    /// `\uXXXX` with this many hex digits still to read
+    Unicode { remaining: u8, value: u16 },
+}
+
+/// Counts prompt tokens while a JSON request body streams past
+///
+/// Tracks only the top-level `"prompt"` string, decoding escapes and UTF-8 on
+/// the fly, so the count is available without parsing the full body. The result
+/// equals `estimate_tokens` applied to the decoded prompt.
+#[derive(Debug)]
+pub struct PromptTokenCounter {
+    depth: u32,
+    top_is_object: bool,
+    expecting_key: bool,
+    in_string: bool,
+    escape: Escape,
+    capturing_key: bool,
+    key: Vec<u8>,
+    last_key_is_prompt: bool,
+    in_prompt: bool,
+    prompt_chars: u64,
+    found: bool,
+}
+
+impl Default for PromptTokenCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PromptTokenCounter {
+    pub fn new() -> Self {
+        Self {
+            depth: 0,
+            top_is_object: false,
+            expecting_key: false,
+            in_string: false,
+            escape: Escape::None,
+            capturing_key: false,
+            key: Vec::new(),
+            last_key_is_prompt: false,
+            in_prompt: false,
+            prompt_chars: 0,
+            found: false,
+        }
+    }
+
+    /// Feeds the next slice of the request body
+    pub fn feed(&mut self, chunk: &[u8]) {
+        for &byte in chunk {
+            if self.in_string {
+                self.feed_string_byte(byte);
+            } else {
+                self.feed_structural_byte(byte);
+            }
+        }
+    }
+
+    /// Estimated prompt tokens, or None if no complete top-level prompt string was seen
+    pub fn prompt_tokens(&self) -> Option<u32> {
+        self.found.then(|| estimate_tokens_from_chars(self.prompt_chars))
+    }
+
+    fn at_top_level_object(&self) -> bool {
+        self.depth == 1 && self.top_is_object
+    }
+
+    fn feed_structural_byte(&mut self, byte: u8) {
+        match byte {
+            b'"' => {
+                self.in_string = true;
+                if self.at_top_level_object() {
+                    if self.expecting_key {
+                        self.capturing_key = true;
+                        self.key.clear();
+                    } else if self.last_key_is_prompt {
+                        self.in_prompt = true;
+                        self.prompt_chars = 0;
+                    }
+                }
+            }
+            b'{' | b'[' => {
+                self.depth += 1;
+                if self.depth == 1 {
+                    self.top_is_object = byte == b'{';
+                    self.expecting_key = self.top_is_object;
+                }
+            }
+            b'}' | b']' => self.depth = self.depth.saturating_sub(1),
+            b':' if self.at_top_level_object() => self.expecting_key = false,
+            b',' if self.at_top_level_object() => {
+                self.expecting_key = true;
+                self.last_key_is_prompt = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn feed_string_byte(&mut self, byte: u8) {
+        match self.escape {
+            Escape::Backslash => {
+                if byte == b'u' {
+                    self.escape = Escape::Unicode { remaining: 4, value: 0 };
+                } else {
+                    self.escape = Escape::None;
+                    self.push_char(b'\\');
+                }
+            }
+            Escape::Unicode { remaining, value } => {
+                let digit = (byte as char).to_digit(16).unwrap_or(0) as u16;
+                let value = (value << 4) | digit;
+                if remaining > 1 {
+                    self.escape = Escape::Unicode { remaining: remaining - 1, value };
+                } else {
+                    self.escape = Escape::None;
+                    // A surrogate pair is two escapes but one character; count its low half
+                    if !(0xD800..=0xDBFF).contains(&value) {
+                        self.push_char(b'\\');
+                    }
+                }
+            }
+            Escape::None => match byte {
+                b'\\' => self.escape = Escape::Backslash,
+                b'"' => self.end_string(),
+                // UTF-8 continuation bytes belong to the character already counted
+                _ if byte & 0xC0 == 0x80 => {
+                    if self.capturing_key && self.key.len() <= 6 {
+                        self.key.push(byte);
+                    }
+                }
+                _ => self.push_char(byte),
+            },
+        }
+    }
+
+    /// Records one decoded character; `byte` is its raw form for key matching
+    fn push_char(&mut self, byte: u8) {
+        if self.in_prompt {
+            self.prompt_chars += 1;
+        }
+        // Keys only need to match the ASCII literal "prompt"; longer keys can never match
+        if self.capturing_key && self.key.len() <= 6 {
+            self.key.push(byte);
+        }
+    }
+
+    fn end_string(&mut self) {
+        self.in_string = false;
+        if self.capturing_key {
+            self.capturing_key = false;
+            self.last_key_is_prompt = self.key == b"prompt";
+        }
+        if self.in_prompt {
+            self.in_prompt = false;
+            self.found = true;
+        }
+    }
+}
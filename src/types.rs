@@ -1,19 +1,158 @@
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use serde::de::{self, IgnoredAny, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use crate::headers::Annotations;
+use crate::parsers::{detect_backend_from_path, BackendType, RequestShape};
+use crate::timing::{ThroughputSource, Timings};
 
 /// Data extracted from the request body
 #[derive(Clone, Debug)]
 pub struct RequestData {
+    /// Unique ID assigned to this request by the proxy
+    pub request_id: String,
     pub model: String,
+    /// The prompt as configured by `prompt_capture`, without the system prompt
     pub prompt: String,
+    /// System and developer messages, after any top-level `system` prompt
+    pub system_prompt: Option<String>,
+    /// Characters in the full prompt, whatever `prompt` keeps of it
+    pub full_prompt_chars: u64,
+    /// The full prompt, kept only for the local tokenizer; `None` when it isn't
+    /// running or the request carried no prompt
+    pub full_prompt: Option<String>,
+    /// Normalized hash of the full prompt; `None` when no prompt was parsed
+    pub prompt_sha256: Option<String>,
+    /// Prompt tokens estimated while the request body streamed in
+    pub streamed_prompt_tokens: Option<u32>,
+    /// Which prompt field the body used, a hint at the backend's API
+    pub request_shape: RequestShape,
+    /// The request asked for token log probabilities, and how many alternatives per token
+    pub logprobs_requested: bool,
+    pub top_logprobs: Option<u32>,
+    /// Sampling parameters the request set
+    pub params: SamplingParams,
+    /// False when the body was read as JSON but didn't match any known request format
+    pub request_parse_ok: bool,
+    /// Who sent the request
+    pub client: ClientInfo,
+    /// Short SHA-256 fingerprint of the request's API key, and its configured label
+    pub api_key_fingerprint: Option<String>,
+    pub api_key_label: Option<String>,
+    /// Tags, metadata, and conversation ID from the `x-llm-logger-*` headers
+    pub annotations: Annotations,
+    /// Number of messages in a chat request
+    pub turn_index: Option<u32>,
+    /// Messages in the prompt, counting a separate system prompt
+    pub message_count: Option<u32>,
+    /// The prompt includes a system (or developer) message
+    pub system_prompt_present: bool,
+    /// Shape of a chat request's messages
+    pub prompt_stats: Option<PromptStats>,
+    /// Images, audio, and inline payload bytes attached to the request
+    pub attachments: Attachments,
+    /// Tools the request offered the model, and the first `MAX_TOOLS_OFFERED` names
+    pub tools_offered_count: u32,
+    pub tools_offered: Vec<String>,
+    /// The request asked for a streamed response
+    pub streamed: bool,
+    /// When the request reached the proxy
+    pub arrival: Arrival,
+    /// How long the middleware took to read the request body
+    pub body_read: Duration,
     #[allow(dead_code)]
     pub raw_body: bytes::Bytes,
 }
 
+/// When a request reached the proxy, on both the wall clock and the monotonic clock
+///
+/// An `Instant` can't be turned into a date, so the wall-clock reading is kept
+/// alongside it and later times are derived from the elapsed monotonic time.
+#[derive(Clone, Copy, Debug)]
+pub struct Arrival {
+    pub wall: SystemTime,
+    pub instant: tokio::time::Instant,
+}
+
+impl Arrival {
+    pub fn now() -> Self {
+        Self {
+            wall: SystemTime::now(),
+            instant: tokio::time::Instant::now(),
+        }
+    }
+
+    /// Wall-clock start of the request and, as of now, its end
+    pub fn bounds(&self) -> (DateTime<Utc>, DateTime<Utc>) {
+        let started = DateTime::<Utc>::from(self.wall);
+        let elapsed = chrono::Duration::from_std(self.instant.elapsed()).unwrap_or_default();
+        (started, started + elapsed)
+    }
+}
+
+/// Structure of a chat request's messages
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct PromptStats {
+    /// Messages per role, counting a separate system prompt as `system`
+    pub roles: BTreeMap<String, u32>,
+    /// Characters of system prompt, across any system and developer messages
+    pub system_prompt_chars: u64,
+    /// Some message includes an image part
+    pub has_images: bool,
+}
+
+/// Identifies the client behind a request in a shared deployment
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct ClientInfo {
+    /// Socket address the request came from
+    pub addr: Option<String>,
+    pub user_agent: Option<String>,
+    /// Value of the configured `client_identity_header`; a SHA-256 for credential headers
+    pub identity: Option<String>,
+}
+
 /// Token usage information
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct TokenUsage {
     pub prompt_tokens: Option<u32>,
     pub completion_tokens: Option<u32>,
+    /// Total as reported by the upstream, which some gateways don't keep equal to the parts
+    pub total_tokens: Option<u32>,
+    /// Prompt tokens written to the provider's prompt cache
+    pub cache_write_tokens: Option<u32>,
+    /// Prompt tokens read from the provider's prompt cache
+    pub cache_read_tokens: Option<u32>,
+    /// Upstream-reported timings from Ollama's final chunk
+    pub total_duration_ms: Option<u64>,
+    pub load_duration_ms: Option<u64>,
+    pub prompt_eval_duration_ms: Option<u64>,
+    /// Upstream-reported generation time (Ollama `eval_duration`)
+    pub eval_duration_ms: Option<u64>,
+    /// Parsed SSE events or NDJSON lines
+    pub event_count: u64,
+    /// Tool calls started in the response (each new call carries an `id`)
+    pub tool_call_count: u32,
+    /// Buffered data exceeded the parser's cap and was discarded
+    pub parse_truncated: bool,
+    /// Generated text accumulated from the stream, if the parser was asked to keep it
+    pub completion_text: String,
+    /// `completion_text` reached the parser's limit and the rest was dropped
+    pub completion_text_truncated: bool,
+    /// Why the upstream stopped generating, as reported on the final chunk
+    pub finish_reason: Option<String>,
+    /// Model named by the first chunk that carries one
+    pub response_model: Option<String>,
+    /// Some chunk carried a non-null `logprobs` object
+    pub logprobs_returned: bool,
+    /// `completion_tokens` was estimated from the text, not reported by the upstream
+    pub estimated: bool,
+    /// The backend's end-of-response signal was seen (`[DONE]`, `done: true`, ...)
+    pub saw_terminal: bool,
 }
 
 impl TokenUsage {
@@ -21,43 +160,666 @@ impl TokenUsage {
         Self {
             prompt_tokens,
             completion_tokens,
+            ..Self::default()
+        }
+    }
+
+    /// The reported total, or the sum of the parts when both are known
+    pub fn total(&self) -> Option<u32> {
+        let sum = self
+            .prompt_tokens
+            .zip(self.completion_tokens)
+            .map(|(prompt, completion)| prompt.saturating_add(completion));
+        match (self.total_tokens, sum) {
+            (Some(reported), Some(sum)) if reported != sum => {
+                tracing::debug!(
+                    "Upstream total_tokens={} differs from prompt + completion = {}, keeping the reported total",
+                    reported,
+                    sum
+                );
+                Some(reported)
+            }
+            (reported, sum) => reported.or(sum),
+        }
+    }
+
+    /// Appends generated text, keeping at most `limit` bytes in `completion_text`
+    pub fn push_completion_text(&mut self, text: &str, limit: usize) {
+        if self.completion_text_truncated || limit == 0 {
+            return;
+        }
+        let room = limit.saturating_sub(self.completion_text.len());
+        if text.len() <= room {
+            self.completion_text.push_str(text);
+            return;
+        }
+        let mut end = room;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.completion_text.push_str(&text[..end]);
+        self.completion_text_truncated = true;
+    }
+
+    /// Keeps the first non-empty model name the response reports
+    pub fn record_response_model(&mut self, model: Option<&str>) {
+        if self.response_model.is_none() {
+            self.response_model = model.filter(|m| !m.is_empty()).map(str::to_string);
         }
     }
 }
 
+/// Layout of `LLMMetrics` records, written to every record as `schema_version`
+///
+/// Bumped whenever a field is removed or renamed, or changes type or meaning, with a
+/// fixture of the new layout added under `tests/fixtures/schema/`. New fields alone
+/// don't bump it: older readers ignore them and older records deserialize with
+/// their defaults.
+pub const SCHEMA_VERSION: u32 = 2;
+
 /// Complete metrics for a single LLM request
-#[derive(Clone, Debug, Serialize)]
+///
+/// Deserializes records from any earlier schema version; fields they lack take
+/// their defaults, and records from before versioning read as version 0.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct LLMMetrics {
+    /// `SCHEMA_VERSION` when the record was written
+    pub schema_version: u32,
+    /// ID the proxy assigned, also returned to the client in `x-llm-logger-request-id`
+    pub request_id: String,
+    /// The upstream's own `x-request-id`, for matching the provider's logs
+    pub upstream_request_id: Option<String>,
+    /// Backend whose parser handled the response
+    pub backend: BackendType,
+    /// Port of the loopback upstream that served the request
+    pub backend_port: u16,
+    /// URL the request was sent to, without its query string
+    pub upstream_url: String,
+    /// Upstream response status, or 502 when the upstream couldn't be reached
+    pub status_code: u16,
+    /// A 2xx response whose stream completed
+    pub success: bool,
+    /// Model named in the request, which may be an alias
     pub model: String,
+    /// Model the upstream reports serving, e.g. a dated snapshot or fully qualified tag
+    pub response_model: Option<String>,
+    /// Log probabilities asked for in the request and whether the response included any
+    pub logprobs_requested: bool,
+    pub top_logprobs: Option<u32>,
+    pub logprobs_returned: bool,
+    /// Sampling parameters the request set; unset ones are omitted
+    pub params: SamplingParams,
+    /// False when the request body couldn't be parsed, leaving `model` and `prompt` unknown
+    pub request_parse_ok: bool,
+    /// Who sent the request
+    pub client: ClientInfo,
+    /// First 8 hex characters of the SHA-256 of the request's API key; the key itself
+    /// is never recorded
+    pub api_key_fingerprint: Option<String>,
+    /// Name configured for the fingerprint in `api_key_labels`
+    pub api_key_label: Option<String>,
+    /// Caller-supplied labels from the `x-llm-logger-tags` and `x-llm-logger-meta` headers
+    pub tags: Vec<String>,
+    pub metadata: BTreeMap<String, String>,
+    /// Groups the turns of one chat: the `x-llm-logger-conversation-id` header, or a
+    /// hash of the conversation's opening messages
+    pub conversation_id: Option<String>,
+    /// Request ID of the archived request this one replays, via `/replay/:request_id`
+    pub replay_of: Option<String>,
+    /// Number of messages in the request, which grows by at least two each turn
+    pub turn_index: Option<u32>,
+    /// The request asked for a streamed response, explicitly or by the endpoint's default
+    pub streamed: bool,
+    /// The user-side prompt; system prompts are recorded separately since schema version 2
     pub prompt: String,
+    /// Characters in the captured prompt (the last user message under
+    /// `prompt_capture = "last_user_message"`) before `prompt_logging` cut or hashed it;
+    /// `prompt_chars` counts the full prompt
+    pub prompt_len: u64,
+    /// SHA-256 of the full prompt with whitespace normalized, for counting repeated
+    /// prompts; unaffected by `prompt_capture` and `prompt_logging`
+    pub prompt_sha256: Option<String>,
+    /// Messages in the prompt, counting a separate system prompt; absent for plain prompts
+    pub message_count: Option<u32>,
+    /// The prompt includes a system (or developer) message
+    pub system_prompt_present: bool,
+    /// The system prompt as `system_prompt_logging` keeps it
+    pub system_prompt: Option<String>,
+    /// SHA-256 of the system prompt with whitespace normalized, for grouping requests
+    /// by system prompt version without storing it
+    pub system_prompt_hash: Option<String>,
+    /// Roles, system prompt size, and images of a chat request; absent for plain prompts
+    pub prompt_stats: Option<PromptStats>,
+    /// Image and audio parts in the request, however they were attached
+    pub image_count: u32,
+    pub audio_count: u32,
+    /// Bytes of inline (base64 or data-URI) attachment payloads, as encoded in the body
+    pub attachment_bytes: u64,
+    /// Number of tools (or legacy functions) the request offered the model
+    pub tools_offered_count: u32,
+    /// Names of the offered tools, in request order, up to `MAX_TOOLS_OFFERED`
+    pub tools_offered: Vec<String>,
     pub prompt_tokens: Option<u32>,
     pub completion_tokens: Option<u32>,
+    /// `completion_tokens` is an estimate because the upstream didn't report usage
+    pub tokens_estimated: bool,
+    /// Upstream-reported total, else prompt + completion when both are known
+    pub total_tokens: Option<u32>,
+    /// Prompt-cache writes and reads, which are billed at different rates than other input
+    pub cache_write_tokens: Option<u32>,
+    pub cache_read_tokens: Option<u32>,
+    /// Estimated cost in USD; None when the model has no configured price
+    pub cost_usd: Option<f64>,
+    /// Estimate counted from the request body's `prompt` field, independent of the upstream
+    pub streamed_prompt_tokens: Option<u32>,
+    /// Prompt and response text counted by the local tokenizer (`tiktoken` feature);
+    /// never substituted for the upstream's `prompt_tokens` or `completion_tokens`
+    pub estimated_prompt_tokens: Option<u32>,
+    pub estimated_completion_tokens: Option<u32>,
+    /// Time from the request reaching the proxy until the upstream's response headers
+    /// arrived, including any wait for a concurrency slot
+    pub upstream_connect_ms: Option<u64>,
+    /// Time from the request reaching the proxy until the response finished
+    pub total_ms: u64,
+    /// Breakdown of `total_ms` into body reading, upstream, and proxy time; absent
+    /// when the upstream never answered
+    pub timings: Option<Timings>,
+    /// Same as `total_ms`, kept for existing consumers
     pub latency_ms: u64,
+    /// Upstream attempts, counting failovers and retries (0 when none was sent)
+    pub attempts: u8,
+    /// Whether the request took more than one attempt
+    pub retried: bool,
+    /// From sending the final attempt to the end of the response; `total_ms` also
+    /// covers failed attempts and the backoff between them
+    pub attempt_latency_ms: Option<u64>,
+    /// Time until the first content-bearing chunk (equals latency for non-streaming responses)
+    pub ttft_ms: Option<u64>,
+    /// Time spent generating the completion
+    pub generation_time_ms: Option<u64>,
+    /// Decode throughput, and whether it was upstream-reported or proxy-measured
+    pub tokens_per_second: Option<f64>,
+    pub tokens_per_second_source: Option<ThroughputSource>,
+    /// Upstream-reported phase timings (Ollama): total, model load, prompt eval, generation
+    pub upstream_total_duration_ms: Option<u64>,
+    pub upstream_load_duration_ms: Option<u64>,
+    pub upstream_prompt_eval_duration_ms: Option<u64>,
+    pub upstream_eval_duration_ms: Option<u64>,
+    /// Gaps between consecutive upstream data frames
+    pub chunk_gap_ms_min: Option<f64>,
+    pub chunk_gap_ms_mean: Option<f64>,
+    pub chunk_gap_ms_max: Option<f64>,
+    pub chunk_gap_ms_p95: Option<f64>,
+    /// Total response body bytes, upstream data frames, and parsed events
+    pub response_bytes: u64,
+    /// Also read as `chunk_count`
+    #[serde(alias = "chunk_count")]
+    pub frame_count: u64,
+    pub event_count: u64,
+    /// Whether the response invoked tools, and how many calls it started
+    pub had_tool_calls: bool,
+    pub tool_call_count: u32,
+    /// The upstream stalled and the stream was cut off; counts may be partial
+    pub truncated: bool,
+    /// The parser dropped oversized undelimited data; token counts may be missing
+    pub parse_truncated: bool,
+    /// The client went away before the response finished streaming
+    pub client_disconnected: bool,
+    /// The parser saw the backend's end-of-response signal and the body ended cleanly
+    pub completed: bool,
+    /// Why reading the upstream body failed, if it did
+    pub stream_error: Option<String>,
+    /// Why the request never reached the upstream, if it didn't
+    pub upstream_error: Option<String>,
+    /// Trailer fields the upstream sent after the body, e.g. `grpc-status`
+    pub trailers: BTreeMap<String, String>,
+    /// Text lengths and completion-quality heuristics, present when `completion_heuristics` is enabled
+    ///
+    /// `prompt_chars` is also present under `prompt_capture = "last_user_message"`, and
+    /// always counts the full prompt, where `prompt_len` counts only what was captured.
+    pub prompt_chars: Option<u64>,
+    pub completion_chars: Option<u64>,
+    pub empty_completion: Option<bool>,
+    pub looks_truncated: Option<bool>,
+    /// The response text, cut to `response_text_max_chars`, when `log_response_text` is enabled
+    pub response_text: Option<String>,
+    /// SHA-256 of the full response text, when `hash_response_text` is enabled
+    pub response_text_sha256: Option<String>,
+    /// When the request reached the proxy and when its response ended, as RFC 3339
+    /// and as Unix epoch milliseconds
+    pub started_at: String,
+    pub started_at_ms: u64,
+    pub finished_at: String,
+    pub finished_at_ms: u64,
+    /// Same as `finished_at`, kept for existing consumers
     pub timestamp: String,
 }
 
 /// Ollama streaming response format
 #[derive(Debug, Deserialize)]
 pub struct OllamaStreamResponse {
+    #[serde(default)]
+    pub model: Option<String>,
     #[serde(default)]
     pub done: bool,
+    /// Generated text for `/api/generate`
+    #[serde(default)]
+    pub response: Option<String>,
+    /// Generated message for `/api/chat`
+    #[serde(default)]
+    pub message: Option<OllamaMessage>,
     #[serde(default)]
     pub prompt_eval_count: Option<u32>,
     #[serde(default)]
     pub eval_count: Option<u32>,
+    /// Durations in nanoseconds, reported on the final chunk
+    #[serde(default)]
+    pub total_duration: Option<u64>,
+    #[serde(default)]
+    pub load_duration: Option<u64>,
+    #[serde(default)]
+    pub prompt_eval_duration: Option<u64>,
+    #[serde(default)]
+    pub eval_duration: Option<u64>,
+    /// Why generation stopped (`stop`, `length`), reported on the final chunk
+    #[serde(default)]
+    pub done_reason: Option<String>,
+}
+
+/// Message object in Ollama chat responses
+#[derive(Debug, Deserialize)]
+pub struct OllamaMessage {
+    #[serde(default)]
+    pub content: String,
+}
+
+impl OllamaStreamResponse {
+    /// Returns true if this chunk carries generated text
+    pub fn has_content(&self) -> bool {
+        !self.text().is_empty()
+    }
+
+    /// Generated text carried by this chunk, from either endpoint
+    pub fn text(&self) -> &str {
+        match (&self.response, &self.message) {
+            (Some(response), _) if !response.is_empty() => response,
+            (_, Some(message)) => &message.content,
+            _ => "",
+        }
+    }
 }
 
 /// OpenAI-compatible usage format
 #[derive(Debug, Deserialize)]
 pub struct OpenAIUsage {
     pub prompt_tokens: u32,
-    pub completion_tokens: u32,
+    /// Absent on embeddings responses
+    #[serde(default)]
+    pub completion_tokens: Option<u32>,
+    #[serde(default)]
+    pub total_tokens: Option<u32>,
 }
 
 /// OpenAI-compatible response format
 #[derive(Debug, Deserialize)]
 pub struct OpenAIResponse {
+    #[serde(default)]
+    pub model: Option<String>,
     pub usage: Option<OpenAIUsage>,
+    #[serde(default)]
+    pub choices: Vec<OpenAIChoice>,
+}
+
+impl OpenAIResponse {
+    /// Returns true if any choice carries generated text or a tool call
+    pub fn has_content(&self) -> bool {
+        self.choices.iter().any(|c| c.text().is_some_and(|text| !text.is_empty()))
+            || self.deltas().any(|d| d.has_tool_call())
+    }
+
+    /// Returns true if any choice carries log probabilities
+    pub fn has_logprobs(&self) -> bool {
+        self.choices.iter().any(|c| c.logprobs.is_some())
+    }
+
+    /// Number of tool calls started in this chunk
+    pub fn new_tool_calls(&self) -> u32 {
+        self.deltas()
+            .map(|d| {
+                let started = d.tool_calls.iter().flatten().filter(|t| t.id.is_some()).count();
+                started as u32 + u32::from(d.function_call.as_ref().is_some_and(|f| f.name.is_some()))
+            })
+            .sum()
+    }
+
+    /// Generated text carried by this chunk's first choice
+    pub fn text(&self) -> Option<&str> {
+        self.choices.first()?.text()
+    }
+
+    /// Finish reason reported by any choice in this chunk
+    pub fn finish_reason(&self) -> Option<&str> {
+        self.choices.iter().find_map(|c| c.finish_reason.as_deref())
+    }
+
+    fn deltas(&self) -> impl Iterator<Item = &OpenAIDelta> {
+        self.choices.iter().filter_map(|c| c.delta.as_ref().or(c.message.as_ref()))
+    }
+}
+
+/// An SSE `data:` payload: a response object, or an array of them from some gateways
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum OpenAIPayload {
+    Single(OpenAIResponse),
+    Batch(Vec<OpenAIResponse>),
+}
+
+impl OpenAIPayload {
+    pub fn into_responses(self) -> Vec<OpenAIResponse> {
+        match self {
+            OpenAIPayload::Single(response) => vec![response],
+            OpenAIPayload::Batch(responses) => responses,
+        }
+    }
+}
+
+/// Payload of a typed `response.*` event from the OpenAI Responses API stream
+#[derive(Debug, Deserialize)]
+pub struct OpenAIResponsesEvent {
+    /// Text fragment of `response.output_text.delta`
+    #[serde(default)]
+    pub delta: Option<String>,
+    /// Output item started by `response.output_item.added`
+    #[serde(default)]
+    pub item: Option<OpenAIResponsesItem>,
+    /// The response so far, on `response.created` and the terminal events
+    #[serde(default)]
+    pub response: Option<OpenAIResponsesObject>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenAIResponsesItem {
+    /// `message`, `function_call`, `reasoning`, ...
+    #[serde(rename = "type", default)]
+    pub item_type: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenAIResponsesObject {
+    #[serde(default)]
+    pub model: Option<String>,
+    /// `completed`, `incomplete`, or `failed` once the response is over
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub usage: Option<OpenAIResponsesUsage>,
+}
+
+/// Responses API token counts, named like Anthropic's
+#[derive(Debug, Deserialize)]
+pub struct OpenAIResponsesUsage {
+    #[serde(default)]
+    pub input_tokens: Option<u32>,
+    #[serde(default)]
+    pub output_tokens: Option<u32>,
+    #[serde(default)]
+    pub total_tokens: Option<u32>,
+}
+
+/// A single choice in an OpenAI-compatible streaming chunk
+#[derive(Debug, Deserialize)]
+pub struct OpenAIChoice {
+    #[serde(default)]
+    pub delta: Option<OpenAIDelta>,
+    /// Generated text on the legacy `/v1/completions` endpoint, which has no delta
+    #[serde(default)]
+    pub text: Option<String>,
+    /// Complete message in a non-streaming chat response
+    #[serde(default)]
+    pub message: Option<OpenAIDelta>,
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+    /// Token log probabilities; `null` unless the request asked for them
+    #[serde(default)]
+    pub logprobs: Option<serde::de::IgnoredAny>,
+}
+
+impl OpenAIChoice {
+    /// Generated text from either the chat delta or the legacy completions field
+    pub fn text(&self) -> Option<&str> {
+        self.delta
+            .as_ref()
+            .or(self.message.as_ref())
+            .and_then(|d| d.content.as_deref())
+            .or(self.text.as_deref())
+    }
+}
+
+/// Incremental content in an OpenAI-compatible streaming chunk
+#[derive(Debug, Deserialize)]
+pub struct OpenAIDelta {
+    #[serde(default)]
+    pub content: Option<String>,
+    /// Tool call fragments; the first fragment of each call carries its `id`
+    #[serde(default)]
+    pub tool_calls: Option<Vec<OpenAIToolCallDelta>>,
+    /// Legacy single function call
+    #[serde(default)]
+    pub function_call: Option<OpenAIFunctionDelta>,
+}
+
+impl OpenAIDelta {
+    pub fn has_tool_call(&self) -> bool {
+        self.tool_calls.as_ref().is_some_and(|calls| !calls.is_empty()) || self.function_call.is_some()
+    }
+}
+
+/// A fragment of a streamed tool call
+#[derive(Debug, Deserialize)]
+pub struct OpenAIToolCallDelta {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub function: Option<OpenAIFunctionDelta>,
+}
+
+/// A fragment of a streamed function name and arguments
+#[derive(Debug, Deserialize)]
+pub struct OpenAIFunctionDelta {
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// An Anthropic Messages API stream event, or a complete non-streaming response
+#[derive(Debug, Deserialize)]
+pub struct AnthropicEvent {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    /// The message shell on `message_start`
+    #[serde(default)]
+    pub message: Option<AnthropicMessage>,
+    /// Block opened by `content_block_start`
+    #[serde(default)]
+    pub content_block: Option<AnthropicContentBlock>,
+    /// Text or stop reason carried by `content_block_delta` and `message_delta`
+    #[serde(default)]
+    pub delta: Option<AnthropicDelta>,
+    /// Running output count on `message_delta`; full usage on a non-streaming response
+    #[serde(default)]
+    pub usage: Option<AnthropicUsage>,
+    /// Content of a non-streaming response
+    #[serde(default)]
+    pub content: Vec<AnthropicContentBlock>,
+    #[serde(default)]
+    pub stop_reason: Option<String>,
+    /// Model of a non-streaming response
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnthropicMessage {
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub usage: Option<AnthropicUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnthropicContentBlock {
+    #[serde(rename = "type")]
+    pub block_type: String,
+    #[serde(default)]
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnthropicDelta {
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub stop_reason: Option<String>,
+}
+
+/// Anthropic token counts; `message_delta` carries only `output_tokens`
+#[derive(Debug, Deserialize)]
+pub struct AnthropicUsage {
+    #[serde(default)]
+    pub input_tokens: Option<u32>,
+    #[serde(default)]
+    pub output_tokens: Option<u32>,
+    /// Prompt tokens written to the cache, billed above the base input rate
+    #[serde(default)]
+    pub cache_creation_input_tokens: Option<u32>,
+    /// Prompt tokens served from the cache, billed well below the base input rate
+    #[serde(default)]
+    pub cache_read_input_tokens: Option<u32>,
+}
+
+/// Payload of a Bedrock `InvokeModelWithResponseStream` `chunk` event
+#[derive(Debug, Deserialize)]
+pub struct BedrockChunkPayload {
+    /// Base64-encoded model-specific JSON chunk
+    pub bytes: String,
+}
+
+/// A decoded Bedrock model chunk; only the fields used for metrics are modeled
+#[derive(Debug, Deserialize)]
+pub struct BedrockChunk {
+    /// Anthropic-style chunk type, e.g. `content_block_delta`
+    #[serde(default, rename = "type")]
+    pub chunk_type: Option<String>,
+    /// Generated text for Titan models
+    #[serde(default, rename = "outputText")]
+    pub output_text: Option<String>,
+    /// Generated text for Llama and legacy Claude models
+    #[serde(default)]
+    pub generation: Option<String>,
+    #[serde(default)]
+    pub completion: Option<String>,
+    /// Why generation stopped, for Llama and legacy Claude models
+    #[serde(default)]
+    pub stop_reason: Option<String>,
+    /// Why generation stopped, for Titan models
+    #[serde(default, rename = "completionReason")]
+    pub completion_reason: Option<String>,
+    /// Added by Bedrock to the final chunk of every model
+    #[serde(default, rename = "amazon-bedrock-invocationMetrics")]
+    pub invocation_metrics: Option<BedrockInvocationMetrics>,
+}
+
+impl BedrockChunk {
+    /// Returns true if this chunk carries generated text
+    pub fn has_content(&self) -> bool {
+        self.chunk_type.as_deref() == Some("content_block_delta") || self.text().is_some_and(|t| !t.is_empty())
+    }
+
+    /// Generated text for the models whose chunks carry it at the top level
+    pub fn text(&self) -> Option<&str> {
+        self.output_text.as_deref().or(self.generation.as_deref()).or(self.completion.as_deref())
+    }
+}
+
+/// Token counts Bedrock reports for an invocation
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BedrockInvocationMetrics {
+    pub input_token_count: u32,
+    pub output_token_count: u32,
+}
+
+/// Payload of a Bedrock `ConverseStream` `metadata` event
+#[derive(Debug, Deserialize)]
+pub struct BedrockConverseMetadata {
+    #[serde(default)]
+    pub usage: Option<BedrockConverseUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BedrockConverseUsage {
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    #[serde(default)]
+    pub total_tokens: Option<u32>,
+}
+
+/// A Cohere chat stream event (one NDJSON line)
+#[derive(Debug, Deserialize)]
+pub struct CohereStreamEvent {
+    pub event_type: String,
+    /// Generated text on `text-generation` events
+    #[serde(default)]
+    pub text: Option<String>,
+    /// Calls emitted on `tool-calls-generation` events
+    #[serde(default)]
+    pub tool_calls: Option<Vec<serde_json::Value>>,
+    /// Final response on the `stream-end` event
+    #[serde(default)]
+    pub response: Option<CohereResponse>,
+    /// Why generation stopped (`COMPLETE`, `MAX_TOKENS`), on the `stream-end` event
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+}
+
+impl CohereStreamEvent {
+    /// Returns true if this event carries generated text
+    pub fn has_content(&self) -> bool {
+        self.event_type == "text-generation" && self.text.as_deref().is_some_and(|t| !t.is_empty())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CohereResponse {
+    #[serde(default)]
+    pub meta: Option<CohereMeta>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CohereMeta {
+    #[serde(default)]
+    pub tokens: Option<CohereTokens>,
+}
+
+/// Token counts reported on `stream-end`
+#[derive(Debug, Deserialize)]
+pub struct CohereTokens {
+    #[serde(default)]
+    pub input_tokens: Option<u32>,
+    #[serde(default)]
+    pub output_tokens: Option<u32>,
+}
+
+/// Just the `model` of a request body
+///
+/// Read on its own, so the model policy holds for bodies whose other fields
+/// don't fit `GenericRequest` (an array `prompt`, for one).
+#[derive(Debug, Deserialize)]
+pub struct ModelField {
+    #[serde(default)]
+    pub model: Option<String>,
 }
 
 /// Generic request body for extracting model and prompt
@@ -69,10 +831,359 @@ pub struct GenericRequest {
     pub prompt: Option<String>,
     #[serde(default)]
     pub messages: Option<Vec<Message>>,
+    /// Anthropic's system prompt, kept outside `messages`
+    #[serde(default)]
+    pub system: Option<MessageContent>,
+    /// `true` on chat completions; the number of alternatives on legacy completions
+    #[serde(default)]
+    pub logprobs: Option<serde_json::Value>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub top_logprobs: Option<u32>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub temperature: Option<f64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub max_tokens: Option<u32>,
+    /// OpenAI's replacement for `max_tokens` on newer models
+    #[serde(default, deserialize_with = "lenient")]
+    pub max_completion_tokens: Option<u32>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub top_p: Option<f64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub presence_penalty: Option<f64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub frequency_penalty: Option<f64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub seed: Option<i64>,
+    /// Ollama's home for sampling parameters
+    #[serde(default, deserialize_with = "lenient")]
+    pub options: Option<OllamaOptions>,
+    #[serde(default)]
+    pub stream: Option<bool>,
+    /// Ollama's base64 images for `/api/generate`
+    #[serde(default)]
+    pub images: Vec<PayloadLen>,
+    /// Tools offered to the model (OpenAI, Anthropic, Responses API)
+    #[serde(default)]
+    pub tools: Option<Vec<ToolDefinition>>,
+    /// OpenAI's legacy function-calling list
+    #[serde(default)]
+    pub functions: Option<Vec<FunctionDefinition>>,
+}
+
+impl GenericRequest {
+    /// How many tools the request offered, and the names of the first `MAX_TOOLS_OFFERED`
+    pub fn tools_offered(&self) -> (u32, Vec<String>) {
+        let tools = self.tools.iter().flatten().map(ToolDefinition::name);
+        let functions = self.functions.iter().flatten().map(|f| f.name.as_deref());
+        let mut count = 0;
+        let mut names = Vec::new();
+        for name in tools.chain(functions) {
+            count += 1;
+            if names.len() < MAX_TOOLS_OFFERED {
+                names.push(name.unwrap_or_default().to_string());
+            }
+        }
+        (count, names)
+    }
+
+    /// Images and audio attached anywhere in the request, and their inline size
+    pub fn attachments(&self) -> Attachments {
+        let mut attachments = Attachments::default();
+        for image in &self.images {
+            attachments.image_count += 1;
+            attachments.attachment_bytes += image.0;
+        }
+        for message in self.messages.iter().flatten() {
+            for image in &message.images {
+                attachments.image_count += 1;
+                attachments.attachment_bytes += image.0;
+            }
+            if let MessageContent::Blocks(blocks) = &message.content {
+                for block in blocks {
+                    match block.kind.as_str() {
+                        "image_url" | "image" | "input_image" => attachments.image_count += 1,
+                        "input_audio" | "audio" => attachments.audio_count += 1,
+                        _ => {}
+                    }
+                    attachments.attachment_bytes += block.payload_len();
+                }
+            }
+        }
+        attachments
+    }
+
+    /// Whether the request asks for a streamed response
+    ///
+    /// Without a `stream` field, Ollama endpoints stream and everything else doesn't.
+    pub fn streamed(&self, path: &str) -> bool {
+        self.stream
+            .unwrap_or_else(|| detect_backend_from_path(path) == BackendType::Ollama)
+    }
+
+    /// Sampling parameters from the top level, falling back to Ollama's `options`
+    pub fn sampling_params(&self) -> SamplingParams {
+        let options = self.options.as_ref();
+        SamplingParams {
+            temperature: self.temperature.or(options.and_then(|o| o.temperature)),
+            max_tokens: self
+                .max_tokens
+                .or(self.max_completion_tokens)
+                .or(options.and_then(|o| o.num_predict)),
+            top_p: self.top_p.or(options.and_then(|o| o.top_p)),
+            presence_penalty: self.presence_penalty.or(options.and_then(|o| o.presence_penalty)),
+            frequency_penalty: self.frequency_penalty.or(options.and_then(|o| o.frequency_penalty)),
+            seed: self.seed.or(options.and_then(|o| o.seed)),
+        }
+    }
+
+    /// Whether the body asks for token log probabilities
+    pub fn logprobs_requested(&self) -> bool {
+        match &self.logprobs {
+            Some(serde_json::Value::Bool(requested)) => *requested,
+            Some(serde_json::Value::Number(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Alternatives requested per token, from either API's field
+    pub fn top_logprobs(&self) -> Option<u32> {
+        self.top_logprobs.or_else(|| {
+            self.logprobs
+                .as_ref()
+                .and_then(serde_json::Value::as_u64)
+                .and_then(|n| u32::try_from(n).ok())
+        })
+    }
+}
+
+/// Most tool names recorded per request; agent frameworks can offer hundreds
+pub const MAX_TOOLS_OFFERED: usize = 64;
+
+/// A tool offered in the request; only its name is read
+///
+/// OpenAI nests the name under `function`; Anthropic and the Responses API put it
+/// at the top level. Built-in tools without a name (e.g. `web_search_preview`) are
+/// named by their type.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ToolDefinition {
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub name: Option<String>,
+    pub function: Option<FunctionDefinition>,
+}
+
+impl ToolDefinition {
+    pub fn name(&self) -> Option<&str> {
+        self.function
+            .as_ref()
+            .and_then(|f| f.name.as_deref())
+            .or(self.name.as_deref())
+            .or(self.kind.as_deref())
+    }
+}
+
+/// A function definition, in `tools[].function` or the legacy `functions` list
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FunctionDefinition {
+    pub name: Option<String>,
+}
+
+/// Sampling parameters in Ollama's `options` object
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct OllamaOptions {
+    #[serde(deserialize_with = "lenient")]
+    pub temperature: Option<f64>,
+    /// Ollama's name for the completion token limit; -1 (unlimited) reads as None
+    #[serde(deserialize_with = "lenient")]
+    pub num_predict: Option<u32>,
+    #[serde(deserialize_with = "lenient")]
+    pub top_p: Option<f64>,
+    #[serde(deserialize_with = "lenient")]
+    pub presence_penalty: Option<f64>,
+    #[serde(deserialize_with = "lenient")]
+    pub frequency_penalty: Option<f64>,
+    #[serde(deserialize_with = "lenient")]
+    pub seed: Option<i64>,
+}
+
+/// Reads an optional request field, treating a value of the wrong type or out of
+/// range as absent so one odd sampling parameter doesn't fail the whole body
+fn lenient<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: serde::de::DeserializeOwned,
+{
+    let value = serde_json::Value::deserialize(deserializer)?;
+    Ok(T::deserialize(value).ok())
+}
+
+/// Sampling parameters that shape a completion's quality and cost
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct SamplingParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    /// Completion token limit, whatever the API called it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Message {
     pub role: String,
-    pub content: String,
+    #[serde(default)]
+    pub content: MessageContent,
+    /// Ollama's base64 images, kept beside the text content
+    #[serde(default)]
+    pub images: Vec<PayloadLen>,
+}
+
+/// Message content: a plain string, or an array of typed parts (Anthropic blocks, OpenAI vision parts)
+#[derive(Debug, Default, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Blocks(Vec<ContentBlock>),
+    /// `null` or missing, as on assistant messages that only call tools
+    #[default]
+    Empty,
+}
+
+impl MessageContent {
+    /// The text of the content, with text blocks joined by newlines and other blocks skipped
+    pub fn text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Blocks(blocks) => blocks
+                .iter()
+                .filter(|block| block.kind == "text")
+                .filter_map(|block| block.text.as_deref())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            MessageContent::Empty => String::new(),
+        }
+    }
+
+    /// Whether any part is an image (`image_url`, Anthropic's `image`, or `input_image`)
+    pub fn has_images(&self) -> bool {
+        match self {
+            MessageContent::Blocks(blocks) => blocks
+                .iter()
+                .any(|block| matches!(block.kind.as_str(), "image_url" | "image" | "input_image")),
+            _ => false,
+        }
+    }
+}
+
+/// One part of structured message content (`text`, `image`, `image_url`, `tool_use`, ...)
+///
+/// Attachment payloads are only measured, never copied out of the body.
+#[derive(Debug, Deserialize)]
+pub struct ContentBlock {
+    #[serde(rename = "type", default)]
+    pub kind: String,
+    #[serde(default)]
+    pub text: Option<String>,
+    /// OpenAI's `{"url": ...}` object, or the bare URL in the Responses API
+    #[serde(default)]
+    pub image_url: Option<PayloadLen>,
+    /// Anthropic's `image` and `document` source
+    #[serde(default)]
+    pub source: Option<InlineData>,
+    /// OpenAI's `{"data": ..., "format": ...}` audio input
+    #[serde(default)]
+    pub input_audio: Option<InlineData>,
+    /// Responses API `input_file` contents
+    #[serde(default)]
+    pub file_data: Option<PayloadLen>,
+}
+
+impl ContentBlock {
+    /// Bytes of inline payload carried by the part
+    pub fn payload_len(&self) -> u64 {
+        let inline = |data: &Option<InlineData>| data.as_ref().and_then(|d| d.data).map_or(0, |len| len.0);
+        self.image_url.map_or(0, |len| len.0)
+            + inline(&self.source)
+            + inline(&self.input_audio)
+            + self.file_data.map_or(0, |len| len.0)
+    }
+}
+
+/// An attachment object whose payload is in `data` (Anthropic `source`, OpenAI `input_audio`)
+#[derive(Debug, Default, Deserialize)]
+pub struct InlineData {
+    #[serde(default)]
+    pub data: Option<PayloadLen>,
+}
+
+/// Image, audio, and inline payload totals for a request
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Attachments {
+    pub image_count: u32,
+    pub audio_count: u32,
+    pub attachment_bytes: u64,
+}
+
+/// Encoded size of an attachment payload, read without copying the string
+///
+/// A data URI counts the bytes after its comma, a remote `http(s)` URL counts
+/// nothing, and anything else is taken as raw base64. Also accepts an object,
+/// measuring its `url` field.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PayloadLen(pub u64);
+
+impl PayloadLen {
+    fn of(value: &str) -> Self {
+        let len = if let Some(uri) = value.strip_prefix("data:") {
+            uri.split_once(',').map_or(0, |(_, payload)| payload.len())
+        } else if value.starts_with("http://") || value.starts_with("https://") {
+            0
+        } else {
+            value.len()
+        };
+        Self(len as u64)
+    }
+}
+
+impl<'de> Deserialize<'de> for PayloadLen {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct PayloadVisitor;
+
+        impl<'de> Visitor<'de> for PayloadVisitor {
+            type Value = PayloadLen;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a URL, base64 string, or object with a url")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<PayloadLen, E> {
+                Ok(PayloadLen::of(value))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<PayloadLen, A::Error> {
+                let mut len = PayloadLen::default();
+                while let Some(key) = map.next_key::<String>()? {
+                    if key == "url" {
+                        len = map.next_value()?;
+                    } else {
+                        map.next_value::<IgnoredAny>()?;
+                    }
+                }
+                Ok(len)
+            }
+        }
+
+        deserializer.deserialize_any(PayloadVisitor)
+    }
 }
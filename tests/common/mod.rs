@@ -0,0 +1,114 @@
+// tests/common/mod.rs
+#![allow(dead_code)]
+
+#[path = "../mock_server.rs"]
+pub mod mock_server;
+
+use axum::Router;
+use rust_llm_logger::app::{self, AppState};
+use rust_llm_logger::config::Config;
+use rust_llm_logger::sinks::MemorySink;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Serves `router` on an ephemeral loopback port and returns its address
+pub async fn spawn_server(router: Router) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .unwrap();
+    });
+    addr
+}
+
+/// Starts the proxy with the given config and returns its address
+pub async fn spawn_proxy(config: Config) -> SocketAddr {
+    spawn_server(app::router(AppState::new(config))).await
+}
+
+/// Starts the proxy with an in-memory sink attached
+pub async fn spawn_proxy_with_sink(config: Config) -> (SocketAddr, Arc<MemorySink>) {
+    let sink = Arc::new(MemorySink::new());
+    let state = AppState::new(config).with_sink(sink.clone());
+    (spawn_server(app::router(state)).await, sink)
+}
+
+/// Waits until the sink holds at least `count` records (metrics are emitted after the body ends)
+pub async fn wait_for_records(sink: &MemorySink, count: usize) -> Vec<rust_llm_logger::types::LLMMetrics> {
+    for _ in 0..200 {
+        let records = sink.records();
+        if records.len() >= count {
+            return records;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("timed out waiting for {} metrics records", count);
+}
+
+/// Sends a JSON POST through the proxy and returns the status and full response body
+pub async fn post_json(proxy: SocketAddr, upstream_port: u16, path: &str, body: &str) -> (u16, String) {
+    post_json_to(proxy, &upstream_port.to_string(), path, body).await
+}
+
+/// Like `post_json`, addressing the upstream by port or pool name
+pub async fn post_json_to(proxy: SocketAddr, backend: &str, path: &str, body: &str) -> (u16, String) {
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let req = hyper::Request::post(format!("http://{}/proxy/{}/{}", proxy, backend, path))
+        .header("content-type", "application/json")
+        .body(axum::body::Body::from(body.to_string()))
+        .unwrap();
+    let resp = client.request(req).await.unwrap();
+    let status = resp.status().as_u16();
+    let bytes = http_body_util::BodyExt::collect(resp.into_body())
+        .await
+        .unwrap()
+        .to_bytes();
+    (status, String::from_utf8_lossy(&bytes).to_string())
+}
+
+/// Log output captured from the tracing subscriber
+#[derive(Clone, Default)]
+pub struct LogCapture(Arc<Mutex<Vec<u8>>>);
+
+impl LogCapture {
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).to_string()
+    }
+}
+
+impl std::io::Write for LogCapture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for LogCapture {
+    type Writer = LogCapture;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Captures all log output on the current thread until the guard is dropped
+///
+/// Tests using this must run on the current-thread runtime so spawned proxy tasks log here too.
+pub fn capture_logs() -> (LogCapture, tracing::subscriber::DefaultGuard) {
+    let capture = LogCapture::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(capture.clone())
+        .with_max_level(tracing::Level::TRACE)
+        .with_ansi(false)
+        .finish();
+    let guard = tracing::subscriber::set_default(subscriber);
+    (capture, guard)
+}
@@ -0,0 +1,28 @@
+// tests/encoding.rs
+
+use bytes::Bytes;
+use rust_llm_logger::encoding::ParserDecoder;
+
+#[test]
+fn test_identity_decoding_shares_the_chunk_buffer() {
+    // A large chunk, as a non-streaming completion would arrive
+    let chunk = Bytes::from(vec![b'x'; 8 * 1024 * 1024]);
+    let mut decoder = ParserDecoder::for_encoding(None);
+
+    let plain = decoder.decode(&chunk).expect("identity chunks pass through");
+
+    // Same allocation: the parser's copy of the stream costs a refcount, not 8 MiB
+    assert_eq!(plain.as_ptr(), chunk.as_ptr());
+    assert_eq!(plain.len(), chunk.len());
+    assert_eq!(decoder.finish(), None);
+}
+
+#[test]
+fn test_identity_decoding_shares_split_chunks() {
+    let body = Bytes::from(vec![b'y'; 1024 * 1024]);
+    let (first, second) = (body.slice(..1000), body.slice(1000..));
+    let mut decoder = ParserDecoder::for_encoding(Some("identity"));
+
+    assert_eq!(decoder.decode(&first).unwrap().as_ptr(), body.as_ptr());
+    assert_eq!(decoder.decode(&second).unwrap().as_ptr(), body[1000..].as_ptr());
+}
@@ -0,0 +1,134 @@
+// tests/metrics.rs
+
+use rust_llm_logger::parsers::BackendType;
+use rust_llm_logger::timing::{ThroughputSource, Timings};
+use rust_llm_logger::types::{ClientInfo, LLMMetrics, PromptStats, SamplingParams, SCHEMA_VERSION};
+
+const SNAPSHOT: &str = include_str!("fixtures/metrics_snapshot.json");
+
+/// A record with every field set, so the snapshot shows the whole schema
+fn full_record() -> LLMMetrics {
+    LLMMetrics {
+        schema_version: SCHEMA_VERSION,
+        request_id: "7f1c2b9e-4d3a-4e8f-9b21-0c5d6e7f8a90".to_string(),
+        upstream_request_id: Some("req_abc123".to_string()),
+        backend: BackendType::OpenAI,
+        backend_port: 8080,
+        upstream_url: "http://127.0.0.1:8080/v1/chat/completions".to_string(),
+        status_code: 200,
+        success: true,
+        model: "gpt-4o".to_string(),
+        response_model: Some("gpt-4o-2024-08-06".to_string()),
+        logprobs_requested: true,
+        top_logprobs: Some(2),
+        logprobs_returned: true,
+        params: SamplingParams {
+            temperature: Some(0.5),
+            max_tokens: Some(256),
+            top_p: Some(0.9),
+            presence_penalty: Some(0.0),
+            frequency_penalty: Some(0.25),
+            seed: Some(7),
+        },
+        request_parse_ok: true,
+        client: ClientInfo {
+            addr: Some("127.0.0.1:52814".to_string()),
+            user_agent: Some("curl/8.5.0".to_string()),
+            identity: Some("search".to_string()),
+        },
+        api_key_fingerprint: Some("3f9a12bc".to_string()),
+        api_key_label: Some("billing-service".to_string()),
+        tags: vec!["eval".to_string()],
+        metadata: [("pipeline".to_string(), "nightly".to_string())].into_iter().collect(),
+        conversation_id: Some("3f9a1c07b2e45d18".to_string()),
+        replay_of: Some("nightly-eval-0042".to_string()),
+        turn_index: Some(2),
+        streamed: true,
+        prompt: "user: hi".to_string(),
+        prompt_len: 8,
+        prompt_sha256: Some(rust_llm_logger::text::normalized_sha256("user: hi")),
+        message_count: Some(3),
+        system_prompt_present: true,
+        system_prompt: Some("You are a helpful assistant.".to_string()),
+        system_prompt_hash: Some(rust_llm_logger::text::normalized_sha256("You are a helpful assistant.")),
+        prompt_stats: Some(PromptStats {
+            roles: [("system".to_string(), 1), ("user".to_string(), 2)].into_iter().collect(),
+            system_prompt_chars: 28,
+            has_images: false,
+        }),
+        image_count: 0,
+        audio_count: 0,
+        attachment_bytes: 0,
+        tools_offered_count: 2,
+        tools_offered: vec!["get_weather".to_string(), "search_docs".to_string()],
+        prompt_tokens: Some(8),
+        completion_tokens: Some(12),
+        tokens_estimated: false,
+        total_tokens: Some(20),
+        cache_write_tokens: Some(0),
+        cache_read_tokens: Some(0),
+        cost_usd: Some(0.00014),
+        streamed_prompt_tokens: Some(2),
+        estimated_prompt_tokens: Some(3),
+        estimated_completion_tokens: Some(41),
+        upstream_connect_ms: Some(40),
+        total_ms: 500,
+        timings: Some(Timings {
+            body_read_ms: 0.042,
+            upstream_ttfb_ms: Some(85.1),
+            stream_ms: Some(412.5),
+            proxy_overhead_ms: 0.731,
+        }),
+        latency_ms: 500,
+        attempts: 1,
+        retried: false,
+        attempt_latency_ms: Some(480),
+        ttft_ms: Some(120),
+        generation_time_ms: Some(380),
+        tokens_per_second: Some(31.5),
+        tokens_per_second_source: Some(ThroughputSource::Proxy),
+        upstream_total_duration_ms: Some(490),
+        upstream_load_duration_ms: Some(5),
+        upstream_prompt_eval_duration_ms: Some(100),
+        upstream_eval_duration_ms: Some(380),
+        chunk_gap_ms_min: Some(10.0),
+        chunk_gap_ms_mean: Some(30.0),
+        chunk_gap_ms_max: Some(60.0),
+        chunk_gap_ms_p95: Some(55.0),
+        response_bytes: 2048,
+        frame_count: 13,
+        event_count: 14,
+        had_tool_calls: false,
+        tool_call_count: 0,
+        truncated: false,
+        parse_truncated: false,
+        client_disconnected: false,
+        completed: true,
+        stream_error: None,
+        upstream_error: None,
+        trailers: [("grpc-status".to_string(), "0".to_string())].into_iter().collect(),
+        prompt_chars: Some(8),
+        completion_chars: Some(52),
+        empty_completion: Some(false),
+        looks_truncated: Some(false),
+        response_text: Some("Hello! How can I help?".to_string()),
+        response_text_sha256: Some("0f1e2d".to_string()),
+        started_at: "2025-11-09T12:34:56.289Z".to_string(),
+        started_at_ms: 1_762_691_696_289,
+        finished_at: "2025-11-09T12:34:56.789Z".to_string(),
+        finished_at_ms: 1_762_691_696_789,
+        timestamp: "2025-11-09T12:34:56.789Z".to_string(),
+    }
+}
+
+/// Field names and order are a contract with downstream ingestion; change them deliberately
+#[test]
+fn test_metrics_json_matches_snapshot() {
+    let actual = serde_json::to_string_pretty(&full_record()).unwrap();
+    assert_eq!(
+        actual.trim(),
+        SNAPSHOT.trim(),
+        "metrics JSON changed; if intended, update tests/fixtures/metrics_snapshot.json to:\n{}",
+        actual
+    );
+}
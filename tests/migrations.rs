@@ -0,0 +1,76 @@
+// tests/migrations.rs
+//
+// Records written under every schema version, checked in under tests/fixtures/schema,
+// must keep deserializing into the current LLMMetrics.
+
+use rust_llm_logger::parsers::BackendType;
+use rust_llm_logger::types::{LLMMetrics, SCHEMA_VERSION};
+use std::path::PathBuf;
+
+/// `(version, fixture JSON)` for every `v<N>.json` in the schema fixtures
+fn fixtures() -> Vec<(u32, String)> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/schema");
+    let mut fixtures: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .map(|path| {
+            let stem = path.file_stem().unwrap().to_str().unwrap();
+            let version = stem.strip_prefix('v').and_then(|v| v.parse().ok());
+            let version = version.unwrap_or_else(|| panic!("unexpected schema fixture {}", path.display()));
+            (version, std::fs::read_to_string(&path).unwrap())
+        })
+        .collect();
+    fixtures.sort_by_key(|(version, _)| *version);
+    fixtures
+}
+
+#[test]
+fn test_every_schema_version_deserializes() {
+    let fixtures = fixtures();
+    let versions: Vec<u32> = fixtures.iter().map(|(version, _)| *version).collect();
+    assert_eq!(versions, (0..=SCHEMA_VERSION).collect::<Vec<_>>(), "one fixture per schema version");
+
+    for (version, json) in fixtures {
+        let record: LLMMetrics =
+            serde_json::from_str(&json).unwrap_or_else(|e| panic!("v{} no longer deserializes: {}", version, e));
+        assert_eq!(record.schema_version, version);
+        assert_eq!(record.model, "gpt-4o");
+        assert_eq!(record.backend, BackendType::OpenAI);
+    }
+}
+
+#[test]
+fn test_current_version_round_trips_every_field() {
+    let (_, json) = fixtures().pop().unwrap();
+    let record: LLMMetrics = serde_json::from_str(&json).unwrap();
+    let expected: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(serde_json::to_value(&record).unwrap(), expected);
+}
+
+#[test]
+fn test_fields_missing_from_older_records_take_defaults() {
+    let (_, json) = fixtures().remove(0);
+    let record: LLMMetrics = serde_json::from_str(&json).unwrap();
+
+    // Present in the oldest layout
+    assert_eq!(record.backend_port, 8080);
+    assert_eq!(record.prompt_tokens, Some(8));
+
+    // Added since
+    assert_eq!(record.schema_version, 0);
+    assert_eq!(record.timings, None);
+    assert_eq!(record.prompt_sha256, None);
+    assert!(record.tools_offered.is_empty());
+    assert!(record.trailers.is_empty());
+}
+
+#[test]
+fn test_chunk_count_reads_as_frame_count() {
+    let (_, json) = fixtures().pop().unwrap();
+    let mut fields: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&json).unwrap();
+    let frames = fields.remove("frame_count").unwrap();
+    fields.insert("chunk_count".to_string(), frames.clone());
+
+    let record: LLMMetrics = serde_json::from_value(fields.into()).unwrap();
+    assert_eq!(serde_json::json!(record.frame_count), frames);
+}
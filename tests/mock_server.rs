@@ -1,7 +1,7 @@
 use axum::{
     body::Body,
     extract::Json,
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
     routing::post,
     Router,
@@ -10,6 +10,20 @@ use serde::{Deserialize, Serialize};
 use tokio::time::{sleep, Duration};
 use tokio_stream::wrappers::ReceiverStream;
 
+/// Request header holding back the response headers, to simulate a slow connect or queue
+const HEADERS_DELAY_HEADER: &str = "x-mock-headers-delay-ms";
+/// Request header holding back the first streamed chunk, to simulate slow prompt processing
+const FIRST_CHUNK_DELAY_HEADER: &str = "x-mock-first-chunk-delay-ms";
+
+fn requested_delay(headers: &HeaderMap, name: &str) -> Duration {
+    let millis = headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    Duration::from_millis(millis)
+}
+
 #[derive(Deserialize)]
 struct OllamaRequest {
     model: String,
@@ -92,16 +106,19 @@ struct Usage {
     total_tokens: u32,
 }
 
-async fn ollama_generate(Json(req): Json<OllamaRequest>) -> Response {
+async fn ollama_generate(headers: HeaderMap, Json(req): Json<OllamaRequest>) -> Response {
     println!("Mock Ollama: Received request for model: {}", req.model);
 
     if !req.stream {
         return (StatusCode::BAD_REQUEST, "Non-streaming not implemented").into_response();
     }
 
+    sleep(requested_delay(&headers, HEADERS_DELAY_HEADER)).await;
+    let first_chunk_delay = requested_delay(&headers, FIRST_CHUNK_DELAY_HEADER);
     let (tx, rx) = tokio::sync::mpsc::channel::<Result<String, std::io::Error>>(32);
 
     tokio::spawn(async move {
+        sleep(first_chunk_delay).await;
         let response_text = "The sky appears blue due to a phenomenon called Rayleigh scattering. \
                            When sunlight enters Earth's atmosphere, it collides with gas molecules. \
                            Blue light has a shorter wavelength and gets scattered more than other colors, \
@@ -151,16 +168,19 @@ async fn ollama_generate(Json(req): Json<OllamaRequest>) -> Response {
         .unwrap()
 }
 
-async fn openai_chat_completions(Json(req): Json<OpenAIRequest>) -> Response {
+async fn openai_chat_completions(headers: HeaderMap, Json(req): Json<OpenAIRequest>) -> Response {
     println!("Mock OpenAI: Received request for model: {}", req.model);
 
     if !req.stream {
         return (StatusCode::BAD_REQUEST, "Non-streaming not implemented").into_response();
     }
 
+    sleep(requested_delay(&headers, HEADERS_DELAY_HEADER)).await;
+    let first_chunk_delay = requested_delay(&headers, FIRST_CHUNK_DELAY_HEADER);
     let (tx, rx) = tokio::sync::mpsc::channel::<Result<String, std::io::Error>>(32);
 
     tokio::spawn(async move {
+        sleep(first_chunk_delay).await;
         let response_text = "Rust and C++ are both systems programming languages, but they differ in key ways. \
                            Rust provides memory safety without garbage collection through its ownership system. \
                            C++ offers more manual control but requires careful memory management.";
@@ -228,11 +248,20 @@ async fn openai_chat_completions(Json(req): Json<OpenAIRequest>) -> Response {
         .unwrap()
 }
 
+/// Mock Ollama API
+pub fn ollama_app() -> Router {
+    Router::new().route("/api/generate", post(ollama_generate))
+}
+
+/// Mock OpenAI-compatible API
+pub fn openai_app() -> Router {
+    Router::new().route("/v1/chat/completions", post(openai_chat_completions))
+}
+
 #[tokio::main]
 async fn main() {
     // Ollama mock server on port 11434
-    let ollama_app = Router::new()
-        .route("/api/generate", post(ollama_generate));
+    let ollama_app = ollama_app();
 
     tokio::spawn(async {
         let listener = tokio::net::TcpListener::bind("127.0.0.1:11434")
@@ -243,8 +272,7 @@ async fn main() {
     });
 
     // OpenAI-compatible mock server on port 8080
-    let openai_app = Router::new()
-        .route("/v1/chat/completions", post(openai_chat_completions));
+    let openai_app = openai_app();
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:8080")
         .await
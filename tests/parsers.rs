@@ -1,7 +1,10 @@
 // tests/parsers.rs
 
 use bytes::Bytes;
-use rust_llm_logger::parsers::{BackendStreamParser, OllamaParser};
+use rust_llm_logger::parsers::{
+    detect_backend, detect_backend_type, parse_response, AnthropicParser, BackendStreamParser,
+    BackendType, BedrockParser, CohereParser, DetectionHints, RequestShape, FallbackParser, OllamaParser, OpenAIParser, SseEvent, SseScanner,
+};
 use rust_llm_logger::types::TokenUsage;
 
 #[tokio::test]
@@ -36,7 +39,683 @@ async fn test_ollama_parser_missing_prompt_tokens() {
         TokenUsage {
             prompt_tokens: None,
             completion_tokens: Some(42),
+            event_count: 3,
+            response_model: Some("llama2".to_string()),
+            saw_terminal: true,
+            ..TokenUsage::default()
         },
         "Parser should correctly extract completion_tokens even when prompt_tokens is missing"
     );
 }
+
+#[tokio::test]
+async fn test_parse_response_ollama_fixture() {
+    let body = include_bytes!("fixtures/ollama_generate.ndjson");
+
+    let usage = parse_response(BackendType::Ollama, body).await;
+
+    assert_eq!(usage.prompt_tokens, Some(26));
+    assert_eq!(usage.completion_tokens, Some(5));
+    assert_eq!(usage.eval_duration_ms, Some(4709));
+    assert_eq!(usage.event_count, 6);
+}
+
+#[tokio::test]
+async fn test_parse_response_openai_fixture() {
+    let body = include_bytes!("fixtures/openai_chat.sse");
+
+    let usage = parse_response(BackendType::OpenAI, body).await;
+
+    assert_eq!(usage.prompt_tokens, Some(9));
+    assert_eq!(usage.completion_tokens, Some(7));
+    assert_eq!(usage.event_count, 10, "9 JSON events plus [DONE]");
+}
+
+/// Feeds `body` to a fresh parser in small chunks so CRLF pairs straddle chunk boundaries
+async fn parse_in_chunks(backend_type: BackendType, body: &[u8]) -> TokenUsage {
+    let mut parser = rust_llm_logger::parsers::create_parser(backend_type);
+    parser.capture_completion_text(usize::MAX);
+    for chunk in body.chunks(7) {
+        parser.feed_chunk(&Bytes::copy_from_slice(chunk)).await;
+    }
+    parser.finalize().await
+}
+
+#[tokio::test]
+async fn test_crlf_fixtures_match_lf_results() {
+    let ollama_lf = include_bytes!("fixtures/ollama_generate.ndjson");
+    let ollama_crlf = include_bytes!("fixtures/ollama_generate_crlf.ndjson");
+    let openai_lf = include_bytes!("fixtures/openai_chat.sse");
+    let openai_crlf = include_bytes!("fixtures/openai_chat_crlf.sse");
+
+    for (backend, lf, crlf) in [
+        (BackendType::Ollama, &ollama_lf[..], &ollama_crlf[..]),
+        (BackendType::OpenAI, &openai_lf[..], &openai_crlf[..]),
+    ] {
+        let expected = parse_response(backend, lf).await;
+        assert!(expected.completion_tokens.is_some());
+        assert_eq!(parse_response(backend, crlf).await, expected, "{:?}", backend);
+        assert_eq!(parse_in_chunks(backend, crlf).await, expected, "{:?}", backend);
+    }
+}
+
+#[tokio::test]
+async fn test_openai_crlf_done_marker_is_recognized() {
+    let usage = parse_openai_events(&[
+        "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":2,\"completion_tokens\":3}}\r\n\r\n",
+        "data: [DONE]\r\n\r\n",
+    ])
+    .await;
+
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens), (Some(2), Some(3)));
+    assert_eq!(usage.event_count, 2);
+}
+
+#[tokio::test]
+async fn test_parse_response_unknown_backend_yields_no_usage() {
+    let body = include_bytes!("fixtures/ollama_generate.ndjson");
+
+    let usage = parse_response(BackendType::Unknown, body).await;
+
+    assert_eq!(usage, TokenUsage::default());
+}
+
+#[tokio::test]
+async fn test_openai_parser_array_wrapped_payloads() {
+    let mut parser: Box<dyn BackendStreamParser> = Box::new(OpenAIParser::new());
+
+    let delta = Bytes::from_static(
+        b"data: [{\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"}}]}]\n\n",
+    );
+    let usage = Bytes::from_static(
+        b"data: [{\"choices\":[],\"usage\":{\"prompt_tokens\":11,\"completion_tokens\":3,\"total_tokens\":14}}]\n\n",
+    );
+    let done = Bytes::from_static(b"data: [DONE]\n\n");
+
+    parser.feed_chunk(&delta).await;
+    assert!(parser.saw_content(), "content inside an array payload should be seen");
+    parser.feed_chunk(&usage).await;
+    parser.feed_chunk(&done).await;
+
+    let usage = parser.finalize().await;
+    assert_eq!(usage.prompt_tokens, Some(11));
+    assert_eq!(usage.completion_tokens, Some(3));
+}
+
+/// Feeds each event as its own chunk and returns the final usage
+async fn parse_openai_events(events: &[&str]) -> TokenUsage {
+    let mut parser: Box<dyn BackendStreamParser> = Box::new(OpenAIParser::new());
+    parser.capture_completion_text(usize::MAX);
+    for event in events {
+        parser.feed_chunk(&Bytes::copy_from_slice(event.as_bytes())).await;
+    }
+    parser.finalize().await
+}
+
+const CONTENT_EVENT: &str =
+    "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"},\"finish_reason\":null}]}\n\n";
+const STOP_EVENT: &str =
+    "data: {\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n";
+const INCLUDE_USAGE_EVENT: &str =
+    "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":13,\"completion_tokens\":4,\"total_tokens\":17}}\n\n";
+const DONE_EVENT: &str = "data: [DONE]\n\n";
+
+#[tokio::test]
+async fn test_openai_include_usage_after_content() {
+    let usage = parse_openai_events(&[CONTENT_EVENT, STOP_EVENT, INCLUDE_USAGE_EVENT, DONE_EVENT]).await;
+
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens), (Some(13), Some(4)));
+}
+
+#[tokio::test]
+async fn test_openai_include_usage_after_done_marker() {
+    let usage = parse_openai_events(&[CONTENT_EVENT, STOP_EVENT, DONE_EVENT, INCLUDE_USAGE_EVENT]).await;
+
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens), (Some(13), Some(4)));
+}
+
+#[tokio::test]
+async fn test_openai_include_usage_without_trailing_blank_line() {
+    let unterminated = INCLUDE_USAGE_EVENT.trim_end();
+    let usage = parse_openai_events(&[CONTENT_EVENT, DONE_EVENT, unterminated]).await;
+
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens), (Some(13), Some(4)));
+}
+
+#[tokio::test]
+async fn test_openai_data_field_without_space() {
+    let compact = INCLUDE_USAGE_EVENT.replacen("data: ", "data:", 1);
+    let usage = parse_openai_events(&[CONTENT_EVENT, &compact, "data:[DONE]\n\n"]).await;
+
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens), (Some(13), Some(4)));
+}
+
+#[tokio::test]
+async fn test_openai_usage_split_across_data_lines() {
+    let split = concat!(
+        "data: {\"choices\":[],\n",
+        "data: \"usage\":{\"prompt_tokens\":13,\n",
+        "data: \"completion_tokens\":4,\"total_tokens\":17}}\n\n",
+    );
+    let usage = parse_openai_events(&[CONTENT_EVENT, split, DONE_EVENT]).await;
+
+    assert_eq!(usage.prompt_tokens, Some(13));
+    assert_eq!(usage.completion_tokens, Some(4));
+    assert_eq!(usage.event_count, 3);
+}
+
+#[tokio::test]
+async fn test_openai_multi_line_data_with_interleaved_comments() {
+    let split = concat!(
+        ": keep-alive\n",
+        "data: {\"choices\":[],\n",
+        ": proxy comment\n",
+        "data:\"usage\":{\"prompt_tokens\":13,\"completion_tokens\":4}}\n\n",
+    );
+    // Deliver the event in pieces so the block is reassembled across chunks
+    let (head, tail) = split.split_at(30);
+    let usage = parse_openai_events(&[CONTENT_EVENT, head, tail, DONE_EVENT]).await;
+
+    assert_eq!(usage.prompt_tokens, Some(13));
+    assert_eq!(usage.completion_tokens, Some(4));
+}
+
+#[tokio::test]
+async fn test_ollama_parser_extracts_duration_fields() {
+    let mut parser: Box<dyn BackendStreamParser> = Box::new(OllamaParser::new());
+
+    // Final chunk exactly as emitted by Ollama's /api/chat
+    let final_chunk = Bytes::from_static(br#"{"model":"llama3.2:3b-instruct-q4_K_M","created_at":"2025-11-09T12:34:58.789Z","message":{"role":"assistant","content":""},"done_reason":"stop","done":true,"total_duration":5191566416,"load_duration":2154458,"prompt_eval_count":26,"prompt_eval_duration":383809000,"eval_count":298,"eval_duration":4799921000}
+"#);
+    parser.feed_chunk(&final_chunk).await;
+    let usage = parser.finalize().await;
+
+    assert_eq!(usage.prompt_tokens, Some(26));
+    assert_eq!(usage.completion_tokens, Some(298));
+    assert_eq!(usage.total_duration_ms, Some(5191));
+    assert_eq!(usage.load_duration_ms, Some(2));
+    assert_eq!(usage.prompt_eval_duration_ms, Some(383));
+    assert_eq!(usage.eval_duration_ms, Some(4799));
+}
+
+#[tokio::test]
+async fn test_openai_parser_counts_tool_calls() {
+    let usage = parse_openai_events(&[
+        "data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":null,\"tool_calls\":[{\"index\":0,\"id\":\"call_abc\",\"type\":\"function\",\"function\":{\"name\":\"get_weather\",\"arguments\":\"\"}}]}}]}\n\n",
+        "data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"city\\\":\"}}]}}]}\n\n",
+        "data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"Paris\\\"}\"}}]}}]}\n\n",
+        "data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":1,\"id\":\"call_def\",\"type\":\"function\",\"function\":{\"name\":\"get_time\",\"arguments\":\"{}\"}}]}}]}\n\n",
+        "data: {\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"tool_calls\"}]}\n\n",
+        "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":80,\"completion_tokens\":31,\"total_tokens\":111}}\n\n",
+        DONE_EVENT,
+    ])
+    .await;
+
+    assert_eq!(usage.tool_call_count, 2);
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens), (Some(80), Some(31)));
+}
+
+#[tokio::test]
+async fn test_openai_parser_plain_content_has_no_tool_calls() {
+    let usage = parse_openai_events(&[CONTENT_EVENT, STOP_EVENT, INCLUDE_USAGE_EVENT, DONE_EVENT]).await;
+
+    assert_eq!(usage.tool_call_count, 0);
+}
+
+#[tokio::test]
+async fn test_openai_parser_dispatches_typed_responses_events() {
+    let usage = parse_openai_events(&[
+        "event: response.created\nid: 1\ndata: {\"type\":\"response.created\",\"response\":{\"model\":\"gpt-4o-2024-08-06\",\"status\":\"in_progress\"}}\n\n",
+        "event: response.output_text.delta\nid: 2\ndata: {\"type\":\"response.output_text.delta\",\"delta\":\"Hello\"}\n\n",
+        "event: response.output_text.delta\nid: 3\ndata: {\"type\":\"response.output_text.delta\",\"delta\":\" there\"}\n\n",
+        "event: response.output_item.added\nid: 4\ndata: {\"type\":\"response.output_item.added\",\"item\":{\"type\":\"function_call\",\"name\":\"get_weather\"}}\n\n",
+        "event: response.completed\nid: 5\ndata: {\"type\":\"response.completed\",\"response\":{\"model\":\"gpt-4o-2024-08-06\",\"status\":\"completed\",\"usage\":{\"input_tokens\":12,\"output_tokens\":3,\"total_tokens\":15}}}\n\n",
+    ])
+    .await;
+
+    assert_eq!(usage.completion_text, "Hello there");
+    assert_eq!(usage.tool_call_count, 1);
+    assert_eq!(usage.response_model.as_deref(), Some("gpt-4o-2024-08-06"));
+    assert_eq!(usage.finish_reason.as_deref(), Some("completed"));
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.total_tokens), (Some(12), Some(3), Some(15)));
+    assert!(usage.saw_terminal);
+    assert_eq!(usage.event_count, 5);
+}
+
+#[tokio::test]
+async fn test_openai_parser_does_not_read_error_events_as_chunks() {
+    // An error event's data would otherwise parse as an (empty) completion chunk
+    let usage = parse_openai_events(&[
+        CONTENT_EVENT,
+        "event: error\ndata: {\"choices\":[],\"usage\":{\"prompt_tokens\":1,\"completion_tokens\":1,\"total_tokens\":2}}\n\n",
+        "event: message\ndata: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"!\"},\"finish_reason\":null}]}\n\n",
+    ])
+    .await;
+
+    assert_eq!(usage.completion_text, "Hi!");
+    assert_eq!(usage.prompt_tokens, None);
+    assert!(!usage.saw_terminal);
+}
+
+fn scan_all(chunks: &[&str]) -> Vec<SseEvent> {
+    let mut scanner = SseScanner::new();
+    let mut events = Vec::new();
+    for chunk in chunks {
+        scanner.push(chunk.as_bytes());
+        while let Some(event) = scanner.next_event() {
+            events.push(event);
+        }
+    }
+    events.extend(scanner.finish());
+    events
+}
+
+#[test]
+fn test_sse_scanner_exposes_event_and_id_fields() {
+    let events = scan_all(&[
+        "event: message_start\nid: 1\ndata: {\"a\":1}\n\n",
+        "event: message_delta\ndata:{\"b\":2}\n\n",
+    ]);
+
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].event.as_deref(), Some("message_start"));
+    assert_eq!(events[0].id.as_deref(), Some("1"));
+    assert_eq!(events[0].data, "{\"a\":1}");
+    assert_eq!(events[1].event.as_deref(), Some("message_delta"));
+    assert_eq!(events[1].id, None);
+    assert_eq!(events[1].data, "{\"b\":2}");
+}
+
+#[test]
+fn test_sse_scanner_skips_comments_and_dataless_blocks() {
+    let events = scan_all(&[
+        ": keep-alive\n\n",
+        "retry: 3000\n\n",
+        ": note\nevent: ping\ndata: first\n: another note\ndata: second\n\n",
+    ]);
+
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].event.as_deref(), Some("ping"));
+    assert_eq!(events[0].data, "first\nsecond");
+}
+
+#[test]
+fn test_sse_scanner_parses_retry_field() {
+    let events = scan_all(&["retry: 1500\ndata: x\n\n", "retry: soon\ndata: y\n\n"]);
+
+    assert_eq!(events[0].retry, Some(1500));
+    assert_eq!(events[1].retry, None);
+}
+
+#[test]
+fn test_sse_scanner_handles_missing_final_newline() {
+    let events = scan_all(&["data: one\n\n", "event: last\ndata: two"]);
+
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[1].event.as_deref(), Some("last"));
+    assert_eq!(events[1].data, "two");
+}
+
+#[test]
+fn test_sse_scanner_reassembles_events_split_across_chunks() {
+    let events = scan_all(&["da", "ta: hel", "lo\n", "\ndata: world\n\n"]);
+
+    let data: Vec<_> = events.iter().map(|e| e.data.as_str()).collect();
+    assert_eq!(data, vec!["hello", "world"]);
+}
+
+#[tokio::test]
+async fn test_fallback_parser_uses_first_candidate_with_usage() {
+    let body = include_bytes!("fixtures/openai_chat.sse");
+    let mut parser: Box<dyn BackendStreamParser> =
+        Box::new(FallbackParser::new(vec![BackendType::Ollama, BackendType::OpenAI]));
+
+    // Deliver in uneven chunks as the tee would
+    for chunk in body.chunks(37) {
+        parser.feed_chunk(&Bytes::copy_from_slice(chunk)).await;
+    }
+    let usage = parser.finalize().await;
+
+    assert_eq!(usage.prompt_tokens, Some(9));
+    assert_eq!(usage.completion_tokens, Some(7));
+}
+
+#[tokio::test]
+async fn test_fallback_parser_without_match_yields_no_usage() {
+    let mut parser: Box<dyn BackendStreamParser> =
+        Box::new(FallbackParser::new(vec![BackendType::Ollama, BackendType::OpenAI]));
+    parser.feed_chunk(&Bytes::from_static(b"<html>not an llm</html>")).await;
+
+    assert_eq!(parser.finalize().await, TokenUsage::default());
+}
+
+#[tokio::test]
+async fn test_openai_multibyte_content_streamed_one_byte_at_a_time() {
+    let body = concat!(
+        "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"空が青いのは\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"レイリー散乱のためです。\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":12,\"completion_tokens\":9,\"total_tokens\":21}}\n\n",
+        "data: [DONE]\n\n",
+    );
+
+    // Every multi-byte character straddles a chunk boundary
+    let mut parser: Box<dyn BackendStreamParser> = Box::new(OpenAIParser::new());
+    for byte in body.as_bytes() {
+        parser.feed_chunk(&Bytes::copy_from_slice(&[*byte])).await;
+    }
+    assert!(parser.saw_content());
+    let usage = parser.finalize().await;
+
+    assert_eq!(usage.prompt_tokens, Some(12));
+    assert_eq!(usage.completion_tokens, Some(9));
+    assert_eq!(usage.event_count, 4);
+}
+
+#[tokio::test]
+async fn test_bedrock_parser_invoke_fixture() {
+    let body = include_bytes!("fixtures/bedrock_invoke.eventstream");
+
+    let usage = parse_response(BackendType::Bedrock, body).await;
+    assert_eq!(usage.prompt_tokens, Some(14));
+    assert_eq!(usage.completion_tokens, Some(11));
+    assert_eq!(usage.event_count, 8);
+
+    // Frames split at every byte boundary decode the same
+    let mut parser: Box<dyn BackendStreamParser> = Box::new(BedrockParser::new());
+    for byte in body.iter() {
+        parser.feed_chunk(&Bytes::copy_from_slice(&[*byte])).await;
+    }
+    assert!(parser.saw_content());
+    assert_eq!(parser.finalize().await, usage);
+}
+
+#[tokio::test]
+async fn test_bedrock_parser_skips_frames_with_bad_checksum() {
+    let mut body = include_bytes!("fixtures/bedrock_invoke.eventstream").to_vec();
+    // Corrupt the message CRC of the final (metrics-bearing) frame
+    let last = body.len() - 1;
+    body[last] ^= 0xFF;
+
+    let usage = parse_response(BackendType::Bedrock, &body).await;
+    assert_eq!(usage.prompt_tokens, None);
+    assert_eq!(usage.event_count, 7);
+}
+
+#[test]
+fn test_detect_bedrock_event_stream() {
+    assert_eq!(
+        detect_backend_type("application/vnd.amazon.eventstream"),
+        BackendType::Bedrock
+    );
+}
+
+#[tokio::test]
+async fn test_ollama_parser_discards_oversized_line_and_recovers() {
+    let junk = Bytes::from(vec![b'x'; 64 * 1024]);
+    let mut parser: Box<dyn BackendStreamParser> = Box::new(OllamaParser::new());
+
+    // 100MB without a single newline
+    for _ in 0..1600 {
+        parser.feed_chunk(&junk).await;
+    }
+    parser
+        .feed_chunk(&Bytes::from_static(
+            b"\n{\"done\":true,\"prompt_eval_count\":3,\"eval_count\":4}\n",
+        ))
+        .await;
+    let usage = parser.finalize().await;
+
+    assert!(usage.parse_truncated);
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens), (Some(3), Some(4)));
+}
+
+#[tokio::test]
+async fn test_openai_parser_discards_oversized_event() {
+    let junk = Bytes::from(vec![b'x'; 64 * 1024]);
+    let mut parser: Box<dyn BackendStreamParser> = Box::new(OpenAIParser::with_max_buffer(1024 * 1024));
+
+    parser.feed_chunk(&Bytes::from_static(b"data: ")).await;
+    for _ in 0..160 {
+        parser.feed_chunk(&junk).await;
+    }
+    parser.feed_chunk(&Bytes::from_static(b"\n\n")).await;
+    parser.feed_chunk(&Bytes::copy_from_slice(INCLUDE_USAGE_EVENT.as_bytes())).await;
+    let usage = parser.finalize().await;
+
+    assert!(usage.parse_truncated);
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens), (Some(13), Some(4)));
+}
+
+#[tokio::test]
+async fn test_cohere_parser_chat_fixture() {
+    let body = include_bytes!("fixtures/cohere_chat.ndjson");
+
+    let usage = parse_response(BackendType::Cohere, body).await;
+    assert_eq!(usage.prompt_tokens, Some(75));
+    assert_eq!(usage.completion_tokens, Some(5));
+    assert_eq!(usage.event_count, 6);
+
+    let mut parser: Box<dyn BackendStreamParser> = Box::new(CohereParser::new());
+    parser.capture_completion_text(usize::MAX);
+    for byte in body.iter() {
+        parser.feed_chunk(&Bytes::copy_from_slice(&[*byte])).await;
+    }
+    assert!(parser.saw_content());
+    assert_eq!(parser.finalize().await, usage);
+}
+
+#[tokio::test]
+async fn test_cohere_parser_aborted_before_stream_end() {
+    let body = include_str!("fixtures/cohere_chat.ndjson");
+    // Drop the terminal stream-end line
+    let aborted: String = body.lines().take(5).map(|line| format!("{line}\n")).collect();
+
+    let usage = parse_response(BackendType::Cohere, aborted.as_bytes()).await;
+    assert_eq!(usage.prompt_tokens, None);
+    assert_eq!(usage.completion_tokens, None);
+    assert_eq!(usage.event_count, 5);
+}
+
+#[test]
+fn test_detect_cohere_stream() {
+    assert_eq!(detect_backend_type("application/stream+json"), BackendType::Cohere);
+}
+
+#[tokio::test]
+async fn test_parsers_keep_completion_text_only_when_asked() {
+    let body = b"{\"response\":\"h\xc3\xa9llo\",\"done\":false}\n{\"response\":\" world\",\"done\":true,\"eval_count\":2}\n";
+    let parse = |limit: Option<usize>| async move {
+        let mut parser: Box<dyn BackendStreamParser> = Box::new(OllamaParser::new());
+        if let Some(limit) = limit {
+            parser.capture_completion_text(limit);
+        }
+        parser.feed_chunk(&Bytes::from_static(body)).await;
+        parser.finalize().await
+    };
+
+    assert_eq!(parse(None).await.completion_text, "");
+    assert_eq!(parse(Some(usize::MAX)).await.completion_text, "h\u{e9}llo world");
+    // Cut at the limit, never inside a character
+    assert_eq!(parse(Some(2)).await.completion_text, "h");
+    assert_eq!(parse(Some(8)).await.completion_text, "h\u{e9}llo w");
+    let usage = parse(Some(8)).await;
+    assert!(usage.completion_text_truncated);
+    assert_eq!(usage.completion_tokens, Some(2));
+}
+
+#[tokio::test]
+async fn test_parsers_capture_completion_text_and_finish_reason() {
+    let usage = parse_response(BackendType::OpenAI, include_bytes!("fixtures/openai_chat.sse")).await;
+    assert_eq!(usage.completion_text, "Hello! How can I help?");
+    assert_eq!(usage.finish_reason.as_deref(), Some("stop"));
+
+    let usage = parse_response(BackendType::Cohere, include_bytes!("fixtures/cohere_chat.ndjson")).await;
+    assert_eq!(usage.completion_text, "The sky is blue.");
+    assert_eq!(usage.finish_reason.as_deref(), Some("COMPLETE"));
+}
+
+#[tokio::test]
+async fn test_parsers_record_first_reported_model() {
+    let usage = parse_response(BackendType::Ollama, include_bytes!("fixtures/ollama_generate.ndjson")).await;
+    assert_eq!(usage.response_model.as_deref(), Some("llama2"));
+
+    let usage = parse_response(BackendType::OpenAI, include_bytes!("fixtures/openai_chat.sse")).await;
+    assert_eq!(usage.response_model.as_deref(), Some("gpt-4o-mini"));
+
+    let usage = parse_response(BackendType::Anthropic, include_bytes!("fixtures/anthropic_messages.sse")).await;
+    assert_eq!(usage.response_model.as_deref(), Some("claude-3-5-sonnet-20241022"));
+
+    // Later chunks naming a different model don't replace the first
+    let body = b"data: {\"model\":\"gpt-4o-2024-08-06\",\"choices\":[]}\n\ndata: {\"model\":\"other\",\"choices\":[]}\n\n";
+    let usage = parse_response(BackendType::OpenAI, body).await;
+    assert_eq!(usage.response_model.as_deref(), Some("gpt-4o-2024-08-06"));
+
+    let usage = parse_response(BackendType::Cohere, include_bytes!("fixtures/cohere_chat.ndjson")).await;
+    assert_eq!(usage.response_model, None);
+}
+
+#[tokio::test]
+async fn test_openai_parser_legacy_completions_text() {
+    let body = include_bytes!("fixtures/openai_completions.sse");
+
+    let first_event_end = body.windows(2).position(|w| w == b"\n\n").unwrap() + 2;
+
+    let mut parser: Box<dyn BackendStreamParser> = Box::new(OpenAIParser::new());
+    parser.capture_completion_text(usize::MAX);
+    parser.feed_chunk(&Bytes::from_static(&body[..first_event_end])).await;
+    assert!(parser.saw_content(), "`choices[].text` counts as content");
+    parser.feed_chunk(&Bytes::from_static(&body[first_event_end..])).await;
+    let usage = parser.finalize().await;
+
+    assert_eq!(usage.completion_text, "Paris is the capital.");
+    assert_eq!(usage.finish_reason.as_deref(), Some("stop"));
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens), (Some(6), Some(5)));
+}
+
+#[test]
+fn test_detect_backend_from_common_paths() {
+    let cases = [
+        ("/api/generate", "application/x-ndjson", BackendType::Ollama),
+        ("/api/chat", "application/json", BackendType::Ollama),
+        ("/v1/chat/completions", "text/event-stream", BackendType::OpenAI),
+        // Non-streaming OpenAI responses are plain JSON
+        ("/v1/chat/completions", "application/json", BackendType::OpenAI),
+        ("/v1/completions", "text/event-stream", BackendType::OpenAI),
+        ("/v1/embeddings", "application/json", BackendType::OpenAI),
+        ("/v1/messages", "text/event-stream", BackendType::Anthropic),
+        ("/openai/deployments/gpt4o/chat/completions", "text/event-stream", BackendType::OpenAI),
+        ("/model/anthropic.claude-v2/invoke-with-response-stream", "application/json", BackendType::Bedrock),
+        // Unrecognized paths fall back to the content-type
+        ("/custom/generate", "text/event-stream", BackendType::OpenAI),
+        ("/custom/generate", "text/plain", BackendType::Unknown),
+    ];
+
+    for (path, content_type, expected) in cases {
+        let hints = DetectionHints {
+            path,
+            content_type,
+            ..DetectionHints::default()
+        };
+        assert_eq!(detect_backend(&hints), expected, "{path} ({content_type})");
+    }
+}
+
+#[test]
+fn test_detect_backend_priority() {
+    let base = DetectionHints {
+        path: "/custom",
+        content_type: "text/plain",
+        ..DetectionHints::default()
+    };
+
+    // Explicit per-backend config beats everything
+    let forced = DetectionHints {
+        forced: Some(BackendType::Cohere),
+        path: "/v1/chat/completions",
+        ..base
+    };
+    assert_eq!(detect_backend(&forced), BackendType::Cohere);
+
+    // The body's shape is the last resort, after the configured default
+    let sniffed = DetectionHints {
+        request_shape: RequestShape::Messages,
+        ..base
+    };
+    assert_eq!(detect_backend(&sniffed), BackendType::OpenAI);
+    let prompt = DetectionHints {
+        request_shape: RequestShape::Prompt,
+        ..base
+    };
+    assert_eq!(detect_backend(&prompt), BackendType::Ollama);
+    let defaulted = DetectionHints {
+        default: Some(BackendType::Bedrock),
+        ..sniffed
+    };
+    assert_eq!(detect_backend(&defaulted), BackendType::Bedrock);
+}
+
+#[tokio::test]
+async fn test_anthropic_parser_messages_stream() {
+    let body = include_bytes!("fixtures/anthropic_messages.sse");
+
+    let usage = parse_response(BackendType::Anthropic, body).await;
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens), (Some(25), Some(15)));
+    assert_eq!(usage.completion_text, "Hello!");
+    assert_eq!(usage.finish_reason.as_deref(), Some("end_turn"));
+
+    let mut parser: Box<dyn BackendStreamParser> = Box::new(AnthropicParser::new());
+    parser.capture_completion_text(usize::MAX);
+    for byte in body.iter() {
+        parser.feed_chunk(&Bytes::copy_from_slice(&[*byte])).await;
+    }
+    assert!(parser.saw_content());
+    assert_eq!(parser.finalize().await, usage);
+}
+
+#[tokio::test]
+async fn test_non_streaming_json_responses() {
+    let openai = br#"{"id":"chatcmpl-1","object":"chat.completion","choices":[{"index":0,"message":{"role":"assistant","content":"Hi there."},"finish_reason":"stop"}],"usage":{"prompt_tokens":9,"completion_tokens":3,"total_tokens":12}}"#;
+    let usage = parse_response(BackendType::OpenAI, openai).await;
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens), (Some(9), Some(3)));
+    assert_eq!(usage.completion_text, "Hi there.");
+
+    let embeddings = br#"{"object":"list","data":[{"object":"embedding","index":0,"embedding":[0.1,0.2]}],"usage":{"prompt_tokens":5,"total_tokens":5}}"#;
+    let usage = parse_response(BackendType::OpenAI, embeddings).await;
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens), (Some(5), None));
+
+    let anthropic = br#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"Hi there."}],"stop_reason":"end_turn","usage":{"input_tokens":10,"output_tokens":4}}"#;
+    let usage = parse_response(BackendType::Anthropic, anthropic).await;
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens), (Some(10), Some(4)));
+    assert_eq!(usage.completion_text, "Hi there.");
+}
+
+#[tokio::test]
+async fn test_parsers_report_terminal_signal() {
+    let fixtures: [(BackendType, &[u8]); 5] = [
+        (BackendType::Ollama, include_bytes!("fixtures/ollama_generate.ndjson")),
+        (BackendType::OpenAI, include_bytes!("fixtures/openai_chat.sse")),
+        (BackendType::Anthropic, include_bytes!("fixtures/anthropic_messages.sse")),
+        (BackendType::Bedrock, include_bytes!("fixtures/bedrock_invoke.eventstream")),
+        (BackendType::Cohere, include_bytes!("fixtures/cohere_chat.ndjson")),
+    ];
+    for (backend, body) in fixtures {
+        assert!(parse_response(backend, body).await.saw_terminal, "{:?} full stream", backend);
+
+        // Cut off partway, as a dropped connection would
+        let cut = parse_response(backend, &body[..body.len() / 3]).await;
+        assert!(!cut.saw_terminal, "{:?} truncated stream", backend);
+    }
+}
+
+#[tokio::test]
+async fn test_total_tokens_reported_or_summed() {
+    let usage = parse_response(BackendType::OpenAI, include_bytes!("fixtures/openai_chat.sse")).await;
+    assert_eq!(usage.total_tokens, Some(16));
+    assert_eq!(usage.total(), Some(16));
+
+    // Anthropic reports no total, so it is the sum of the parts
+    let usage = parse_response(BackendType::Anthropic, include_bytes!("fixtures/anthropic_messages.sse")).await;
+    assert_eq!((usage.total_tokens, usage.total()), (None, Some(40)));
+
+    assert_eq!(TokenUsage::new(Some(5), None).total(), None);
+}
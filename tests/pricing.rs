@@ -0,0 +1,90 @@
+// tests/pricing.rs
+
+use rust_llm_logger::config::Config;
+use rust_llm_logger::parsers::BackendType;
+use rust_llm_logger::pricing::{ModelPrice, Pricing, PricingConfig};
+use rust_llm_logger::types::TokenUsage;
+
+fn usage(prompt: u32, completion: u32) -> TokenUsage {
+    TokenUsage::new(Some(prompt), Some(completion))
+}
+
+fn assert_close(actual: Option<f64>, expected: f64) {
+    let actual = actual.expect("expected a cost");
+    assert!((actual - expected).abs() < 1e-9, "cost {} != {}", actual, expected);
+}
+
+#[test]
+fn test_most_specific_pattern_wins() {
+    let pricing = Pricing::new(&PricingConfig::default());
+
+    // gpt-4o-mini* beats gpt-4o*, which beats gpt-4*
+    assert_eq!(pricing.price_for("gpt-4o-mini-2024-07-18"), Some(&ModelPrice::new(0.15, 0.60)));
+    assert_eq!(pricing.price_for("gpt-4o-2024-08-06"), Some(&ModelPrice::new(2.50, 10.00)));
+    assert_eq!(pricing.price_for("gpt-4-0613"), Some(&ModelPrice::new(30.00, 60.00)));
+    assert_eq!(pricing.price_for("Claude-3-5-Sonnet-20241022"), Some(&ModelPrice::new(3.00, 15.00)));
+    assert_eq!(pricing.price_for("llama2"), None);
+
+    // An exact name beats any prefix, however long
+    let config = PricingConfig {
+        models: [("gpt-4o-mini-2024-07-18".to_string(), ModelPrice::new(1.0, 1.0))].into(),
+        ..PricingConfig::default()
+    };
+    let pricing = Pricing::new(&config);
+    assert_eq!(pricing.price_for("gpt-4o-mini-2024-07-18"), Some(&ModelPrice::new(1.0, 1.0)));
+    assert_eq!(pricing.price_for("gpt-4o-mini"), Some(&ModelPrice::new(0.15, 0.60)));
+}
+
+#[test]
+fn test_cost_uses_cache_multipliers_and_unknown_models_are_unpriced() {
+    let pricing = Pricing::new(&PricingConfig::default());
+
+    // 1M input at $3 + 1M output at $15
+    let cost = pricing.cost(BackendType::Anthropic, &["claude-3-5-sonnet-latest"], &usage(1_000_000, 1_000_000));
+    assert_close(cost, 18.0);
+
+    // Cache writes at 1.25x and reads at 0.1x the $3 input rate
+    let cached = TokenUsage {
+        cache_write_tokens: Some(1_000_000),
+        cache_read_tokens: Some(1_000_000),
+        ..usage(0, 0)
+    };
+    assert_close(pricing.cost(BackendType::Anthropic, &["claude-3-5-sonnet-latest"], &cached), 3.75 + 0.3);
+
+    assert_eq!(pricing.cost(BackendType::Ollama, &["llama2"], &usage(100, 100)), None);
+    assert_eq!(pricing.cost(BackendType::OpenAI, &["gpt-4o"], &TokenUsage::default()), None);
+
+    // The first model that has a price is used
+    let cost = pricing.cost(BackendType::OpenAI, &["gpt-4o-mini-2024-07-18", "gpt-4o"], &usage(1_000_000, 0));
+    assert_close(cost, 0.15);
+}
+
+#[test]
+fn test_config_overrides_and_extends_builtin_prices() {
+    let config = Config::from_toml(
+        r#"
+[pricing.models."gpt-4o*"]
+input = 2.0
+output = 8.0
+
+[pricing.models."my-finetune"]
+input = 5.0
+output = 5.0
+cache_read = 0.0
+
+[pricing.backends.ollama]
+input = 0.0
+output = 0.0
+"#,
+    )
+    .unwrap();
+    let pricing = Pricing::new(&config.pricing);
+
+    assert_eq!(pricing.price_for("gpt-4o-2024-08-06"), Some(&ModelPrice::new(2.0, 8.0)));
+    assert_eq!(pricing.price_for("gpt-4o-mini"), Some(&ModelPrice::new(0.15, 0.60)), "untouched built-ins remain");
+    assert_close(pricing.cost(BackendType::OpenAI, &["my-finetune"], &usage(1_000_000, 0)), 5.0);
+    assert_close(pricing.cost(BackendType::Ollama, &["llama2"], &usage(500, 500)), 0.0);
+
+    let without_builtin = Config::from_toml("[pricing]\nbuiltin = false\n").unwrap();
+    assert_eq!(Pricing::new(&without_builtin.pricing).price_for("gpt-4o"), None);
+}
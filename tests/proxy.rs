@@ -0,0 +1,3729 @@
+// tests/proxy.rs
+
+mod common;
+
+use axum::{http::HeaderMap, routing::post, Router};
+use rust_llm_logger::app::{self, AppState};
+use rust_llm_logger::archive::ArchiveConfig;
+use rust_llm_logger::balancer::{BalanceStrategy, PoolConfig};
+use rust_llm_logger::config::{Config, ExpectContinueMode, PromptCapture};
+use rust_llm_logger::headers::HeaderConfig;
+use rust_llm_logger::parsers::BackendType;
+use rust_llm_logger::retry::RetryConfig;
+use rust_llm_logger::sinks::MemorySink;
+use rust_llm_logger::text::TextLogging;
+use rust_llm_logger::types::{PromptStats, SamplingParams, MAX_TOOLS_OFFERED, SCHEMA_VERSION};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Upstream that echoes the body and records the `Expect` header it received
+async fn spawn_echo_upstream() -> (u16, Arc<Mutex<Option<Option<String>>>>) {
+    let seen = Arc::new(Mutex::new(None));
+    let seen_clone = seen.clone();
+    let router = Router::new().route(
+        "/api/generate",
+        post(move |headers: HeaderMap, body: String| {
+            let seen = seen_clone.clone();
+            async move {
+                let expect = headers
+                    .get("expect")
+                    .map(|v| v.to_str().unwrap().to_string());
+                *seen.lock().unwrap() = Some(expect);
+                ([("content-type", "application/json")], body)
+            }
+        }),
+    );
+    let addr = common::spawn_server(router).await;
+    (addr.port(), seen)
+}
+
+/// Sends the request headers, returns the interim response (if any) and then the final response
+async fn send_with_expect(proxy: std::net::SocketAddr, upstream_port: u16, body: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(proxy).await.unwrap();
+    let head = format!(
+        "POST /proxy/{}/api/generate HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nExpect: 100-continue\r\nConnection: close\r\n\r\n",
+        upstream_port,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await.unwrap();
+
+    // Wait for the interim response before sending the body, like a real client
+    let mut buf = [0u8; 1024];
+    let n = stream.read(&mut buf).await.unwrap();
+    let interim = String::from_utf8_lossy(&buf[..n]).to_string();
+    if !interim.starts_with("HTTP/1.1 100") {
+        return (interim, String::new());
+    }
+
+    stream.write_all(body.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    (interim, response)
+}
+
+#[tokio::test]
+async fn test_expect_continue_is_answered_and_stripped() {
+    let (upstream_port, seen) = spawn_echo_upstream().await;
+    let proxy = common::spawn_proxy(Config::default()).await;
+
+    let body = r#"{"model":"llama2","prompt":"hi"}"#;
+    let (interim, response) = send_with_expect(proxy, upstream_port, body).await;
+
+    assert!(interim.starts_with("HTTP/1.1 100 Continue"), "got interim: {interim}");
+    assert!(response.starts_with("HTTP/1.1 200"), "got response: {response}");
+    assert!(response.ends_with(body), "body should round-trip: {response}");
+    assert_eq!(*seen.lock().unwrap(), Some(None), "Expect header should be stripped upstream");
+}
+
+#[tokio::test]
+async fn test_expect_continue_forward_mode_keeps_header() {
+    let (upstream_port, seen) = spawn_echo_upstream().await;
+    let config = Config {
+        expect_continue: ExpectContinueMode::Forward,
+        ..Config::default()
+    };
+    let proxy = common::spawn_proxy(config).await;
+
+    let body = r#"{"model":"llama2","prompt":"hi"}"#;
+    let (_, response) = send_with_expect(proxy, upstream_port, body).await;
+
+    assert!(response.starts_with("HTTP/1.1 200"), "got response: {response}");
+    assert_eq!(
+        *seen.lock().unwrap(),
+        Some(Some("100-continue".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn test_expect_continue_reject_mode_returns_417() {
+    let (upstream_port, seen) = spawn_echo_upstream().await;
+    let config = Config {
+        expect_continue: ExpectContinueMode::Reject,
+        ..Config::default()
+    };
+    let proxy = common::spawn_proxy(config).await;
+
+    let (interim, _) = send_with_expect(proxy, upstream_port, "{}").await;
+
+    assert!(interim.starts_with("HTTP/1.1 417"), "got: {interim}");
+    assert!(seen.lock().unwrap().is_none(), "upstream should never be called");
+}
+
+#[tokio::test]
+async fn test_ttft_is_much_smaller_than_latency() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let (status, _) = common::post_json(
+        proxy,
+        upstream.port(),
+        "api/generate",
+        r#"{"model":"llama2","prompt":"Why is the sky blue?","stream":true}"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+
+    let records = common::wait_for_records(&sink, 1).await;
+    let metrics = &records[0];
+    let ttft = metrics.ttft_ms.expect("streaming response should record TTFT");
+
+    // The mock sleeps 10ms per word across ~40 words, so the first token arrives far earlier
+    assert!(
+        ttft * 4 < metrics.latency_ms,
+        "ttft_ms={} latency_ms={}",
+        ttft,
+        metrics.latency_ms
+    );
+}
+
+/// Upstream that always answers with the given status
+async fn spawn_status_upstream(status: u16) -> u16 {
+    let router = Router::new().route(
+        "/api/generate",
+        post(move || async move {
+            (
+                axum::http::StatusCode::from_u16(status).unwrap(),
+                [("content-type", "application/json")],
+                r#"{"error":"upstream failure"}"#,
+            )
+        }),
+    );
+    common::spawn_server(router).await.port()
+}
+
+#[tokio::test]
+async fn test_circuit_breaker_status_weights() {
+    let config = Config::from_toml(
+        r#"
+        [circuit_breaker]
+        enabled = true
+        failure_threshold = 1.0
+        cooldown_secs = 60
+
+        [circuit_breaker.status_weights]
+        429 = 0.25
+        500 = 1.0
+        "#,
+    )
+    .unwrap();
+    let proxy = common::spawn_proxy(config).await;
+    let body = r#"{"model":"llama2","prompt":"hi"}"#;
+
+    // 429s are weighted lightly: the breaker only trips on the fourth one
+    let rate_limited = spawn_status_upstream(429).await;
+    for _ in 0..4 {
+        let (status, _) = common::post_json(proxy, rate_limited, "api/generate", body).await;
+        assert_eq!(status, 429);
+    }
+    let (status, _) = common::post_json(proxy, rate_limited, "api/generate", body).await;
+    assert_eq!(status, 503, "breaker should open after four weighted 429s");
+
+    // A single 500 is enough to trip the breaker
+    let failing = spawn_status_upstream(500).await;
+    let (status, _) = common::post_json(proxy, failing, "api/generate", body).await;
+    assert_eq!(status, 500);
+    let (status, _) = common::post_json(proxy, failing, "api/generate", body).await;
+    assert_eq!(status, 503, "breaker should open after one 500");
+}
+
+/// Fetches `/stats` from the proxy as JSON
+async fn get_stats(proxy: std::net::SocketAddr) -> serde_json::Value {
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let resp = client
+        .get(format!("http://{}/stats", proxy).parse().unwrap())
+        .await
+        .unwrap();
+    let body = http_body_util::BodyExt::collect(resp.into_body()).await.unwrap().to_bytes();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn test_circuit_breaker_trips_on_connection_failures() {
+    let config = Config::from_toml(
+        r#"
+        [circuit_breaker]
+        enabled = true
+        failure_threshold = 3.0
+        cooldown_secs = 60
+        "#,
+    )
+    .unwrap();
+    let proxy = common::spawn_proxy(config).await;
+    let dead = closed_port().await;
+    let target = format!("127.0.0.1:{}", dead);
+    let body = r#"{"model":"llama2","prompt":"hi"}"#;
+
+    for _ in 0..2 {
+        let (status, _) = common::post_json(proxy, dead, "api/generate", body).await;
+        assert_eq!(status, 502);
+    }
+    let stats = get_stats(proxy).await;
+    assert_eq!(stats["breakers"][&target]["state"], "closed");
+    assert_eq!(stats["breakers"][&target]["failure_score"], 2.0);
+
+    let (status, _) = common::post_json(proxy, dead, "api/generate", body).await;
+    assert_eq!(status, 502);
+
+    // Open: rejected without another connection attempt
+    let (status, response) = common::post_json(proxy, dead, "api/generate", body).await;
+    assert_eq!(status, 503);
+    assert_eq!(response, "Upstream circuit breaker open");
+
+    let stats = get_stats(proxy).await;
+    assert_eq!(stats["breakers"][&target]["state"], "open");
+    assert_eq!(stats["breakers"][&target]["cooldown_remaining_secs"], 60);
+}
+
+#[tokio::test]
+async fn test_circuit_breaker_rejections_are_recorded() {
+    let config = Config::from_toml("[circuit_breaker]\nenabled = true\nfailure_threshold = 1.0\ncooldown_secs = 60\n").unwrap();
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+    let dead = closed_port().await;
+    let body = r#"{"model":"llama2","prompt":"hi"}"#;
+
+    let (status, _) = common::post_json(proxy, dead, "api/generate", body).await;
+    assert_eq!(status, 502);
+    let (status, _) = common::post_json(proxy, dead, "api/generate", body).await;
+    assert_eq!(status, 503);
+
+    let records = common::wait_for_records(&sink, 2).await;
+    assert_eq!(records[0].status_code, 502);
+    assert!(records[0].upstream_error.is_some());
+    assert_eq!(records[1].status_code, 503);
+    assert!(!records[1].success);
+    assert_eq!(records[1].model, "llama2");
+    assert_eq!(records[1].backend_port, dead);
+    assert_eq!(records[1].upstream_error.as_deref(), Some("Upstream circuit breaker open"));
+}
+
+#[tokio::test]
+async fn test_lost_probe_does_not_lock_out_upstream() {
+    let config = Config::from_toml(
+        r#"
+        [circuit_breaker]
+        enabled = true
+        failure_threshold = 1.0
+        cooldown_secs = 1
+
+        [concurrency]
+        max_concurrent = 1
+        max_queued = 0
+        "#,
+    )
+    .unwrap();
+    let proxy = common::spawn_proxy(config).await;
+    let busy_upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let dead = closed_port().await;
+    let body = r#"{"model":"llama2","prompt":"hi"}"#;
+
+    let (status, _) = common::post_json(proxy, dead, "api/generate", body).await;
+    assert_eq!(status, 502);
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+    // The request is turned away by the limiter before it can claim the probe
+    let busy = tokio::spawn(post_delayed(proxy, busy_upstream.port(), "llama2", 300));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let (status, response) = common::post_json(proxy, dead, "api/generate", body).await;
+    assert_eq!(status, 503);
+    assert_eq!(response, "Too many concurrent requests");
+    let target = format!("127.0.0.1:{}", dead);
+    assert_eq!(get_stats(proxy).await["breakers"][&target]["state"], "open");
+    assert_eq!(busy.await.unwrap(), 200);
+
+    // Once another cooldown passes, a new probe reaches the upstream
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    let (status, _) = common::post_json(proxy, dead, "api/generate", body).await;
+    assert_eq!(status, 502);
+}
+
+#[tokio::test]
+async fn test_error_body_is_logged_and_forwarded() {
+    let (logs, _guard) = common::capture_logs();
+
+    let router = Router::new().route(
+        "/v1/chat/completions",
+        post(|| async {
+            (
+                axum::http::StatusCode::BAD_REQUEST,
+                [("content-type", "application/json")],
+                r#"{"error":{"message":"context length exceeded","type":"invalid_request_error"}}"#,
+            )
+        }),
+    );
+    let upstream = common::spawn_server(router).await;
+    let config = Config {
+        error_body_log_bytes: Some(1024),
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let (status, body) = common::post_json(
+        proxy,
+        upstream.port(),
+        "v1/chat/completions",
+        r#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi"}]}"#,
+    )
+    .await;
+    assert_eq!(status, 400);
+    assert!(body.contains("context length exceeded"), "client still gets the error body");
+
+    common::wait_for_records(&sink, 1).await;
+    let logs = logs.contents();
+    let line = logs
+        .lines()
+        .find(|l| l.contains("Upstream error response"))
+        .expect("error body should be logged");
+    assert!(line.contains("status=400"), "{line}");
+    assert!(line.contains("request_id="), "{line}");
+    assert!(line.contains("context length exceeded"), "{line}");
+}
+
+#[tokio::test]
+async fn test_archive_matches_transmitted_bytes() {
+    let dir = tempfile::tempdir().unwrap();
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let config = Config {
+        archive: Some(ArchiveConfig {
+            dir: dir.path().to_path_buf(),
+            ..ArchiveConfig::default()
+        }),
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let request_body = r#"{"model":"llama2","prompt":"Why is the sky blue?","stream":true}"#;
+    let (status, response_body) =
+        common::post_json(proxy, upstream.port(), "api/generate", request_body).await;
+    assert_eq!(status, 200);
+    common::wait_for_records(&sink, 1).await;
+
+    let mut files: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    files.sort();
+    assert_eq!(files.len(), 3, "expected request, request head, and response files: {:?}", files);
+
+    let request_file = files.iter().find(|p| p.extension().unwrap() == "request").unwrap();
+    let response_file = files.iter().find(|p| p.extension().unwrap() == "response").unwrap();
+    assert_eq!(request_file.file_stem(), response_file.file_stem(), "files share the request ID");
+    assert_eq!(std::fs::read_to_string(request_file).unwrap(), request_body);
+    assert_eq!(std::fs::read_to_string(response_file).unwrap(), response_body);
+}
+
+#[tokio::test]
+async fn test_refused_requests_are_not_archived() {
+    let dir = tempfile::tempdir().unwrap();
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let mut config = Config {
+        archive: Some(ArchiveConfig {
+            dir: dir.path().to_path_buf(),
+            ..ArchiveConfig::default()
+        }),
+        ..Config::default()
+    };
+    config.model_policy.deny = vec!["blocked".to_string()];
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let refused = r#"{"model":"blocked","prompt":"Why is the sky blue?","stream":true}"#;
+    let (status, _) = common::post_json(proxy, upstream.port(), "api/generate", refused).await;
+    assert_eq!(status, 403);
+    let allowed = r#"{"model":"llama2","prompt":"Why is the sky blue?","stream":true}"#;
+    common::post_json(proxy, upstream.port(), "api/generate", allowed).await;
+    common::wait_for_records(&sink, 1).await;
+
+    // Only the allowed request can be replayed
+    let requests: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().unwrap() == "request")
+        .collect();
+    assert_eq!(requests.len(), 1, "{:?}", requests);
+    assert_eq!(std::fs::read_to_string(&requests[0]).unwrap(), allowed);
+}
+
+/// POSTs to `/replay/:request_id` with extra headers and returns the status and body
+async fn replay(proxy: std::net::SocketAddr, request_id: &str, headers: &[(&str, &str)]) -> (u16, String) {
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let mut req = hyper::Request::post(format!("http://{}/replay/{}", proxy, request_id));
+    for (name, value) in headers {
+        req = req.header(*name, *value);
+    }
+    let resp = client.request(req.body(axum::body::Body::empty()).unwrap()).await.unwrap();
+    let status = resp.status().as_u16();
+    let bytes = http_body_util::BodyExt::collect(resp.into_body()).await.unwrap().to_bytes();
+    (status, String::from_utf8_lossy(&bytes).to_string())
+}
+
+#[tokio::test]
+async fn test_replay_reissues_archived_request() {
+    let dir = tempfile::tempdir().unwrap();
+    let (upstream_port, seen) = spawn_header_recording_upstream().await;
+    let config = Config {
+        archive: Some(ArchiveConfig {
+            dir: dir.path().to_path_buf(),
+            replay: true,
+        }),
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let original = [
+        ("x-request-id", "original-1"),
+        ("authorization", "Bearer sk-original"),
+        ("anthropic-version", "2023-06-01"),
+        ("cookie", "session=secret"),
+        ("x-custom", "dropped"),
+    ];
+    assert_eq!(post_with_headers(proxy, upstream_port, &original).await, 200);
+    common::wait_for_records(&sink, 1).await;
+
+    // The head is written beside the body; credentials and unlisted headers are not
+    let head_path = dir.path().join("original-1.request.json");
+    for _ in 0..200 {
+        if head_path.exists() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    let head = std::fs::read_to_string(&head_path).unwrap();
+    assert!(head.contains("/proxy/"), "{}", head);
+    assert!(!head.contains("sk-original"), "{}", head);
+    assert!(!head.contains("session=secret"), "{}", head);
+    assert!(!head.contains("x-custom"), "{}", head);
+
+    let (status, body) = replay(proxy, "original-1", &[("authorization", "Bearer sk-replay")]).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body, r#"{"model":"llama2","prompt":"Hi"}"#);
+
+    let headers = seen.lock().unwrap().clone().unwrap();
+    assert_eq!(headers["anthropic-version"], "2023-06-01");
+    assert_eq!(headers["authorization"], "Bearer sk-replay");
+    assert!(!headers.contains_key("x-custom"));
+
+    let records = common::wait_for_records(&sink, 2).await;
+    assert_eq!(records[0].replay_of, None);
+    assert_eq!(records[1].replay_of.as_deref(), Some("original-1"));
+    assert_ne!(records[1].request_id, "original-1");
+    assert_eq!(records[1].model, "llama2");
+    assert_eq!(records[1].prompt, records[0].prompt);
+
+    let (status, _) = replay(proxy, "missing", &[]).await;
+    assert_eq!(status, 404);
+    let (status, _) = replay(proxy, "not..valid", &[]).await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn test_replay_needs_archive() {
+    let proxy = common::spawn_proxy(Config::default()).await;
+    let (status, body) = replay(proxy, "anything", &[]).await;
+    assert_eq!(status, 404);
+    assert_eq!(body, "Request archiving is not enabled");
+
+    // Archiving alone doesn't open /replay
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        archive: Some(ArchiveConfig {
+            dir: dir.path().to_path_buf(),
+            replay: false,
+        }),
+        ..Config::default()
+    };
+    let proxy = common::spawn_proxy(config).await;
+    let (status, body) = replay(proxy, "anything", &[]).await;
+    assert_eq!(status, 404);
+    assert_eq!(body, "Replay is not enabled");
+}
+
+#[tokio::test]
+async fn test_chunk_gap_stats_follow_mock_cadence() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    common::post_json(
+        proxy,
+        upstream.port(),
+        "api/generate",
+        r#"{"model":"llama2","prompt":"Why is the sky blue?","stream":true}"#,
+    )
+    .await;
+
+    let records = common::wait_for_records(&sink, 1).await;
+    let metrics = &records[0];
+
+    // The mock emits a chunk every 10ms
+    let mean = metrics.chunk_gap_ms_mean.expect("mean gap recorded");
+    assert!((5.0..50.0).contains(&mean), "mean gap {}ms", mean);
+    assert!(metrics.chunk_gap_ms_min.unwrap() <= mean);
+    assert!(metrics.chunk_gap_ms_max.unwrap() >= metrics.chunk_gap_ms_p95.unwrap());
+}
+
+#[tokio::test]
+async fn test_default_backend_type_parses_unknown_content_type() {
+    let router = Router::new().route(
+        "/api/generate",
+        post(|| async {
+            (
+                [("content-type", "text/plain")],
+                "{\"response\":\"Hi\",\"done\":false}\n{\"response\":\"\",\"done\":true,\"prompt_eval_count\":7,\"eval_count\":2}\n",
+            )
+        }),
+    );
+    let upstream = common::spawn_server(router).await;
+    let config = Config::from_toml(r#"default_backend_type = "ollama""#).unwrap();
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    common::post_json(proxy, upstream.port(), "api/generate", r#"{"model":"llama2","prompt":"hi"}"#).await;
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].prompt_tokens, Some(7));
+    assert_eq!(records[0].completion_tokens, Some(2));
+}
+
+#[tokio::test]
+async fn test_forced_backend_type_parses_text_plain_ndjson() {
+    let router = Router::new().route(
+        "/custom/generate",
+        post(|| async {
+            (
+                [("content-type", "text/plain")],
+                "{\"model\":\"llama2\",\"response\":\"Hi\",\"done\":false}\n{\"model\":\"llama2\",\"response\":\"\",\"done\":true,\"prompt_eval_count\":7,\"eval_count\":2}\n",
+            )
+        }),
+    );
+    let upstream = common::spawn_server(router).await;
+    let body = r#"{"model":"llama2","input":"hi"}"#;
+
+    // Nothing in the path, content-type, or request body identifies the backend
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+    common::post_json(proxy, upstream.port(), "custom/generate", body).await;
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].backend, BackendType::Unknown);
+    assert_eq!(records[0].completion_tokens, None);
+
+    let config = Config::from_toml(&format!("[backends.{}]\nbackend_type = \"ollama\"\n", upstream.port())).unwrap();
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+    common::post_json(proxy, upstream.port(), "custom/generate", body).await;
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].backend, BackendType::Ollama);
+    assert_eq!(records[0].prompt_tokens, Some(7));
+    assert_eq!(records[0].completion_tokens, Some(2));
+}
+
+#[tokio::test]
+async fn test_response_counters_match_mock_streams() {
+    let ollama = common::spawn_server(common::mock_server::ollama_app()).await;
+    let openai = common::spawn_server(common::mock_server::openai_app()).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let (_, ollama_body) = common::post_json(
+        proxy,
+        ollama.port(),
+        "api/generate",
+        r#"{"model":"llama2","prompt":"Why is the sky blue?","stream":true}"#,
+    )
+    .await;
+    let records = common::wait_for_records(&sink, 1).await;
+    let metrics = &records[0];
+    assert_eq!(metrics.response_bytes, ollama_body.len() as u64);
+    assert_eq!(metrics.event_count, ollama_body.lines().count() as u64);
+    assert!(metrics.frame_count >= 1 && metrics.frame_count <= metrics.event_count);
+
+    let (_, openai_body) = common::post_json(
+        proxy,
+        openai.port(),
+        "v1/chat/completions",
+        r#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi"}],"stream":true}"#,
+    )
+    .await;
+    let records = common::wait_for_records(&sink, 2).await;
+    let metrics = &records[1];
+    assert_eq!(metrics.response_bytes, openai_body.len() as u64);
+    assert_eq!(metrics.event_count, openai_body.matches("data:").count() as u64);
+    assert!(metrics.frame_count >= 1 && metrics.frame_count <= metrics.event_count);
+}
+
+#[tokio::test]
+async fn test_response_counters_match_paced_stream() {
+    // Three frames, the last carrying two events
+    const FRAMES: [&str; 3] = [
+        "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"The\"}}]}\n\n",
+        "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\" sky\"}}]}\n\n",
+        "data: {\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\ndata: [DONE]\n\n",
+    ];
+    let router = Router::new().route(
+        "/v1/chat/completions",
+        post(|| async {
+            // Paced so each one reaches the proxy as its own frame
+            let stream = futures::StreamExt::then(futures::stream::iter(FRAMES), |frame| async move {
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                Ok::<_, std::io::Error>(bytes::Bytes::from_static(frame.as_bytes()))
+            });
+            ([("content-type", "text/event-stream")], axum::body::Body::from_stream(stream))
+        }),
+    );
+    let upstream = common::spawn_server(router).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let body = r#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi"}],"stream":true}"#;
+    common::post_json(proxy, upstream.port(), "v1/chat/completions", body).await;
+    let records = common::wait_for_records(&sink, 1).await;
+
+    assert_eq!(records[0].response_bytes, FRAMES.concat().len() as u64);
+    assert_eq!(records[0].frame_count, 3);
+    assert_eq!(records[0].event_count, 4);
+}
+
+/// Upstream that records the order requests arrive in and holds each one briefly
+async fn spawn_ordering_upstream() -> (u16, Arc<Mutex<Vec<String>>>) {
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let order_clone = order.clone();
+    let router = Router::new().route(
+        "/api/generate",
+        post(move |body: String| {
+            let order = order_clone.clone();
+            async move {
+                order.lock().unwrap().push(body.clone());
+                tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+                ([("content-type", "application/json")], body)
+            }
+        }),
+    );
+    let addr = common::spawn_server(router).await;
+    (addr.port(), order)
+}
+
+async fn post_with_priority(proxy: std::net::SocketAddr, upstream_port: u16, label: &str, priority: &str) {
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let req = hyper::Request::post(format!("http://{}/proxy/{}/api/generate", proxy, upstream_port))
+        .header("content-type", "application/json")
+        .header("x-priority", priority)
+        .body(axum::body::Body::from(label.to_string()))
+        .unwrap();
+    let resp = client.request(req).await.unwrap();
+    http_body_util::BodyExt::collect(resp.into_body()).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_high_priority_jumps_queued_low_priority() {
+    let (upstream_port, order) = spawn_ordering_upstream().await;
+    let mut config = Config::default();
+    config.concurrency.max_concurrent = Some(1);
+    let proxy = common::spawn_proxy(config).await;
+
+    // Occupy the only slot, then queue two low-priority requests ahead of a high-priority one
+    let mut handles = Vec::new();
+    for (label, priority) in [("busy", "bogus"), ("low-1", "low"), ("low-2", "LOW"), ("high", "high")] {
+        handles.push(tokio::spawn(post_with_priority(proxy, upstream_port, label, priority)));
+        tokio::time::sleep(std::time::Duration::from_millis(30)).await;
+    }
+    for handle in handles {
+        handle.await.unwrap();
+    }
+
+    assert_eq!(*order.lock().unwrap(), vec!["busy", "high", "low-1", "low-2"]);
+}
+
+#[tokio::test]
+async fn test_priority_header_not_forwarded() {
+    let router = Router::new().route(
+        "/api/generate",
+        post(|headers: HeaderMap| async move { format!("{:?}", headers.get("x-priority")) }),
+    );
+    let upstream = common::spawn_server(router).await;
+    let proxy = common::spawn_proxy(Config::default()).await;
+
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let req = hyper::Request::post(format!("http://{}/proxy/{}/api/generate", proxy, upstream.port()))
+        .header("content-type", "application/json")
+        .header("x-priority", "high")
+        .body(axum::body::Body::from(r#"{"model":"llama2","prompt":"Hi"}"#))
+        .unwrap();
+    let resp = client.request(req).await.unwrap();
+    let body = http_body_util::BodyExt::collect(resp.into_body()).await.unwrap().to_bytes();
+
+    // The priority is for the proxy's queue; upstreams never see it
+    assert_eq!(body, "None");
+}
+
+/// Sends an Ollama request whose response headers the mock holds back for `delay_ms`
+async fn post_delayed(proxy: std::net::SocketAddr, upstream_port: u16, model: &str, delay_ms: u64) -> u16 {
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let body = format!(r#"{{"model":"{}","prompt":"Hi","stream":true}}"#, model);
+    let req = hyper::Request::post(format!("http://{}/proxy/{}/api/generate", proxy, upstream_port))
+        .header("content-type", "application/json")
+        .header("x-mock-headers-delay-ms", delay_ms.to_string())
+        .body(axum::body::Body::from(body))
+        .unwrap();
+    let resp = client.request(req).await.unwrap();
+    let status = resp.status().as_u16();
+    http_body_util::BodyExt::collect(resp.into_body()).await.unwrap();
+    status
+}
+
+#[tokio::test]
+async fn test_concurrency_limit_rejects_request_beyond_limit() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let mut config = Config::default();
+    config.concurrency.max_concurrent = Some(2);
+    config.concurrency.max_queued = Some(0);
+    let proxy = common::spawn_proxy(config).await;
+
+    let busy: Vec<_> = (0..2)
+        .map(|_| tokio::spawn(post_delayed(proxy, upstream.port(), "llama2", 500)))
+        .collect();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let stats = get_stats(proxy).await;
+    assert_eq!(stats["concurrency"]["global"], serde_json::json!({"limit": 2, "in_flight": 2, "queued": 0}));
+
+    assert_eq!(post_delayed(proxy, upstream.port(), "llama2", 0).await, 503);
+
+    for handle in busy {
+        assert_eq!(handle.await.unwrap(), 200);
+    }
+    assert_eq!(post_delayed(proxy, upstream.port(), "llama2", 0).await, 200);
+    assert_eq!(get_stats(proxy).await["concurrency"]["global"]["in_flight"], 0);
+}
+
+#[tokio::test]
+async fn test_per_model_concurrency_limit() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let mut config = Config::default();
+    config.concurrency.per_model.insert("llama2".to_string(), 1);
+    config.concurrency.max_queued = Some(0);
+    let proxy = common::spawn_proxy(config).await;
+
+    let busy = tokio::spawn(post_delayed(proxy, upstream.port(), "llama2", 500));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    assert_eq!(post_delayed(proxy, upstream.port(), "llama2", 0).await, 503);
+    assert_eq!(post_delayed(proxy, upstream.port(), "mistral", 0).await, 200);
+
+    let stats = get_stats(proxy).await;
+    assert_eq!(stats["concurrency"]["global"], serde_json::Value::Null);
+    assert_eq!(stats["concurrency"]["models"]["llama2"]["in_flight"], 1);
+
+    assert_eq!(busy.await.unwrap(), 200);
+}
+
+#[tokio::test]
+async fn test_graceful_shutdown_drains_in_flight_stream() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let sink = Arc::new(MemorySink::new());
+    let state = AppState::new(Config::default()).with_sink(sink.clone());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = listener.local_addr().unwrap();
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(app::serve(listener, state, async {
+        let _ = shutdown_rx.await;
+    }));
+
+    // The mock streams for ~400ms; shut down while it is mid-stream
+    let request = tokio::spawn(common::post_json(
+        proxy,
+        upstream.port(),
+        "api/generate",
+        r#"{"model":"llama2","prompt":"Why is the sky blue?","stream":true}"#,
+    ));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    shutdown_tx.send(()).unwrap();
+
+    server.await.unwrap().unwrap();
+
+    // Once serve returns the metrics must already be recorded, without polling
+    let records = sink.records();
+    assert_eq!(records.len(), 1);
+    assert!(records[0].completion_tokens.is_some());
+
+    let (status, body) = request.await.unwrap();
+    assert_eq!(status, 200);
+    assert!(body.contains("\"done\":true"), "response was truncated: {}", body);
+}
+
+#[tokio::test]
+async fn test_slow_consumer_receives_every_chunk() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let config = Config {
+        stream_channel_capacity: 1,
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let req = hyper::Request::post(format!("http://{}/proxy/{}/api/generate", proxy, upstream.port()))
+        .header("content-type", "application/json")
+        .body(axum::body::Body::from(r#"{"model":"llama2","prompt":"Hi","stream":true}"#))
+        .unwrap();
+    let mut body = client.request(req).await.unwrap().into_body();
+
+    // Read far slower than the mock produces so the single-slot buffer stays full
+    let mut received = Vec::new();
+    while let Some(frame) = http_body_util::BodyExt::frame(&mut body).await {
+        if let Ok(data) = frame.unwrap().into_data() {
+            received.extend_from_slice(&data);
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(25)).await;
+    }
+
+    let records = common::wait_for_records(&sink, 1).await;
+    let lines = received.split(|&b| b == b'\n').filter(|l| !l.is_empty()).count();
+    assert_eq!(received.len() as u64, records[0].response_bytes);
+    assert_eq!(lines as u64, records[0].event_count);
+    assert!(String::from_utf8_lossy(&received).contains("\"done\":true"));
+}
+
+/// Posts through the proxy with an `x-inject-delay-ms` header and returns the client-observed time
+async fn post_with_delay_header(proxy: std::net::SocketAddr, upstream_port: u16, delay_ms: u64) -> std::time::Duration {
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let req = hyper::Request::post(format!("http://{}/proxy/{}/api/generate", proxy, upstream_port))
+        .header("content-type", "application/json")
+        .header("x-inject-delay-ms", delay_ms.to_string())
+        .body(axum::body::Body::from(r#"{"model":"llama2","prompt":"Hi"}"#))
+        .unwrap();
+    let start = std::time::Instant::now();
+    let resp = client.request(req).await.unwrap();
+    http_body_util::BodyExt::collect(resp.into_body()).await.unwrap();
+    start.elapsed()
+}
+
+#[tokio::test]
+async fn test_injected_delay_adds_to_latency() {
+    let (upstream_port, _) = spawn_echo_upstream().await;
+    let mut config = Config::default();
+    config.debug.enabled = true;
+    config.debug.allow_delay_header = true;
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let elapsed = post_with_delay_header(proxy, upstream_port, 300).await;
+    let records = common::wait_for_records(&sink, 1).await;
+
+    assert!(elapsed.as_millis() >= 300, "client saw {:?}", elapsed);
+    assert!(records[0].latency_ms >= 300, "latency_ms={}", records[0].latency_ms);
+}
+
+#[tokio::test]
+async fn test_delay_header_ignored_without_debug_flag() {
+    let (upstream_port, _) = spawn_echo_upstream().await;
+    let proxy = common::spawn_proxy(Config::default()).await;
+
+    let elapsed = post_with_delay_header(proxy, upstream_port, 2000).await;
+
+    assert!(elapsed.as_millis() < 1000, "client saw {:?}", elapsed);
+}
+
+#[test]
+fn test_huge_delay_header_is_capped() {
+    let debug = rust_llm_logger::debug::DebugConfig {
+        enabled: true,
+        inject_jitter_ms: 10,
+        allow_delay_header: true,
+        ..Default::default()
+    };
+
+    // Jitter on top of u64::MAX must not overflow
+    let delay = debug.injected_delay(Some(&u64::MAX.to_string()));
+    assert_eq!(delay, Some(std::time::Duration::from_secs(60)));
+}
+
+/// Upstream that sends one NDJSON chunk and then stalls without closing the stream
+async fn spawn_stalling_upstream() -> u16 {
+    let router = Router::new().route(
+        "/api/generate",
+        post(|| async {
+            let first = futures::stream::once(async {
+                Ok::<_, std::io::Error>(bytes::Bytes::from_static(
+                    b"{\"model\":\"llama2\",\"response\":\"Hi\",\"done\":false}\n",
+                ))
+            });
+            let body = axum::body::Body::from_stream(futures::StreamExt::chain(first, futures::stream::pending()));
+            ([("content-type", "application/x-ndjson")], body)
+        }),
+    );
+    common::spawn_server(router).await.port()
+}
+
+#[tokio::test]
+async fn test_stalled_upstream_is_truncated_after_idle_timeout() {
+    let upstream_port = spawn_stalling_upstream().await;
+    let config = Config {
+        stream_idle_timeout_ms: 200,
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let req = hyper::Request::post(format!("http://{}/proxy/{}/api/generate", proxy, upstream_port))
+        .header("content-type", "application/json")
+        .body(axum::body::Body::from(r#"{"model":"llama2","prompt":"Hi","stream":true}"#))
+        .unwrap();
+    let resp = client.request(req).await.unwrap();
+
+    // The client stream ends with an error instead of hanging
+    let body = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        http_body_util::BodyExt::collect(resp.into_body()),
+    )
+    .await
+    .expect("client stream should end once the idle timeout fires");
+    assert!(body.is_err());
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert!(records[0].truncated);
+    assert_eq!(records[0].frame_count, 1);
+    assert!(records[0].latency_ms >= 200);
+}
+
+#[tokio::test]
+async fn test_client_disconnect_is_flagged_in_metrics() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let req = hyper::Request::post(format!("http://{}/proxy/{}/api/generate", proxy, upstream.port()))
+        .header("content-type", "application/json")
+        .body(axum::body::Body::from(r#"{"model":"llama2","prompt":"Hi","stream":true}"#))
+        .unwrap();
+    let mut body = client.request(req).await.unwrap().into_body();
+
+    // Read one chunk of the ~400ms stream, then hang up
+    http_body_util::BodyExt::frame(&mut body).await.unwrap().unwrap();
+    drop(body);
+    drop(client);
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert!(records[0].client_disconnected);
+    assert!(records[0].frame_count < 40, "frame_count={}", records[0].frame_count);
+
+    // A response read to the end is not flagged
+    let (status, _) = common::post_json(
+        proxy,
+        upstream.port(),
+        "api/generate",
+        r#"{"model":"llama2","prompt":"Hi","stream":true}"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+    let records = common::wait_for_records(&sink, 2).await;
+    assert!(!records[1].client_disconnected);
+}
+
+#[tokio::test]
+async fn test_streamed_prompt_tokens_recorded_in_metrics() {
+    let (upstream_port, _) = spawn_echo_upstream().await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let prompt = "Why is the sky blue? ".repeat(5_000);
+    let body = serde_json::json!({"model": "llama2", "prompt": prompt}).to_string();
+    let (status, _) = common::post_json(proxy, upstream_port, "api/generate", &body).await;
+    assert_eq!(status, 200);
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(
+        records[0].streamed_prompt_tokens,
+        Some(rust_llm_logger::tokens::estimate_tokens(&prompt))
+    );
+}
+
+#[tokio::test]
+async fn test_denied_model_is_rejected_before_upstream() {
+    let (upstream_port, seen) = spawn_echo_upstream().await;
+    let mut config = Config::default();
+    config.model_policy.deny = vec!["text-davinci-003".to_string()];
+    let proxy = common::spawn_proxy(config).await;
+
+    let (status, body) = common::post_json(
+        proxy,
+        upstream_port,
+        "api/generate",
+        r#"{"model":"TEXT-DAVINCI-003","prompt":"Hi"}"#,
+    )
+    .await;
+    assert_eq!(status, 403);
+    let error: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(error["error"]["type"], "model_not_permitted");
+    assert!(seen.lock().unwrap().is_none(), "upstream must not be called");
+
+    let (status, _) =
+        common::post_json(proxy, upstream_port, "api/generate", r#"{"model":"llama2","prompt":"Hi"}"#).await;
+    assert_eq!(status, 200);
+}
+
+#[tokio::test]
+async fn test_denied_model_is_rejected_when_body_does_not_fully_parse() {
+    let (upstream_port, seen) = spawn_echo_upstream().await;
+    let mut config = Config::default();
+    config.model_policy.deny = vec!["text-davinci-003".to_string()];
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    // A prompt array is legal on OpenAI completions but doesn't fit the full request parse
+    let (status, _) = common::post_json(
+        proxy,
+        upstream_port,
+        "api/generate",
+        r#"{"model":"text-davinci-003","prompt":["hi"]}"#,
+    )
+    .await;
+    assert_eq!(status, 403);
+    assert!(seen.lock().unwrap().is_none(), "upstream must not be called");
+
+    let (status, _) =
+        common::post_json(proxy, upstream_port, "api/generate", r#"{"model":"llama2","prompt":["hi"]}"#).await;
+    assert_eq!(status, 200);
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].model, "llama2");
+}
+
+#[tokio::test]
+async fn test_allowlist_only_permits_listed_models() {
+    let (upstream_port, _) = spawn_echo_upstream().await;
+    let mut config = Config::default();
+    config.model_policy.allow = Some(vec!["llama2".to_string()]);
+    let proxy = common::spawn_proxy(config).await;
+
+    let (status, _) =
+        common::post_json(proxy, upstream_port, "api/generate", r#"{"model":"llama2","prompt":"Hi"}"#).await;
+    assert_eq!(status, 200);
+
+    let (status, _) =
+        common::post_json(proxy, upstream_port, "api/generate", r#"{"model":"mistral","prompt":"Hi"}"#).await;
+    assert_eq!(status, 403);
+
+    // A body that names no model, or can't be read at all, isn't on the allowlist either
+    let (status, _) = common::post_json(proxy, upstream_port, "api/generate", r#"{"prompt":"Hi"}"#).await;
+    assert_eq!(status, 403);
+    let (status, _) = common::post_json(proxy, upstream_port, "api/generate", r#"{"model":"mistral","#).await;
+    assert_eq!(status, 403);
+}
+
+#[tokio::test]
+async fn test_newline_free_stream_is_forwarded_with_bounded_parsing() {
+    const CHUNK: usize = 64 * 1024;
+    const CHUNKS: usize = 320;
+
+    let router = Router::new().route(
+        "/api/generate",
+        post(|| async {
+            let chunk = bytes::Bytes::from(vec![b'x'; CHUNK]);
+            let stream = futures::stream::iter((0..CHUNKS).map(move |_| Ok::<_, std::io::Error>(chunk.clone())));
+            ([("content-type", "application/x-ndjson")], axum::body::Body::from_stream(stream))
+        }),
+    );
+    let upstream_port = common::spawn_server(router).await.port();
+    let config = Config {
+        parser_max_buffer_bytes: 1024 * 1024,
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let (status, body) =
+        common::post_json(proxy, upstream_port, "api/generate", r#"{"model":"llama2","prompt":"Hi"}"#).await;
+    assert_eq!(status, 200);
+    assert_eq!(body.len(), CHUNK * CHUNKS, "every byte reaches the client");
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert!(records[0].parse_truncated);
+    assert_eq!(records[0].response_bytes, (CHUNK * CHUNKS) as u64);
+}
+
+#[tokio::test]
+async fn test_delimiter_free_sse_stream_is_forwarded_with_bounded_parsing() {
+    const CHUNK: usize = 64 * 1024;
+    const CHUNKS: usize = 320;
+
+    // One `data:` line that never ends in a blank line
+    let router = Router::new().route(
+        "/v1/chat/completions",
+        post(|| async {
+            let chunk = bytes::Bytes::from(vec![b'x'; CHUNK]);
+            let head = futures::stream::iter([Ok::<_, std::io::Error>(bytes::Bytes::from_static(b"data: "))]);
+            let junk = futures::stream::iter((0..CHUNKS).map(move |_| Ok(chunk.clone())));
+            ([("content-type", "text/event-stream")], axum::body::Body::from_stream(futures::StreamExt::chain(head, junk)))
+        }),
+    );
+    let upstream_port = common::spawn_server(router).await.port();
+    let config = Config {
+        parser_max_buffer_bytes: 1024 * 1024,
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let (status, body) = common::post_json(
+        proxy,
+        upstream_port,
+        "v1/chat/completions",
+        r#"{"model":"gpt-4o","stream":true,"messages":[{"role":"user","content":"Hi"}]}"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body.len(), "data: ".len() + CHUNK * CHUNKS, "every byte reaches the client");
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert!(records[0].parse_truncated);
+    assert_eq!(records[0].backend, BackendType::OpenAI);
+    assert_eq!(records[0].prompt_tokens, None);
+}
+
+/// Upstream that records the full header map of the last request it received
+async fn spawn_header_recording_upstream() -> (u16, Arc<Mutex<Option<HeaderMap>>>) {
+    let seen = Arc::new(Mutex::new(None));
+    let seen_clone = seen.clone();
+    let router = Router::new().route(
+        "/api/generate",
+        post(move |headers: HeaderMap, body: String| {
+            let seen = seen_clone.clone();
+            async move {
+                *seen.lock().unwrap() = Some(headers);
+                ([("content-type", "application/json")], body)
+            }
+        }),
+    );
+    let addr = common::spawn_server(router).await;
+    (addr.port(), seen)
+}
+
+/// Sends a JSON POST carrying extra request headers and returns the status
+async fn post_with_headers(proxy: std::net::SocketAddr, upstream_port: u16, headers: &[(&str, &str)]) -> u16 {
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let mut req = hyper::Request::post(format!("http://{}/proxy/{}/api/generate", proxy, upstream_port))
+        .header("content-type", "application/json");
+    for (name, value) in headers {
+        req = req.header(*name, *value);
+    }
+    let req = req
+        .body(axum::body::Body::from(r#"{"model":"llama2","prompt":"Hi"}"#))
+        .unwrap();
+    let resp = client.request(req).await.unwrap();
+    let status = resp.status().as_u16();
+    http_body_util::BodyExt::collect(resp.into_body()).await.unwrap();
+    status
+}
+
+#[tokio::test]
+async fn test_provider_headers_survive_header_stripping() {
+    let (upstream_port, seen) = spawn_header_recording_upstream().await;
+    let config = Config {
+        headers: HeaderConfig {
+            strip: vec!["Anthropic-Beta".to_string(), "x-internal-debug".to_string()],
+            ..HeaderConfig::default()
+        },
+        ..Config::default()
+    };
+    let proxy = common::spawn_proxy(config).await;
+
+    let status = post_with_headers(
+        proxy,
+        upstream_port,
+        &[
+            ("anthropic-beta", "prompt-caching-2024-07-31"),
+            ("x-internal-debug", "1"),
+            ("x-hop", "dropped"),
+            ("connection", "anthropic-beta, x-hop"),
+        ],
+    )
+    .await;
+    assert_eq!(status, 200);
+
+    let headers = seen.lock().unwrap().take().expect("upstream saw the request");
+    assert_eq!(headers.get("anthropic-beta").unwrap(), "prompt-caching-2024-07-31");
+    assert!(headers.get("x-internal-debug").is_none(), "denylisted header should be stripped");
+    assert!(headers.get("x-hop").is_none(), "headers named in Connection are hop-by-hop");
+}
+
+#[tokio::test]
+async fn test_azure_deployment_path_supplies_model_and_keeps_api_version() {
+    let seen_query = Arc::new(Mutex::new(None));
+    let seen_clone = seen_query.clone();
+    let router = Router::new().route(
+        "/openai/deployments/:deployment/chat/completions",
+        post(move |uri: axum::http::Uri| {
+            let seen = seen_clone.clone();
+            async move {
+                *seen.lock().unwrap() = uri.query().map(str::to_string);
+                let body = concat!(
+                    "data: {\"choices\":[],\"prompt_filter_results\":[{\"prompt_index\":0,\"content_filter_results\":{}}]}\n\n",
+                    "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi!\"},\"finish_reason\":null}]}\n\n",
+                    "data: {\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+                    "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":8,\"completion_tokens\":2,\"total_tokens\":10}}\n\n",
+                    "data: [DONE]\n\n",
+                );
+                ([("content-type", "text/event-stream")], body)
+            }
+        }),
+    );
+    let upstream_port = common::spawn_server(router).await.port();
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let (status, _) = common::post_json(
+        proxy,
+        upstream_port,
+        "openai/deployments/gpt4o-prod/chat/completions?api-version=2024-06-01",
+        r#"{"messages":[{"role":"user","content":"Hello"}],"stream":true}"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(seen_query.lock().unwrap().as_deref(), Some("api-version=2024-06-01"));
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].model, "gpt4o-prod");
+    assert_eq!(records[0].backend, BackendType::OpenAI);
+    assert_eq!((records[0].prompt_tokens, records[0].completion_tokens), (Some(8), Some(2)));
+}
+
+#[tokio::test]
+async fn test_gzipped_stream_is_forwarded_compressed_and_parsed_decoded() {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(include_bytes!("fixtures/ollama_generate.ndjson")).unwrap();
+    let compressed = bytes::Bytes::from(encoder.finish().unwrap());
+
+    let upstream_body = compressed.clone();
+    let router = Router::new().route(
+        "/api/generate",
+        post(move || {
+            let body = upstream_body.clone();
+            async move {
+                // Split the gzip stream at arbitrary points, as a network would
+                let chunks: Vec<_> = body.chunks(7).map(bytes::Bytes::copy_from_slice).collect();
+                let stream = futures::stream::iter(chunks.into_iter().map(Ok::<_, std::io::Error>));
+                (
+                    [("content-type", "application/x-ndjson"), ("content-encoding", "gzip")],
+                    axum::body::Body::from_stream(stream),
+                )
+            }
+        }),
+    );
+    let upstream_port = common::spawn_server(router).await.port();
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let req = hyper::Request::post(format!("http://{}/proxy/{}/api/generate", proxy, upstream_port))
+        .header("content-type", "application/json")
+        .header("accept-encoding", "gzip")
+        .body(axum::body::Body::from(r#"{"model":"llama2","prompt":"Why is the sky blue?"}"#))
+        .unwrap();
+    let resp = client.request(req).await.unwrap();
+    assert_eq!(resp.headers().get("content-encoding").unwrap(), "gzip");
+    let received = http_body_util::BodyExt::collect(resp.into_body()).await.unwrap().to_bytes();
+    assert_eq!(received, compressed, "client gets the compressed bytes untouched");
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!((records[0].prompt_tokens, records[0].completion_tokens), (Some(26), Some(5)));
+    assert_eq!(records[0].response_bytes, compressed.len() as u64);
+}
+
+#[tokio::test]
+async fn test_accept_encoding_forced_to_identity_for_loopback_upstreams() {
+    let (upstream_port, seen) = spawn_header_recording_upstream().await;
+    let proxy = common::spawn_proxy(Config::default()).await;
+
+    assert_eq!(post_with_headers(proxy, upstream_port, &[("accept-encoding", "gzip, br")]).await, 200);
+    let headers = seen.lock().unwrap().take().unwrap();
+    assert_eq!(headers.get_all("accept-encoding").iter().collect::<Vec<_>>(), ["identity"]);
+
+    // A request that already asks for identity is forwarded as-is
+    assert_eq!(post_with_headers(proxy, upstream_port, &[("accept-encoding", "identity")]).await, 200);
+    let headers = seen.lock().unwrap().take().unwrap();
+    assert_eq!(headers.get("accept-encoding").unwrap(), "identity");
+}
+
+#[tokio::test]
+async fn test_accept_encoding_kept_when_backend_opts_out() {
+    let (upstream_port, seen) = spawn_header_recording_upstream().await;
+    let config = Config::from_toml(&format!(
+        "[backends.{}]\nstrip_accept_encoding = false\n",
+        upstream_port
+    ))
+    .unwrap();
+    let proxy = common::spawn_proxy(config).await;
+
+    assert_eq!(post_with_headers(proxy, upstream_port, &[("accept-encoding", "gzip, br")]).await, 200);
+    let headers = seen.lock().unwrap().take().unwrap();
+    assert_eq!(headers.get("accept-encoding").unwrap(), "gzip, br");
+}
+
+#[tokio::test]
+async fn test_stats_endpoint_aggregates_requests() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    for _ in 0..2 {
+        common::post_json(
+            proxy,
+            upstream.port(),
+            "api/generate",
+            r#"{"model":"llama2","prompt":"Why is the sky blue?","stream":true}"#,
+        )
+        .await;
+    }
+    let records = common::wait_for_records(&sink, 2).await;
+
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let resp = client
+        .get(format!("http://{}/stats", proxy).parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let body = http_body_util::BodyExt::collect(resp.into_body()).await.unwrap().to_bytes();
+    let stats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+    let prompt_tokens: u64 = records.iter().map(|r| u64::from(r.prompt_tokens.unwrap())).sum();
+    let completion_tokens: u64 = records.iter().map(|r| u64::from(r.completion_tokens.unwrap())).sum();
+    let latency_ms: u64 = records.iter().map(|r| r.latency_ms).sum();
+    assert_eq!(stats["requests"], 2);
+    assert_eq!(stats["prompt_tokens"], prompt_tokens);
+    assert_eq!(stats["completion_tokens"], completion_tokens);
+    assert_eq!(stats["average_latency_ms"], latency_ms as f64 / 2.0);
+    assert_eq!(stats["models"]["llama2"]["requests"], 2);
+    assert_eq!(stats["models"]["llama2"]["completion_tokens"], completion_tokens);
+    assert_eq!(stats["breakers"], serde_json::json!({}));
+}
+
+#[tokio::test]
+async fn test_response_model_recorded_alongside_request_alias() {
+    let router = Router::new().route(
+        "/v1/chat/completions",
+        post(|| async {
+            (
+                [("content-type", "text/event-stream")],
+                concat!(
+                    "data: {\"model\":\"gpt-4o-2024-08-06\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"}}]}\n\n",
+                    "data: {\"model\":\"gpt-4o-2024-08-06\",\"choices\":[],\"usage\":{\"prompt_tokens\":4,\"completion_tokens\":1}}\n\n",
+                    "data: [DONE]\n\n",
+                ),
+            )
+        }),
+    );
+    let upstream = common::spawn_server(router).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    common::post_json(
+        proxy,
+        upstream.port(),
+        "v1/chat/completions",
+        r#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi"}],"stream":true}"#,
+    )
+    .await;
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].model, "gpt-4o");
+    assert_eq!(records[0].response_model.as_deref(), Some("gpt-4o-2024-08-06"));
+}
+
+/// Upstream that sends a couple of OpenAI deltas and then either stops or fails
+async fn spawn_cut_off_upstream(fail: bool) -> u16 {
+    let router = Router::new().route(
+        "/v1/chat/completions",
+        post(move || async move {
+            let mut chunks: Vec<Result<bytes::Bytes, std::io::Error>> = vec![
+                Ok(bytes::Bytes::from_static(b"data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"The\"}}]}\n\n")),
+                Ok(bytes::Bytes::from_static(b"data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\" sky\"}}]}\n\n")),
+            ];
+            if fail {
+                chunks.push(Err(std::io::Error::other("connection reset")));
+            }
+            // Pace the chunks so the headers and deltas are flushed before any failure
+            let stream = futures::StreamExt::then(futures::stream::iter(chunks), |chunk| async move {
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                chunk
+            });
+            ([("content-type", "text/event-stream")], axum::body::Body::from_stream(stream))
+        }),
+    );
+    common::spawn_server(router).await.port()
+}
+
+#[tokio::test]
+async fn test_stream_without_terminal_marker_is_not_completed() {
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+    let body = r#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi"}],"stream":true}"#;
+
+    let upstream = common::spawn_server(common::mock_server::openai_app()).await;
+    common::post_json(proxy, upstream.port(), "v1/chat/completions", body).await;
+    let records = common::wait_for_records(&sink, 1).await;
+    assert!(records[0].completed);
+    assert_eq!(records[0].stream_error, None);
+
+    let cut_off = spawn_cut_off_upstream(false).await;
+    common::post_json(proxy, cut_off, "v1/chat/completions", body).await;
+    let records = common::wait_for_records(&sink, 2).await;
+    assert!(!records[1].completed, "no [DONE] or usage chunk was sent");
+    assert_eq!(records[1].stream_error, None);
+
+    let failing = spawn_cut_off_upstream(true).await;
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let req = hyper::Request::post(format!("http://{}/proxy/{}/v1/chat/completions", proxy, failing))
+        .header("content-type", "application/json")
+        .body(axum::body::Body::from(body))
+        .unwrap();
+    if let Ok(resp) = client.request(req).await {
+        let _ = http_body_util::BodyExt::collect(resp.into_body()).await;
+    }
+    let records = common::wait_for_records(&sink, 3).await;
+    assert!(!records[2].completed);
+    assert!(records[2].stream_error.is_some(), "error frame should be recorded");
+}
+
+/// Minimal WebSocket upstream: accepts the handshake, then echoes every byte back
+async fn spawn_websocket_echo_upstream() -> (u16, Arc<Mutex<Option<String>>>) {
+    let handshake = Arc::new(Mutex::new(None));
+    let seen = handshake.clone();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut head = Vec::new();
+        let mut byte = [0u8; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).await.unwrap();
+            head.push(byte[0]);
+        }
+        *seen.lock().unwrap() = Some(String::from_utf8_lossy(&head).to_lowercase());
+        stream
+            .write_all(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n")
+            .await
+            .unwrap();
+        let (mut reader, mut writer) = stream.split();
+        let _ = tokio::io::copy(&mut reader, &mut writer).await;
+    });
+    (port, handshake)
+}
+
+#[tokio::test]
+async fn test_websocket_upgrade_is_tunneled() {
+    let (upstream_port, handshake) = spawn_websocket_echo_upstream().await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let mut stream = TcpStream::connect(proxy).await.unwrap();
+    let request = format!(
+        "GET /proxy/{}/v1/realtime?model=gpt-4o-realtime-preview HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        upstream_port
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).await.unwrap();
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8_lossy(&head).to_lowercase();
+    assert!(head.starts_with("http/1.1 101"), "got: {head}");
+    assert!(head.contains("upgrade: websocket"), "got: {head}");
+
+    let forwarded = handshake.lock().unwrap().clone().unwrap();
+    assert!(forwarded.starts_with("get /v1/realtime?model=gpt-4o-realtime-preview"), "{forwarded}");
+    assert!(forwarded.contains("upgrade: websocket"), "{forwarded}");
+    assert!(forwarded.contains("sec-websocket-key: dghlihnhbxbszsbub25jzq=="), "{forwarded}");
+
+    // A masked text frame carrying "hello" comes back byte for byte
+    let frame = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+    stream.write_all(&frame).await.unwrap();
+    let mut echoed = [0u8; 11];
+    tokio::time::timeout(std::time::Duration::from_secs(5), stream.read_exact(&mut echoed))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(echoed, frame);
+    assert!(sink.records().is_empty(), "recorded once the socket closes");
+
+    drop(stream);
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].model, "gpt-4o-realtime-preview");
+    assert_eq!(records[0].status_code, 101);
+    assert_eq!(records[0].response_bytes, frame.len() as u64);
+    assert_eq!(records[0].prompt_tokens, None);
+}
+
+/// Sends a raw HTTP/1.1 request head (and body) and returns the response's status line
+async fn send_raw(proxy: std::net::SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(proxy).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n") {
+        stream.read_exact(&mut byte).await.unwrap();
+        head.push(byte[0]);
+    }
+    String::from_utf8_lossy(&head).trim_end().to_string()
+}
+
+#[tokio::test]
+async fn test_websocket_handshake_is_subject_to_model_policy() {
+    let (upstream_port, handshake) = spawn_websocket_echo_upstream().await;
+    let mut config = Config::default();
+    config.model_policy.deny = vec!["gpt-4o-realtime-preview".to_string()];
+    let proxy = common::spawn_proxy(config).await;
+
+    let status = send_raw(
+        proxy,
+        &format!(
+            "GET /proxy/{}/v1/realtime?model=gpt-4o-realtime-preview HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            upstream_port
+        ),
+    )
+    .await;
+    assert_eq!(status, "HTTP/1.1 403 Forbidden");
+    assert!(handshake.lock().unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_upgrade_headers_on_a_post_do_not_skip_the_proxy() {
+    let upstream = common::spawn_server(common::mock_server::openai_app()).await;
+    let mut config = Config::default();
+    config.model_policy.deny = vec!["gpt-4".to_string()];
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let body = r#"{"model":"gpt-4","messages":[{"role":"user","content":"Hi"}]}"#;
+    let status = send_raw(
+        proxy,
+        &format!(
+            "POST /proxy/{}/v1/chat/completions HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            upstream.port(),
+            body.len(),
+            body
+        ),
+    )
+    .await;
+    assert_eq!(status, "HTTP/1.1 403 Forbidden");
+
+    // An allowed one goes through the proxy and is recorded like any other
+    let body = r#"{"model":"gpt-4o","stream":true,"messages":[{"role":"user","content":"Hi"}]}"#;
+    let status = send_raw(
+        proxy,
+        &format!(
+            "POST /proxy/{}/v1/chat/completions HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            upstream.port(),
+            body.len(),
+            body
+        ),
+    )
+    .await;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].model, "gpt-4o");
+    assert_eq!(records[0].status_code, 200);
+}
+
+#[tokio::test]
+async fn test_anthropic_cache_tokens_reach_metrics() {
+    let router = Router::new().route(
+        "/v1/messages",
+        post(|| async {
+            (
+                [("content-type", "text/event-stream")],
+                concat!(
+                    "event: message_start\n",
+                    "data: {\"type\":\"message_start\",\"message\":{\"model\":\"claude-3-5-sonnet-20241022\",\"usage\":{\"input_tokens\":12,\"cache_creation_input_tokens\":2048,\"cache_read_input_tokens\":1024,\"output_tokens\":1}}}\n\n",
+                    "event: content_block_delta\n",
+                    "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hi\"}}\n\n",
+                    "event: message_delta\n",
+                    "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":3}}\n\n",
+                    "event: message_stop\n",
+                    "data: {\"type\":\"message_stop\"}\n\n",
+                ),
+            )
+        }),
+    );
+    let upstream = common::spawn_server(router).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    common::post_json(
+        proxy,
+        upstream.port(),
+        "v1/messages",
+        r#"{"model":"claude-3-5-sonnet-latest","max_tokens":64,"messages":[{"role":"user","content":"hi"}],"stream":true}"#,
+    )
+    .await;
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!((records[0].prompt_tokens, records[0].completion_tokens), (Some(12), Some(3)));
+    assert_eq!(records[0].cache_write_tokens, Some(2048));
+    assert_eq!(records[0].cache_read_tokens, Some(1024));
+}
+
+/// OpenAI-compatible upstream whose `/with-logprobs` route attaches log probabilities
+async fn spawn_logprobs_upstream() -> u16 {
+    let plain = "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"},\"logprobs\":null}]}\n\ndata: [DONE]\n\n";
+    let with_logprobs = "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"},\"logprobs\":{\"content\":[{\"token\":\"Hi\",\"logprob\":-0.01,\"top_logprobs\":[]}]}}]}\n\ndata: [DONE]\n\n";
+    let router = Router::new()
+        .route("/plain/chat/completions", post(move || async move { ([("content-type", "text/event-stream")], plain) }))
+        .route("/with-logprobs/chat/completions", post(move || async move { ([("content-type", "text/event-stream")], with_logprobs) }));
+    common::spawn_server(router).await.port()
+}
+
+#[tokio::test]
+async fn test_logprobs_requested_and_returned_are_recorded() {
+    let upstream_port = spawn_logprobs_upstream().await;
+    let config = Config {
+        estimate_missing_tokens: true,
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    // Requested but the upstream ignored it
+    let body = r#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi"}],"logprobs":true,"top_logprobs":3,"stream":true}"#;
+    common::post_json(proxy, upstream_port, "plain/chat/completions", body).await;
+    let records = common::wait_for_records(&sink, 1).await;
+    let m = &records[0];
+    assert_eq!((m.logprobs_requested, m.top_logprobs, m.logprobs_returned), (true, Some(3), false));
+    // The upstream sent no usage, so the completion count is estimated from the text
+    assert_eq!((m.completion_tokens, m.tokens_estimated), (Some(1), true));
+
+    // Returned without being asked for
+    let body = r#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi"}],"stream":true}"#;
+    common::post_json(proxy, upstream_port, "with-logprobs/chat/completions", body).await;
+    let records = common::wait_for_records(&sink, 2).await;
+    let m = &records[1];
+    assert_eq!((m.logprobs_requested, m.top_logprobs, m.logprobs_returned), (false, None, true));
+
+    // Legacy completions ask with a count instead of a flag
+    let body = r#"{"model":"gpt-3.5-turbo-instruct","prompt":"hi","logprobs":5,"stream":true}"#;
+    common::post_json(proxy, upstream_port, "with-logprobs/chat/completions", body).await;
+    let records = common::wait_for_records(&sink, 3).await;
+    let m = &records[2];
+    assert_eq!((m.logprobs_requested, m.top_logprobs, m.logprobs_returned), (true, Some(5), true));
+}
+
+/// OpenAI-compatible upstream that only sends usage when asked, recording each body it receives
+async fn spawn_usage_on_request_upstream() -> (u16, Arc<Mutex<Vec<(Option<String>, String)>>>) {
+    let bodies = Arc::new(Mutex::new(Vec::new()));
+    let seen = bodies.clone();
+    let router = Router::new().route(
+        "/v1/chat/completions",
+        post(move |headers: HeaderMap, body: String| {
+            let seen = seen.clone();
+            async move {
+                let content_length = headers.get("content-length").map(|v| v.to_str().unwrap().to_string());
+                let request: serde_json::Value = serde_json::from_str(&body).unwrap();
+                seen.lock().unwrap().push((content_length, body));
+
+                let mut stream = String::from("data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"}}]}\n\n");
+                if request["stream_options"]["include_usage"] == true {
+                    stream.push_str("data: {\"choices\":[],\"usage\":{\"prompt_tokens\":8,\"completion_tokens\":1}}\n\n");
+                }
+                stream.push_str("data: [DONE]\n\n");
+                ([("content-type", "text/event-stream")], stream)
+            }
+        }),
+    );
+    (common::spawn_server(router).await.port(), bodies)
+}
+
+#[tokio::test]
+async fn test_include_usage_injected_when_enabled() {
+    let (upstream_port, bodies) = spawn_usage_on_request_upstream().await;
+    let config = Config {
+        inject_include_usage: true,
+        estimate_missing_tokens: false,
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let body = r#"{"model":"gpt-4o", "messages":[{"role":"user","content":"hi"}],"stream":true}"#;
+    let (status, received) = common::post_json(proxy, upstream_port, "v1/chat/completions", body).await;
+    assert_eq!(status, 200);
+    assert!(received.ends_with("data: [DONE]\n\n"), "client gets a normal stream: {received}");
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!((records[0].prompt_tokens, records[0].completion_tokens), (Some(8), Some(1)));
+
+    let (content_length, forwarded) = bodies.lock().unwrap()[0].clone();
+    assert_eq!(
+        forwarded,
+        r#"{"stream_options":{"include_usage":true},"model":"gpt-4o", "messages":[{"role":"user","content":"hi"}],"stream":true}"#
+    );
+    assert_eq!(content_length, Some(forwarded.len().to_string()));
+
+    // A client's own stream_options and non-streaming requests are left alone
+    let explicit = r#"{"model":"gpt-4o","messages":[],"stream":true,"stream_options":{"include_usage":false}}"#;
+    common::post_json(proxy, upstream_port, "v1/chat/completions", explicit).await;
+    let non_streaming = r#"{"model":"gpt-4o","messages":[],"stream":false}"#;
+    common::post_json(proxy, upstream_port, "v1/chat/completions", non_streaming).await;
+    let bodies = bodies.lock().unwrap();
+    assert_eq!(bodies[1].1, explicit);
+    assert_eq!(bodies[2].1, non_streaming);
+}
+
+#[tokio::test]
+async fn test_include_usage_injected_into_chunked_request() {
+    let (upstream_port, bodies) = spawn_usage_on_request_upstream().await;
+    let config = Config {
+        inject_include_usage: true,
+        ..Config::default()
+    };
+    let (proxy, _sink) = common::spawn_proxy_with_sink(config).await;
+
+    // The rewritten body goes out with a length of its own, not the client's chunked framing
+    let body = r#"{"model":"gpt-4o","messages":[],"stream":true}"#;
+    let status = send_raw(
+        proxy,
+        &format!(
+            "POST /proxy/{}/v1/chat/completions HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+            upstream_port,
+            body.len(),
+            body
+        ),
+    )
+    .await;
+    assert!(status.starts_with("HTTP/1.1 200"), "got: {status}");
+
+    let (content_length, forwarded) = bodies.lock().unwrap()[0].clone();
+    assert_eq!(forwarded, r#"{"stream_options":{"include_usage":true},"model":"gpt-4o","messages":[],"stream":true}"#);
+    assert_eq!(content_length, Some(forwarded.len().to_string()));
+}
+
+#[tokio::test]
+async fn test_include_usage_not_injected_by_default() {
+    let (upstream_port, bodies) = spawn_usage_on_request_upstream().await;
+    let config = Config {
+        estimate_missing_tokens: false,
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let body = r#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi"}],"stream":true}"#;
+    common::post_json(proxy, upstream_port, "v1/chat/completions", body).await;
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!((records[0].prompt_tokens, records[0].completion_tokens), (None, None));
+    assert_eq!(bodies.lock().unwrap()[0].1, body);
+}
+
+#[tokio::test]
+async fn test_multipart_upload_streams_through_unparsed() {
+    let received = Arc::new(Mutex::new(None));
+    let seen = received.clone();
+    let router = Router::new().route(
+        "/v1/audio/transcriptions",
+        post(move |headers: HeaderMap, body: bytes::Bytes| {
+            let seen = seen.clone();
+            async move {
+                let content_type = headers.get("content-type").unwrap().to_str().unwrap().to_string();
+                *seen.lock().unwrap() = Some((content_type, body));
+                ([("content-type", "application/json")], r#"{"text":"hello"}"#)
+            }
+        }),
+    );
+    let upstream_port = common::spawn_server(router).await.port();
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let boundary = "----llmloggerboundary";
+    let mut body = format!(
+        "--{boundary}\r\nContent-Disposition: form-data; name=\"model\"\r\n\r\nwhisper-1\r\n--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.wav\"\r\nContent-Type: audio/wav\r\n\r\n"
+    )
+    .into_bytes();
+    body.extend((0..=255u8).cycle().take(64 * 1024));
+    body.extend(format!("\r\n--{boundary}--\r\n").into_bytes());
+    let content_type = format!("multipart/form-data; boundary={boundary}");
+
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let req = hyper::Request::post(format!("http://{}/proxy/{}/v1/audio/transcriptions", proxy, upstream_port))
+        .header("content-type", &content_type)
+        .body(axum::body::Body::from(body.clone()))
+        .unwrap();
+    let resp = client.request(req).await.unwrap();
+    assert_eq!(resp.status(), 200);
+    http_body_util::BodyExt::collect(resp.into_body()).await.unwrap();
+
+    let (forwarded_type, forwarded_body) = received.lock().unwrap().take().unwrap();
+    assert_eq!(forwarded_type, content_type);
+    assert_eq!(forwarded_body, body, "upload must arrive byte for byte");
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].model, "unknown");
+    assert_eq!(records[0].prompt, "", "non-JSON bodies aren't parsed");
+}
+
+#[tokio::test]
+async fn test_mislabelled_json_body_is_parsed_and_policed() {
+    let (upstream_port, _) = spawn_echo_upstream().await;
+    let mut config = Config::default();
+    config.model_policy.deny = vec!["llama2".to_string()];
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let send = |model: &str| {
+        let body = format!(r#"  {{"model":"{model}","prompt":"Hi","stream":false}}"#);
+        let req = hyper::Request::post(format!("http://{}/proxy/{}/api/generate", proxy, upstream_port))
+            .header("content-type", "application/octet-stream")
+            .body(axum::body::Body::from(body))
+            .unwrap();
+        client.request(req)
+    };
+
+    let resp = send("llama2").await.unwrap();
+    assert_eq!(resp.status(), 403);
+
+    let resp = send("mistral").await.unwrap();
+    assert_eq!(resp.status(), 200);
+    http_body_util::BodyExt::collect(resp.into_body()).await.unwrap();
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].model, "mistral");
+    assert_eq!(records[0].prompt, "Hi");
+}
+
+#[tokio::test]
+async fn test_mislabelled_json_after_whitespace_frame_is_policed() {
+    let (upstream_port, seen) = spawn_echo_upstream().await;
+    let mut config = Config::default();
+    config.model_policy.deny = vec!["llama2".to_string()];
+    let proxy = common::spawn_proxy(config).await;
+
+    // The first frame is only whitespace; the object opens in the next one
+    let frames = [" ", "\n", r#"{"model":"llama2","prompt":"Hi","stream":false}"#];
+    let stream = futures::stream::iter(frames.map(|frame| Ok::<_, std::io::Error>(bytes::Bytes::from(frame))));
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let req = hyper::Request::post(format!("http://{}/proxy/{}/api/generate", proxy, upstream_port))
+        .header("content-type", "application/octet-stream")
+        .body(axum::body::Body::from_stream(stream))
+        .unwrap();
+    let resp = client.request(req).await.unwrap();
+    assert_eq!(resp.status(), 403);
+    assert!(seen.lock().unwrap().is_none(), "upstream must not be called");
+}
+
+#[tokio::test]
+async fn test_allowlist_refuses_unnamed_uploads_but_not_bodyless_requests() {
+    let router = Router::new()
+        .route("/v1/audio/transcriptions", post(|| async { r#"{"text":"hello"}"# }))
+        .route("/v1/models", axum::routing::get(|| async { r#"{"data":[]}"# }));
+    let upstream_port = common::spawn_server(router).await.port();
+    let mut config = Config::default();
+    config.model_policy.allow = Some(vec!["whisper-1".to_string()]);
+    let proxy = common::spawn_proxy(config).await;
+
+    // The model sits in a form field the proxy doesn't read
+    let boundary = "----llmloggerboundary";
+    let body = format!(
+        "--{boundary}\r\nContent-Disposition: form-data; name=\"model\"\r\n\r\nwhisper-1\r\n--{boundary}--\r\n"
+    );
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let req = hyper::Request::post(format!("http://{}/proxy/{}/v1/audio/transcriptions", proxy, upstream_port))
+        .header("content-type", format!("multipart/form-data; boundary={boundary}"))
+        .body(axum::body::Body::from(body))
+        .unwrap();
+    assert_eq!(client.request(req).await.unwrap().status(), 403);
+
+    let req = hyper::Request::get(format!("http://{}/proxy/{}/v1/models", proxy, upstream_port))
+        .body(axum::body::Body::empty())
+        .unwrap();
+    assert_eq!(client.request(req).await.unwrap().status(), 200);
+}
+
+#[tokio::test]
+async fn test_reported_total_tokens_kept_when_parts_disagree() {
+    let (logs, _guard) = common::capture_logs();
+    let router = Router::new().route(
+        "/v1/chat/completions",
+        post(|| async {
+            (
+                [("content-type", "text/event-stream")],
+                "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":9,\"completion_tokens\":7,\"total_tokens\":20}}\n\ndata: [DONE]\n\n",
+            )
+        }),
+    );
+    let upstream = common::spawn_server(router).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    common::post_json(
+        proxy,
+        upstream.port(),
+        "v1/chat/completions",
+        r#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi"}],"stream":true}"#,
+    )
+    .await;
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!((records[0].prompt_tokens, records[0].completion_tokens), (Some(9), Some(7)));
+    assert_eq!(records[0].total_tokens, Some(20));
+    assert!(
+        logs.contents().contains("total_tokens=20 differs from prompt + completion = 16"),
+        "{}",
+        logs.contents()
+    );
+}
+
+#[tokio::test]
+async fn test_get_request_passes_through_without_body_parsing() {
+    let (logs, _guard) = common::capture_logs();
+    let seen = Arc::new(Mutex::new(None));
+    let recorded = seen.clone();
+    let router = Router::new().route(
+        "/v1/models",
+        axum::routing::get(move |uri: hyper::Uri, headers: HeaderMap| {
+            let recorded = recorded.clone();
+            async move {
+                let auth = headers.get("authorization").map(|v| v.to_str().unwrap().to_string());
+                *recorded.lock().unwrap() = Some((uri.to_string(), auth));
+                ([("content-type", "application/json")], r#"{"object":"list","data":[{"id":"gpt-4o"}]}"#)
+            }
+        }),
+    );
+    let upstream_port = common::spawn_server(router).await.port();
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let req = hyper::Request::get(format!("http://{}/proxy/{}/v1/models?limit=5", proxy, upstream_port))
+        .header("authorization", "Bearer sk-test")
+        .body(axum::body::Body::empty())
+        .unwrap();
+    let resp = client.request(req).await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let body = http_body_util::BodyExt::collect(resp.into_body()).await.unwrap().to_bytes();
+    assert_eq!(body, r#"{"object":"list","data":[{"id":"gpt-4o"}]}"#);
+
+    let (uri, auth) = seen.lock().unwrap().take().unwrap();
+    assert_eq!(uri, "/v1/models?limit=5");
+    assert_eq!(auth.as_deref(), Some("Bearer sk-test"));
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].model, "unknown");
+    assert!(!logs.contents().contains("Failed to parse request body"), "{}", logs.contents());
+}
+
+#[tokio::test]
+async fn test_unreachable_upstream_is_recorded_as_502() {
+    // Bind and release a port so nothing is listening on it
+    let dead_port = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let (status, _) = common::post_json(
+        proxy,
+        dead_port,
+        "v1/chat/completions",
+        r#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi"}]}"#,
+    )
+    .await;
+    assert_eq!(status, 502);
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].status_code, 502);
+    assert!(!records[0].success);
+    assert_eq!(records[0].model, "gpt-4o");
+    assert_eq!(records[0].backend, BackendType::OpenAI);
+    assert!(records[0].upstream_error.is_some());
+    assert_eq!(records[0].schema_version, SCHEMA_VERSION);
+}
+
+#[tokio::test]
+async fn test_upstream_failure_body_matches_client_format() {
+    let dead = closed_port().await;
+    let proxy = common::spawn_proxy(Config::default()).await;
+
+    let (status, body) = common::post_json(
+        proxy,
+        dead,
+        "v1/chat/completions",
+        r#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi"}],"stream":true}"#,
+    )
+    .await;
+    assert_eq!(status, 502);
+    let error: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(error["error"]["type"], "server_error");
+    assert!(error["error"]["message"].as_str().unwrap().starts_with("Upstream error: "));
+
+    let (status, body) = common::post_json(proxy, dead, "api/chat", r#"{"model":"llama2","messages":[]}"#).await;
+    assert_eq!(status, 502);
+    let error: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert!(error["error"].as_str().unwrap().starts_with("Upstream error: "));
+
+    let (status, body) = common::post_json(
+        proxy,
+        dead,
+        "v1/messages",
+        r#"{"model":"claude-3-5-haiku-latest","max_tokens":8,"messages":[{"role":"user","content":"hi"}]}"#,
+    )
+    .await;
+    assert_eq!(status, 502);
+    let error: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(error["type"], "error");
+    assert_eq!(error["error"]["type"], "api_error");
+
+    // Nothing identifies the API, so the error stays plain text
+    let (status, body) = common::post_json(proxy, dead, "custom/endpoint", r#"{"input":"hi"}"#).await;
+    assert_eq!(status, 502);
+    assert!(body.starts_with("Upstream error: "), "{}", body);
+}
+
+#[tokio::test]
+async fn test_rate_limited_response_is_recorded_as_failure() {
+    let router = Router::new().route(
+        "/v1/chat/completions",
+        post(|| async {
+            (
+                hyper::StatusCode::TOO_MANY_REQUESTS,
+                [("content-type", "application/json")],
+                r#"{"error":{"message":"Rate limit reached","type":"rate_limit_error"}}"#,
+            )
+        }),
+    );
+    let upstream = common::spawn_server(router).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let (status, _) = common::post_json(
+        proxy,
+        upstream.port(),
+        "v1/chat/completions",
+        r#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi"}]}"#,
+    )
+    .await;
+    assert_eq!(status, 429);
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].status_code, 429);
+    assert!(!records[0].success);
+    assert_eq!(records[0].upstream_error, None);
+}
+
+async fn send_with_request_id(proxy: std::net::SocketAddr, upstream_port: u16, request_id: Option<&str>) -> hyper::HeaderMap {
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let mut req = hyper::Request::post(format!("http://{}/proxy/{}/v1/chat/completions", proxy, upstream_port))
+        .header("content-type", "application/json");
+    if let Some(id) = request_id {
+        req = req.header("x-request-id", id);
+    }
+    let req = req
+        .body(axum::body::Body::from(
+            r#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi"}],"stream":true}"#,
+        ))
+        .unwrap();
+    let resp = client.request(req).await.unwrap();
+    let headers = resp.headers().clone();
+    http_body_util::BodyExt::collect(resp.into_body()).await.unwrap();
+    headers
+}
+
+#[tokio::test]
+async fn test_request_id_returned_to_client_and_recorded() {
+    let (logs, _guard) = common::capture_logs();
+    let router = common::mock_server::openai_app().layer(axum::middleware::map_response(
+        |mut resp: axum::response::Response| async move {
+            resp.headers_mut().insert("x-request-id", "req_upstream123".parse().unwrap());
+            resp
+        },
+    ));
+    let upstream = common::spawn_server(router).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let headers = send_with_request_id(proxy, upstream.port(), None).await;
+    let request_id = headers["x-llm-logger-request-id"].to_str().unwrap().to_string();
+    assert!(uuid::Uuid::parse_str(&request_id).is_ok(), "{}", request_id);
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].request_id, request_id);
+    assert_eq!(records[0].upstream_request_id.as_deref(), Some("req_upstream123"));
+    assert!(
+        logs.contents().contains(&format!("llm_request{{request_id={}}}", request_id)),
+        "{}",
+        logs.contents()
+    );
+}
+
+#[tokio::test]
+async fn test_client_request_id_reused_only_when_safe() {
+    let upstream = common::spawn_server(common::mock_server::openai_app()).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let headers = send_with_request_id(proxy, upstream.port(), Some("client-abc_123")).await;
+    assert_eq!(headers["x-llm-logger-request-id"], "client-abc_123");
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].request_id, "client-abc_123");
+    assert_eq!(records[0].upstream_request_id, None);
+
+    // Request IDs name archive files, so path-like values are replaced
+    let headers = send_with_request_id(proxy, upstream.port(), Some("../../etc/passwd")).await;
+    let request_id = headers["x-llm-logger-request-id"].to_str().unwrap();
+    assert!(uuid::Uuid::parse_str(request_id).is_ok(), "{}", request_id);
+    let records = common::wait_for_records(&sink, 2).await;
+    assert_eq!(records[1].request_id, request_id);
+}
+
+#[tokio::test]
+async fn test_latency_split_into_connect_ttft_and_total() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let req = hyper::Request::post(format!("http://{}/proxy/{}/api/generate", proxy, upstream.port()))
+        .header("content-type", "application/json")
+        .header("x-mock-headers-delay-ms", "100")
+        .header("x-mock-first-chunk-delay-ms", "150")
+        .body(axum::body::Body::from(r#"{"model":"llama2","prompt":"Why is the sky blue?","stream":true}"#))
+        .unwrap();
+    let resp = client.request(req).await.unwrap();
+    http_body_util::BodyExt::collect(resp.into_body()).await.unwrap();
+
+    let records = common::wait_for_records(&sink, 1).await;
+    let metrics = &records[0];
+    let connect = metrics.upstream_connect_ms.unwrap();
+    let ttft = metrics.ttft_ms.unwrap();
+
+    // Headers are held 100ms, then the first chunk another 150ms, all from one baseline
+    assert!((100..250).contains(&connect), "connect {}ms", connect);
+    assert!(ttft >= 250, "ttft {}ms", ttft);
+    assert!(metrics.total_ms > ttft, "total {}ms, ttft {}ms", metrics.total_ms, ttft);
+    assert_eq!(metrics.latency_ms, metrics.total_ms);
+}
+
+#[tokio::test]
+async fn test_openai_sampling_params_recorded() {
+    let upstream = common::spawn_server(common::mock_server::openai_app()).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    common::post_json(
+        proxy,
+        upstream.port(),
+        "v1/chat/completions",
+        r#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi"}],"stream":true,
+            "temperature":0.2,"max_completion_tokens":256,"top_p":0.9,"presence_penalty":0.5,
+            "frequency_penalty":-0.5,"seed":42,"user":"someone","n":1}"#,
+    )
+    .await;
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(
+        records[0].params,
+        SamplingParams {
+            temperature: Some(0.2),
+            max_tokens: Some(256),
+            top_p: Some(0.9),
+            presence_penalty: Some(0.5),
+            frequency_penalty: Some(-0.5),
+            seed: Some(42),
+        }
+    );
+    let json = serde_json::to_value(&records[0]).unwrap();
+    assert_eq!(json["params"]["max_tokens"], 256);
+}
+
+#[tokio::test]
+async fn test_ollama_options_recorded_as_sampling_params() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    common::post_json(
+        proxy,
+        upstream.port(),
+        "api/generate",
+        r#"{"model":"llama2","prompt":"hi","stream":true,
+            "options":{"temperature":0.7,"num_predict":128,"seed":7,"num_ctx":4096}}"#,
+    )
+    .await;
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(
+        records[0].params,
+        SamplingParams {
+            temperature: Some(0.7),
+            max_tokens: Some(128),
+            seed: Some(7),
+            ..SamplingParams::default()
+        }
+    );
+    // Unset parameters are left out of the record
+    let json = serde_json::to_value(&records[0]).unwrap();
+    assert_eq!(json["params"], serde_json::json!({"temperature": 0.7, "max_tokens": 128, "seed": 7}));
+}
+
+#[tokio::test]
+async fn test_out_of_range_sampling_params_do_not_fail_the_parse() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    // Ollama's "unlimited" -1, a float token limit, and a string temperature
+    common::post_json(
+        proxy,
+        upstream.port(),
+        "api/generate",
+        r#"{"model":"llama2","prompt":"hi","stream":true,"max_tokens":256.5,
+            "options":{"temperature":"warm","num_predict":-1,"seed":7}}"#,
+    )
+    .await;
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert!(records[0].request_parse_ok);
+    assert_eq!(records[0].model, "llama2");
+    assert_eq!(
+        records[0].params,
+        SamplingParams {
+            seed: Some(7),
+            ..SamplingParams::default()
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_anthropic_system_and_content_blocks_form_prompt() {
+    let router = Router::new().route(
+        "/v1/messages",
+        post(|| async {
+            (
+                [("content-type", "application/json")],
+                r#"{"type":"message","model":"claude-3-5-haiku-20241022","content":[{"type":"text","text":"Paris."}],"usage":{"input_tokens":30,"output_tokens":3}}"#,
+            )
+        }),
+    );
+    let upstream = common::spawn_server(router).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    common::post_json(
+        proxy,
+        upstream.port(),
+        "v1/messages",
+        r#"{"model":"claude-3-5-haiku-latest","max_tokens":64,
+            "system":[{"type":"text","text":"You are terse.","cache_control":{"type":"ephemeral"}}],
+            "messages":[
+                {"role":"user","content":[
+                    {"type":"text","text":"Here is a map."},
+                    {"type":"image","source":{"type":"base64","media_type":"image/png","data":"iVBORw0KGgo="}},
+                    {"type":"text","text":"What is the capital of France?"}
+                ]},
+                {"role":"assistant","content":"Paris."},
+                {"role":"user","content":"And of Spain?"}
+            ]}"#,
+    )
+    .await;
+
+    let records = common::wait_for_records(&sink, 1).await;
+    // Anthropic's top-level system prompt is kept apart from the conversation
+    assert_eq!(
+        records[0].prompt,
+        "user: Here is a map.\nWhat is the capital of France?\nassistant: Paris.\nuser: And of Spain?"
+    );
+    assert_eq!(records[0].system_prompt.as_deref(), Some("You are terse."));
+    assert_eq!(
+        records[0].system_prompt_hash.as_deref(),
+        Some(rust_llm_logger::text::sha256_hex("You are terse.").as_str())
+    );
+    assert_eq!(records[0].params.max_tokens, Some(64));
+
+    // The separate system prompt counts as a system message
+    let stats = records[0].prompt_stats.as_ref().unwrap();
+    let roles: Vec<_> = stats.roles.iter().map(|(role, n)| (role.as_str(), *n)).collect();
+    assert_eq!(roles, vec![("assistant", 1), ("system", 1), ("user", 2)]);
+    assert_eq!(stats.system_prompt_chars, "You are terse.".len() as u64);
+    assert!(stats.has_images);
+    assert_eq!(records[0].message_count, Some(4));
+}
+
+#[tokio::test]
+async fn test_system_prompts_group_by_hash_under_privacy_modes() {
+    let router = Router::new().fallback(|| async { ([("content-type", "application/json")], "{}") });
+    let upstream = common::spawn_server(router).await;
+    let config = Config {
+        prompt_logging: TextLogging::None,
+        system_prompt_logging: Some(TextLogging::Hash),
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let cases = [
+        // OpenAI: system and developer messages, wherever they appear
+        (
+            "v1/chat/completions",
+            r#"{"model":"gpt-4o","messages":[
+                {"role":"system","content":"You are a weather bot."},
+                {"role":"user","content":"Weather?"},
+                {"role":"developer","content":"Answer in Celsius."}
+            ]}"#,
+        ),
+        // Anthropic: the top-level system field, as text blocks
+        (
+            "v1/messages",
+            r#"{"model":"claude-3-5-haiku-latest","max_tokens":64,
+                "system":[{"type":"text","text":"You are a weather bot."},{"type":"text","text":"Answer in Celsius."}],
+                "messages":[{"role":"user","content":"Weather?"}]}"#,
+        ),
+        ("v1/chat/completions", r#"{"model":"gpt-4o","messages":[{"role":"user","content":"Weather?"}]}"#),
+    ];
+    for (i, (path, body)) in cases.iter().enumerate() {
+        common::post_json(proxy, upstream.port(), path, body).await;
+        common::wait_for_records(&sink, i + 1).await;
+    }
+    let records = sink.records();
+
+    let system_prompt = "You are a weather bot.\nAnswer in Celsius.";
+    let hash = rust_llm_logger::text::normalized_sha256(system_prompt);
+    for record in &records[..2] {
+        assert_eq!(record.prompt, "");
+        assert_eq!(record.system_prompt_hash.as_deref(), Some(hash.as_str()));
+        let expected = format!("sha256:{} chars:{}", rust_llm_logger::text::sha256_hex(system_prompt), system_prompt.len());
+        assert_eq!(record.system_prompt.as_deref(), Some(expected.as_str()));
+    }
+    assert_eq!((&records[2].system_prompt, &records[2].system_prompt_hash), (&None, &None));
+
+    // Unset, the system prompt follows prompt_logging
+    let config = Config {
+        prompt_logging: TextLogging::None,
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+    common::post_json(proxy, upstream.port(), cases[0].0, cases[0].1).await;
+    let record = common::wait_for_records(&sink, 1).await.remove(0);
+    assert_eq!(record.system_prompt, None);
+    assert_eq!(record.system_prompt_hash.as_deref(), Some(hash.as_str()));
+}
+
+#[tokio::test]
+async fn test_last_user_message_capture_summarizes_history() {
+    let router = Router::new().fallback(|| async {
+        (
+            [("content-type", "application/json")],
+            r#"{"id":"chatcmpl-1","object":"chat.completion","model":"gpt-4o","choices":[{"index":0,"message":{"role":"assistant","content":"Sunny."},"finish_reason":"stop"}],"usage":{"prompt_tokens":90,"completion_tokens":2,"total_tokens":92}}"#,
+        )
+    });
+    let upstream = common::spawn_server(router).await;
+    let config = Config {
+        prompt_capture: PromptCapture::LastUserMessage,
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let cases = [
+        // Plain history, no system message
+        (
+            "v1/chat/completions",
+            r#"{"model":"gpt-4o","messages":[
+                {"role":"user","content":"Hi"},
+                {"role":"assistant","content":"Hello!"},
+                {"role":"user","content":"What is the weather in Oslo?"}
+            ]}"#,
+        ),
+        // System message, and an assistant tool call (null content) with its result
+        (
+            "v1/chat/completions",
+            r#"{"model":"gpt-4o","messages":[
+                {"role":"system","content":"You are a weather bot."},
+                {"role":"user","content":"What is the weather in Oslo?"},
+                {"role":"assistant","content":null,"tool_calls":[{"id":"call_1","type":"function","function":{"name":"weather","arguments":"{\"city\":\"Oslo\"}"}}]},
+                {"role":"tool","tool_call_id":"call_1","content":"{\"sky\":\"clear\"}"},
+                {"role":"assistant","content":"Sunny."},
+                {"role":"user","content":"And tomorrow?"}
+            ]}"#,
+        ),
+        // Anthropic's separate system prompt
+        (
+            "v1/messages",
+            r#"{"model":"claude-3-5-haiku-latest","max_tokens":64,"system":"Be brief.",
+                "messages":[{"role":"user","content":[{"type":"text","text":"Weather?"}]}]}"#,
+        ),
+        // Plain prompts are kept whole
+        ("v1/completions", r#"{"model":"gpt-3.5-turbo-instruct","prompt":"Once upon a time"}"#),
+    ];
+    for (i, (path, body)) in cases.iter().enumerate() {
+        common::post_json(proxy, upstream.port(), path, body).await;
+        common::wait_for_records(&sink, i + 1).await;
+    }
+    let records = sink.records();
+
+    assert!(records.iter().all(|r| r.request_parse_ok));
+    let summary: Vec<_> = records
+        .iter()
+        .map(|r| (r.prompt.as_str(), r.message_count, r.system_prompt_present))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("What is the weather in Oslo?", Some(3), false),
+            ("And tomorrow?", Some(6), true),
+            ("Weather?", Some(2), true),
+            ("Once upon a time", None, false),
+        ]
+    );
+
+    // prompt_chars still measures the whole history
+    let full_history = "user: Hi\nassistant: Hello!\nuser: What is the weather in Oslo?";
+    assert_eq!(records[0].prompt_chars, Some(full_history.chars().count() as u64));
+    assert_eq!(records[3].prompt_chars, Some("Once upon a time".len() as u64));
+}
+
+#[tokio::test]
+async fn test_full_prompt_capture_is_default() {
+    let router = Router::new().fallback(|| async { ([("content-type", "application/json")], "{}") });
+    let upstream = common::spawn_server(router).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let body = r#"{"model":"gpt-4o","messages":[
+        {"role":"system","content":"Be brief."},
+        {"role":"user","content":"Hi"},
+        {"role":"assistant","content":null,"tool_calls":[]},
+        {"role":"user","content":"Again"}
+    ]}"#;
+    common::post_json(proxy, upstream.port(), "v1/chat/completions", body).await;
+
+    let record = &common::wait_for_records(&sink, 1).await[0];
+    assert_eq!(record.prompt, "user: Hi\nassistant: \nuser: Again");
+    assert_eq!(record.system_prompt.as_deref(), Some("Be brief."));
+    assert_eq!((record.message_count, record.system_prompt_present), (Some(4), true));
+    assert_eq!(record.prompt_chars, None);
+    let stats = record.prompt_stats.as_ref().unwrap();
+    assert_eq!((stats.roles["user"], stats.roles["assistant"]), (2, 1));
+    assert!(!stats.has_images);
+}
+
+#[tokio::test]
+async fn test_openai_vision_message_text_parts_form_prompt() {
+    let router = Router::new().route(
+        "/v1/chat/completions",
+        post(|| async {
+            (
+                [("content-type", "application/json")],
+                r#"{"id":"chatcmpl-1","object":"chat.completion","model":"gpt-4o","choices":[{"index":0,"message":{"role":"assistant","content":"A cat."},"finish_reason":"stop"}],"usage":{"prompt_tokens":800,"completion_tokens":3,"total_tokens":803}}"#,
+            )
+        }),
+    );
+    let upstream = common::spawn_server(router).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    common::post_json(
+        proxy,
+        upstream.port(),
+        "v1/chat/completions",
+        r#"{"model":"gpt-4o","messages":[
+            {"role":"system","content":"Describe images briefly."},
+            {"role":"user","content":[
+                {"type":"text","text":"What is in this picture?"},
+                {"type":"image_url","image_url":{"url":"https://example.com/cat.png","detail":"low"}}
+            ]}
+        ]}"#,
+    )
+    .await;
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].prompt, "user: What is in this picture?");
+    assert_eq!(records[0].system_prompt.as_deref(), Some("Describe images briefly."));
+    assert_eq!(records[0].model, "gpt-4o");
+    assert_eq!(records[0].prompt_tokens, Some(800));
+    assert_eq!(
+        records[0].prompt_stats,
+        Some(PromptStats {
+            roles: [("system".to_string(), 1), ("user".to_string(), 1)].into_iter().collect(),
+            system_prompt_chars: "Describe images briefly.".len() as u64,
+            has_images: true,
+        })
+    );
+    // A remote image is counted but carries no inline bytes
+    assert_eq!((records[0].image_count, records[0].attachment_bytes), (1, 0));
+}
+
+#[tokio::test]
+async fn test_attachments_counted_without_reaching_prompt() {
+    let router = Router::new().fallback(|| async { ([("content-type", "application/json")], "{}") });
+    let upstream = common::spawn_server(router).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let png = "iVBORw0KGgo".repeat(1000);
+    let jpeg = "/9j/4AAQSkZJRg".repeat(500);
+    let wav = "UklGRiQAAABXQVZF".repeat(100);
+    let body = serde_json::json!({
+        "model": "gpt-4o",
+        "messages": [{"role": "user", "content": [
+            {"type": "text", "text": "Compare these two pictures and this clip."},
+            {"type": "image_url", "image_url": {"url": format!("data:image/png;base64,{}", png)}},
+            {"type": "image_url", "image_url": {"url": format!("data:image/jpeg;base64,{}", jpeg), "detail": "high"}},
+            {"type": "input_audio", "input_audio": {"data": wav, "format": "wav"}}
+        ]}]
+    });
+    common::post_json(proxy, upstream.port(), "v1/chat/completions", &body.to_string()).await;
+    common::wait_for_records(&sink, 1).await;
+
+    // Ollama keeps base64 images beside the message text
+    let body = serde_json::json!({
+        "model": "llava",
+        "messages": [{"role": "user", "content": "What is this?", "images": [png]}],
+        "stream": false
+    });
+    common::post_json(proxy, upstream.port(), "api/chat", &body.to_string()).await;
+
+    let records = common::wait_for_records(&sink, 2).await;
+    assert_eq!(records[0].prompt, "user: Compare these two pictures and this clip.");
+    assert_eq!((records[0].image_count, records[0].audio_count), (2, 1));
+    assert_eq!(records[0].attachment_bytes, (png.len() + jpeg.len() + wav.len()) as u64);
+    assert!(records[0].prompt_stats.as_ref().unwrap().has_images);
+
+    assert_eq!(records[1].prompt, "user: What is this?");
+    assert_eq!((records[1].image_count, records[1].audio_count), (1, 0));
+    assert_eq!(records[1].attachment_bytes, png.len() as u64);
+    assert!(records[1].prompt_stats.as_ref().unwrap().has_images);
+}
+
+#[tokio::test]
+async fn test_tools_offered_are_recorded() {
+    let router = Router::new().fallback(|| async { ([("content-type", "application/json")], "{}") });
+    let upstream = common::spawn_server(router).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let many: Vec<_> = (0..150)
+        .map(|i| serde_json::json!({"type": "function", "function": {"name": format!("tool_{}", i), "parameters": {}}}))
+        .collect();
+    let cases = [
+        (
+            "v1/chat/completions",
+            serde_json::json!({"model": "gpt-4o", "messages": [{"role": "user", "content": "Weather?"}], "tools": [
+                {"type": "function", "function": {"name": "get_weather", "parameters": {"type": "object"}}},
+                {"type": "function", "function": {"name": "search_docs", "description": "Search", "strict": true}}
+            ]}),
+        ),
+        (
+            "v1/chat/completions",
+            serde_json::json!({"model": "gpt-3.5-turbo", "messages": [{"role": "user", "content": "Weather?"}], "functions": [
+                {"name": "get_weather", "parameters": {"type": "object"}}
+            ]}),
+        ),
+        (
+            "v1/messages",
+            serde_json::json!({"model": "claude-3-5-haiku-latest", "max_tokens": 64,
+                "messages": [{"role": "user", "content": "Weather?"}],
+                "tools": [{"name": "get_weather", "input_schema": {"type": "object"}}]}),
+        ),
+        (
+            "v1/responses",
+            serde_json::json!({"model": "gpt-4o", "input": "Weather?", "tools": [{"type": "web_search_preview"}]}),
+        ),
+        (
+            "v1/chat/completions",
+            serde_json::json!({"model": "gpt-4o", "messages": [{"role": "user", "content": "Hi"}], "tools": many}),
+        ),
+        ("v1/chat/completions", serde_json::json!({"model": "gpt-4o", "messages": [{"role": "user", "content": "Hi"}]})),
+    ];
+    for (i, (path, body)) in cases.iter().enumerate() {
+        common::post_json(proxy, upstream.port(), path, &body.to_string()).await;
+        common::wait_for_records(&sink, i + 1).await;
+    }
+    let records = sink.records();
+    assert!(records.iter().all(|r| r.request_parse_ok && r.schema_version == SCHEMA_VERSION));
+
+    assert_eq!(records[0].tools_offered_count, 2);
+    assert_eq!(records[0].tools_offered, vec!["get_weather", "search_docs"]);
+    assert_eq!(records[1].tools_offered_count, 1);
+    assert_eq!(records[1].tools_offered, vec!["get_weather"]);
+    assert_eq!(records[2].tools_offered, vec!["get_weather"]);
+    assert_eq!(records[3].tools_offered, vec!["web_search_preview"]);
+
+    // The full count is kept, but only the first names
+    assert_eq!(records[4].tools_offered_count, 150);
+    assert_eq!(records[4].tools_offered.len(), MAX_TOOLS_OFFERED);
+    assert_eq!(records[4].tools_offered.last().unwrap(), &format!("tool_{}", MAX_TOOLS_OFFERED - 1));
+
+    assert_eq!((records[5].tools_offered_count, records[5].tools_offered.len()), (0, 0));
+}
+
+#[tokio::test]
+async fn test_response_text_captured_truncated_and_hashed() {
+    let upstream = common::spawn_server(common::mock_server::openai_app()).await;
+    let body = r#"{"model":"gpt-4o","messages":[{"role":"user","content":"Compare Rust and C++"}],"stream":true}"#;
+
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+    common::post_json(proxy, upstream.port(), "v1/chat/completions", body).await;
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!((&records[0].response_text, &records[0].response_text_sha256), (&None, &None));
+
+    let config = Config {
+        log_response_text: true,
+        response_text_max_chars: 20,
+        hash_response_text: true,
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+    for _ in 0..2 {
+        common::post_json(proxy, upstream.port(), "v1/chat/completions", body).await;
+    }
+    let records = common::wait_for_records(&sink, 2).await;
+    assert_eq!(records[0].response_text.as_deref(), Some("Rust and C++ are bot"));
+
+    // The hash covers the whole text, not the excerpt, and is the same for the same text
+    let hash = records[0].response_text_sha256.as_deref().unwrap();
+    assert_ne!(hash, rust_llm_logger::text::sha256_hex("Rust and C++ are bot"));
+    assert_eq!(records[1].response_text_sha256.as_deref(), Some(hash));
+}
+
+#[tokio::test]
+async fn test_prompt_logging_modes_keep_content_out_of_records() {
+    let upstream = common::spawn_server(common::mock_server::openai_app()).await;
+    let body = r#"{"model":"gpt-4o","messages":[{"role":"user","content":"Compare Rust and C++"}],"stream":true}"#;
+
+    let mut records = Vec::new();
+    for mode in [TextLogging::Full, TextLogging::Hash, TextLogging::Truncated, TextLogging::None] {
+        let config = Config {
+            prompt_logging: mode,
+            prompt_logging_max_chars: 7,
+            log_response_text: true,
+            ..Config::default()
+        };
+        let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+        common::post_json(proxy, upstream.port(), "v1/chat/completions", body).await;
+        records.push(common::wait_for_records(&sink, 1).await.remove(0));
+    }
+    let [full, hashed, truncated, none] = records.try_into().unwrap();
+
+    let prompt = full.prompt.clone();
+    let response = full.response_text.clone().unwrap();
+    assert!(prompt.contains("Compare Rust and C++"), "{}", prompt);
+    assert!(response.len() > 7, "{}", response);
+
+    let leaks = |record: &rust_llm_logger::types::LLMMetrics| {
+        let json = serde_json::to_string(record).unwrap();
+        json.contains(&prompt) || json.contains(&response) || json.contains("Compare")
+    };
+
+    let expected = |text: &str| {
+        format!("sha256:{} chars:{}", rust_llm_logger::text::sha256_hex(text), text.chars().count())
+    };
+    assert_eq!(hashed.prompt, expected(&prompt));
+    assert_eq!(hashed.response_text, Some(expected(&response)));
+    assert!(!leaks(&hashed));
+
+    assert_eq!(truncated.prompt, format!("{}…", prompt.chars().take(7).collect::<String>()));
+    assert_eq!(truncated.response_text, Some(format!("{}…", response.chars().take(7).collect::<String>())));
+    assert!(!leaks(&truncated));
+
+    assert_eq!(none.prompt, "");
+    assert_eq!(none.response_text, None);
+    assert!(!leaks(&none));
+
+    // Token counts, and the prompt's original length, still come from the real prompt
+    assert_eq!(none.prompt_tokens, full.prompt_tokens);
+    let prompt_len = prompt.chars().count() as u64;
+    for record in [&full, &hashed, &truncated, &none] {
+        assert_eq!(record.prompt_len, prompt_len);
+    }
+}
+
+#[tokio::test]
+async fn test_truncated_prompt_ends_in_ellipsis_only_when_cut() {
+    let router = Router::new().fallback(|| async { ([("content-type", "application/json")], "{}") });
+    let upstream = common::spawn_server(router).await;
+    let config = Config {
+        prompt_logging: TextLogging::Truncated,
+        prompt_logging_max_chars: 12,
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    for (i, prompt) in ["Why is the sky blue on a clear day?", "Short one", "Exactly 12 c"].iter().enumerate() {
+        let body = serde_json::json!({"model": "llama2", "prompt": prompt, "stream": false});
+        common::post_json(proxy, upstream.port(), "api/generate", &body.to_string()).await;
+        common::wait_for_records(&sink, i + 1).await;
+    }
+    let records = sink.records();
+    let kept: Vec<_> = records.iter().map(|r| (r.prompt.as_str(), r.prompt_len)).collect();
+    assert_eq!(kept, vec![("Why is the s…", 35), ("Short one", 9), ("Exactly 12 c", 12)]);
+}
+
+#[tokio::test]
+async fn test_identical_prompts_hash_equally_whatever_is_recorded() {
+    let router = Router::new().fallback(|| async { ([("content-type", "application/json")], "{}") });
+    let upstream = common::spawn_server(router).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+    let redacting = Config {
+        prompt_logging: TextLogging::None,
+        ..Config::default()
+    };
+    let (redacted_proxy, redacted_sink) = common::spawn_proxy_with_sink(redacting).await;
+
+    let chat = r#"{"model":"gpt-4o","messages":[{"role":"user","content":"What is the capital of France?"}]}"#;
+    let reformatted = r#"{"model":"gpt-4o","messages":[{"role":"user","content":"What is the  capital of\nFrance? "}]}"#;
+    let other = r#"{"model":"gpt-4o","messages":[{"role":"user","content":"What is the capital of Spain?"}]}"#;
+    for (i, body) in [chat, chat, reformatted, other].iter().enumerate() {
+        common::post_json(proxy, upstream.port(), "v1/chat/completions", body).await;
+        common::wait_for_records(&sink, i + 1).await;
+    }
+    common::post_json(redacted_proxy, upstream.port(), "v1/chat/completions", chat).await;
+
+    let records = sink.records();
+    let hashes: Vec<_> = records.iter().map(|r| r.prompt_sha256.clone().unwrap()).collect();
+    assert_eq!(hashes[0], rust_llm_logger::text::sha256_hex("user: What is the capital of France?"));
+    assert_eq!(hashes[1], hashes[0]);
+    assert_eq!(hashes[2], hashes[0]);
+    assert_ne!(hashes[3], hashes[0]);
+
+    // Redacting the prompt text leaves the hash usable for deduplication
+    let redacted = common::wait_for_records(&redacted_sink, 1).await.remove(0);
+    assert_eq!(redacted.prompt, "");
+    assert_eq!(redacted.prompt_sha256.as_deref(), Some(hashes[0].as_str()));
+}
+
+#[tokio::test]
+async fn test_unparseable_request_body_flagged_in_metrics() {
+    let upstream = common::spawn_server(common::mock_server::openai_app()).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    common::post_json(proxy, upstream.port(), "v1/chat/completions", "this is not json").await;
+    let records = common::wait_for_records(&sink, 1).await;
+    assert!(!records[0].request_parse_ok);
+    assert_eq!(records[0].model, "unknown");
+
+    common::post_json(
+        proxy,
+        upstream.port(),
+        "v1/chat/completions",
+        r#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi"}],"stream":true}"#,
+    )
+    .await;
+    let records = common::wait_for_records(&sink, 2).await;
+    assert!(records[1].request_parse_ok);
+}
+
+#[tokio::test]
+async fn test_client_address_user_agent_and_identity_header_recorded() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let config = Config {
+        client_identity_header: Some("x-team".to_string()),
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    post_with_headers(proxy, upstream.port(), &[("user-agent", "eval-harness/1.2"), ("x-team", "search")]).await;
+
+    let records = common::wait_for_records(&sink, 1).await;
+    let client = &records[0].client;
+    assert!(client.addr.as_deref().unwrap().starts_with("127.0.0.1:"), "{:?}", client.addr);
+    assert_eq!(client.user_agent.as_deref(), Some("eval-harness/1.2"));
+    assert_eq!(client.identity.as_deref(), Some("search"));
+}
+
+#[tokio::test]
+async fn test_authorization_identity_is_hashed_never_recorded() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let config = Config {
+        client_identity_header: Some("Authorization".to_string()),
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    post_with_headers(proxy, upstream.port(), &[("authorization", "Bearer sk-live-secret-123")]).await;
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(
+        records[0].client.identity.as_deref(),
+        Some(rust_llm_logger::text::sha256_hex("sk-live-secret-123").as_str())
+    );
+    let serialized = serde_json::to_string(&records[0]).unwrap();
+    assert!(!serialized.contains("sk-live-secret-123"), "{}", serialized);
+    let logfmt = rust_llm_logger::sinks::log::to_logfmt(&records[0]);
+    assert!(!logfmt.contains("sk-live-secret-123"), "{}", logfmt);
+}
+
+#[tokio::test]
+async fn test_upstream_timeout_before_headers_returns_504() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let config = Config {
+        upstream_timeout_ms: 200,
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let req = hyper::Request::post(format!("http://{}/proxy/{}/api/generate", proxy, upstream.port()))
+        .header("content-type", "application/json")
+        .header("x-mock-headers-delay-ms", "2000")
+        .body(axum::body::Body::from(r#"{"model":"llama2","prompt":"Hi","stream":true}"#))
+        .unwrap();
+    let started = std::time::Instant::now();
+    let resp = client.request(req).await.unwrap();
+    assert_eq!(resp.status(), 504);
+    assert!(started.elapsed() < std::time::Duration::from_millis(1500), "{:?}", started.elapsed());
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].status_code, 504);
+    assert_eq!(records[0].upstream_error.as_deref(), Some("no response within 200ms"));
+}
+
+#[tokio::test]
+async fn test_upstream_timeout_mid_stream_truncates() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let config = Config {
+        upstream_timeout_ms: 150,
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    // The mock streams a word every 10ms for about half a second
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let req = hyper::Request::post(format!("http://{}/proxy/{}/api/generate", proxy, upstream.port()))
+        .header("content-type", "application/json")
+        .body(axum::body::Body::from(r#"{"model":"llama2","prompt":"Hi","stream":true}"#))
+        .unwrap();
+    let resp = client.request(req).await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert!(http_body_util::BodyExt::collect(resp.into_body()).await.is_err());
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert!(records[0].truncated);
+    assert!(!records[0].success);
+    assert!(records[0].frame_count > 0);
+    assert_eq!(records[0].stream_error.as_deref(), Some("upstream request timeout"));
+}
+
+#[tokio::test]
+async fn test_tags_and_metadata_recorded_and_not_forwarded() {
+    let (upstream_port, seen) = spawn_header_recording_upstream().await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let status = post_with_headers(
+        proxy,
+        upstream_port,
+        &[
+            ("x-llm-logger-tags", "eval, run-42,,"),
+            ("x-llm-logger-meta", r#"{"pipeline":"nightly","shard":3}"#),
+        ],
+    )
+    .await;
+    assert_eq!(status, 200);
+
+    let headers = seen.lock().unwrap().take().expect("upstream saw the request");
+    assert!(!headers.contains_key("x-llm-logger-tags"));
+    assert!(!headers.contains_key("x-llm-logger-meta"));
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].tags, ["eval", "run-42"]);
+    assert_eq!(
+        records[0].metadata,
+        [("pipeline", "nightly"), ("shard", "3")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    );
+}
+
+#[tokio::test]
+async fn test_tag_and_metadata_limits_enforced() {
+    let (upstream_port, _) = spawn_header_recording_upstream().await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let tags = (0..20).map(|i| format!("t{}", i)).chain(["x".repeat(300)]).collect::<Vec<_>>().join(",");
+    let long_value = "v".repeat(300);
+    let meta = format!(r#"{{"keep":"yes","drop":"{}"}}"#, long_value);
+    post_with_headers(proxy, upstream_port, &[("x-llm-logger-tags", &tags), ("x-llm-logger-meta", &meta)]).await;
+    post_with_headers(proxy, upstream_port, &[("x-llm-logger-meta", "not json")]).await;
+
+    let records = common::wait_for_records(&sink, 2).await;
+    assert_eq!(records[0].tags.len(), 16);
+    assert_eq!(records[0].tags.last().map(String::as_str), Some("t15"));
+    assert_eq!(records[0].metadata.keys().collect::<Vec<_>>(), ["keep"]);
+    assert!(records[1].metadata.is_empty());
+}
+
+#[tokio::test]
+async fn test_multi_turn_chat_shares_conversation_id() {
+    let upstream = common::spawn_server(common::mock_server::openai_app()).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let system = r#"{"role":"system","content":"You are a travel guide."}"#;
+    let turns = [
+        format!(r#"[{},{{"role":"user","content":"Where should I go in Japan?"}}]"#, system),
+        format!(
+            r#"[{},{{"role":"user","content":"Where should I go in Japan?"}},{{"role":"assistant","content":"Kyoto."}},{{"role":"user","content":"When?"}}]"#,
+            system
+        ),
+        format!(
+            r#"[{},{{"role":"user","content":"Where should I go in Japan?"}},{{"role":"assistant","content":"Kyoto."}},{{"role":"user","content":"When?"}},{{"role":"assistant","content":"Autumn."}},{{"role":"user","content":"Why?"}}]"#,
+            system
+        ),
+        // A different chat
+        format!(r#"[{},{{"role":"user","content":"Where should I go in Italy?"}}]"#, system),
+    ];
+    for (i, messages) in turns.iter().enumerate() {
+        let body = format!(r#"{{"model":"gpt-4o","messages":{},"stream":true}}"#, messages);
+        common::post_json(proxy, upstream.port(), "v1/chat/completions", &body).await;
+        common::wait_for_records(&sink, i + 1).await;
+    }
+
+    let records = sink.records();
+    let id = records[0].conversation_id.clone().expect("chat requests get a conversation id");
+    assert_eq!(id.len(), 16);
+    assert_eq!(records[1].conversation_id.as_ref(), Some(&id));
+    assert_eq!(records[2].conversation_id.as_ref(), Some(&id));
+    assert_ne!(records[3].conversation_id.as_ref(), Some(&id));
+    let turn_indexes: Vec<_> = records.iter().map(|r| r.turn_index).collect();
+    assert_eq!(turn_indexes, [Some(2), Some(4), Some(6), Some(2)]);
+}
+
+#[tokio::test]
+async fn test_conversation_id_header_wins_and_is_not_forwarded() {
+    let (upstream_port, seen) = spawn_header_recording_upstream().await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    post_with_headers(proxy, upstream_port, &[("x-llm-logger-conversation-id", "support-ticket-981")]).await;
+
+    let headers = seen.lock().unwrap().take().expect("upstream saw the request");
+    assert!(!headers.contains_key("x-llm-logger-conversation-id"));
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].conversation_id.as_deref(), Some("support-ticket-981"));
+    assert_eq!(records[0].turn_index, None);
+}
+
+#[tokio::test]
+async fn test_backend_header_rules_strip_and_inject() {
+    let (upstream_port, seen) = spawn_header_recording_upstream().await;
+    std::env::set_var("LLM_LOGGER_TEST_UPSTREAM_KEY", "sk-server-owned");
+    let config = Config::from_toml(&format!(
+        r#"
+        [backends.{}]
+        strip_headers = ["x-client-key"]
+        inject_headers = {{ authorization = {{ env = "LLM_LOGGER_TEST_UPSTREAM_KEY", prefix = "Bearer " }}, x-org = "research" }}
+        "#,
+        upstream_port
+    ))
+    .unwrap();
+    assert!(!format!("{:?}", config).contains("sk-server-owned"));
+    let proxy = common::spawn_proxy(config).await;
+
+    post_with_headers(
+        proxy,
+        upstream_port,
+        &[("authorization", "Bearer sk-client-supplied"), ("x-client-key", "secret")],
+    )
+    .await;
+
+    let headers = seen.lock().unwrap().take().expect("upstream saw the request");
+    assert_eq!(headers["authorization"], "Bearer sk-server-owned");
+    assert_eq!(headers["x-org"], "research");
+    assert!(!headers.contains_key("x-client-key"));
+}
+
+#[test]
+fn test_injected_header_from_missing_env_var_fails_to_load() {
+    let result = Config::from_toml(
+        r#"
+        [backends.8080]
+        inject_headers = { authorization = { env = "LLM_LOGGER_TEST_UNSET_KEY" } }
+        "#,
+    );
+    assert!(result.unwrap_err().to_string().contains("LLM_LOGGER_TEST_UNSET_KEY"));
+}
+
+#[tokio::test]
+async fn test_credential_headers_never_appear_in_logs() {
+    let (logs, _guard) = common::capture_logs();
+    let (upstream_port, seen) = spawn_header_recording_upstream().await;
+    let proxy = common::spawn_proxy(Config::default()).await;
+
+    post_with_headers(
+        proxy,
+        upstream_port,
+        &[
+            ("authorization", "Bearer sk-bearer-secret"),
+            ("api-key", "azure-secret"),
+            ("x-api-key", "anthropic-secret"),
+        ],
+    )
+    .await;
+
+    // Credentials still reach the upstream untouched
+    let headers = seen.lock().unwrap().take().expect("upstream saw the request");
+    assert_eq!(headers["authorization"], "Bearer sk-bearer-secret");
+
+    let output = logs.contents();
+    assert!(output.contains("\"authorization\": \"[REDACTED]\""), "{}", output);
+    for secret in ["sk-bearer-secret", "azure-secret", "anthropic-secret"] {
+        assert!(!output.contains(secret), "{} leaked:\n{}", secret, output);
+    }
+}
+
+#[tokio::test]
+async fn test_api_key_fingerprints_and_labels() {
+    let (logs, _guard) = common::capture_logs();
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let billing = rust_llm_logger::text::sha256_hex("sk-billing-secret")[..8].to_string();
+    let config = Config {
+        api_key_labels: [(billing.clone(), "billing".to_string())].into(),
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    post_with_headers(proxy, upstream.port(), &[("authorization", "Bearer sk-billing-secret")]).await;
+    common::wait_for_records(&sink, 1).await;
+    post_with_headers(proxy, upstream.port(), &[("api-key", "azure-search-secret")]).await;
+    common::wait_for_records(&sink, 2).await;
+    post_with_headers(proxy, upstream.port(), &[]).await;
+    let records = common::wait_for_records(&sink, 3).await;
+
+    // Labelled Bearer token
+    assert_eq!(records[0].api_key_fingerprint.as_deref(), Some(billing.as_str()));
+    assert_eq!(records[0].api_key_label.as_deref(), Some("billing"));
+    // Azure key without a configured label
+    let azure = rust_llm_logger::text::sha256_hex("azure-search-secret")[..8].to_string();
+    assert_eq!(records[1].api_key_fingerprint.as_deref(), Some(azure.as_str()));
+    assert_eq!(records[1].api_key_label, None);
+    // No credential at all
+    assert_eq!((&records[2].api_key_fingerprint, &records[2].api_key_label), (&None, &None));
+
+    let output = logs.contents() + &serde_json::to_string(&records).unwrap();
+    for secret in ["sk-billing-secret", "azure-search-secret"] {
+        assert!(!output.contains(secret), "{} leaked:\n{}", secret, output);
+    }
+}
+
+#[tokio::test]
+async fn test_streamed_defaults_follow_endpoint_convention() {
+    let router = Router::new().fallback(|| async { ([("content-type", "application/json")], "{}") });
+    let upstream = common::spawn_server(router).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let cases = [
+        ("api/generate", r#"{"model":"llama2","prompt":"hi"}"#, true),
+        ("api/chat", r#"{"model":"llama2","messages":[{"role":"user","content":"hi"}]}"#, true),
+        ("api/generate", r#"{"model":"llama2","prompt":"hi","stream":false}"#, false),
+        ("v1/chat/completions", r#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi"}]}"#, false),
+        ("v1/chat/completions", r#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi"}],"stream":true}"#, true),
+        ("v1/messages", r#"{"model":"claude-3-5-haiku-latest","max_tokens":8,"messages":[{"role":"user","content":"hi"}]}"#, false),
+    ];
+    for (i, (path, body, _)) in cases.iter().enumerate() {
+        common::post_json(proxy, upstream.port(), path, body).await;
+        common::wait_for_records(&sink, i + 1).await;
+    }
+
+    let records = sink.records();
+    for ((path, body, expected), record) in cases.iter().zip(&records) {
+        assert_eq!(record.streamed, *expected, "{} {}", path, body);
+    }
+}
+
+#[tokio::test]
+async fn test_metrics_record_backend_port_and_upstream_url() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let body = r#"{"model":"llama2","prompt":"Hi","stream":true}"#;
+    common::post_json(proxy, upstream.port(), "api/generate?trace=1", body).await;
+    common::wait_for_records(&sink, 1).await;
+
+    let record = &sink.records()[0];
+    assert_eq!(record.backend, BackendType::Ollama);
+    assert_eq!(record.backend_port, upstream.port());
+    assert_eq!(record.upstream_url, format!("http://127.0.0.1:{}/api/generate", upstream.port()));
+}
+
+/// Config with a single pool named `ollama` over `targets`
+fn pool_config(targets: Vec<u16>, strategy: BalanceStrategy) -> Config {
+    Config {
+        pools: [("ollama".to_string(), PoolConfig { targets, strategy })].into_iter().collect(),
+        ..Config::default()
+    }
+}
+
+/// A loopback port with nothing listening on it
+async fn closed_port() -> u16 {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+#[tokio::test]
+async fn test_pool_round_robin_alternates_upstreams() {
+    let first = common::spawn_server(common::mock_server::ollama_app()).await;
+    let second = common::spawn_server(common::mock_server::ollama_app()).await;
+    let config = pool_config(vec![first.port(), second.port()], BalanceStrategy::RoundRobin);
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let body = r#"{"model":"llama2","prompt":"Hi","stream":true}"#;
+    for i in 0..4 {
+        let (status, _) = common::post_json_to(proxy, "ollama", "api/generate", body).await;
+        assert_eq!(status, 200);
+        common::wait_for_records(&sink, i + 1).await;
+    }
+
+    let ports: Vec<u16> = sink.records().iter().map(|r| r.backend_port).collect();
+    assert_eq!(ports, vec![first.port(), second.port(), first.port(), second.port()]);
+}
+
+#[tokio::test]
+async fn test_pool_fails_over_on_connection_error() {
+    let dead = closed_port().await;
+    let live = common::spawn_server(common::mock_server::ollama_app()).await;
+    let config = pool_config(vec![dead, live.port()], BalanceStrategy::RoundRobin);
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let body = r#"{"model":"llama2","prompt":"Hi","stream":true}"#;
+    let (status, response) = common::post_json_to(proxy, "ollama", "api/generate", body).await;
+    assert_eq!(status, 200, "{}", response);
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].backend_port, live.port());
+    assert_eq!(records[0].upstream_url, format!("http://127.0.0.1:{}/api/generate", live.port()));
+    assert!(records[0].success);
+    assert_eq!(records[0].attempts, 2);
+}
+
+/// Upstream answering 503 to its first `failures` requests and like Ollama after that
+async fn spawn_flaky_upstream(failures: usize) -> (u16, Arc<std::sync::atomic::AtomicUsize>) {
+    use axum::response::IntoResponse;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let router = Router::new().route(
+        "/api/generate",
+        post(move || {
+            let counter = counter.clone();
+            async move {
+                if counter.fetch_add(1, Ordering::SeqCst) < failures {
+                    return (axum::http::StatusCode::SERVICE_UNAVAILABLE, "overloaded").into_response();
+                }
+                let body = "{\"model\":\"llama2\",\"response\":\"Hi\",\"done\":true,\"prompt_eval_count\":3,\"eval_count\":1}\n";
+                ([("content-type", "application/x-ndjson")], body).into_response()
+            }
+        }),
+    );
+    (common::spawn_server(router).await.port(), requests)
+}
+
+fn retry_config(max_attempts: u8) -> Config {
+    Config {
+        retry: RetryConfig {
+            max_attempts,
+            backoff_ms: 50,
+            ..RetryConfig::default()
+        },
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn test_retried_request_records_attempts_once() {
+    let (upstream_port, requests) = spawn_flaky_upstream(2).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(retry_config(3)).await;
+
+    let (status, body) =
+        common::post_json(proxy, upstream_port, "api/generate", r#"{"model":"llama2","prompt":"Hi"}"#).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 3);
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].attempts, 3);
+    assert!(records[0].retried);
+    assert!(records[0].success);
+    assert_eq!(records[0].completion_tokens, Some(1));
+
+    // Backoffs of 50ms and 100ms come before the final attempt
+    let attempt_ms = records[0].attempt_latency_ms.unwrap();
+    assert!(records[0].total_ms >= attempt_ms + 150, "{} vs {}", records[0].total_ms, attempt_ms);
+
+    // The failed attempts produce no records of their own
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert_eq!(sink.records().len(), 1);
+}
+
+#[tokio::test]
+async fn test_exhausted_retries_return_the_last_response() {
+    let (upstream_port, requests) = spawn_flaky_upstream(5).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(retry_config(2)).await;
+
+    let (status, _) =
+        common::post_json(proxy, upstream_port, "api/generate", r#"{"model":"llama2","prompt":"Hi"}"#).await;
+    assert_eq!(status, 503);
+    assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!((records[0].attempts, records[0].status_code), (2, 503));
+    assert!(records[0].retried && !records[0].success);
+}
+
+#[tokio::test]
+async fn test_requests_are_not_retried_by_default() {
+    let (upstream_port, requests) = spawn_flaky_upstream(1).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let (status, _) =
+        common::post_json(proxy, upstream_port, "api/generate", r#"{"model":"llama2","prompt":"Hi"}"#).await;
+    assert_eq!(status, 503);
+    assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].attempts, 1);
+    assert!(!records[0].retried);
+}
+
+#[tokio::test]
+async fn test_connection_failures_are_retried_and_counted() {
+    let dead = closed_port().await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(retry_config(3)).await;
+
+    let (status, _) = common::post_json(proxy, dead, "api/generate", r#"{"model":"llama2","prompt":"Hi"}"#).await;
+    assert_eq!(status, 502);
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].attempts, 3);
+    assert_eq!(records[0].attempt_latency_ms, None);
+}
+
+#[tokio::test]
+async fn test_pool_least_connections_avoids_busy_upstream() {
+    let first = common::spawn_server(common::mock_server::ollama_app()).await;
+    let second = common::spawn_server(common::mock_server::ollama_app()).await;
+    let config = pool_config(vec![first.port(), second.port()], BalanceStrategy::LeastConnections);
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    // Keeps a request in flight on the first upstream until the other two have finished
+    let slow = tokio::spawn(async move {
+        let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+            .build_http::<axum::body::Body>();
+        let req = hyper::Request::post(format!("http://{}/proxy/ollama/api/generate", proxy))
+            .header("content-type", "application/json")
+            .header("x-mock-headers-delay-ms", "1500")
+            .body(axum::body::Body::from(r#"{"model":"llama2","prompt":"Hi","stream":true}"#))
+            .unwrap();
+        let resp = client.request(req).await.unwrap();
+        http_body_util::BodyExt::collect(resp.into_body()).await.unwrap();
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let body = r#"{"model":"llama2","prompt":"Hi","stream":true}"#;
+    for i in 0..2 {
+        common::post_json_to(proxy, "ollama", "api/generate", body).await;
+        common::wait_for_records(&sink, i + 1).await;
+    }
+    slow.await.unwrap();
+    let records = common::wait_for_records(&sink, 3).await;
+
+    let ports: Vec<u16> = records.iter().map(|r| r.backend_port).collect();
+    assert_eq!(ports, vec![second.port(), second.port(), first.port()]);
+}
+
+#[tokio::test]
+async fn test_unknown_pool_is_not_found() {
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+    let (status, body) = common::post_json_to(proxy, "missing", "api/generate", "{}").await;
+    assert_eq!(status, 404);
+    assert_eq!(body, "Unknown backend: missing");
+
+    // Rejected before any upstream was chosen, but still recorded
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].status_code, 404);
+    assert!(!records[0].success);
+    assert_eq!(records[0].backend_port, 0);
+    assert_eq!(records[0].upstream_url, "");
+    assert_eq!(records[0].upstream_error.as_deref(), Some("Unknown backend: missing"));
+}
+
+#[test]
+fn test_pool_config_rejects_empty_and_numeric_names() {
+    let config = Config::from_toml("[pools.ollama]\ntargets = [11434, 11435]\nstrategy = \"least_connections\"\n").unwrap();
+    assert_eq!(config.pools["ollama"].targets, vec![11434, 11435]);
+    assert_eq!(config.pools["ollama"].strategy, BalanceStrategy::LeastConnections);
+
+    assert!(Config::from_toml("[pools.ollama]\ntargets = []\n").is_err());
+    assert!(Config::from_toml("[pools.11434]\ntargets = [11435]\n").is_err());
+}
+
+#[tokio::test]
+async fn test_wall_clock_bounds_span_the_request() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let before = chrono::Utc::now().timestamp_millis() as u64;
+    let body = r#"{"model":"llama2","prompt":"Why is the sky blue?","stream":true}"#;
+    common::post_json(proxy, upstream.port(), "api/generate", body).await;
+    let record = common::wait_for_records(&sink, 1).await.remove(0);
+
+    assert!(record.started_at_ms >= before);
+    let span = record.finished_at_ms - record.started_at_ms;
+    // The span also covers reading the request body, which the latency timer doesn't
+    assert!(span >= record.latency_ms && span <= record.latency_ms + 50, "{} vs {}", span, record.latency_ms);
+
+    let started = chrono::DateTime::parse_from_rfc3339(&record.started_at).unwrap();
+    let finished = chrono::DateTime::parse_from_rfc3339(&record.finished_at).unwrap();
+    assert_eq!(started.timestamp_millis() as u64, record.started_at_ms);
+    assert_eq!(finished.timestamp_millis() as u64, record.finished_at_ms);
+    assert_eq!(record.timestamp, record.finished_at);
+}
+
+/// Pauses before each chunk, standing in for a CPU-heavy parser
+struct SlowParser(Box<dyn rust_llm_logger::parsers::BackendStreamParser>);
+
+#[async_trait::async_trait]
+impl rust_llm_logger::parsers::BackendStreamParser for SlowParser {
+    async fn feed_chunk(&mut self, chunk: &bytes::Bytes) {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        self.0.feed_chunk(chunk).await;
+    }
+
+    fn saw_content(&self) -> bool {
+        self.0.saw_content()
+    }
+
+    async fn finalize(self: Box<Self>) -> rust_llm_logger::types::TokenUsage {
+        self.0.finalize().await
+    }
+}
+
+#[tokio::test]
+async fn test_slow_parser_does_not_delay_client_stream() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let sink = Arc::new(MemorySink::new());
+    let state = AppState::new(Config::default())
+        .with_sink(sink.clone())
+        .with_parser_factory(Arc::new(|backend| {
+            Box::new(SlowParser(rust_llm_logger::parsers::create_parser(backend)))
+        }));
+    let proxy = common::spawn_server(app::router(state)).await;
+
+    let started = std::time::Instant::now();
+    let body = r#"{"model":"llama2","prompt":"Why is the sky blue?","stream":true}"#;
+    let (status, _) = common::post_json(proxy, upstream.port(), "api/generate", body).await;
+    let client_elapsed = started.elapsed();
+    assert_eq!(status, 200);
+
+    // The parser is still working through the chunks the client already has
+    assert!(sink.records().is_empty());
+    let record = loop {
+        if let Some(record) = sink.records().pop() {
+            break record;
+        }
+        assert!(started.elapsed() < std::time::Duration::from_secs(10), "no metrics record");
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    };
+    let parse_time = std::time::Duration::from_millis(50 * record.frame_count);
+    assert!(record.frame_count >= 10, "{}", record.frame_count);
+    assert!(client_elapsed < parse_time / 2, "client took {:?}, parsing {:?}", client_elapsed, parse_time);
+    assert!(started.elapsed() >= parse_time);
+    assert!(record.completion_tokens.is_some());
+
+    // Timings come from when chunks arrived, not when they were parsed
+    assert!(record.latency_ms < parse_time.as_millis() as u64 / 2, "{}", record.latency_ms);
+    assert!(record.ttft_ms.unwrap() < 300, "{:?}", record.ttft_ms);
+}
+
+#[tokio::test]
+async fn test_lagging_parser_is_cut_off_instead_of_queueing_the_response() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let sink = Arc::new(MemorySink::new());
+    let config = Config {
+        parser_channel_capacity: 1,
+        ..Config::default()
+    };
+    let state = AppState::new(config)
+        .with_sink(sink.clone())
+        .with_parser_factory(Arc::new(|backend| {
+            Box::new(SlowParser(rust_llm_logger::parsers::create_parser(backend)))
+        }));
+    let proxy = common::spawn_server(app::router(state)).await;
+
+    let body = r#"{"model":"llama2","prompt":"Why is the sky blue?","stream":true}"#;
+    let (status, response) = common::post_json(proxy, upstream.port(), "api/generate", body).await;
+    assert_eq!(status, 200);
+    assert!(response.contains("\"done\":true"), "the client still gets the whole stream");
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert!(records[0].parse_truncated);
+    assert_eq!(records[0].completion_tokens, None);
+}
+
+#[tokio::test]
+async fn test_local_token_estimates_sit_beside_reported_counts() {
+    let router = Router::new().route(
+        "/api/generate",
+        post(|| async {
+            (
+                [("content-type", "application/x-ndjson")],
+                "{\"response\":\"Hello\",\"done\":false}\n{\"response\":\"\",\"done\":true,\"prompt_eval_count\":7,\"eval_count\":2}\n",
+            )
+        }),
+    );
+    let upstream = common::spawn_server(router).await;
+    let sink = Arc::new(MemorySink::new());
+    let config = Config {
+        estimate_tokens_locally: true,
+        ..Config::default()
+    };
+    let state = AppState::new(config)
+        .with_sink(sink.clone())
+        .with_tokenizer(rust_llm_logger::tokens::TokenEstimators::new(Box::new(
+            rust_llm_logger::tokens::CharsPerToken(1.0),
+        )));
+    let proxy = common::spawn_server(app::router(state)).await;
+
+    common::post_json(proxy, upstream.port(), "api/generate", r#"{"model":"llama2","prompt":"hi there"}"#).await;
+    let records = common::wait_for_records(&sink, 1).await;
+
+    // Reported counts are kept as-is; the tokenizer's counts go in their own fields
+    assert_eq!((records[0].prompt_tokens, records[0].completion_tokens), (Some(7), Some(2)));
+    assert!(!records[0].tokens_estimated);
+    assert_eq!(records[0].estimated_prompt_tokens, Some(8));
+    assert_eq!(records[0].estimated_completion_tokens, Some(5));
+}
+
+#[tokio::test]
+async fn test_local_prompt_estimate_covers_the_whole_prompt() {
+    let router = Router::new().fallback(|| async {
+        (
+            [("content-type", "application/x-ndjson")],
+            "{\"response\":\"Hello\",\"done\":false}\n{\"response\":\"\",\"done\":true}\n",
+        )
+    });
+    let upstream = common::spawn_server(router).await;
+    let sink = Arc::new(MemorySink::new());
+    let config = Config {
+        estimate_tokens_locally: true,
+        prompt_capture: PromptCapture::LastUserMessage,
+        ..Config::default()
+    };
+    let state = AppState::new(config)
+        .with_sink(sink.clone())
+        .with_tokenizer(rust_llm_logger::tokens::TokenEstimators::new(Box::new(
+            rust_llm_logger::tokens::CharsPerToken(1.0),
+        )));
+    let proxy = common::spawn_server(app::router(state)).await;
+
+    let chat = r#"{"model":"llama2","messages":[
+        {"role":"user","content":"Hi"},
+        {"role":"assistant","content":"Hello!"},
+        {"role":"user","content":"Bye"}
+    ]}"#;
+    common::post_json(proxy, upstream.port(), "api/chat", chat).await;
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].prompt, "Bye");
+    // "user: Hi\nassistant: Hello!\nuser: Bye", not just the captured last message
+    assert_eq!(records[0].estimated_prompt_tokens, Some(36));
+
+    // A request without a prompt has nothing to count, not a placeholder
+    common::post_json(proxy, upstream.port(), "api/generate", r#"{"model":"llama2"}"#).await;
+    let records = common::wait_for_records(&sink, 2).await;
+    assert_eq!(records[1].estimated_prompt_tokens, None);
+}
+
+#[tokio::test]
+async fn test_no_local_token_estimates_by_default() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    common::post_json(proxy, upstream.port(), "api/generate", r#"{"model":"llama2","prompt":"Hi"}"#).await;
+    let records = common::wait_for_records(&sink, 1).await;
+
+    assert_eq!((records[0].estimated_prompt_tokens, records[0].estimated_completion_tokens), (None, None));
+}
+
+/// Serves `router` over a single HTTP version: HTTP/2 with prior knowledge, or HTTP/1.1
+async fn spawn_single_version_server(router: Router, http2: bool) -> std::net::SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let io = hyper_util::rt::TokioIo::new(stream);
+            let service = hyper_util::service::TowerToHyperService::new(router.clone());
+            tokio::spawn(async move {
+                if http2 {
+                    let builder = hyper::server::conn::http2::Builder::new(hyper_util::rt::TokioExecutor::new());
+                    let _ = builder.serve_connection(io, service).await;
+                } else {
+                    let _ = hyper::server::conn::http1::Builder::new().serve_connection(io, service).await;
+                }
+            });
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn test_http2_upstream_streams_responses() {
+    // Only HTTP/2 is accepted, so a successful response proves the proxy spoke it
+    let upstream = spawn_single_version_server(common::mock_server::ollama_app(), true).await;
+    let config = Config {
+        upstream_http2: true,
+        ..Config::default()
+    };
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let body = r#"{"model":"llama2","prompt":"Why is the sky blue?","stream":true}"#;
+    let (status, response) = common::post_json(proxy, upstream.port(), "api/generate", body).await;
+    assert_eq!(status, 200, "{}", response);
+    assert!(response.lines().count() > 1, "expected a streamed body: {}", response);
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert!(records[0].completed);
+    assert!(records[0].frame_count > 1);
+    assert!(records[0].completion_tokens.unwrap() > 0);
+}
+
+#[tokio::test]
+async fn test_http2_falls_back_to_http1_upstream() {
+    let (logs, _guard) = common::capture_logs();
+    let upstream = spawn_single_version_server(common::mock_server::ollama_app(), false).await;
+    let config = Config::from_toml(&format!("[backends.{}]\nhttp2 = true", upstream.port())).unwrap();
+    let (proxy, sink) = common::spawn_proxy_with_sink(config).await;
+
+    let body = r#"{"model":"llama2","prompt":"Hi","stream":true}"#;
+    for i in 1..=2 {
+        let (status, response) = common::post_json(proxy, upstream.port(), "api/generate", body).await;
+        assert_eq!(status, 200, "{}", response);
+        common::wait_for_records(&sink, i).await;
+    }
+
+    // The first request found out the upstream only speaks HTTP/1.1; the second went straight there
+    let output = logs.contents();
+    assert_eq!(output.matches("retrying over HTTP/1.1").count(), 1, "{}", output);
+    assert!(sink.records().iter().all(|r| r.completed));
+}
+
+/// Upstream that answers with an NDJSON body followed by the given trailers
+fn trailer_upstream(trailers: &'static [(&'static str, &'static str)]) -> Router {
+    Router::new().route(
+        "/api/generate",
+        post(move || async move {
+            let mut fields = hyper::HeaderMap::new();
+            for (name, value) in trailers {
+                fields.insert(*name, value.parse().unwrap());
+            }
+            let frames = vec![
+                Ok::<_, std::io::Error>(hyper::body::Frame::data(bytes::Bytes::from_static(
+                    b"{\"response\":\"Hi\",\"done\":false}\n{\"response\":\"\",\"done\":true,\"eval_count\":1}\n",
+                ))),
+                Ok(hyper::body::Frame::trailers(fields)),
+            ];
+            let body = http_body_util::StreamBody::new(futures::stream::iter(frames));
+            // HTTP/1.1 only carries trailers declared up front
+            let declared = trailers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ");
+            (
+                [("content-type", "application/x-ndjson".to_string()), ("trailer", declared)],
+                axum::body::Body::new(body),
+            )
+        }),
+    )
+}
+
+/// Posts a generate request that accepts trailers, returning the status and trailers received
+async fn post_accepting_trailers(proxy: std::net::SocketAddr, upstream_port: u16) -> (u16, Option<hyper::HeaderMap>) {
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let req = hyper::Request::post(format!("http://{}/proxy/{}/api/generate", proxy, upstream_port))
+        .header("content-type", "application/json")
+        .header("te", "trailers")
+        .body(axum::body::Body::from(r#"{"model":"llama2","prompt":"Hi"}"#))
+        .unwrap();
+    let resp = client.request(req).await.unwrap();
+    let status = resp.status().as_u16();
+    let collected = http_body_util::BodyExt::collect(resp.into_body()).await.unwrap();
+    (status, collected.trailers().cloned())
+}
+
+#[tokio::test]
+async fn test_upstream_trailers_reach_client_and_metrics() {
+    let upstream = common::spawn_server(trailer_upstream(&[("x-usage-tokens", "12"), ("grpc-status", "0")])).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let (status, trailers) = post_accepting_trailers(proxy, upstream.port()).await;
+    assert_eq!(status, 200);
+    let trailers = trailers.expect("trailers forwarded to the client");
+    assert_eq!(trailers["x-usage-tokens"], "12");
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].trailers.get("x-usage-tokens").map(String::as_str), Some("12"));
+    assert_eq!(records[0].trailers.get("grpc-status").map(String::as_str), Some("0"));
+    assert_eq!(records[0].stream_error, None);
+    assert!(records[0].completed);
+}
+
+#[tokio::test]
+async fn test_failing_grpc_status_trailer_marks_stream_incomplete() {
+    let upstream = common::spawn_server(trailer_upstream(&[
+        ("grpc-status", "8"),
+        ("grpc-message", "quota exhausted"),
+        ("api-key", "sk-trailer-secret"),
+    ]))
+    .await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    post_accepting_trailers(proxy, upstream.port()).await;
+
+    let records = common::wait_for_records(&sink, 1).await;
+    assert_eq!(records[0].stream_error.as_deref(), Some("grpc-status 8: quota exhausted"));
+    assert!(!records[0].completed);
+    assert_eq!(records[0].trailers["api-key"], "[REDACTED]");
+}
+
+#[tokio::test]
+async fn test_timings_show_negligible_proxy_overhead() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    let body = r#"{"model":"llama2","prompt":"Why is the sky blue?","stream":true}"#;
+    common::post_json(proxy, upstream.port(), "api/generate", body).await;
+    let records = common::wait_for_records(&sink, 1).await;
+    let timings = records[0].timings.as_ref().expect("timings recorded");
+
+    // The mock streams a word every 10ms, all of it upstream time
+    let stream_ms = timings.stream_ms.expect("stream time recorded");
+    assert!(stream_ms > 200.0, "{:?}", timings);
+    assert!(timings.upstream_ttfb_ms.is_some(), "{:?}", timings);
+    assert!(timings.body_read_ms <= timings.proxy_overhead_ms, "{:?}", timings);
+    assert!(timings.proxy_overhead_ms < 10.0, "{:?}", timings);
+    assert!(timings.proxy_overhead_ms < records[0].total_ms as f64);
+}
+
+#[tokio::test]
+async fn test_total_time_counts_from_arrival() {
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let (proxy, sink) = common::spawn_proxy_with_sink(Config::default()).await;
+
+    // The client sends half the body, then waits before sending the rest
+    let halves = [r#"{"model":"llama2","#, r#""prompt":"Hi","stream":true}"#];
+    let halves = futures::StreamExt::then(futures::stream::iter(halves), |half| async move {
+        if half.starts_with('"') {
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        }
+        Ok::<_, std::io::Error>(bytes::Bytes::from_static(half.as_bytes()))
+    });
+    let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+        .build_http::<axum::body::Body>();
+    let req = hyper::Request::post(format!("http://{}/proxy/{}/api/generate", proxy, upstream.port()))
+        .header("content-type", "application/json")
+        .body(axum::body::Body::from_stream(halves))
+        .unwrap();
+    let resp = client.request(req).await.unwrap();
+    http_body_util::BodyExt::collect(resp.into_body()).await.unwrap();
+
+    let records = common::wait_for_records(&sink, 1).await;
+    let timings = records[0].timings.as_ref().expect("timings recorded");
+    assert!(timings.body_read_ms >= 200.0, "{:?}", timings);
+    assert!(records[0].upstream_connect_ms.unwrap() >= 200, "{:?}", records[0].upstream_connect_ms);
+    assert!(records[0].ttft_ms.unwrap() >= 200, "{:?}", records[0].ttft_ms);
+    assert!(records[0].total_ms as f64 >= timings.body_read_ms + timings.stream_ms.unwrap_or_default());
+    assert_eq!(records[0].latency_ms, records[0].total_ms);
+}
+
+/// Serves `router` over HTTP/1.1, counting the connections opened to it
+async fn spawn_connection_counting_server(router: Router) -> (std::net::SocketAddr, Arc<std::sync::atomic::AtomicUsize>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connections = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = connections.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let io = hyper_util::rt::TokioIo::new(stream);
+            let service = hyper_util::service::TowerToHyperService::new(router.clone());
+            tokio::spawn(async move {
+                let _ = hyper::server::conn::http1::Builder::new().serve_connection(io, service).await;
+            });
+        }
+    });
+    (addr, connections)
+}
+
+/// Connections the upstream saw for two requests sent `pause` apart
+async fn connections_for_two_requests(pool: &str, pause: std::time::Duration) -> usize {
+    let (upstream, connections) = spawn_connection_counting_server(common::mock_server::ollama_app()).await;
+    let config = Config::from_toml(&format!("[upstream_pool]\n{}", pool)).unwrap();
+    let proxy = common::spawn_proxy(config).await;
+
+    let body = r#"{"model":"llama2","prompt":"Hi","stream":false}"#;
+    common::post_json(proxy, upstream.port(), "api/generate", body).await;
+    tokio::time::sleep(pause).await;
+    common::post_json(proxy, upstream.port(), "api/generate", body).await;
+    connections.load(std::sync::atomic::Ordering::SeqCst)
+}
+
+#[tokio::test]
+async fn test_upstream_connections_are_reused_by_default() {
+    let pause = std::time::Duration::from_millis(300);
+    assert_eq!(connections_for_two_requests("", pause).await, 1);
+}
+
+#[tokio::test]
+async fn test_upstream_pool_without_idle_connections_redials() {
+    let pause = std::time::Duration::from_millis(50);
+    assert_eq!(connections_for_two_requests("max_idle_per_host = 0", pause).await, 2);
+}
+
+#[tokio::test]
+async fn test_upstream_pool_idle_timeout_closes_connections() {
+    let pause = std::time::Duration::from_millis(300);
+    assert_eq!(connections_for_two_requests("idle_timeout_ms = 100", pause).await, 2);
+}
+
+#[test]
+fn test_upstream_pool_config_parses() {
+    let config = Config::from_toml("[upstream_pool]\nmax_idle_per_host = 4\ntcp_keepalive_secs = 0").unwrap();
+    assert_eq!(config.upstream_pool.max_idle_per_host, 4);
+    assert_eq!(config.upstream_pool.tcp_keepalive_secs, 0);
+    assert_eq!(config.upstream_pool.idle_timeout_ms, 90_000);
+}
@@ -0,0 +1,42 @@
+// tests/quality.rs
+
+use rust_llm_logger::quality::CompletionQuality;
+
+#[test]
+fn test_clean_completion_is_not_flagged() {
+    let quality = CompletionQuality::assess("The capital of France is Paris.", Some("stop"));
+
+    assert_eq!(
+        quality,
+        CompletionQuality {
+            chars: 31,
+            empty: false,
+            looks_truncated: false,
+        }
+    );
+}
+
+#[test]
+fn test_mid_sentence_completion_without_stop_looks_truncated() {
+    let quality = CompletionQuality::assess("The capital of France is", Some("length"));
+    assert!(quality.looks_truncated);
+    assert!(!quality.empty);
+
+    // Streams cut off before any finish reason arrives are judged the same way
+    assert!(CompletionQuality::assess("Once upon a", None).looks_truncated);
+}
+
+#[test]
+fn test_natural_stop_overrides_missing_punctuation() {
+    // Lists and code often end without a sentence terminator
+    let quality = CompletionQuality::assess("- apples\n- pears\n", Some("end_turn"));
+    assert!(!quality.looks_truncated);
+}
+
+#[test]
+fn test_empty_completion() {
+    let quality = CompletionQuality::assess("  \n", Some("stop"));
+    assert!(quality.empty);
+    assert!(!quality.looks_truncated);
+    assert_eq!(quality.chars, 3);
+}
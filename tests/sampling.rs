@@ -0,0 +1,80 @@
+// tests/sampling.rs
+
+use rust_llm_logger::sampling::{Sampler, SamplingConfig};
+use rust_llm_logger::types::LLMMetrics;
+
+fn healthy() -> LLMMetrics {
+    LLMMetrics {
+        latency_ms: 100,
+        status_code: 200,
+        success: true,
+        completed: true,
+        ..LLMMetrics::default()
+    }
+}
+
+fn recorded(sampler: &Sampler, requests: usize) -> usize {
+    (0..requests)
+        .filter(|_| sampler.should_record(&healthy()))
+        .count()
+}
+
+#[test]
+fn test_default_sampling_records_everything() {
+    let sampler = Sampler::new(SamplingConfig::default());
+    assert_eq!(recorded(&sampler, 1000), 1000);
+}
+
+#[test]
+fn test_every_n_records_exactly_one_in_n() {
+    let sampler = Sampler::new(SamplingConfig {
+        every_n: Some(10),
+        ..SamplingConfig::default()
+    });
+    assert_eq!(recorded(&sampler, 1000), 100);
+}
+
+#[test]
+fn test_probabilistic_rate_is_roughly_honored() {
+    let sampler = Sampler::new(SamplingConfig {
+        rate: 0.25,
+        ..SamplingConfig::default()
+    });
+    let count = recorded(&sampler, 20_000);
+    assert!((4_500..5_500).contains(&count), "recorded {} of 20000 at rate 0.25", count);
+}
+
+#[test]
+fn test_errors_and_slow_requests_bypass_sampling() {
+    let sampler = Sampler::new(SamplingConfig {
+        rate: 0.0,
+        always_log_latency_ms: Some(5_000),
+        ..SamplingConfig::default()
+    });
+    assert_eq!(recorded(&sampler, 100), 0);
+
+    let server_error = LLMMetrics {
+        status_code: 500,
+        success: false,
+        ..healthy()
+    };
+    assert!(sampler.should_record(&server_error));
+    let cut_off = LLMMetrics {
+        completed: false,
+        success: false,
+        ..healthy()
+    };
+    assert!(sampler.should_record(&cut_off));
+    let slow = LLMMetrics {
+        latency_ms: 6_000,
+        ..healthy()
+    };
+    assert!(sampler.should_record(&slow));
+
+    let strict = Sampler::new(SamplingConfig {
+        rate: 0.0,
+        always_log_errors: false,
+        ..SamplingConfig::default()
+    });
+    assert!(!strict.should_record(&server_error));
+}
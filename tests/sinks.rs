@@ -0,0 +1,285 @@
+// tests/sinks.rs
+
+mod common;
+
+use axum::{http::HeaderMap, http::StatusCode, routing::post, Router};
+use rust_llm_logger::app::{self, AppState};
+use rust_llm_logger::config::Config;
+use rust_llm_logger::parsers::BackendType;
+use rust_llm_logger::sinks::file::{FileSink, FileSinkConfig};
+use rust_llm_logger::sinks::genai::{genai_system, GenAiSink};
+use rust_llm_logger::sinks::log::{to_logfmt, LogConfig, LogFormat, TracingSink};
+use rust_llm_logger::sinks::webhook::{sign_payload, WebhookConfig, SIGNATURE_HEADER};
+use rust_llm_logger::sinks::MetricsSink;
+use rust_llm_logger::types::LLMMetrics;
+use tokio_util::task::TaskTracker;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Signature header and body of each delivery the receiver saw
+type Deliveries = Arc<Mutex<Vec<(Option<String>, String)>>>;
+
+/// Receiver that fails the first delivery and accepts the retry
+async fn spawn_flaky_receiver() -> (std::net::SocketAddr, Deliveries) {
+    let deliveries: Deliveries = Arc::new(Mutex::new(Vec::new()));
+    let deliveries_clone = deliveries.clone();
+    let receiver = Router::new().route(
+        "/collect",
+        post(move |headers: HeaderMap, body: String| {
+            let deliveries = deliveries_clone.clone();
+            async move {
+                let signature = headers
+                    .get(SIGNATURE_HEADER)
+                    .map(|v| v.to_str().unwrap().to_string());
+                let mut deliveries = deliveries.lock().unwrap();
+                deliveries.push((signature, body));
+                if deliveries.len() == 1 {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                } else {
+                    StatusCode::OK
+                }
+            }
+        }),
+    );
+    (common::spawn_server(receiver).await, deliveries)
+}
+
+#[tokio::test]
+async fn test_webhook_sink_retries_and_signs_payload() {
+    let (receiver_addr, deliveries) = spawn_flaky_receiver().await;
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let config = Config {
+        webhook: Some(WebhookConfig {
+            url: format!("http://{}/collect", receiver_addr),
+            hmac_secret: Some("s3cret".to_string()),
+            ..WebhookConfig::default()
+        }),
+        ..Config::default()
+    };
+    let proxy = common::spawn_proxy(config).await;
+
+    let (status, _) = common::post_json(
+        proxy,
+        upstream.port(),
+        "api/generate",
+        r#"{"model":"llama2","prompt":"Why is the sky blue?","stream":true}"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+
+    let mut delivered = Vec::new();
+    for _ in 0..200 {
+        delivered = deliveries.lock().unwrap().clone();
+        if delivered.len() >= 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(delivered.len(), 2, "first delivery fails, the retry succeeds");
+
+    let (signature, body) = &delivered[1];
+    assert_eq!(signature.as_deref(), Some(sign_payload("s3cret", body.as_bytes()).as_str()));
+
+    let metrics: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(metrics["model"], "llama2");
+    assert_eq!(metrics["prompt_tokens"], 5);
+}
+
+#[tokio::test]
+async fn test_graceful_shutdown_waits_for_webhook_retries() {
+    let (receiver_addr, deliveries) = spawn_flaky_receiver().await;
+    let upstream = common::spawn_server(common::mock_server::ollama_app()).await;
+    let config = Config {
+        webhook: Some(WebhookConfig {
+            url: format!("http://{}/collect", receiver_addr),
+            ..WebhookConfig::default()
+        }),
+        ..Config::default()
+    };
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = listener.local_addr().unwrap();
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(app::serve(listener, AppState::new(config), async {
+        let _ = shutdown_rx.await;
+    }));
+
+    let body = r#"{"model":"llama2","prompt":"Hi","stream":true}"#;
+    let (status, _) = common::post_json(proxy, upstream.port(), "api/generate", body).await;
+    assert_eq!(status, 200);
+    shutdown_tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+
+    // The retry lands after a backoff, but serve doesn't return before it
+    assert_eq!(deliveries.lock().unwrap().len(), 2);
+}
+
+fn sample_metrics(i: usize) -> LLMMetrics {
+    LLMMetrics {
+        model: "llama2".to_string(),
+        prompt: format!("prompt number {}", i),
+        prompt_tokens: Some(10),
+        completion_tokens: Some(20),
+        latency_ms: 100,
+        ..LLMMetrics::default()
+    }
+}
+
+fn dir_entries(dir: &std::path::Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn test_file_sink_rotates_past_size_threshold() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("metrics.jsonl");
+    // Room for three of the longest records, however large records grow
+    let line_len = serde_json::to_vec(&sample_metrics(19)).unwrap().len() as u64 + 1;
+    let max_bytes = 3 * line_len;
+    let sink = FileSink::new(
+        FileSinkConfig {
+            path: path.clone(),
+            max_bytes: Some(max_bytes),
+            ..FileSinkConfig::default()
+        },
+        TaskTracker::new(),
+    );
+
+    for i in 0..20 {
+        sink.record(&sample_metrics(i)).await;
+    }
+
+    let names = dir_entries(dir.path());
+    assert!(names.len() >= 7, "expected at most three records per segment, found {:?}", names);
+    assert!(names.contains(&"metrics.jsonl".to_string()));
+    assert!(names.iter().any(|n| n.starts_with("metrics-") && n.ends_with(".jsonl")));
+
+    // Every record landed in exactly one segment
+    let total_lines: usize = names
+        .iter()
+        .map(|n| std::fs::read_to_string(dir.path().join(n)).unwrap().lines().count())
+        .sum();
+    assert_eq!(total_lines, 20);
+    assert!(std::fs::metadata(&path).unwrap().len() <= max_bytes);
+}
+
+#[tokio::test]
+async fn test_file_sink_gzips_closed_segments() {
+    let dir = tempfile::tempdir().unwrap();
+    let tasks = TaskTracker::new();
+    let sink = FileSink::new(
+        FileSinkConfig {
+            path: dir.path().join("metrics.jsonl"),
+            max_bytes: Some(512),
+            gzip: true,
+            ..FileSinkConfig::default()
+        },
+        tasks.clone(),
+    );
+
+    for i in 0..10 {
+        sink.record(&sample_metrics(i)).await;
+    }
+
+    // Compression runs on the tracker, so shutdown waits for it like any other task
+    tasks.close();
+    tasks.wait().await;
+    let names = dir_entries(dir.path());
+    assert!(names.iter().any(|n| n.ends_with(".jsonl.gz")), "found {:?}", names);
+
+    // The closed segment decompresses to whole records
+    let gz = names.iter().find(|n| n.ends_with(".jsonl.gz")).unwrap();
+    let mut contents = String::new();
+    let file = std::fs::File::open(dir.path().join(gz)).unwrap();
+    std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(file), &mut contents).unwrap();
+    assert!(!contents.is_empty());
+    for line in contents.lines() {
+        serde_json::from_str::<LLMMetrics>(line).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_file_sink_rolls_over_file_left_from_an_earlier_day() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("metrics.jsonl");
+    std::fs::write(&path, "{\"stale\":true}\n").unwrap();
+    let two_days_ago = std::time::SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60);
+    std::fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(two_days_ago)
+        .unwrap();
+
+    let sink = FileSink::new(
+        FileSinkConfig {
+            path: path.clone(),
+            rotate_daily: true,
+            ..FileSinkConfig::default()
+        },
+        TaskTracker::new(),
+    );
+    sink.record(&sample_metrics(0)).await;
+    sink.record(&sample_metrics(1)).await;
+
+    let names = dir_entries(dir.path());
+    assert_eq!(names.len(), 2, "expected the old file rotated away: {:?}", names);
+    let active = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(active.lines().count(), 2);
+    assert!(!active.contains("stale"));
+}
+
+#[tokio::test]
+async fn test_genai_sink_uses_semantic_convention_attributes() {
+    let (logs, _guard) = common::capture_logs();
+
+    let metrics = LLMMetrics {
+        backend: BackendType::OpenAI,
+        ttft_ms: Some(40),
+        ..sample_metrics(0)
+    };
+    GenAiSink.record(&metrics).await;
+
+    let output = logs.contents();
+    assert!(output.contains("gen_ai:"), "missing gen_ai target: {}", output);
+    assert!(output.contains("gen_ai.system=\"openai\""), "{}", output);
+    assert!(output.contains("gen_ai.request.model=\"llama2\""), "{}", output);
+    assert!(output.contains("gen_ai.usage.input_tokens=10"), "{}", output);
+    assert!(output.contains("gen_ai.usage.output_tokens=20"), "{}", output);
+
+    assert_eq!(genai_system(BackendType::Ollama), "ollama");
+    assert_eq!(genai_system(BackendType::Unknown), "_OTHER");
+}
+
+#[tokio::test]
+async fn test_tracing_sink_compact_format_without_summary_line() {
+    let (logs, _guard) = common::capture_logs();
+
+    let sink = TracingSink::new(LogConfig {
+        format: LogFormat::Compact,
+        summary_line: false,
+    });
+    sink.record(&sample_metrics(0)).await;
+
+    let output = logs.contents();
+    assert!(!output.contains("LLM Request Complete"), "{}", output);
+    assert_eq!(output.lines().count(), 1, "{}", output);
+    assert!(output.contains(r#""model":"llama2""#), "{}", output);
+}
+
+#[test]
+fn test_logfmt_quotes_only_when_needed_and_skips_nulls() {
+    let line = to_logfmt(&sample_metrics(3));
+
+    assert!(line.contains("model=llama2"), "{}", line);
+    assert!(line.contains(r#"prompt="prompt number 3""#), "{}", line);
+    assert!(line.contains("prompt_tokens=10"), "{}", line);
+    assert!(line.contains("latency_ms=100"), "{}", line);
+    assert!(!line.contains("ttft_ms="), "null fields are omitted: {}", line);
+    assert!(!line.contains('\n'));
+}
@@ -0,0 +1,40 @@
+// tests/text.rs
+
+use rust_llm_logger::text::{excerpt, normalized_sha256, sha256_hex};
+
+#[test]
+fn test_excerpt_cuts_multibyte_text_on_char_boundaries() {
+    let text = "héllo wörld 👋🏽 日本語";
+
+    assert_eq!(excerpt(text, 2), "hé");
+    assert_eq!(excerpt(text, 13), "héllo wörld 👋");
+    assert_eq!(excerpt(text, 16), "héllo wörld 👋🏽 日");
+    assert_eq!(excerpt(text, 100), text);
+    assert_eq!(excerpt(text, 0), text);
+
+    // Every cut is valid UTF-8 that round-trips through JSON
+    for max_chars in 1..=text.chars().count() {
+        let cut = excerpt(text, max_chars);
+        assert_eq!(cut.chars().count(), max_chars);
+        let json = serde_json::to_string(cut).unwrap();
+        assert_eq!(serde_json::from_str::<String>(&json).unwrap(), cut);
+    }
+}
+
+#[test]
+fn test_sha256_is_stable_hex() {
+    assert_eq!(
+        sha256_hex("hello"),
+        "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+    );
+    assert_eq!(sha256_hex("héllo 👋"), sha256_hex("héllo 👋"));
+    assert_ne!(sha256_hex("héllo 👋"), sha256_hex("hello 👋"));
+}
+
+#[test]
+fn test_normalized_sha256_ignores_whitespace_layout() {
+    assert_eq!(normalized_sha256("user: hi there"), sha256_hex("user: hi there"));
+    assert_eq!(normalized_sha256("  user:\thi\r\n\n there "), sha256_hex("user: hi there"));
+    assert_ne!(normalized_sha256("user: hi there"), normalized_sha256("user: hithere"));
+    assert_eq!(normalized_sha256(""), sha256_hex(""));
+}
@@ -0,0 +1,88 @@
+// tests/timing.rs
+
+use rust_llm_logger::timing::{compute_throughput, ChunkGapStats, ThroughputSource};
+use std::time::Duration;
+
+#[test]
+fn test_throughput_prefers_upstream_eval_duration() {
+    // 100 tokens over an upstream-reported 2s, even though the proxy saw a longer window
+    let throughput = compute_throughput(
+        Some(100),
+        Some(Duration::from_millis(50)),
+        Some(Duration::from_millis(3050)),
+        Some(2000),
+    );
+
+    assert_eq!(throughput.generation_time_ms, Some(2000));
+    assert_eq!(throughput.tokens_per_second, Some(50.0));
+    assert_eq!(throughput.source, Some(ThroughputSource::Upstream));
+}
+
+#[test]
+fn test_throughput_from_proxy_chunk_timestamps() {
+    let throughput = compute_throughput(
+        Some(40),
+        Some(Duration::from_millis(100)),
+        Some(Duration::from_millis(600)),
+        None,
+    );
+
+    assert_eq!(throughput.generation_time_ms, Some(500));
+    assert_eq!(throughput.tokens_per_second, Some(80.0));
+    assert_eq!(throughput.source, Some(ThroughputSource::Proxy));
+}
+
+#[test]
+fn test_throughput_single_chunk_has_no_rate() {
+    let at = Some(Duration::from_millis(120));
+
+    let throughput = compute_throughput(Some(12), at, at, None);
+
+    assert_eq!(throughput.tokens_per_second, None);
+    assert_eq!(throughput.source, None);
+}
+
+#[test]
+fn test_throughput_zero_upstream_duration_has_no_rate() {
+    let throughput = compute_throughput(Some(12), None, None, Some(0));
+
+    assert_eq!(throughput.generation_time_ms, Some(0));
+    assert_eq!(throughput.tokens_per_second, None);
+}
+
+#[test]
+fn test_chunk_gap_stats_exact_for_small_streams() {
+    let mut stats = ChunkGapStats::new();
+    let mut at = 0;
+    for gap in 1..=100u64 {
+        at += gap;
+        stats.record_frame(Duration::from_millis(at));
+    }
+    // The first frame has nothing to compare against, so gaps 2..=100 are recorded
+    let summary = stats.summary().unwrap();
+
+    assert_eq!(summary.min_ms, 2.0);
+    assert_eq!(summary.max_ms, 100.0);
+    assert_eq!(summary.mean_ms, 51.0);
+    assert_eq!(summary.p95_ms, 96.0);
+}
+
+#[test]
+fn test_chunk_gap_stats_bounded_for_long_streams() {
+    let mut stats = ChunkGapStats::new();
+    for i in 0..100_000u64 {
+        stats.record_frame(Duration::from_millis(i * 10));
+    }
+    let summary = stats.summary().unwrap();
+
+    assert_eq!(summary.mean_ms, 10.0);
+    assert_eq!(summary.p95_ms, 10.0);
+}
+
+#[test]
+fn test_chunk_gap_stats_needs_two_frames() {
+    let mut stats = ChunkGapStats::new();
+    stats.record_frame(Duration::from_millis(5));
+
+    assert_eq!(stats.summary(), None);
+}
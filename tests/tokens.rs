@@ -0,0 +1,142 @@
+// tests/tokens.rs
+
+use rust_llm_logger::tokens::{
+    estimate_tokens, CharsPerToken, LexicalEstimator, PromptTokenCounter, TokenEstimator, TokenEstimators,
+};
+use rust_llm_logger::types::TokenUsage;
+
+/// Decodes the prompt the way the buffered path does and returns its estimate
+fn buffered_count(body: &str) -> u32 {
+    let value: serde_json::Value = serde_json::from_str(body).unwrap();
+    estimate_tokens(value["prompt"].as_str().unwrap())
+}
+
+fn streamed_count(body: &str, chunk_size: usize) -> Option<u32> {
+    let mut counter = PromptTokenCounter::new();
+    for chunk in body.as_bytes().chunks(chunk_size) {
+        counter.feed(chunk);
+    }
+    counter.prompt_tokens()
+}
+
+#[test]
+fn test_streamed_prompt_count_matches_buffered_count_for_large_prompt() {
+    // Mix ASCII, escapes, multi-byte UTF-8, and a surrogate-pair escape
+    let piece = "The sky is \"blue\" because of Rayleigh scattering.\n\t空が青い 😀 \u{00e9}";
+    let prompt: String = piece.repeat(20_000);
+    let body = serde_json::json!({
+        "model": "llama2",
+        "options": {"prompt": "nested, must be ignored"},
+        "prompt": prompt,
+        "stream": true,
+    })
+    .to_string()
+    .replace('😀', "\\ud83d\\ude00");
+    assert!(body.len() > 1_000_000);
+
+    let expected = buffered_count(&body);
+    assert_eq!(streamed_count(&body, 8192), Some(expected));
+    assert_eq!(streamed_count(&body, 3), Some(expected));
+}
+
+#[test]
+fn test_streamed_prompt_count_absent_without_prompt_field() {
+    let body = r#"{"model":"llama2","messages":[{"role":"user","prompt":"hi","content":"prompt"}]}"#;
+
+    assert_eq!(streamed_count(body, 5), None);
+}
+
+#[test]
+fn test_estimate_tokens_rounds_up() {
+    assert_eq!(estimate_tokens(""), 0);
+    assert_eq!(estimate_tokens("abcd"), 1);
+    assert_eq!(estimate_tokens("abcde"), 2);
+}
+
+#[test]
+fn test_lexical_estimates_are_close_to_known_counts() {
+    // Counts reported by the upstreams (cl100k / llama vocabularies)
+    let known = [
+        ("Hello! How can I help?", 7),
+        ("The sky is blue.", 5),
+        ("The quick brown fox jumps over the lazy dog.", 10),
+    ];
+    for (text, actual) in known {
+        let estimate = LexicalEstimator.estimate(text);
+        let error = (f64::from(estimate) - f64::from(actual)).abs() / f64::from(actual);
+        assert!(error <= 0.2, "{:?}: estimated {}, actual {}", text, estimate, actual);
+    }
+}
+
+#[test]
+fn test_estimators_are_chosen_by_longest_model_prefix() {
+    let estimators = TokenEstimators::default()
+        .with_family("llama", Box::new(CharsPerToken(4.0)))
+        .with_family("llama-3", Box::new(CharsPerToken(2.0)));
+    let text = "abcdefgh";
+
+    assert_eq!(estimators.for_model("Llama-2-7b").estimate(text), 2);
+    assert_eq!(estimators.for_model("llama-3.1-8b").estimate(text), 4);
+    assert_eq!(estimators.for_model("gpt-4o").estimate(text), LexicalEstimator.estimate(text));
+}
+
+#[test]
+fn test_backfill_only_fills_missing_counts() {
+    let estimators = TokenEstimators::default();
+
+    let mut usage = TokenUsage {
+        completion_text: "The sky is blue.".to_string(),
+        ..TokenUsage::default()
+    };
+    estimators.backfill("llama2", &mut usage);
+    assert_eq!((usage.completion_tokens, usage.estimated), (Some(5), true));
+
+    let mut reported = TokenUsage {
+        completion_tokens: Some(42),
+        completion_text: "The sky is blue.".to_string(),
+        ..TokenUsage::default()
+    };
+    estimators.backfill("llama2", &mut reported);
+    assert_eq!((reported.completion_tokens, reported.estimated), (Some(42), false));
+
+    let mut empty = TokenUsage::default();
+    estimators.backfill("llama2", &mut empty);
+    assert_eq!((empty.completion_tokens, empty.estimated), (None, false));
+}
+
+#[cfg(not(feature = "tiktoken"))]
+#[test]
+fn test_no_local_tokenizer_without_feature() {
+    assert!(rust_llm_logger::tokens::local_tokenizer().is_none());
+}
+
+#[cfg(feature = "tiktoken")]
+#[test]
+fn test_tiktoken_counts_are_within_band_of_known_counts() {
+    let tokenizer = rust_llm_logger::tokens::local_tokenizer().expect("built with the tiktoken feature");
+    // cl100k / o200k counts from OpenAI's tokenizer
+    let known = [
+        ("Hello! How can I help?", 7),
+        ("The quick brown fox jumps over the lazy dog.", 10),
+    ];
+    for model in ["gpt-4", "gpt-4o-mini"] {
+        for (text, actual) in known {
+            let estimate = tokenizer.for_model(model).estimate(text);
+            assert!(estimate.abs_diff(actual) <= 1, "{} {:?}: estimated {}, actual {}", model, text, estimate, actual);
+        }
+    }
+}
+
+#[cfg(feature = "tiktoken")]
+#[test]
+fn test_tiktoken_picks_vocabulary_by_model() {
+    use rust_llm_logger::tokens::Tiktoken;
+    let tokenizer = rust_llm_logger::tokens::local_tokenizer().unwrap();
+    let text = "Zusammenfassung der Ergebnisse: 日本語のテキストも含まれています。";
+
+    assert_eq!(tokenizer.for_model("gpt-4o").estimate(text), Tiktoken::o200k().estimate(text));
+    assert_eq!(tokenizer.for_model("o3-mini").estimate(text), Tiktoken::o200k().estimate(text));
+    assert_eq!(tokenizer.for_model("gpt-3.5-turbo").estimate(text), Tiktoken::cl100k().estimate(text));
+    assert_eq!(tokenizer.for_model("llama3").estimate(text), Tiktoken::cl100k().estimate(text));
+    assert_ne!(Tiktoken::o200k().estimate(text), Tiktoken::cl100k().estimate(text));
+}